@@ -8,10 +8,12 @@ use criterion::{Bencher, Criterion};
 use rayon::prelude::*;
 use solana::bank::Bank;
 use solana::banking_stage::BankingStage;
+use solana::bounded_queue::{bounded_channel, ShedPolicy};
 use solana::mint::Mint;
-use solana::packet::{to_packets_chunked, PacketRecycler};
+use solana::packet::{to_packets_chunked, BlobRecycler, PacketRecycler};
 use solana::record_stage::Signal;
 use solana::signature::{KeyPair, KeyPairUtil};
+use solana::sigverify_stage::{Verified, VERIFIED_QUEUE_CAPACITY};
 use solana::transaction::Transaction;
 use std::iter;
 use std::sync::mpsc::{channel, Receiver};
@@ -115,9 +117,11 @@ fn bench_banking_stage_multi_accounts(bencher: &mut Bencher) {
         })
         .collect();
 
-    let (verified_sender, verified_receiver) = channel();
+    let (verified_sender, verified_receiver) =
+        bounded_channel(VERIFIED_QUEUE_CAPACITY, ShedPolicy::DropOldest);
     let (signal_sender, signal_receiver) = channel();
     let packet_recycler = PacketRecycler::default();
+    let blob_recycler = BlobRecycler::default();
 
     let setup_transactions: Vec<_> = (0..num_src_accounts)
         .map(|i| {
@@ -138,14 +142,19 @@ fn bench_banking_stage_multi_accounts(bencher: &mut Bencher) {
                 .into_iter()
                 .map(|x| {
                     let len = (*x).read().unwrap().packets.len();
-                    (x, iter::repeat(1).take(len).collect())
+                    Verified::Packets(x, iter::repeat(1).take(len).collect())
                 })
                 .collect();
 
         let verified_setup_len = verified_setup.len();
         verified_sender.send(verified_setup).unwrap();
-        BankingStage::process_packets(&bank, &verified_receiver, &signal_sender, &packet_recycler)
-            .unwrap();
+        BankingStage::process_packets(
+            &bank,
+            &verified_receiver,
+            &signal_sender,
+            &packet_recycler,
+            &blob_recycler,
+        ).unwrap();
 
         check_txs(verified_setup_len, &signal_receiver, num_src_accounts);
 
@@ -153,14 +162,19 @@ fn bench_banking_stage_multi_accounts(bencher: &mut Bencher) {
             .into_iter()
             .map(|x| {
                 let len = (*x).read().unwrap().packets.len();
-                (x, iter::repeat(1).take(len).collect())
+                Verified::Packets(x, iter::repeat(1).take(len).collect())
             })
             .collect();
 
         let verified_len = verified.len();
         verified_sender.send(verified).unwrap();
-        BankingStage::process_packets(&bank, &verified_receiver, &signal_sender, &packet_recycler)
-            .unwrap();
+        BankingStage::process_packets(
+            &bank,
+            &verified_receiver,
+            &signal_sender,
+            &packet_recycler,
+            &blob_recycler,
+        ).unwrap();
 
         check_txs(verified_len, &signal_receiver, tx);
     });
@@ -187,9 +201,11 @@ fn bench_banking_stage_single_from(bencher: &mut Bencher) {
         })
         .collect();
 
-    let (verified_sender, verified_receiver) = channel();
+    let (verified_sender, verified_receiver) =
+        bounded_channel(VERIFIED_QUEUE_CAPACITY, ShedPolicy::DropOldest);
     let (signal_sender, signal_receiver) = channel();
     let packet_recycler = PacketRecycler::default();
+    let blob_recycler = BlobRecycler::default();
 
     bencher.iter(move || {
         let bank = Arc::new(Bank::new(&mint));
@@ -197,13 +213,18 @@ fn bench_banking_stage_single_from(bencher: &mut Bencher) {
             .into_iter()
             .map(|x| {
                 let len = (*x).read().unwrap().packets.len();
-                (x, iter::repeat(1).take(len).collect())
+                Verified::Packets(x, iter::repeat(1).take(len).collect())
             })
             .collect();
         let verified_len = verified.len();
         verified_sender.send(verified).unwrap();
-        BankingStage::process_packets(&bank, &verified_receiver, &signal_sender, &packet_recycler)
-            .unwrap();
+        BankingStage::process_packets(
+            &bank,
+            &verified_receiver,
+            &signal_sender,
+            &packet_recycler,
+            &blob_recycler,
+        ).unwrap();
 
         check_txs(verified_len, &signal_receiver, tx);
     });
@@ -0,0 +1,87 @@
+#[macro_use]
+extern crate criterion;
+extern crate solana;
+
+use criterion::{Bencher, Criterion};
+use solana::erasure::{Result, DEFAULT_NUM_CODING, NUM_CODED};
+#[cfg(feature = "jerasure")]
+use solana::jerasure;
+use solana::packet::BLOB_DATA_SIZE;
+use solana::reed_solomon;
+
+type EncodeFn = fn(&mut [&mut [u8]], &[&[u8]]) -> Result<()>;
+type DecodeFn = fn(&mut [&mut [u8]], &[&[u8]], &[i32]) -> Result<()>;
+
+const NUM_DATA: usize = NUM_CODED - DEFAULT_NUM_CODING;
+
+fn data_blocks() -> Vec<Vec<u8>> {
+    (0..NUM_DATA)
+        .map(|i| (0..BLOB_DATA_SIZE).map(|k| (k * 7 + i) as u8).collect())
+        .collect()
+}
+
+fn bench_encode(bencher: &mut Bencher, encode: EncodeFn) {
+    let data = data_blocks();
+    let data_slices: Vec<_> = data.iter().map(|x| x.as_slice()).collect();
+    let mut coding = vec![vec![0u8; BLOB_DATA_SIZE]; DEFAULT_NUM_CODING];
+    bencher.iter(|| {
+        let mut coding_slices: Vec<_> = coding.iter_mut().map(|x| x.as_mut_slice()).collect();
+        encode(&mut coding_slices, &data_slices).unwrap();
+    });
+}
+
+fn bench_decode(bencher: &mut Bencher, encode: EncodeFn, decode: DecodeFn) {
+    let mut data = data_blocks();
+    let mut coding = vec![vec![0u8; BLOB_DATA_SIZE]; DEFAULT_NUM_CODING];
+    {
+        let data_slices: Vec<_> = data.iter().map(|x| x.as_slice()).collect();
+        let mut coding_slices: Vec<_> = coding.iter_mut().map(|x| x.as_mut_slice()).collect();
+        encode(&mut coding_slices, &data_slices).unwrap();
+    }
+    let coding_slices: Vec<_> = coding.iter().map(|x| x.as_slice()).collect();
+    // lose as many data blocks as can be recovered
+    let mut erasures: Vec<i32> = (0..DEFAULT_NUM_CODING as i32).collect();
+    erasures.push(-1);
+    bencher.iter(|| {
+        let mut data_slices: Vec<_> = data.iter_mut().map(|x| x.as_mut_slice()).collect();
+        decode(&mut data_slices, &coding_slices, &erasures).unwrap();
+    });
+}
+
+fn bench(criterion: &mut Criterion) {
+    criterion.bench_function("bench_encode_reed_solomon", |bencher| {
+        bench_encode(bencher, reed_solomon::generate_coding_blocks);
+    });
+    criterion.bench_function("bench_decode_reed_solomon", |bencher| {
+        bench_decode(
+            bencher,
+            reed_solomon::generate_coding_blocks,
+            reed_solomon::decode_blocks,
+        );
+    });
+    bench_jerasure(criterion);
+}
+
+#[cfg(feature = "jerasure")]
+fn bench_jerasure(criterion: &mut Criterion) {
+    criterion.bench_function("bench_encode_jerasure", |bencher| {
+        bench_encode(bencher, jerasure::generate_coding_blocks);
+    });
+    criterion.bench_function("bench_decode_jerasure", |bencher| {
+        bench_decode(
+            bencher,
+            jerasure::generate_coding_blocks,
+            jerasure::decode_blocks,
+        );
+    });
+}
+
+#[cfg(not(feature = "jerasure"))]
+fn bench_jerasure(_criterion: &mut Criterion) {}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(2);
+    targets = bench
+);
+criterion_main!(benches);
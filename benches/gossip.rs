@@ -0,0 +1,159 @@
+#[macro_use]
+extern crate criterion;
+extern crate solana;
+
+use criterion::{Bencher, Criterion};
+use solana::bank::Bank;
+use solana::choose_gossip_peer_strategy::GossipStrategy;
+use solana::crdt::{Crdt, NodeInfo};
+use solana::mint::Mint;
+use solana::packet::{to_blob, BlobRecycler};
+use solana::signature::{KeyPair, KeyPairUtil};
+use solana::streamer::{default_window, Window};
+use solana::transaction::{Transaction, Vote};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+const NUM_NODES: usize = 128;
+const MAX_ROUNDS: usize = 100 * NUM_NODES;
+
+/// A cluster of gossip nodes that talk through function calls instead of sockets
+struct Network {
+    nodes: Vec<Arc<RwLock<Crdt>>>,
+    addrs: HashMap<SocketAddr, usize>,
+}
+
+/// Every tenth node holds a thousand times the stake of the others
+fn stake(i: usize) -> i64 {
+    if i % 10 == 0 {
+        1000
+    } else {
+        1
+    }
+}
+
+/// Start a network where everyone only knows node 0, and where the nodes look
+/// up each other's stake in a bank if `staked`, every one of them having voted
+fn new_network(strategy: GossipStrategy, staked: bool) -> Network {
+    let keypairs: Vec<_> = (0..NUM_NODES).map(|_| KeyPair::new()).collect();
+    let infos: Vec<_> = keypairs
+        .iter()
+        .enumerate()
+        .map(|(i, keypair)| {
+            let addr = SocketAddr::from(([127, 0, 0, 1], 10_000 + 10 * i as u16));
+            NodeInfo::new_leader_with_pubkey(keypair.pubkey(), &addr)
+        })
+        .collect();
+    let bank = if staked {
+        let mint = Mint::new(1_000_000);
+        let bank = Bank::new(&mint);
+        let vote = Vote {
+            version: 0,
+            contact_info_version: 0,
+        };
+        for (i, keypair) in keypairs.iter().enumerate() {
+            bank.transfer(stake(i), &mint.keypair(), keypair.pubkey(), mint.last_id())
+                .unwrap();
+            let tx = Transaction::new_vote(keypair, vote.clone(), mint.last_id(), 0);
+            bank.process_transaction(&tx).unwrap();
+        }
+        Some(Arc::new(bank))
+    } else {
+        None
+    };
+    let nodes = infos
+        .iter()
+        .map(|info| {
+            let mut crdt = Crdt::new(info.clone()).unwrap();
+            crdt.set_gossip_strategy(strategy);
+            if let Some(ref bank) = bank {
+                crdt.set_bank(bank.clone());
+            }
+            if info.id != infos[0].id {
+                crdt.insert(&infos[0]);
+            }
+            Arc::new(RwLock::new(crdt))
+        })
+        .collect();
+    let addrs = infos
+        .iter()
+        .enumerate()
+        .map(|(i, info)| (info.contact_info.ncp, i))
+        .collect();
+    Network { nodes, addrs }
+}
+
+/// Every node sends one gossip request and applies the response to it
+fn gossip_round(network: &Network, window: &Window, recycler: &BlobRecycler) {
+    for node in &network.nodes {
+        let (addr, req) = match node.read().unwrap().gossip_request() {
+            Ok(req) => req,
+            Err(_) => continue,
+        };
+        let peer = &network.nodes[network.addrs[&addr]];
+        let req = to_blob(req, addr, recycler).unwrap();
+        for rsp in Crdt::handle_blob(peer, window, recycler, &req.read().unwrap()) {
+            Crdt::handle_blob(node, window, recycler, &rsp.read().unwrap());
+        }
+    }
+}
+
+/// Gossip until every node has heard from every other, returning the rounds taken
+fn converge(strategy: GossipStrategy, staked: bool) -> usize {
+    let network = new_network(strategy, staked);
+    let window = default_window();
+    let recycler = BlobRecycler::default();
+    for round in 1..MAX_ROUNDS {
+        gossip_round(&network, &window, &recycler);
+        let converged = network
+            .nodes
+            .iter()
+            .all(|node| node.read().unwrap().convergence() == NUM_NODES as u64);
+        if converged {
+            return round;
+        }
+    }
+    panic!(
+        "{} nodes didn't converge in {} rounds",
+        NUM_NODES, MAX_ROUNDS
+    );
+}
+
+fn bench_convergence(bencher: &mut Bencher, strategy: GossipStrategy, staked: bool) {
+    bencher.iter(|| converge(strategy, staked));
+}
+
+fn bench(criterion: &mut Criterion) {
+    // The time taken depends on the cost of a round as much as on the number
+    // of them, so report that too
+    for &(strategy, staked) in &[
+        (GossipStrategy::Random, false),
+        (GossipStrategy::Weighted, false),
+        (GossipStrategy::Weighted, true),
+    ] {
+        println!(
+            "{:?} strategy{}: {} nodes converged in {} rounds",
+            strategy,
+            if staked { " with stakes" } else { "" },
+            NUM_NODES,
+            converge(strategy, staked)
+        );
+    }
+    criterion.bench_function("bench_gossip_convergence_random", |bencher| {
+        bench_convergence(bencher, GossipStrategy::Random, false);
+    });
+    criterion.bench_function("bench_gossip_convergence_weighted", |bencher| {
+        bench_convergence(bencher, GossipStrategy::Weighted, false);
+    });
+    criterion.bench_function("bench_gossip_convergence_weighted_staked", |bencher| {
+        bench_convergence(bencher, GossipStrategy::Weighted, true);
+    });
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(2);
+    targets = bench
+);
+criterion_main!(benches);
@@ -3,9 +3,11 @@ extern crate criterion;
 extern crate solana;
 
 use criterion::{Bencher, Criterion};
+use solana::entry::{Entry, CHECKPOINT_HASHES};
 use solana::hash::{hash, Hash};
 use solana::ledger::{next_entries, reconstruct_entries_from_blobs, Block};
 use solana::packet::BlobRecycler;
+use solana::recorder::Recorder;
 use solana::signature::{KeyPair, KeyPairUtil};
 use solana::transaction::Transaction;
 use std::collections::VecDeque;
@@ -26,10 +28,40 @@ fn bench_block_to_blobs_to_block(bencher: &mut Bencher) {
     });
 }
 
+/// Hashes in the tick the PoH benches generate and verify
+const NUM_TICK_HASHES: u64 = 32 * CHECKPOINT_HASHES;
+
+fn generate_tick(start_hash: &Hash) -> Entry {
+    let mut recorder = Recorder::new(*start_hash);
+    for _ in 0..NUM_TICK_HASHES {
+        recorder.hash();
+    }
+    recorder.record(vec![]).pop().unwrap()
+}
+
+fn bench_poh_generate(bencher: &mut Bencher) {
+    let zero = Hash::default();
+    bencher.iter(|| generate_tick(&zero));
+}
+
+fn bench_poh_verify(bencher: &mut Bencher) {
+    let zero = Hash::default();
+    let entries = vec![generate_tick(&zero)];
+    bencher.iter(|| {
+        assert!(entries.verify(&zero));
+    });
+}
+
 fn bench(criterion: &mut Criterion) {
     criterion.bench_function("bench_block_to_blobs_to_block", |bencher| {
         bench_block_to_blobs_to_block(bencher);
     });
+    criterion.bench_function("bench_poh_generate", |bencher| {
+        bench_poh_generate(bencher);
+    });
+    criterion.bench_function("bench_poh_verify", |bencher| {
+        bench_poh_verify(bencher);
+    });
 }
 
 criterion_group!(
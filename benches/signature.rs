@@ -3,17 +3,67 @@ extern crate criterion;
 extern crate solana;
 
 use criterion::{Bencher, Criterion};
-use solana::signature::GenKeys;
+use solana::hash::Hash;
+use solana::packet::{to_packets, PacketRecycler};
+use solana::signature::{GenKeys, KeyPair, KeyPairUtil};
+use solana::sigverify::{self, SigDedup, DEDUP_CAPACITY};
+use solana::transaction::Transaction;
+
+/// Number of times each transaction is resent by a client
+const NUM_COPIES: usize = 16;
 
 fn bench_gen_keys(b: &mut Bencher) {
     let rnd = GenKeys::new([0u8; 32]);
     b.iter(|| rnd.gen_n_keypairs(1000));
 }
 
+/// Transactions from clients that resend each one `NUM_COPIES` times
+fn duplicated_transactions() -> Vec<Transaction> {
+    let keypair = KeyPair::new();
+    let mut transactions = vec![];
+    for i in 0..256 {
+        let tx = Transaction::new(&keypair, keypair.pubkey(), i, Hash::default());
+        for _ in 0..NUM_COPIES {
+            transactions.push(tx.clone());
+        }
+    }
+    transactions
+}
+
+fn bench_sigverify(bencher: &mut Bencher) {
+    let recycler = PacketRecycler::default();
+    let batches = to_packets(&recycler, &duplicated_transactions());
+    bencher.iter(|| {
+        let results = sigverify::ed25519_verify(&batches);
+        assert!(results.iter().all(|r| r.iter().all(|v| *v == 1)));
+    })
+}
+
+fn bench_sigverify_dedup(bencher: &mut Bencher) {
+    let recycler = PacketRecycler::default();
+    let transactions = duplicated_transactions();
+    bencher.iter_with_setup(
+        || to_packets(&recycler, &transactions),
+        |batches| {
+            let mut dedup = SigDedup::new(DEDUP_CAPACITY);
+            dedup.dedup_packets(&batches);
+            let results = sigverify::ed25519_verify(&batches);
+            let num_verified: usize = results.iter().map(|r| r.len()).sum();
+            assert_eq!(num_verified, transactions.len() / NUM_COPIES);
+        },
+    )
+}
+
 fn bench(criterion: &mut Criterion) {
     criterion.bench_function("bench_gen_keys", |bencher| {
         bench_gen_keys(bencher);
     });
+    criterion.bench_function("bench_sigverify", |bencher| {
+        bench_sigverify(bencher);
+    });
+    criterion.bench_function("bench_sigverify_dedup", |bencher| {
+        bench_sigverify_dedup(bencher);
+    });
 }
 
 criterion_group!(
@@ -5,9 +5,11 @@ extern crate solana;
 extern crate criterion;
 
 use criterion::{Bencher, Criterion};
-use solana::packet::{Packet, PacketRecycler, BLOB_SIZE, PACKET_DATA_SIZE};
+use solana::mmsg::{recv_each, recv_mmsg, send_each, send_mmsg, NUM_RCVMMSGS};
+use solana::packet::{Packet, PacketRecycler, BLOB_SIZE, NUM_PACKETS, PACKET_DATA_SIZE};
 use solana::result::Result;
 use solana::streamer::{receiver, PacketReceiver};
+use std::io;
 use std::net::{SocketAddr, UdpSocket};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
@@ -103,10 +105,62 @@ fn bench_streamer(bencher: &mut Bencher) {
     });
 }
 
+type RecvFn = fn(&UdpSocket, &mut [Packet]) -> io::Result<usize>;
+type SendFn = fn(&UdpSocket, &[(&[u8], SocketAddr)]) -> io::Result<usize>;
+
+fn batch_producer(addr: &SocketAddr, send_fn: SendFn, exit: Arc<AtomicBool>) -> JoinHandle<()> {
+    let send = UdpSocket::bind("0.0.0.0:0").unwrap();
+    let addr = *addr;
+    spawn(move || {
+        let data = [0u8; PACKET_DATA_SIZE];
+        let msgs = vec![(&data[..], addr); NUM_RCVMMSGS];
+        while !exit.load(Ordering::Relaxed) {
+            let _ = send_fn(&send, &msgs);
+        }
+    })
+}
+
+/// Receive one full `Packets` buffer worth of datagrams, returns the packets/second rate
+fn bench_recv_with_result(recv_fn: RecvFn, send_fn: SendFn) -> Result<f64> {
+    let read = UdpSocket::bind("127.0.0.1:0")?;
+    read.set_read_timeout(Some(Duration::new(1, 0)))?;
+    let addr = read.local_addr()?;
+    let exit = Arc::new(AtomicBool::new(false));
+    let t_producer1 = batch_producer(&addr, send_fn, exit.clone());
+    let t_producer2 = batch_producer(&addr, send_fn, exit.clone());
+
+    let mut packets = vec![Packet::default(); NUM_PACKETS];
+    let start = SystemTime::now();
+    let mut num = 0;
+    while num < NUM_PACKETS {
+        read.set_nonblocking(false)?;
+        num += recv_fn(&read, &mut packets[num..]).unwrap_or(0);
+    }
+    let elapsed = start.elapsed().unwrap();
+    let ftime = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000f64;
+    exit.store(true, Ordering::Relaxed);
+    t_producer1.join()?;
+    t_producer2.join()?;
+    Ok(num as f64 / ftime)
+}
+
+fn bench_recv(bencher: &mut Bencher, name: &str, recv_fn: RecvFn, send_fn: SendFn) {
+    bencher.iter(|| {
+        let rate = bench_recv_with_result(recv_fn, send_fn).unwrap();
+        info!("{} performance: {:.0} packets/s", name, rate);
+    });
+}
+
 fn bench(criterion: &mut Criterion) {
     criterion.bench_function("bench_streamer", |bencher| {
         bench_streamer(bencher);
     });
+    criterion.bench_function("bench_recv_mmsg", |bencher| {
+        bench_recv(bencher, "recv_mmsg", recv_mmsg, send_mmsg);
+    });
+    criterion.bench_function("bench_recv_each", |bencher| {
+        bench_recv(bencher, "recv_each", recv_each, send_each);
+    });
 }
 
 criterion_group!(
@@ -9,4 +9,4 @@ export PATH=$PATH:/usr/local/cuda/bin
 export RUST_BACKTRACE=1
 
 set -x
-exec cargo test --features=cuda,erasure
+exec cargo test --features=cuda,jerasure
@@ -15,4 +15,6 @@ _ rustup component add rustfmt-preview
 _ cargo fmt -- --write-mode=check
 _ cargo build --verbose
 _ cargo test --verbose
+_ cargo test --verbose --features=erasure
+_ cargo test --verbose --features=ipv6
 _ cargo bench --verbose
@@ -11,7 +11,7 @@ usage () {
     echo "Error: $*"
   fi
   cat <<EOF
-usage: $0 [-n num_tokens] [-l] [-p] [-t node_type]
+usage: $0 [-n num_tokens] [-l] [-p] [-e host:port] [-t node_type]
 
 Creates a fullnode configuration
 
@@ -19,6 +19,8 @@ Creates a fullnode configuration
  -l             - Detect network address from local machine configuration, which
                   may be a private IP address unaccessible on the Intenet (default)
  -p             - Detect public address using public Internet servers
+ -e host:port   - Detect public address by asking the node with this gossip
+                  address, without depending on public Internet servers
  -t node_type   - Create configuration files only for this kind of node.  Valid
                   options are validator or leader.  Creates configuration files
                   for both by default
@@ -27,21 +29,24 @@ EOF
   exit $exitcode
 }
 
-ip_address_arg=-l
+ip_address_args=(-l)
 num_tokens=1000000000
 node_type_leader=true
 node_type_validator=true
-while getopts "h?n:lpt:" opt; do
+while getopts "h?n:lpe:t:" opt; do
   case $opt in
   h|\?)
     usage
     exit 0
     ;;
   l)
-    ip_address_arg=-l
+    ip_address_args=(-l)
     ;;
   p)
-    ip_address_arg=-p
+    ip_address_args=(--public-http)
+    ;;
+  e)
+    ip_address_args=(--public --entry-point="$OPTARG")
     ;;
   n)
     num_tokens="$OPTARG"
@@ -69,8 +74,8 @@ while getopts "h?n:lpt:" opt; do
 done
 
 
-leader_address_args=("$ip_address_arg")
-validator_address_args=("$ip_address_arg" -b 9000)
+leader_address_args=("${ip_address_args[@]}")
+validator_address_args=("${ip_address_args[@]}" -b 9000)
 leader_id_path="$SOLANA_CONFIG_PRIVATE_DIR"/leader-id.json
 validator_id_path="$SOLANA_CONFIG_PRIVATE_DIR"/validator-id.json
 mint_path="$SOLANA_CONFIG_PRIVATE_DIR"/mint.json
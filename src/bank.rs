@@ -14,6 +14,7 @@ use ledger::Block;
 use mint::Mint;
 use payment_plan::{Payment, PaymentPlan, Witness};
 use signature::{KeyPair, PublicKey, Signature};
+use std::cmp;
 use std::collections::hash_map::Entry::Occupied;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::result;
@@ -34,6 +35,45 @@ pub const MAX_ENTRY_IDS: usize = 1024 * 16;
 
 pub const VERIFY_BLOCK_SIZE: usize = 16;
 
+/// The number of tokens the mint pays a replicator for each approved storage proof.
+pub const STORAGE_REWARD: i64 = 1;
+
+/// The number of entry IDs in a stake epoch. The stakes at the start of an epoch
+/// order the broadcast tree until the next one, so that every node with the same
+/// ledger orders it the same way, however the stakes change in between.
+pub const ENTRY_IDS_PER_STAKE_EPOCH: u64 = 1024;
+
+/// A storage proof waiting for approval.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct PendingStorageProof {
+    /// The replicator that submitted the proof.
+    replicator: PublicKey,
+    /// The `last_id` the proof expires with.
+    last_id: Hash,
+    /// The voters that approved the proof so far.
+    approvers: HashSet<PublicKey>,
+}
+
+/// A copy of the bank's state between two entries, which a node can restart
+/// from instead of replaying the ledger from genesis.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BankSnapshot {
+    /// The number of entries the bank had processed.
+    pub entry_height: u64,
+    balances: Vec<(PublicKey, i64)>,
+    pending: Vec<(Signature, Plan)>,
+    /// The tracked `last_id` values, oldest first, with their signatures.
+    last_ids: Vec<(Hash, Vec<Signature>)>,
+    transaction_count: usize,
+    mint_id: Option<PublicKey>,
+    voters: Vec<PublicKey>,
+    storage_proofs: Vec<(Signature, PendingStorageProof)>,
+    hashes_per_tick: Option<u64>,
+    hashes_since_tick: u64,
+    entry_id_count: u64,
+    epoch_stakes: Vec<(PublicKey, u64)>,
+}
+
 /// Reasons a transaction might be rejected.
 #[derive(Debug, PartialEq, Eq)]
 pub enum BankError {
@@ -60,6 +100,9 @@ pub enum BankError {
 
     /// Proof of History verification failed.
     LedgerVerificationFailed,
+
+    /// A tick's `num_hashes` differs from the hashes per tick of the ledger.
+    InvalidTickHashes(u64),
 }
 
 pub type Result<T> = result::Result<T, BankError>;
@@ -86,6 +129,28 @@ pub struct Bank {
     /// The number of transactions the bank has processed without error since the
     /// start of the ledger.
     transaction_count: AtomicUsize,
+
+    /// The account that received the genesis deposit, which pays the storage rewards.
+    mint_id: RwLock<Option<PublicKey>>,
+
+    /// The accounts that have voted on the ledger, which may approve storage proofs.
+    voters: RwLock<HashSet<PublicKey>>,
+
+    /// Storage proofs waiting for approval, by the signature of their transaction.
+    storage_proofs: RwLock<HashMap<Signature, PendingStorageProof>>,
+
+    /// The number of hashes in every tick, if the genesis entries fixed one.
+    hashes_per_tick: RwLock<Option<u64>>,
+
+    /// The hashes of the entries since the last tick, less the ones that mixed
+    /// in their transactions or mixins.
+    hashes_since_tick: RwLock<u64>,
+
+    /// The number of entry IDs registered, the genesis entries included.
+    entry_id_count: RwLock<u64>,
+
+    /// The stakes of the voters when the current stake epoch began.
+    epoch_stakes: RwLock<HashMap<PublicKey, u64>>,
 }
 
 impl Default for Bank {
@@ -96,6 +161,13 @@ impl Default for Bank {
             last_ids: RwLock::new(VecDeque::new()),
             last_ids_sigs: RwLock::new(HashMap::new()),
             transaction_count: AtomicUsize::new(0),
+            mint_id: RwLock::new(None),
+            voters: RwLock::new(HashSet::new()),
+            storage_proofs: RwLock::new(HashMap::new()),
+            hashes_per_tick: RwLock::new(None),
+            hashes_since_tick: RwLock::new(0),
+            entry_id_count: RwLock::new(0),
+            epoch_stakes: RwLock::new(HashMap::new()),
         }
     }
 }
@@ -105,6 +177,7 @@ impl Bank {
     pub fn new_from_deposit(deposit: &Payment) -> Self {
         let bank = Self::default();
         bank.apply_payment(deposit, &mut bank.balances.write().unwrap());
+        *bank.mint_id.write().unwrap() = Some(deposit.to);
         bank
     }
 
@@ -115,10 +188,48 @@ impl Bank {
             tokens: mint.tokens,
         };
         let bank = Self::new_from_deposit(&deposit);
+        *bank.hashes_per_tick.write().unwrap() = mint.hashes_per_tick;
         bank.register_entry_id(&mint.last_id());
         bank
     }
 
+    /// The number of hashes in every tick, if the genesis entries fixed one.
+    pub fn hashes_per_tick(&self) -> Option<u64> {
+        *self.hashes_per_tick.read().unwrap()
+    }
+
+    /// The hashes of the entries since the last tick that count towards the
+    /// next one, for a recorder picking up where the ledger ends.
+    pub fn hashes_since_tick(&self) -> u64 {
+        *self.hashes_since_tick.read().unwrap()
+    }
+
+    /// Check that a tick closes exactly `hashes_per_tick` hashes since the
+    /// last one, not counting the hashes that mix data into the entries in
+    /// between. Without that, the time between ticks would depend on the load.
+    fn check_tick_hashes(&self, entry: &Entry) -> Result<()> {
+        let hashes_per_tick = match self.hashes_per_tick() {
+            Some(hashes_per_tick) => hashes_per_tick,
+            None => return Ok(()),
+        };
+        let mut hashes_since_tick = self.hashes_since_tick.write().unwrap();
+        if entry.is_tick() {
+            let num_hashes = *hashes_since_tick + entry.num_hashes;
+            if num_hashes != hashes_per_tick {
+                return Err(BankError::InvalidTickHashes(num_hashes));
+            }
+            *hashes_since_tick = 0;
+        } else {
+            let num_hashes = *hashes_since_tick + entry.num_hashes.saturating_sub(1);
+            // the tick was due before this entry
+            if num_hashes >= hashes_per_tick {
+                return Err(BankError::InvalidTickHashes(num_hashes));
+            }
+            *hashes_since_tick = num_hashes;
+        }
+        Ok(())
+    }
+
     /// Commit funds to the `payment.to` party.
     fn apply_payment(&self, payment: &Payment, balances: &mut HashMap<PublicKey, i64>) {
         *balances.entry(payment.to).or_insert(0) += payment.tokens;
@@ -191,9 +302,55 @@ impl Bank {
         if last_ids.len() >= MAX_ENTRY_IDS {
             let id = last_ids.pop_front().unwrap();
             last_ids_sigs.remove(&id);
+            self.storage_proofs
+                .write()
+                .expect("'storage_proofs' write lock in register_entry_id")
+                .retain(|_, proof| proof.last_id != id);
         }
         last_ids_sigs.insert(*last_id, HashSet::new());
         last_ids.push_back(*last_id);
+        drop(last_ids_sigs);
+        drop(last_ids);
+
+        let mut entry_id_count = self.entry_id_count.write().unwrap();
+        *entry_id_count += 1;
+        if *entry_id_count % ENTRY_IDS_PER_STAKE_EPOCH == 0 {
+            self.start_stake_epoch();
+        }
+    }
+
+    /// Fix the stakes of the voters for the stake epoch that starts now. Takes
+    /// the balances lock before the voters lock, as transactions do.
+    fn start_stake_epoch(&self) {
+        let bals = self.balances
+            .read()
+            .expect("'balances' read lock in start_stake_epoch");
+        let voters = self.voters
+            .read()
+            .expect("'voters' read lock in start_stake_epoch");
+        let stakes = voters
+            .iter()
+            .map(|id| (*id, voter_stake(&voters, &bals, id)))
+            .filter(|(_, stake)| *stake > 0)
+            .collect();
+        *self.epoch_stakes.write().unwrap() = stakes;
+    }
+
+    /// The current stake epoch, which starts every `ENTRY_IDS_PER_STAKE_EPOCH`
+    /// entry IDs.
+    pub fn stake_epoch(&self) -> u64 {
+        *self.entry_id_count.read().unwrap() / ENTRY_IDS_PER_STAKE_EPOCH
+    }
+
+    /// The stake of `id` when the current stake epoch began, none before the
+    /// first one.
+    pub fn epoch_stake(&self, id: &PublicKey) -> u64 {
+        self.epoch_stakes
+            .read()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .unwrap_or(0)
     }
 
     /// Deduct tokens from the 'from' address the account has sufficient
@@ -260,8 +417,96 @@ impl Bank {
             }
             Instruction::NewVote(_vote) => {
                 info!("GOT VOTE!");
+                self.voters
+                    .write()
+                    .expect("'voters' write lock in apply_credits")
+                    .insert(tx.from);
                 // TODO: record the vote in the stake table...
             }
+            Instruction::NewStorageProof(_proof) => {
+                self.storage_proofs
+                    .write()
+                    .expect("'storage_proofs' write lock in apply_credits")
+                    .insert(
+                        tx.sig,
+                        PendingStorageProof {
+                            replicator: tx.from,
+                            last_id: tx.last_id,
+                            approvers: HashSet::new(),
+                        },
+                    );
+            }
+            Instruction::ApproveStorageProofs(proof_sigs) => {
+                self.approve_storage_proofs(tx.from, proof_sigs, balances);
+            }
+            Instruction::SetHashesPerTick(_) => {
+                // only honored in the genesis entries, by `process_ledger`
+            }
+        }
+    }
+
+    /// Record `from`'s approval of each pending storage proof in `proof_sigs`, and
+    /// pay the replicator of a proof the `STORAGE_REWARD` from the mint once voters
+    /// holding more than 2/3 of the stake of all voters approved it. The bank
+    /// doesn't check proofs itself, so a single key mustn't be able to approve
+    /// one. Only accounts that have voted may approve proofs, and none their own.
+    fn approve_storage_proofs(
+        &self,
+        from: PublicKey,
+        proof_sigs: &[Signature],
+        balances: &mut HashMap<PublicKey, i64>,
+    ) {
+        let voters = self.voters
+            .read()
+            .expect("'voters' read lock in approve_storage_proofs");
+        if !voters.contains(&from) {
+            inc_new_counter!("bank-approve_storage_proofs-not_a_voter", 1);
+            return;
+        }
+        let mint_id = match *self.mint_id.read().unwrap() {
+            Some(mint_id) => mint_id,
+            None => return,
+        };
+        let stake = |keys: &HashSet<PublicKey>, balances: &HashMap<PublicKey, i64>| -> u64 {
+            keys.iter()
+                .map(|key| voter_stake(&voters, balances, key))
+                .sum()
+        };
+        let total_stake = stake(&*voters, &*balances);
+        let mut storage_proofs = self.storage_proofs
+            .write()
+            .expect("'storage_proofs' write lock in approve_storage_proofs");
+        for sig in proof_sigs {
+            let replicator = match storage_proofs.get_mut(sig) {
+                Some(proof) => {
+                    if proof.replicator == from {
+                        continue;
+                    }
+                    proof.approvers.insert(from);
+                    let approved_stake = stake(&proof.approvers, &*balances);
+                    if total_stake == 0 || approved_stake * 3 <= total_stake * 2 {
+                        continue;
+                    }
+                    proof.replicator
+                }
+                None => continue,
+            };
+            let mint_balance = balances.get(&mint_id).cloned().unwrap_or(0);
+            if mint_balance < STORAGE_REWARD {
+                inc_new_counter!("bank-approve_storage_proofs-mint_empty", 1);
+                return;
+            }
+            storage_proofs.remove(sig);
+            if mint_balance == STORAGE_REWARD {
+                balances.remove(&mint_id);
+            } else {
+                balances.insert(mint_id, mint_balance - STORAGE_REWARD);
+            }
+            let payment = Payment {
+                to: replicator,
+                tokens: STORAGE_REWARD,
+            };
+            self.apply_payment(&payment, balances);
         }
     }
 
@@ -320,6 +565,7 @@ impl Bank {
     }
 
     fn process_entry(&self, entry: Entry) -> Result<()> {
+        self.check_tick_hashes(&entry)?;
         if !entry.transactions.is_empty() {
             for result in self.process_transactions(entry.transactions) {
                 result?;
@@ -415,6 +661,16 @@ impl Bank {
             }.expect("invalid ledger, needs to start with a contract");
 
             self.apply_payment(&deposit, &mut self.balances.write().unwrap());
+            *self.mint_id.write().unwrap() = Some(deposit.to);
+
+            // the mint may fix the number of hashes in every tick that follows
+            for tx in &entry1.transactions[1..] {
+                if let Instruction::SetHashesPerTick(hashes_per_tick) = tx.instruction {
+                    if tx.from == deposit.to {
+                        *self.hashes_per_tick.write().unwrap() = Some(hashes_per_tick);
+                    }
+                }
+            }
         }
         self.register_entry_id(&entry0.id);
         self.register_entry_id(&entry1.id);
@@ -435,6 +691,92 @@ impl Bank {
         Ok((entry_count, tail))
     }
 
+    /// Process the entries that follow `entry_height`, the height of the snapshot
+    /// the bank was restored from, and return the new height.
+    pub fn process_ledger_from<I>(&self, entry_height: u64, entries: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        let mut tail = Vec::with_capacity(WINDOW_SIZE as usize);
+        let mut tail_idx = 0;
+        let entry_count = self.process_blocks(entries, &mut tail, &mut tail_idx)?;
+        Ok(entry_height + entry_count)
+    }
+
+    /// Copy the bank's state, which must be at `entry_height`, into a snapshot.
+    /// Only call this between entries, when no transactions are in flight.
+    pub fn snapshot(&self, entry_height: u64) -> BankSnapshot {
+        let last_ids_sigs = self.last_ids_sigs
+            .read()
+            .expect("'last_ids_sigs' read lock in snapshot");
+        let last_ids = self.last_ids
+            .read()
+            .expect("'last_ids' read lock in snapshot")
+            .iter()
+            .map(|id| {
+                let sigs: Vec<Signature> = last_ids_sigs
+                    .get(id)
+                    .map(|sigs| sigs.iter().cloned().collect())
+                    .unwrap_or_default();
+                (*id, sigs)
+            })
+            .collect();
+        BankSnapshot {
+            entry_height,
+            balances: self.balances(),
+            pending: self.pending
+                .read()
+                .expect("'pending' read lock in snapshot")
+                .iter()
+                .map(|(sig, plan)| (*sig, plan.clone()))
+                .collect(),
+            last_ids,
+            transaction_count: self.transaction_count(),
+            mint_id: *self.mint_id.read().unwrap(),
+            voters: self.voters.read().unwrap().iter().cloned().collect(),
+            storage_proofs: self.storage_proofs
+                .read()
+                .expect("'storage_proofs' read lock in snapshot")
+                .iter()
+                .map(|(sig, proof)| (*sig, proof.clone()))
+                .collect(),
+            hashes_per_tick: self.hashes_per_tick(),
+            hashes_since_tick: self.hashes_since_tick(),
+            entry_id_count: *self.entry_id_count.read().unwrap(),
+            epoch_stakes: self.epoch_stakes
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(id, stake)| (*id, *stake))
+                .collect(),
+        }
+    }
+
+    /// Restore a bank from a snapshot. Entries after `snapshot.entry_height`
+    /// are then applied with `process_ledger_from`.
+    pub fn from_snapshot(snapshot: BankSnapshot) -> Self {
+        let mut last_ids = VecDeque::with_capacity(snapshot.last_ids.len());
+        let mut last_ids_sigs = HashMap::new();
+        for (id, sigs) in snapshot.last_ids {
+            last_ids.push_back(id);
+            last_ids_sigs.insert(id, sigs.into_iter().collect());
+        }
+        Bank {
+            balances: RwLock::new(snapshot.balances.into_iter().collect()),
+            pending: RwLock::new(snapshot.pending.into_iter().collect()),
+            last_ids: RwLock::new(last_ids),
+            last_ids_sigs: RwLock::new(last_ids_sigs),
+            transaction_count: AtomicUsize::new(snapshot.transaction_count),
+            mint_id: RwLock::new(snapshot.mint_id),
+            voters: RwLock::new(snapshot.voters.into_iter().collect()),
+            storage_proofs: RwLock::new(snapshot.storage_proofs.into_iter().collect()),
+            hashes_per_tick: RwLock::new(snapshot.hashes_per_tick),
+            hashes_since_tick: RwLock::new(snapshot.hashes_since_tick),
+            entry_id_count: RwLock::new(snapshot.entry_id_count),
+            epoch_stakes: RwLock::new(snapshot.epoch_stakes.into_iter().collect()),
+        }
+    }
+
     /// Process a Witness Signature. Any payment plans waiting on this signature
     /// will progress one step.
     fn apply_signature(&self, from: PublicKey, tx_sig: Signature) -> Result<()> {
@@ -516,6 +858,52 @@ impl Bank {
         bals.get(pubkey).cloned().unwrap_or(0)
     }
 
+    /// The stake of `id`, which is the balance of an account that has voted.
+    /// Accounts that never voted have none.
+    pub fn stake(&self, id: &PublicKey) -> u64 {
+        let bals = self.balances.read().expect("'balances' read lock in stake");
+        let voters = self.voters.read().expect("'voters' read lock in stake");
+        voter_stake(&voters, &bals, id)
+    }
+
+    /// Return every account with a balance, sorted by public key.
+    pub fn balances(&self) -> Vec<(PublicKey, i64)> {
+        let bals = self.balances
+            .read()
+            .expect("'balances' read lock in balances");
+        let mut balances: Vec<_> = bals.iter().map(|(k, v)| (*k, *v)).collect();
+        balances.sort_by(|a, b| a.0.as_slice().cmp(b.0.as_slice()));
+        balances
+    }
+
+    /// Return the contracts awaiting a witness that pay `pubkey` or that
+    /// `pubkey` may witness, sorted by the signature of their transaction.
+    /// Only the first `limit` contracts whose signature sorts past `after`
+    /// are returned, so a long list can be paged through.
+    pub fn pending_contracts(
+        &self,
+        pubkey: &PublicKey,
+        after: Option<&Signature>,
+        limit: usize,
+    ) -> Vec<(Signature, Plan)> {
+        let pending = self.pending
+            .read()
+            .expect("'pending' read lock in pending_contracts");
+        let mut contracts: Vec<_> = pending
+            .iter()
+            .filter(|(sig, plan)| {
+                after.map_or(true, |after| sig.as_slice() > after.as_slice())
+                    && plan.is_party(pubkey)
+            })
+            .collect();
+        contracts.sort_by(|a, b| a.0.as_slice().cmp(b.0.as_slice()));
+        contracts
+            .into_iter()
+            .take(limit)
+            .map(|(sig, plan)| (*sig, plan.clone()))
+            .collect()
+    }
+
     pub fn transaction_count(&self) -> usize {
         self.transaction_count.load(Ordering::Relaxed)
     }
@@ -533,6 +921,19 @@ impl Bank {
     }
 }
 
+/// The stake of `id` given the `voters` and their `balances`
+fn voter_stake(
+    voters: &HashSet<PublicKey>,
+    balances: &HashMap<PublicKey, i64>,
+    id: &PublicKey,
+) -> u64 {
+    if voters.contains(id) {
+        cmp::max(balances.get(id).cloned().unwrap_or(0), 0) as u64
+    } else {
+        0
+    }
+}
+
 fn rotate_vector<T: Clone>(v: Vec<T>, at: usize) -> Vec<T> {
     if at != 0 {
         let mut ret = Vec::with_capacity(v.len());
@@ -547,13 +948,14 @@ fn rotate_vector<T: Clone>(v: Vec<T>, at: usize) -> Vec<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bincode::serialize;
+    use bincode::{deserialize, serialize};
     use entry::next_entry;
     use entry::Entry;
     use entry_writer::{self, EntryWriter};
     use hash::hash;
     use signature::KeyPairUtil;
     use std::io::{BufReader, Cursor, Seek, SeekFrom};
+    use transaction::{StorageProof, Vote};
 
     #[test]
     fn test_two_payments_to_one_party() {
@@ -615,6 +1017,20 @@ mod tests {
         assert_eq!(bank.get_balance(&pubkey), 1_000);
     }
 
+    #[test]
+    fn test_balances() {
+        let mint = Mint::new(10_000);
+        let pubkey = KeyPair::new().pubkey();
+        let bank = Bank::new(&mint);
+        bank.transfer(1_000, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        let balances = bank.balances();
+        assert_eq!(balances.len(), 2);
+        assert!(balances.contains(&(pubkey, 1_000)));
+        assert!(balances.contains(&(mint.pubkey(), 9_000)));
+        assert!(balances[0].0.as_slice() < balances[1].0.as_slice());
+    }
+
     #[test]
     fn test_transfer_to_newb() {
         let mint = Mint::new(10_000);
@@ -688,6 +1104,57 @@ mod tests {
         assert_ne!(bank.get_balance(&mint.pubkey()), 2);
     }
 
+    #[test]
+    fn test_pending_contracts() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let pubkey = KeyPair::new().pubkey();
+        let dt = Utc::now();
+        let sig = bank.transfer_on_date(1, &mint.keypair(), pubkey, dt, mint.last_id())
+            .unwrap();
+        bank.transfer(1, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+
+        // Only the postdated payment waits on a witness.
+        let contracts = bank.pending_contracts(&pubkey, None, 10);
+        assert_eq!(contracts.len(), 1);
+        assert_eq!(contracts[0].0, sig);
+        assert_eq!(bank.pending_contracts(&mint.pubkey(), None, 10), contracts);
+        assert!(
+            bank.pending_contracts(&KeyPair::new().pubkey(), None, 10)
+                .is_empty()
+        );
+
+        bank.apply_signature(mint.pubkey(), sig).unwrap();
+        assert!(bank.pending_contracts(&pubkey, None, 10).is_empty());
+    }
+
+    #[test]
+    fn test_pending_contracts_pages() {
+        let mint = Mint::new(5);
+        let bank = Bank::new(&mint);
+        let pubkey = KeyPair::new().pubkey();
+        for i in 0..5 {
+            let dt = Utc.timestamp(i, 0);
+            bank.transfer_on_date(1, &mint.keypair(), pubkey, dt, mint.last_id())
+                .unwrap();
+        }
+        let contracts = bank.pending_contracts(&pubkey, None, 10);
+        assert_eq!(contracts.len(), 5);
+
+        // each page picks up after the last signature of the one before
+        let first = bank.pending_contracts(&pubkey, None, 2);
+        assert_eq!(first, &contracts[..2]);
+        let second = bank.pending_contracts(&pubkey, Some(&first[1].0), 2);
+        assert_eq!(second, &contracts[2..4]);
+        let last = bank.pending_contracts(&pubkey, Some(&second[1].0), 2);
+        assert_eq!(last, &contracts[4..]);
+        assert!(
+            bank.pending_contracts(&pubkey, Some(&last[0].0), 2)
+                .is_empty()
+        );
+    }
+
     #[test]
     fn test_duplicate_transaction_signature() {
         let mint = Mint::new(1);
@@ -758,6 +1225,150 @@ mod tests {
         assert_eq!(bank.transaction_count(), 1);
     }
 
+    #[test]
+    fn test_stake() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let validator = KeyPair::new();
+        bank.transfer(5, &mint.keypair(), validator.pubkey(), mint.last_id())
+            .unwrap();
+
+        // tokens are only at stake once their account votes
+        assert_eq!(bank.stake(&validator.pubkey()), 0);
+        let vote = Vote {
+            version: 0,
+            contact_info_version: 0,
+        };
+        let vote_tx = Transaction::new_vote(&validator, vote, mint.last_id(), 0);
+        bank.process_transaction(&vote_tx).unwrap();
+        assert_eq!(bank.stake(&validator.pubkey()), 5);
+        assert_eq!(bank.stake(&mint.pubkey()), 0);
+
+        // epoch stakes only change when the next epoch starts
+        assert_eq!(bank.epoch_stake(&validator.pubkey()), 0);
+        let mut i = 0u64;
+        while bank.stake_epoch() == 0 {
+            bank.register_entry_id(&hash(&serialize(&i).unwrap()));
+            i += 1;
+        }
+        assert_eq!(bank.epoch_stake(&validator.pubkey()), 5);
+        bank.transfer(1, &mint.keypair(), validator.pubkey(), bank.last_id())
+            .unwrap();
+        assert_eq!(bank.stake(&validator.pubkey()), 6);
+        assert_eq!(bank.epoch_stake(&validator.pubkey()), 5);
+
+        let bank = Bank::from_snapshot(bank.snapshot(i));
+        assert_eq!(bank.stake_epoch(), 1);
+        assert_eq!(bank.epoch_stake(&validator.pubkey()), 5);
+    }
+
+    #[test]
+    fn test_storage_proof_reward() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let replicator = KeyPair::new();
+        let validator = KeyPair::new();
+        let last_id = mint.last_id();
+        bank.transfer(1, &mint.keypair(), replicator.pubkey(), last_id)
+            .unwrap();
+        bank.transfer(5, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+
+        let proof = StorageProof {
+            entry_height: 0,
+            identity: Signature::default(),
+            sample_seed: Signature::default(),
+            hash: Hash::default(),
+        };
+        let proof_tx = Transaction::new_storage_proof(&replicator, proof.clone(), last_id, 0);
+        bank.process_transaction(&proof_tx).unwrap();
+        let approve = |keypair, sig| {
+            let tx = Transaction::new_storage_approval(keypair, vec![sig], last_id, 0);
+            bank.process_transaction(&tx).unwrap();
+        };
+
+        // only validators that have voted may approve proofs
+        approve(&validator, proof_tx.sig);
+        assert_eq!(bank.get_balance(&replicator.pubkey()), 1);
+        let vote = Vote {
+            version: 0,
+            contact_info_version: 0,
+        };
+        let vote_tx = Transaction::new_vote(&validator, vote.clone(), last_id, 0);
+        bank.process_transaction(&vote_tx).unwrap();
+        approve(&validator, proof_tx.sig);
+        assert_eq!(bank.get_balance(&replicator.pubkey()), 1 + STORAGE_REWARD);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 4 - STORAGE_REWARD);
+
+        // each proof is paid once
+        approve(&validator, proof_tx.sig);
+        assert_eq!(bank.get_balance(&replicator.pubkey()), 1 + STORAGE_REWARD);
+
+        // and no replicator approves its own
+        let proof = StorageProof {
+            entry_height: 1,
+            ..proof
+        };
+        let proof_tx = Transaction::new_storage_proof(&replicator, proof, last_id, 0);
+        bank.process_transaction(&proof_tx).unwrap();
+        let vote_tx = Transaction::new_vote(&replicator, vote, last_id, 0);
+        bank.process_transaction(&vote_tx).unwrap();
+        approve(&replicator, proof_tx.sig);
+        assert_eq!(bank.get_balance(&replicator.pubkey()), 1 + STORAGE_REWARD);
+        approve(&validator, proof_tx.sig);
+        assert_eq!(
+            bank.get_balance(&replicator.pubkey()),
+            1 + 2 * STORAGE_REWARD
+        );
+    }
+
+    #[test]
+    fn test_storage_proof_quorum() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let replicator = KeyPair::new();
+        let validators = [KeyPair::new(), KeyPair::new(), KeyPair::new()];
+        let spammer = KeyPair::new();
+        let last_id = mint.last_id();
+        for (validator, stake) in validators.iter().zip(&[2, 1, 1]) {
+            bank.transfer(*stake, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+        }
+
+        let proof = StorageProof {
+            entry_height: 0,
+            identity: Signature::default(),
+            sample_seed: Signature::default(),
+            hash: Hash::default(),
+        };
+        let proof_tx = Transaction::new_storage_proof(&replicator, proof, last_id, 0);
+        bank.process_transaction(&proof_tx).unwrap();
+        let vote = Vote {
+            version: 0,
+            contact_info_version: 0,
+        };
+        for keypair in validators.iter().chain(Some(&spammer)) {
+            let vote_tx = Transaction::new_vote(keypair, vote.clone(), last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        let approve = |keypair, sig| {
+            let tx = Transaction::new_storage_approval(keypair, vec![sig], last_id, 0);
+            bank.process_transaction(&tx).unwrap();
+        };
+
+        // a voter without stake approves nothing
+        approve(&spammer, proof_tx.sig);
+        assert_eq!(bank.get_balance(&replicator.pubkey()), 0);
+
+        // nor does a voter with half the stake
+        approve(&validators[0], proof_tx.sig);
+        assert_eq!(bank.get_balance(&replicator.pubkey()), 0);
+
+        // but voters with 3/4 of it do
+        approve(&validators[1], proof_tx.sig);
+        assert_eq!(bank.get_balance(&replicator.pubkey()), STORAGE_REWARD);
+    }
+
     #[test]
     fn test_process_empty_entry_is_registered() {
         let mint = Mint::new(1);
@@ -786,6 +1397,46 @@ mod tests {
         assert_eq!(bank.get_balance(&mint.pubkey()), 1);
     }
 
+    #[test]
+    fn test_process_genesis_hashes_per_tick() {
+        let mut mint = Mint::new(1);
+        mint.hashes_per_tick = Some(10);
+        let bank = Bank::default();
+        bank.process_ledger(mint.create_entries()).unwrap();
+        assert_eq!(bank.hashes_per_tick(), Some(10));
+        assert_eq!(Bank::new(&mint).hashes_per_tick(), Some(10));
+
+        // ticks need exactly that many hashes, entries with transactions don't
+        let tick = next_entry(&mint.last_id(), 10, vec![]);
+        bank.process_entries(vec![tick.clone()]).unwrap();
+        let bad_tick = next_entry(&tick.id, 9, vec![]);
+        assert_eq!(
+            bank.process_entries(vec![bad_tick]),
+            Err(BankError::InvalidTickHashes(9))
+        );
+        let tx = Transaction::new(&mint.keypair(), KeyPair::new().pubkey(), 1, tick.id);
+        let entry = next_entry(&tick.id, 3, vec![tx]);
+        bank.process_entries(vec![entry.clone()]).unwrap();
+        assert_eq!(bank.hashes_since_tick(), 2);
+
+        // but their hashes count towards the next tick, less the one mixing in the transaction
+        let bad_tick = next_entry(&entry.id, 10, vec![]);
+        assert_eq!(
+            bank.process_entries(vec![bad_tick]),
+            Err(BankError::InvalidTickHashes(12))
+        );
+        bank.process_entries(vec![next_entry(&entry.id, 8, vec![])])
+            .unwrap();
+        assert_eq!(bank.hashes_since_tick(), 0);
+
+        // and may not run past it
+        let tx = Transaction::new(&mint.keypair(), KeyPair::new().pubkey(), 1, tick.id);
+        assert_eq!(
+            bank.process_entries(vec![next_entry(&entry.id, 11, vec![tx])]),
+            Err(BankError::InvalidTickHashes(10))
+        );
+    }
+
     fn create_sample_block(mint: &Mint, length: usize) -> impl Iterator<Item = Entry> {
         let mut entries = Vec::with_capacity(length);
         let mut hash = mint.last_id();
@@ -872,6 +1523,35 @@ mod tests {
         assert_eq!(bank.get_balance(&mint.pubkey()), 1);
     }
 
+    #[test]
+    fn test_snapshot() {
+        let mint = Mint::new(10);
+        let entries: Vec<_> = create_sample_block(&mint, 6).collect();
+        let bank = Bank::default();
+        let ledger = mint.create_entries().into_iter().chain(entries[..3].to_vec());
+        let (entry_height, _) = bank.process_ledger(ledger).unwrap();
+        let snapshot = bank.snapshot(entry_height);
+        let snapshot: BankSnapshot = deserialize(&serialize(&snapshot).unwrap()).unwrap();
+
+        // replaying the rest from the snapshot matches replaying from genesis
+        let restored = Bank::from_snapshot(snapshot);
+        let height = restored
+            .process_ledger_from(entry_height, entries[3..].to_vec())
+            .unwrap();
+        assert_eq!(height, entry_height + 3);
+        bank.process_entries(entries[3..].to_vec()).unwrap();
+        assert_eq!(restored.balances(), bank.balances());
+        assert_eq!(restored.last_id(), bank.last_id());
+        assert_eq!(restored.transaction_count(), bank.transaction_count());
+
+        // signatures seen before the snapshot are still rejected
+        let tx = &entries[0].transactions[0];
+        assert_eq!(
+            restored.process_transaction(tx),
+            Err(BankError::DuplicateSignature(tx.sig))
+        );
+    }
+
     #[test]
     fn test_rotate_vector() {
         let expect = vec![1, 2, 3, 4];
@@ -4,15 +4,17 @@
 
 use bank::Bank;
 use bincode::deserialize;
+use bounded_queue::BoundedReceiver;
 use counter::Counter;
-use packet::{PacketRecycler, Packets, SharedPackets};
+use packet::{BlobRecycler, PacketRecycler, Packets, SharedBlobs};
 use rayon::prelude::*;
 use record_stage::Signal;
 use result::{Error, Result};
 use service::Service;
+use sigverify_stage::{Verified, VerifiedPackets};
 use std::net::SocketAddr;
 use std::sync::atomic::AtomicUsize;
-use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
 use std::sync::Arc;
 use std::thread::{self, Builder, JoinHandle};
 use std::time::Duration;
@@ -27,15 +29,18 @@ pub struct BankingStage {
 }
 
 impl BankingStage {
-    /// Create the stage using `bank`. Exit when `verified_receiver` is dropped.
-    /// Discard input packets using `packet_recycler` to minimize memory
-    /// allocations in a previous stage such as the `fetch_stage`.
+    /// Create the stage using `bank`, sending the processed transactions to
+    /// `signal_sender`. Exit when `verified_receiver` is dropped.
+    /// Discard input packets using `packet_recycler` and stream blobs using
+    /// `blob_recycler` to minimize memory allocations in a previous stage such
+    /// as the `fetch_stage`.
     pub fn new(
         bank: Arc<Bank>,
-        verified_receiver: Receiver<Vec<(SharedPackets, Vec<u8>)>>,
+        verified_receiver: BoundedReceiver<VerifiedPackets>,
+        signal_sender: Sender<Signal>,
         packet_recycler: PacketRecycler,
-    ) -> (Self, Receiver<Signal>) {
-        let (signal_sender, signal_receiver) = channel();
+        blob_recycler: BlobRecycler,
+    ) -> Self {
         let thread_hdl = Builder::new()
             .name("solana-banking-stage".to_string())
             .spawn(move || loop {
@@ -44,6 +49,7 @@ impl BankingStage {
                     &verified_receiver,
                     &signal_sender,
                     &packet_recycler,
+                    &blob_recycler,
                 ) {
                     match e {
                         Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
@@ -53,7 +59,7 @@ impl BankingStage {
                 }
             })
             .unwrap();
-        (BankingStage { thread_hdl }, signal_receiver)
+        BankingStage { thread_hdl }
     }
 
     /// Convert the transactions from a blob of binary data to a vector of transactions and
@@ -69,13 +75,30 @@ impl BankingStage {
             .collect()
     }
 
+    /// Same as `deserialize_transactions` for transactions that arrived over a stream,
+    /// one per blob.
+    fn deserialize_blob_transactions(
+        blobs: &SharedBlobs,
+    ) -> Vec<Option<(Transaction, SocketAddr)>> {
+        blobs
+            .iter()
+            .map(|b| {
+                let x = b.read().unwrap();
+                deserialize(&x.data[0..x.meta.size])
+                    .map(|req| (req, x.meta.addr()))
+                    .ok()
+            })
+            .collect()
+    }
+
     /// Process the incoming packets and send output `Signal` messages to `signal_sender`.
-    /// Discard packets via `packet_recycler`.
+    /// Discard packets via `packet_recycler` and blobs via `blob_recycler`.
     pub fn process_packets(
         bank: &Arc<Bank>,
-        verified_receiver: &Receiver<Vec<(SharedPackets, Vec<u8>)>>,
+        verified_receiver: &BoundedReceiver<VerifiedPackets>,
         signal_sender: &Sender<Signal>,
         packet_recycler: &PacketRecycler,
+        blob_recycler: &BlobRecycler,
     ) -> Result<()> {
         let timer = Duration::new(1, 0);
         let recv_start = Instant::now();
@@ -88,10 +111,27 @@ impl BankingStage {
             timing::duration_as_ms(&recv_start.elapsed()),
             mms.len(),
         );
-        let count = mms.iter().map(|x| x.1.len()).sum();
+        let count = mms.iter()
+            .map(|x| match x {
+                Verified::Packets(_, vers) | Verified::Blobs(_, vers) => vers.len(),
+            })
+            .sum();
         let proc_start = Instant::now();
-        for (msgs, vers) in mms {
-            let transactions = Self::deserialize_transactions(&msgs.read().unwrap());
+        for verified in mms {
+            let (transactions, vers) = match verified {
+                Verified::Packets(msgs, vers) => {
+                    let transactions = Self::deserialize_transactions(&msgs.read().unwrap());
+                    packet_recycler.recycle(msgs);
+                    (transactions, vers)
+                }
+                Verified::Blobs(blobs, vers) => {
+                    let transactions = Self::deserialize_blob_transactions(&blobs);
+                    for b in blobs {
+                        blob_recycler.recycle(b);
+                    }
+                    (transactions, vers)
+                }
+            };
             reqs_len += transactions.len();
             let transactions = transactions
                 .into_iter()
@@ -111,8 +151,6 @@ impl BankingStage {
             let transactions = results.into_iter().filter_map(|x| x.ok()).collect();
             signal_sender.send(Signal::Transactions(transactions))?;
             debug!("done process_transactions");
-
-            packet_recycler.recycle(msgs);
         }
         let total_time_s = timing::duration_as_s(&proc_start.elapsed());
         let total_time_ms = timing::duration_as_ms(&proc_start.elapsed());
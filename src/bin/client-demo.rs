@@ -5,24 +5,24 @@ extern crate rayon;
 extern crate serde_json;
 extern crate solana;
 
-use bincode::serialize;
+use bincode::{deserialize, serialize};
 use clap::{App, Arg};
 use rayon::prelude::*;
 use solana::crdt::{Crdt, NodeInfo};
-use solana::drone::{DroneRequest, DRONE_PORT};
+use solana::drone::{DroneError, DroneRequest, DroneResponse, CONFIRMATION_TIMEOUT, DRONE_PORT};
 use solana::fullnode::Config;
 use solana::hash::Hash;
 use solana::nat::{udp_public_bind, udp_random_bind};
 use solana::ncp::Ncp;
 use solana::service::Service;
-use solana::signature::{read_keypair, GenKeys, KeyPair, KeyPairUtil};
+use solana::signature::{read_keypair, GenKeys, KeyPair, KeyPairUtil, Signature};
 use solana::streamer::default_window;
 use solana::thin_client::ThinClient;
 use solana::timing::{duration_as_ms, duration_as_s};
 use solana::transaction::Transaction;
 use std::error;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
 use std::process::exit;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -245,9 +245,10 @@ fn main() {
     if starting_balance < txs {
         let airdrop_amount = txs - starting_balance;
         println!("Airdropping {:?} tokens", airdrop_amount);
-        request_airdrop(&drone_addr, &id, airdrop_amount as u64).unwrap();
-        // TODO: return airdrop Result from Drone
-        sleep(Duration::from_millis(100));
+        if let Err(e) = request_airdrop(&drone_addr, &id, airdrop_amount as u64) {
+            println!("Airdrop failed: {}", e);
+            exit(1);
+        }
 
         let balance = client.poll_get_balance(&id.pubkey()).unwrap();
         println!("Your balance is: {:?}", balance);
@@ -371,8 +372,8 @@ fn mk_client(r: &NodeInfo) -> ThinClient {
     )
 }
 
-fn spy_node() -> (NodeInfo, UdpSocket) {
-    let gossip_socket_pair = udp_public_bind("gossip", 8000, 10000);
+fn spy_node(leader: &NodeInfo) -> (NodeInfo, UdpSocket) {
+    let gossip_socket_pair = udp_public_bind("gossip", &leader.contact_info.ncp, 8000, 10000);
     let pubkey = KeyPair::new().pubkey();
     let daddr = "0.0.0.0:0".parse().unwrap();
     assert!(!gossip_socket_pair.addr.ip().is_unspecified());
@@ -397,7 +398,7 @@ fn converge(
 ) -> Vec<NodeInfo> {
     //lets spy on the network
     let daddr = "0.0.0.0:0".parse().unwrap();
-    let (spy, spy_gossip) = spy_node();
+    let (spy, spy_gossip) = spy_node(leader);
     let mut spy_crdt = Crdt::new(spy).expect("Crdt::new");
     spy_crdt.insert(&leader);
     spy_crdt.set_leader(leader.id);
@@ -445,18 +446,28 @@ fn read_leader(path: &str) -> Config {
     serde_json::from_reader(file).unwrap_or_else(|_| panic!("failed to parse {}", path))
 }
 
+/// Ask the drone for an airdrop and wait until the leader confirmed it
+/// # Returns
+/// The signature of the airdrop transaction
 fn request_airdrop(
     drone_addr: &SocketAddr,
     id: &KeyPair,
     tokens: u64,
-) -> Result<(), Box<error::Error>> {
+) -> Result<Signature, Box<error::Error>> {
     let mut stream = TcpStream::connect(drone_addr)?;
-    let req = DroneRequest::GetAirdrop {
+    let req = DroneRequest::GetConfirmedAirdrop {
         airdrop_request_amount: tokens,
         client_public_key: id.pubkey(),
     };
     let tx = serialize(&req).expect("serialize drone request");
-    stream.write_all(&tx).unwrap();
-    // TODO: add timeout to this function, in case of unresponsive drone
-    Ok(())
+    stream.write_all(&tx)?;
+    // the drone waits up to CONFIRMATION_TIMEOUT for the leader
+    stream.set_read_timeout(Some(Duration::new(CONFIRMATION_TIMEOUT + 10, 0)))?;
+    let mut buf = [0u8; 512];
+    let len = stream.read(&mut buf)?;
+    match deserialize(&buf[..len])? {
+        DroneResponse::Confirmed(sig) => Ok(sig),
+        DroneResponse::Sent(sig) => Err(DroneError::NotConfirmed(sig))?,
+        DroneResponse::Error(e) => Err(e)?,
+    }
 }
@@ -1,27 +1,46 @@
 extern crate bincode;
+extern crate bytes;
 extern crate clap;
 extern crate env_logger;
+extern crate futures;
+extern crate rayon;
 extern crate serde_json;
 extern crate solana;
 extern crate tokio;
 extern crate tokio_codec;
 extern crate tokio_io;
 
-use bincode::deserialize;
+use bincode::{deserialize, serialize};
+use bytes::Bytes;
 use clap::{App, Arg};
+use futures::future;
+use futures::sync::oneshot;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use solana::crdt::NodeInfo;
-use solana::drone::{Drone, DroneRequest, DRONE_PORT};
+use solana::drone::{
+    confirm_airdrop, Drone, DroneError, DroneRequest, DroneResponse, Quota, RateLimiter,
+    CONFIRMATION_TIMEOUT, DRONE_PORT,
+};
 use solana::fullnode::Config;
 use solana::metrics::set_panic_hook;
 use solana::signature::read_keypair;
+use solana::timing::timestamp;
 use std::fs::File;
+use std::io;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::process::exit;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::prelude::*;
 use tokio_codec::{BytesCodec, Decoder};
 
+/// Threads that send airdrops to the leader and wait for it to confirm them,
+/// off the event loop
+const AIRDROP_THREADS: usize = 16;
+
 fn main() {
     env_logger::init();
     set_panic_hook("drone");
@@ -59,6 +78,42 @@ fn main() {
                 .takes_value(true)
                 .help("request limit for time slice"),
         )
+        .arg(
+            Arg::with_name("ip-quota")
+                .long("ip-quota")
+                .value_name("NUMBER")
+                .takes_value(true)
+                .help("tokens a client address may get per quota period"),
+        )
+        .arg(
+            Arg::with_name("key-quota")
+                .long("key-quota")
+                .value_name("NUMBER")
+                .takes_value(true)
+                .help("tokens a recipient key may get per quota period"),
+        )
+        .arg(
+            Arg::with_name("quota-period")
+                .long("quota-period")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("time over which the quotas refill"),
+        )
+        .arg(
+            Arg::with_name("allow")
+                .long("allow")
+                .value_name("IP")
+                .takes_value(true)
+                .multiple(true)
+                .help("client address that no quota applies to"),
+        )
+        .arg(
+            Arg::with_name("state")
+                .long("state")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("file to keep the quotas in across restarts"),
+        )
         .get_matches();
 
     let leader: NodeInfo;
@@ -85,24 +140,65 @@ fn main() {
         request_cap = None;
     }
 
+    let mut ip_quota = Quota::default();
+    let mut key_quota = Quota::default();
+    if let Some(n) = matches.value_of("ip-quota") {
+        ip_quota.tokens = n.parse().expect("integer");
+    }
+    if let Some(n) = matches.value_of("key-quota") {
+        key_quota.tokens = n.parse().expect("integer");
+    }
+    if let Some(t) = matches.value_of("quota-period") {
+        let period = Duration::new(t.parse().expect("integer"), 0);
+        ip_quota.period = period;
+        key_quota.period = period;
+    }
+    let mut rate_limiter = if let Some(path) = matches.value_of("state") {
+        RateLimiter::with_state(ip_quota, key_quota, Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("failed to read {}: {}", path, e);
+            exit(1);
+        })
+    } else {
+        RateLimiter::new(ip_quota, key_quota)
+    };
+    if let Some(ips) = matches.values_of("allow") {
+        for ip in ips {
+            rate_limiter.allow_ip(ip.parse().expect("IP address"));
+        }
+    }
+
     let drone_addr: SocketAddr = format!("0.0.0.0:{}", DRONE_PORT).parse().unwrap();
 
-    let drone = Arc::new(Mutex::new(Drone::new(
+    let mut drone = Drone::new(
         mint_keypair,
         drone_addr,
         leader.contact_info.tpu,
         leader.contact_info.rpu,
         time_slice,
         request_cap,
-    )));
+    );
+    drone.rate_limiter = rate_limiter;
+    let drone = Arc::new(Mutex::new(drone));
 
+    // the quotas are saved once a time slice rather than with every airdrop
     let drone1 = drone.clone();
     thread::spawn(move || loop {
         let time = drone1.lock().unwrap().time_slice;
         thread::sleep(time);
-        drone1.lock().unwrap().clear_request_count();
+        let mut drone = drone1.lock().unwrap();
+        drone.clear_request_count();
+        if let Err(e) = drone.rate_limiter.save(timestamp()) {
+            eprintln!("failed to save the drone quotas: {}", e);
+        }
     });
 
+    let airdrops = Arc::new(
+        ThreadPoolBuilder::new()
+            .num_threads(AIRDROP_THREADS)
+            .build()
+            .expect("airdrop thread pool"),
+    );
+
     let socket = TcpListener::bind(&drone_addr).unwrap();
     println!("Drone started. Listening on: {}", drone_addr);
     let done = socket
@@ -110,24 +206,23 @@ fn main() {
         .map_err(|e| println!("failed to accept socket; error = {:?}", e))
         .for_each(move |socket| {
             let drone2 = drone.clone();
-            // let client_ip = socket.peer_addr().expect("drone peer_addr").ip();
+            let airdrops = airdrops.clone();
+            let client_ip = socket.peer_addr().expect("drone peer_addr").ip();
             let framed = BytesCodec::new().framed(socket);
-            let (_writer, reader) = framed.split();
+            let (writer, reader) = framed.split();
 
             let processor = reader
-                .for_each(move |bytes| {
-                    let req: DroneRequest =
-                        deserialize(&bytes).expect("deserialize packet in drone");
-                    println!("Airdrop requested...");
-                    // let res = drone2.lock().unwrap().check_rate_limit(client_ip);
-                    let res1 = drone2.lock().unwrap().send_airdrop(req);
-                    match res1 {
-                        Ok(_) => println!("Airdrop sent!"),
-                        Err(_) => println!("Request limit reached for this time slice"),
+                .and_then(move |bytes| respond(&drone2, &airdrops, client_ip, &bytes))
+                .map(|response| {
+                    match &response {
+                        DroneResponse::Sent(sig) => println!("Airdrop sent: {:?}", sig),
+                        DroneResponse::Confirmed(sig) => println!("Airdrop confirmed: {:?}", sig),
+                        DroneResponse::Error(e) => println!("Airdrop refused: {}", e),
                     }
-                    Ok(())
+                    Bytes::from(serialize(&response).expect("serialize drone response"))
                 })
-                .and_then(|()| {
+                .forward(writer)
+                .and_then(|_| {
                     println!("Socket received FIN packet and closed connection");
                     Ok(())
                 })
@@ -143,6 +238,42 @@ fn main() {
         });
     tokio::run(done);
 }
+type ResponseFuture = Box<Future<Item = DroneResponse, Error = io::Error> + Send>;
+
+/// Handle the request in `bytes` from the client at `client_ip`. The airdrop
+/// is sent, and waited for if the client wants it confirmed, on `airdrops`,
+/// so that neither holds up other requests.
+fn respond(
+    drone: &Arc<Mutex<Drone>>,
+    airdrops: &ThreadPool,
+    client_ip: IpAddr,
+    bytes: &[u8],
+) -> ResponseFuture {
+    let req = match deserialize::<DroneRequest>(bytes) {
+        Ok(req) => req,
+        Err(_) => return Box::new(future::ok(DroneResponse::Error(DroneError::BadRequest))),
+    };
+    println!("Airdrop requested by {}...", client_ip);
+    let drone = drone.clone();
+    let (sender, receiver) = oneshot::channel();
+    airdrops.spawn(move || {
+        let response = match Drone::handle_request(&drone, client_ip, req) {
+            DroneResponse::Sent(signature) if req.confirm() => {
+                let mut client = drone.lock().unwrap().client();
+                let timeout = Duration::new(CONFIRMATION_TIMEOUT, 0);
+                confirm_airdrop(&mut client, signature, timeout)
+            }
+            response => response,
+        };
+        let _ = sender.send(response);
+    });
+    Box::new(receiver.or_else(|_| {
+        future::ok(DroneResponse::Error(DroneError::SendFailed(
+            "airdrop thread failed".to_string(),
+        )))
+    }))
+}
+
 fn read_leader(path: &str) -> Config {
     let file = File::open(path).unwrap_or_else(|_| panic!("file not found: {}", path));
     serde_json::from_reader(file).unwrap_or_else(|_| panic!("failed to parse {}", path))
@@ -4,12 +4,43 @@ extern crate serde_json;
 extern crate solana;
 
 use clap::{App, Arg};
-use solana::crdt::{get_ip_addr, parse_port_or_addr};
+use solana::crdt::{get_alt_ip_addr, get_ip_addr, parse_port_or_addr, NodeInfo};
 use solana::fullnode::Config;
-use solana::nat::get_public_ip_addr;
+use solana::keystore::read_passphrase;
+use solana::nat::{get_public_ip_addr_http, ip_echo};
+use solana::net_utils::udp_bind;
 use solana::signature::read_pkcs8;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::process::exit;
+
+/// Ask `entry_point` for this machine's public address, and warn about the
+/// ports a fullnode bound to `bind_addr` listens on that it can't reach
+fn public_ip_addr(entry_point: &SocketAddr, bind_addr: &SocketAddr) -> io::Result<IpAddr> {
+    let socket = udp_bind(0)?;
+    let addr = ip_echo(&socket, entry_point)?;
+    let contact_info = NodeInfo::new_leader(bind_addr).contact_info;
+    for (name, port) in &[
+        ("tvu", contact_info.tvu.port()),
+        ("tpu", contact_info.tpu.port()),
+    ] {
+        // the echo only goes back to the socket that asked, so ask from the port itself
+        let seen = udp_bind(*port).and_then(|probe| ip_echo(&probe, entry_point));
+        match seen {
+            Ok(seen) => if seen.port() != *port {
+                eprintln!(
+                    "warning: the {} port {} is mapped to {} by a NAT, is it forwarded?",
+                    name, port, seen
+                );
+            },
+            Err(e) => eprintln!(
+                "warning: can't check the {} port {} from {}: {}",
+                name, port, entry_point, e
+            ),
+        }
+    }
+    Ok(addr.ip())
+}
 
 fn main() {
     let matches = App::new("fullnode-config")
@@ -18,7 +49,7 @@ fn main() {
                 .short("l")
                 .long("local")
                 .takes_value(false)
-                .help("detect network address from local machine configuration"),
+                .help("detect network addresses from local machine configuration"),
         )
         .arg(
             Arg::with_name("keypair")
@@ -28,12 +59,33 @@ fn main() {
                 .takes_value(true)
                 .help("/path/to/id.json"),
         )
+        .arg(
+            Arg::with_name("encrypt")
+                .long("encrypt")
+                .help("encrypt the keypair in the config under a passphrase"),
+        )
         .arg(
             Arg::with_name("public")
                 .short("p")
                 .long("public")
                 .takes_value(false)
-                .help("detect public network address using public servers"),
+                .requires("entry-point")
+                .help("detect public network address by asking the entry point"),
+        )
+        .arg(
+            Arg::with_name("public-http")
+                .long("public-http")
+                .takes_value(false)
+                .conflicts_with("public")
+                .help("detect public network address with http://ifconfig.co, for a first node"),
+        )
+        .arg(
+            Arg::with_name("entry-point")
+                .short("e")
+                .long("entry-point")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .help("gossip address of a node to ask for the public network address"),
         )
         .arg(
             Arg::with_name("bind")
@@ -58,7 +110,22 @@ fn main() {
             bind_addr.set_ip(ip);
         }
         if matches.is_present("public") {
-            let ip = get_public_ip_addr().unwrap();
+            let entry_point = matches.value_of("entry-point").unwrap();
+            let entry_point = entry_point.parse().unwrap_or_else(|e| {
+                eprintln!("invalid entry point {}: {}", entry_point, e);
+                exit(1);
+            });
+            let ip = public_ip_addr(&entry_point, &bind_addr).unwrap_or_else(|e| {
+                eprintln!("no ip echo from {}: {}", entry_point, e);
+                exit(1);
+            });
+            bind_addr.set_ip(ip);
+        }
+        if matches.is_present("public-http") {
+            let ip = get_public_ip_addr_http().unwrap_or_else(|e| {
+                eprintln!("no public address from http://ifconfig.co: {}", e);
+                exit(1);
+            });
             bind_addr.set_ip(ip);
         }
         bind_addr
@@ -75,7 +142,19 @@ fn main() {
 
     // we need all the receiving sockets to be bound within the expected
     // port range that we open on aws
-    let config = Config::new(&bind_addr, pkcs8);
+    let mut config = if matches.is_present("encrypt") {
+        let passphrase =
+            read_passphrase("Passphrase for the fullnode config: ").expect("passphrase");
+        Config::new_encrypted(&bind_addr, &pkcs8, &passphrase)
+    } else {
+        Config::new(&bind_addr, pkcs8)
+    };
+    if matches.is_present("local")
+        || matches.is_present("public")
+        || matches.is_present("public-http")
+    {
+        config.node_info.contact_info.alt_ip = get_alt_ip_addr(&bind_addr.ip());
+    }
     let stdout = io::stdout();
     serde_json::to_writer(stdout, &config).expect("serialize");
 }
@@ -6,13 +6,17 @@ extern crate serde_json;
 extern crate solana;
 
 use clap::{App, Arg};
+use solana::choose_gossip_peer_strategy::GossipStrategy;
 use solana::crdt::{NodeInfo, TestNode};
+use solana::entry_writer::SyncPolicy;
 use solana::fullnode::{Config, FullNode, LedgerFile};
 use solana::metrics::set_panic_hook;
+use solana::net_utils::bind_addr;
+use solana::segmented_ledger::SegmentConfig;
 use solana::service::Service;
 use solana::signature::{KeyPair, KeyPairUtil};
 use std::fs::File;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::process::exit;
 //use std::time::Duration;
 
@@ -44,9 +48,71 @@ fn main() -> () {
                 .takes_value(true)
                 .help("use FILE as persistent ledger (defaults to stdin/stdout)"),
         )
+        .arg(
+            Arg::with_name("ledger-sync")
+                .long("ledger-sync")
+                .value_name("POLICY")
+                .takes_value(true)
+                .help("fsync the ledger \"never\", \"always\" or every POLICY milliseconds"),
+        )
+        .arg(
+            Arg::with_name("ledger-dir")
+                .long("ledger-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .conflicts_with("ledger")
+                .help("keep the ledger as segment files in DIR"),
+        )
+        .arg(
+            Arg::with_name("segment-entries")
+                .long("segment-entries")
+                .value_name("NUM")
+                .takes_value(true)
+                .requires("ledger-dir")
+                .help("number of entries in each ledger segment"),
+        )
+        .arg(
+            Arg::with_name("ledger-archive")
+                .long("ledger-archive")
+                .value_name("DIR")
+                .takes_value(true)
+                .requires("ledger-dir")
+                .help("compress old ledger segments into DIR"),
+        )
+        .arg(
+            Arg::with_name("ledger-max-bytes")
+                .long("ledger-max-bytes")
+                .value_name("BYTES")
+                .takes_value(true)
+                .requires("ledger-dir")
+                .help("delete old ledger segments covered by a snapshot to stay under BYTES"),
+        )
+        .arg(
+            Arg::with_name("snapshot-segments")
+                .long("snapshot-segments")
+                .value_name("NUM")
+                .takes_value(true)
+                .requires("ledger-dir")
+                .help("snapshot the bank every NUM ledger segments written, 0 for never"),
+        )
+        .arg(
+            Arg::with_name("gossip-strategy")
+                .long("gossip-strategy")
+                .value_name("STRATEGY")
+                .takes_value(true)
+                .possible_values(&["random", "weighted"])
+                .help("how to pick gossip peers, \"weighted\" by stake (default) or \"random\""),
+        )
+        .arg(
+            Arg::with_name("fanout")
+                .long("fanout")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("number of nodes to retransmit each blob to"),
+        )
         .get_matches();
 
-    let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8000);
+    let bind_addr = bind_addr(8000);
     let mut keypair = KeyPair::new();
     let mut repl_data = NodeInfo::new_leader_with_pubkey(keypair.pubkey(), &bind_addr);
     if let Some(i) = matches.value_of("identity") {
@@ -54,7 +120,10 @@ fn main() -> () {
         if let Ok(file) = File::open(path.clone()) {
             let parse: serde_json::Result<Config> = serde_json::from_reader(file);
             if let Ok(data) = parse {
-                keypair = data.keypair();
+                keypair = data.keypair().unwrap_or_else(|e| {
+                    eprintln!("failed to read the keypair in {}: {}", path, e);
+                    exit(1);
+                });
                 repl_data = data.node_info;
             } else {
                 eprintln!("failed to parse {}", path);
@@ -65,22 +134,66 @@ fn main() -> () {
             exit(1);
         }
     }
+    let sync_policy = matches.value_of("ledger-sync").map(|policy| {
+        policy.parse::<SyncPolicy>().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
+        })
+    });
     let ledger = if let Some(l) = matches.value_of("ledger") {
-        LedgerFile::Path(l.to_string())
+        if let Some(policy) = sync_policy {
+            LedgerFile::SyncedPath(l.to_string(), policy)
+        } else {
+            LedgerFile::Path(l.to_string())
+        }
+    } else if let Some(dir) = matches.value_of("ledger-dir") {
+        let mut config = SegmentConfig::default();
+        if let Some(policy) = sync_policy {
+            config.sync_policy = policy;
+        }
+        if let Some(n) = matches.value_of("segment-entries") {
+            config.entries_per_segment = n.parse().expect("segment-entries");
+        }
+        if let Some(archive_dir) = matches.value_of("ledger-archive") {
+            config.archive_dir = Some(PathBuf::from(archive_dir));
+        }
+        if let Some(max_bytes) = matches.value_of("ledger-max-bytes") {
+            config.max_bytes = Some(max_bytes.parse().expect("ledger-max-bytes"));
+        }
+        if let Some(n) = matches.value_of("snapshot-segments") {
+            let n: u64 = n.parse().expect("snapshot-segments");
+            config.snapshot_segments = if n == 0 { None } else { Some(n) };
+        }
+        LedgerFile::Segmented(dir.to_string(), config)
     } else {
         LedgerFile::StdInOut
     };
 
+    let gossip_strategy = matches
+        .value_of("gossip-strategy")
+        .map_or(GossipStrategy::default(), |s| s.parse().unwrap());
+
+    if let Some(n) = matches.value_of("fanout") {
+        repl_data.fanout = n.parse().expect("fanout");
+    }
+
     let mut node = TestNode::new_with_bind_addr(repl_data, bind_addr);
     let fullnode = if let Some(t) = matches.value_of("testnet") {
         let testnet_address_string = t.to_string();
         let testnet_addr = testnet_address_string.parse().unwrap();
 
-        FullNode::new(node, false, ledger, Some(keypair), Some(testnet_addr))
+        FullNode::new(
+            node,
+            false,
+            ledger,
+            Some(keypair),
+            Some(testnet_addr),
+            gossip_strategy,
+        )
     } else {
         node.data.leader_id = node.data.id;
 
-        FullNode::new(node, true, ledger, None, None)
+        FullNode::new(node, true, ledger, None, None, gossip_strategy)
     };
     fullnode.join().expect("join");
 }
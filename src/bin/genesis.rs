@@ -10,9 +10,11 @@ use atty::{is, Stream};
 use clap::{App, Arg};
 use solana::entry_writer::EntryWriter;
 use solana::mint::Mint;
+use solana::recorder::calibrate_hashes_per_tick;
 use std::error;
 use std::io::{stdin, stdout, Read};
 use std::process::exit;
+use std::time::Duration;
 
 fn main() -> Result<(), Box<error::Error>> {
     let matches = App::new("solana-genesis")
@@ -25,9 +27,34 @@ fn main() -> Result<(), Box<error::Error>> {
                 .required(true)
                 .help("Number of tokens with which to initialize mint"),
         )
+        .arg(
+            Arg::with_name("hashes_per_tick")
+                .long("hashes-per-tick")
+                .value_name("NUMBER")
+                .takes_value(true)
+                .conflicts_with("tick_ms")
+                .help("Fix the number of hashes in every tick of the ledger"),
+        )
+        .arg(
+            Arg::with_name("tick_ms")
+                .long("tick-ms")
+                .value_name("MILLIS")
+                .takes_value(true)
+                .help("Calibrate the hashes in every tick to MILLIS on this machine"),
+        )
         .get_matches();
 
     let tokens = value_t_or_exit!(matches, "tokens", i64);
+    let hashes_per_tick = if matches.is_present("hashes_per_tick") {
+        Some(value_t_or_exit!(matches, "hashes_per_tick", u64))
+    } else if matches.is_present("tick_ms") {
+        let tick_ms = value_t_or_exit!(matches, "tick_ms", u64);
+        let hashes_per_tick = calibrate_hashes_per_tick(Duration::from_millis(tick_ms));
+        eprintln!("{} hashes per tick", hashes_per_tick);
+        Some(hashes_per_tick)
+    } else {
+        None
+    };
 
     if is(Stream::Stdin) {
         eprintln!("nothing found on stdin, expected a json file");
@@ -42,7 +69,8 @@ fn main() -> Result<(), Box<error::Error>> {
     }
 
     let pkcs8: Vec<u8> = serde_json::from_str(&buffer)?;
-    let mint = Mint::new_with_pkcs8(tokens, pkcs8);
+    let mut mint = Mint::new_with_pkcs8(tokens, pkcs8);
+    mint.hashes_per_tick = hashes_per_tick;
 
     let mut writer = stdout();
     EntryWriter::write_entries(&mut writer, mint.create_entries())?;
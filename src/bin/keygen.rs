@@ -2,10 +2,13 @@ extern crate clap;
 extern crate dirs;
 extern crate ring;
 extern crate serde_json;
+extern crate solana;
 
 use clap::{App, Arg};
 use ring::rand::SystemRandom;
 use ring::signature::Ed25519KeyPair;
+use solana::keystore::{read_passphrase, Keystore};
+use solana::mnemonic::{derive_pkcs8, generate_mnemonic, mnemonic_to_seed, read_mnemonic};
 use std::error;
 use std::fs::{self, File};
 use std::io::Write;
@@ -21,11 +24,60 @@ fn main() -> Result<(), Box<error::Error>> {
                 .takes_value(true)
                 .help("path to generated file"),
         )
+        .arg(
+            Arg::with_name("mnemonic")
+                .long("mnemonic")
+                .conflicts_with("recover")
+                .help("derive the keypair from a new mnemonic phrase, printed to stderr"),
+        )
+        .arg(
+            Arg::with_name("recover")
+                .long("recover")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("derive the keypair from the mnemonic phrase in PATH, or stdin if -"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .value_name("NUMBER")
+                .takes_value(true)
+                .default_value("0")
+                .help("the index of the keypair derived from the phrase"),
+        )
+        .arg(
+            Arg::with_name("encrypt")
+                .long("encrypt")
+                .help("encrypt the keypair file under a passphrase"),
+        )
         .get_matches();
 
-    let rnd = SystemRandom::new();
-    let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rnd)?;
-    let serialized = serde_json::to_string(&pkcs8_bytes.to_vec())?;
+    let phrase = if matches.is_present("mnemonic") {
+        let phrase = generate_mnemonic(12)?;
+        eprintln!("Write down this phrase to recover the keypair with --recover:");
+        eprintln!("{}", phrase);
+        Some(phrase)
+    } else if let Some(path) = matches.value_of("recover") {
+        Some(read_mnemonic(path)?)
+    } else {
+        None
+    };
+    let pkcs8_bytes = match phrase {
+        Some(phrase) => {
+            let index = matches.value_of("index").unwrap().parse()?;
+            derive_pkcs8(&mnemonic_to_seed(&phrase, "")?, index)
+        }
+        None => {
+            let rnd = SystemRandom::new();
+            Ed25519KeyPair::generate_pkcs8(&rnd)?.to_vec()
+        }
+    };
+    let serialized = if matches.is_present("encrypt") {
+        let passphrase = read_passphrase("Passphrase for the new keypair: ")?;
+        serde_json::to_string(&Keystore::encrypt(&pkcs8_bytes, &passphrase))?
+    } else {
+        serde_json::to_string(&pkcs8_bytes)?
+    };
 
     let mut path = dirs::home_dir().expect("home directory");
     let outfile = if matches.is_present("outfile") {
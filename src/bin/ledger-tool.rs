@@ -0,0 +1,209 @@
+//! A command-line executable for inspecting and verifying ledger files.
+
+extern crate bs58;
+#[macro_use]
+extern crate clap;
+extern crate solana;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use solana::bank::{Bank, VERIFY_BLOCK_SIZE};
+use solana::entry::Entry;
+use solana::entry_writer::{read_entries, EntryWriter};
+use solana::hash::Hash;
+use solana::ledger::Block;
+use std::error;
+use std::fs::File;
+use std::io::{stdin, stdout, BufReader, Read, Write};
+use std::process::exit;
+
+/// Iterate over the entries in the ledger at `path`, or stdin if it is "-".
+/// Exits on the first entry that doesn't parse.
+fn ledger_entries(path: &str) -> Result<impl Iterator<Item = Entry>, Box<error::Error>> {
+    let reader: Box<Read> = if path == "-" {
+        Box::new(stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    let entries = read_entries(BufReader::new(reader))
+        .enumerate()
+        .map(|(height, entry)| {
+            entry.unwrap_or_else(|e| {
+                eprintln!("failed to parse entry {}: {}", height, e);
+                exit(1);
+            })
+        });
+    Ok(entries)
+}
+
+/// Return the height of the first entry in `block` that doesn't follow from the
+/// one before it, the first following from `last_id`.
+fn first_unverified(block: &[Entry], last_id: &Hash, height: u64) -> Option<u64> {
+    if block.verify(last_id) {
+        return None;
+    }
+    let mut id = *last_id;
+    for (i, entry) in block.iter().enumerate() {
+        if !entry.verify(&id) {
+            return Some(height + i as u64);
+        }
+        id = entry.id;
+    }
+    None
+}
+
+/// Verify the PoH chain, starting it at the first entry, and return the
+/// number of entries.
+fn verify<I: Iterator<Item = Entry>>(entries: I) -> Result<u64, String> {
+    let mut last_id = None;
+    let mut height = 0;
+    let mut block = Vec::with_capacity(VERIFY_BLOCK_SIZE);
+    let mut entries = entries.peekable();
+    while entries.peek().is_some() {
+        block.clear();
+        block.extend(entries.by_ref().take(VERIFY_BLOCK_SIZE));
+        let start = match last_id {
+            Some(last_id) => last_id,
+            None => block[0].id,
+        };
+        let (block, block_height) = if last_id.is_none() {
+            (&block[1..], height + 1)
+        } else {
+            (&block[..], height)
+        };
+        if let Some(bad) = first_unverified(block, &start, block_height) {
+            return Err(format!("PoH verification failed at entry {}", bad));
+        }
+        height = block_height + block.len() as u64;
+        last_id = Some(block.last().map_or(start, |entry| entry.id));
+    }
+    Ok(height)
+}
+
+fn print<I: Iterator<Item = Entry>>(entries: I) {
+    for (height, entry) in entries.enumerate() {
+        println!(
+            "entry {}: id {} num_hashes {}{}",
+            height,
+            bs58::encode(entry.id).into_string(),
+            entry.num_hashes,
+            if entry.has_more { " has_more" } else { "" }
+        );
+        for tx in &entry.transactions {
+            println!(
+                "  tx {} from {} fee {} last_id {}",
+                bs58::encode(tx.sig).into_string(),
+                bs58::encode(tx.from).into_string(),
+                tx.fee,
+                bs58::encode(tx.last_id).into_string(),
+            );
+            println!("    {:?}", tx.instruction);
+        }
+    }
+}
+
+fn balances<I: Iterator<Item = Entry>>(entries: I) -> Result<(), Box<error::Error>> {
+    let bank = Bank::default();
+    let (entry_height, _) = bank
+        .process_ledger(entries)
+        .map_err(|e| format!("replay failed: {:?}", e))?;
+    for (pubkey, balance) in bank.balances() {
+        println!("{} {}", bs58::encode(pubkey).into_string(), balance);
+    }
+    eprintln!(
+        "replayed {} entries, {} transactions",
+        entry_height,
+        bank.transaction_count()
+    );
+    Ok(())
+}
+
+fn slice<I: Iterator<Item = Entry>>(
+    entries: I,
+    matches: &ArgMatches,
+) -> Result<(), Box<error::Error>> {
+    let from = value_t_or_exit!(matches, "from", usize);
+    let to = if matches.is_present("to") {
+        value_t_or_exit!(matches, "to", usize)
+    } else {
+        usize::max_value()
+    };
+    if to < from {
+        Err(format!("--to {} is before --from {}", to, from))?;
+    }
+    let output = matches.value_of("output").unwrap();
+    let mut writer: Box<Write> = if output == "-" {
+        Box::new(stdout())
+    } else {
+        Box::new(File::create(output)?)
+    };
+    EntryWriter::write_entries(&mut writer, entries.skip(from).take(to - from))?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<error::Error>> {
+    let matches = App::new("solana-ledger-tool")
+        .arg(
+            Arg::with_name("ledger")
+                .short("l")
+                .long("ledger")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)
+                .help("the ledger file to read, \"-\" for stdin"),
+        )
+        .subcommand(SubCommand::with_name("verify").about("Verify the ledger's PoH chain"))
+        .subcommand(SubCommand::with_name("print").about("Print the ledger's entries"))
+        .subcommand(
+            SubCommand::with_name("balances")
+                .about("Replay the ledger into a bank and print the final balances"),
+        )
+        .subcommand(
+            SubCommand::with_name("slice")
+                .about("Copy a range of entries to a new ledger file")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("HEIGHT")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the first entry to copy"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("HEIGHT")
+                        .takes_value(true)
+                        .help("the entry to stop before, defaults to the end of the ledger"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the file to write, \"-\" for stdout"),
+                ),
+        )
+        .get_matches();
+
+    let entries = ledger_entries(matches.value_of("ledger").unwrap())?;
+
+    match matches.subcommand() {
+        ("verify", Some(_)) => match verify(entries) {
+            Ok(height) => println!("verified {} entries", height),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        },
+        ("print", Some(_)) => print(entries),
+        ("balances", Some(_)) => balances(entries)?,
+        ("slice", Some(slice_matches)) => slice(entries, slice_matches)?,
+        _ => {
+            eprintln!("{}", matches.usage());
+            exit(1);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+extern crate clap;
+extern crate env_logger;
+extern crate serde_json;
+extern crate solana;
+
+use clap::{App, Arg};
+use solana::crdt::{NodeInfo, TestNode};
+use solana::fullnode::Config;
+use solana::metrics::set_panic_hook;
+use solana::net_utils::bind_addr;
+use solana::replicator::{Replicator, ENTRIES_PER_SEGMENT};
+use solana::service::Service;
+use solana::signature::{KeyPair, KeyPairUtil};
+use std::fs::File;
+use std::path::Path;
+use std::process::exit;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+fn main() {
+    env_logger::init();
+    set_panic_hook("replicator");
+    let matches = App::new("replicator")
+        .arg(
+            Arg::with_name("identity")
+                .short("i")
+                .long("identity")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("run with the identity found in FILE"),
+        )
+        .arg(
+            Arg::with_name("testnet")
+                .short("t")
+                .long("testnet")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .required(true)
+                .help("connect to the network at this gossip entry point"),
+        )
+        .arg(
+            Arg::with_name("segment")
+                .short("s")
+                .long("segment")
+                .value_name("NUM")
+                .takes_value(true)
+                .required(true)
+                .help("store the NUMth segment of the ledger"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)
+                .help("keep the encrypted segment in FILE"),
+        )
+        .get_matches();
+
+    let bind_addr = bind_addr(8000);
+    let mut keypair = KeyPair::new();
+    let mut repl_data = NodeInfo::new_leader_with_pubkey(keypair.pubkey(), &bind_addr);
+    if let Some(path) = matches.value_of("identity") {
+        let config: Config = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| serde_json::from_reader(file).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                eprintln!("failed to read {}: {}", path, e);
+                exit(1);
+            });
+        keypair = config.keypair().unwrap_or_else(|e| {
+            eprintln!("failed to read the keypair in {}: {}", path, e);
+            exit(1);
+        });
+        repl_data = config.node_info;
+    }
+    let testnet_addr = matches
+        .value_of("testnet")
+        .unwrap()
+        .parse()
+        .expect("testnet");
+    let segment: u64 = matches
+        .value_of("segment")
+        .unwrap()
+        .parse()
+        .expect("segment");
+    let store_path = Path::new(matches.value_of("store").unwrap());
+
+    let node = TestNode::new_with_bind_addr(repl_data, bind_addr);
+    let replicator = Replicator::new(
+        keypair,
+        segment * ENTRIES_PER_SEGMENT,
+        node,
+        &NodeInfo::new_entry_point(testnet_addr),
+        store_path,
+        Arc::new(AtomicBool::new(false)),
+    );
+    replicator.join().expect("join");
+}
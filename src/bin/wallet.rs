@@ -1,26 +1,35 @@
 extern crate atty;
 extern crate bincode;
 extern crate bs58;
+extern crate chrono;
 extern crate clap;
 extern crate dirs;
 extern crate env_logger;
 extern crate serde_json;
 extern crate solana;
 
-use bincode::serialize;
+use bincode::{deserialize, serialize};
+use chrono::prelude::*;
 use clap::{App, Arg, SubCommand};
+use solana::bank::MAX_ENTRY_IDS;
+use solana::budget::{Budget, Condition};
 use solana::crdt::NodeInfo;
-use solana::drone::{DroneRequest, DRONE_PORT};
+use solana::drone::{DroneError, DroneRequest, DroneResponse, CONFIRMATION_TIMEOUT, DRONE_PORT};
 use solana::fullnode::Config;
+use solana::mnemonic::{derive_keypair, mnemonic_to_seed, read_mnemonic};
+use solana::net_utils::udp_bind;
+use solana::payment_plan::Payment;
+use solana::request::MAX_PENDING_CONTRACTS;
 use solana::signature::{read_keypair, KeyPair, KeyPairUtil, PublicKey, Signature};
 use solana::thin_client::ThinClient;
+use solana::transaction::{Instruction, Plan, Transaction};
 use std::error;
 use std::fmt;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
-use std::thread::sleep;
+use std::io::BufReader;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
 use std::time::Duration;
 
 enum WalletCommand {
@@ -28,7 +37,18 @@ enum WalletCommand {
     Balance,
     AirDrop(i64),
     Pay(i64, PublicKey),
+    /// Pay `.0` tokens to `.1` once `.2` is satisfied, unless you cancel it first if `.3`
+    PayOnCondition(i64, PublicKey, Condition, bool),
     Confirm(Signature),
+    /// Witness that the given time has passed
+    SendTimestamp(DateTime<Utc>),
+    /// Witness the payment with the given signature, which approves or cancels it
+    SendSignature(Signature),
+    Pending,
+    /// Build an unsigned payment of `.0` tokens from `.1` to `.2`
+    Build(i64, PublicKey, PublicKey),
+    Sign(Transaction),
+    Submit(Transaction),
 }
 
 #[derive(Debug, Clone)]
@@ -91,6 +111,22 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
                 .takes_value(true)
                 .help("/path/to/id.json"),
         )
+        .arg(
+            Arg::with_name("mnemonic")
+                .long("mnemonic")
+                .value_name("PATH")
+                .takes_value(true)
+                .conflicts_with("keypair")
+                .help("Use the keypair derived from the mnemonic phrase in PATH, or stdin if -"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .value_name("NUMBER")
+                .takes_value(true)
+                .default_value("0")
+                .help("The index of the keypair derived from the phrase"),
+        )
         .subcommand(
             SubCommand::with_name("airdrop")
                 .about("Request a batch of tokens")
@@ -123,6 +159,34 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
                         .value_name("PUBKEY")
                         .takes_value(true)
                         .help("The pubkey of recipient"),
+                )
+                .arg(
+                    Arg::with_name("after")
+                        .long("after")
+                        .value_name("DATETIME")
+                        .takes_value(true)
+                        .conflicts_with("require-signature-from")
+                        .help("Pay only once this RFC 3339 date and time is witnessed"),
+                )
+                .arg(
+                    Arg::with_name("require-timestamp-from")
+                        .long("require-timestamp-from")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .requires("after")
+                        .help("The pubkey that witnesses the time, if not your own"),
+                )
+                .arg(
+                    Arg::with_name("require-signature-from")
+                        .long("require-signature-from")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .help("Pay only once this pubkey approves the payment"),
+                )
+                .arg(
+                    Arg::with_name("cancelable")
+                        .long("cancelable")
+                        .help("Allow you to cancel the payment until it's paid"),
                 ),
         )
         .subcommand(
@@ -136,6 +200,80 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
                         .help("The transaction signature to confirm"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("send-timestamp")
+                .about("Witness the time for the payments waiting on it")
+                .arg(
+                    Arg::with_name("date")
+                        .long("date")
+                        .value_name("DATETIME")
+                        .takes_value(true)
+                        .help("The RFC 3339 date and time to witness, or now"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("send-signature")
+                .alias("cancel")
+                .about("Approve a payment waiting on your signature, or cancel your own")
+                .arg(
+                    Arg::with_name("signature")
+                        .index(1)
+                        .value_name("SIGNATURE")
+                        .required(true)
+                        .help("The signature of the payment"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pending")
+                .about("List the payments that wait on or pay your public key"),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Build an unsigned payment to sign offline")
+                .arg(
+                    Arg::with_name("tokens")
+                        .long("tokens")
+                        .value_name("NUMBER")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the number of tokens to send"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .help("The pubkey of the sender, if not your own"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The pubkey of recipient"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sign")
+                .about("Sign a transaction from `build` with your keypair, once you confirm it")
+                .arg(
+                    Arg::with_name("transaction")
+                        .index(1)
+                        .value_name("TRANSACTION")
+                        .help("The base58-encoded transaction, or read it from stdin"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("submit")
+                .about("Submit a transaction from `sign`")
+                .arg(
+                    Arg::with_name("transaction")
+                        .index(1)
+                        .value_name("TRANSACTION")
+                        .help("The base58-encoded transaction, or read it from stdin"),
+                ),
+        )
         .subcommand(SubCommand::with_name("balance").about("Get your balance"))
         .subcommand(SubCommand::with_name("address").about("Get your public key"))
         .get_matches();
@@ -155,13 +293,27 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
         path.extend(&[".config", "solana", "id.json"]);
         path.to_str().unwrap()
     };
-    let id = read_keypair(id_path).or_else(|err| {
-        display_actions();
-        Err(WalletError::BadParameter(format!(
-            "{}: Unable to open keypair file: {}",
-            err, id_path
-        )))
-    })?;
+    // the online steps of an offline payment never see the keypair that signs it
+    let needs_keypair = match matches.subcommand() {
+        ("build", Some(build_matches)) => !build_matches.is_present("from"),
+        ("submit", Some(_)) => false,
+        _ => true,
+    };
+    let id = if let Some(path) = matches.value_of("mnemonic") {
+        let index = matches.value_of("index").unwrap().parse()?;
+        let seed = mnemonic_to_seed(&read_mnemonic(path)?, "")?;
+        derive_keypair(&seed, index)
+    } else if needs_keypair {
+        read_keypair(id_path).or_else(|err| {
+            display_actions();
+            Err(WalletError::BadParameter(format!(
+                "{}: Unable to open keypair file: {}",
+                err, id_path
+            )))
+        })?
+    } else {
+        KeyPair::new()
+    };
 
     let mut drone_addr = leader.contact_info.tpu;
     drone_addr.set_port(DRONE_PORT);
@@ -173,35 +325,79 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
         }
         ("pay", Some(pay_matches)) => {
             let to = if pay_matches.is_present("to") {
-                let pubkey_vec = bs58::decode(pay_matches.value_of("to").unwrap())
-                    .into_vec()
-                    .expect("base58-encoded public key");
-
-                if pubkey_vec.len() != std::mem::size_of::<PublicKey>() {
-                    display_actions();
-                    Err(WalletError::BadParameter("Invalid public key".to_string()))?;
-                }
-                PublicKey::clone_from_slice(&pubkey_vec)
+                parse_pubkey(pay_matches.value_of("to").unwrap())?
             } else {
                 id.pubkey()
             };
 
             let tokens = pay_matches.value_of("tokens").unwrap().parse()?;
 
-            Ok(WalletCommand::Pay(tokens, to))
+            let condition = if let Some(date) = pay_matches.value_of("after") {
+                let witness = match pay_matches.value_of("require-timestamp-from") {
+                    Some(witness) => parse_pubkey(witness)?,
+                    None => id.pubkey(),
+                };
+                Some(Condition::Timestamp(parse_date(date)?, witness))
+            } else if let Some(witness) = pay_matches.value_of("require-signature-from") {
+                Some(Condition::Signature(parse_pubkey(witness)?))
+            } else {
+                None
+            };
+            let cancelable = pay_matches.is_present("cancelable");
+
+            match &condition {
+                None if cancelable => Err(WalletError::BadParameter(
+                    "Only a payment that waits on a witness can be canceled".to_string(),
+                ))?,
+                // your own signature can't both approve and cancel the payment
+                Some(Condition::Signature(witness)) if cancelable && *witness == id.pubkey() => {
+                    Err(WalletError::BadParameter(
+                        "A cancelable payment needs another pubkey's signature".to_string(),
+                    ))?
+                }
+                _ => (),
+            }
+
+            match condition {
+                Some(condition) => Ok(WalletCommand::PayOnCondition(
+                    tokens, to, condition, cancelable,
+                )),
+                None => Ok(WalletCommand::Pay(tokens, to)),
+            }
         }
         ("confirm", Some(confirm_matches)) => {
-            let sig_vec = bs58::decode(confirm_matches.value_of("signature").unwrap())
-                .into_vec()
-                .expect("base58-encoded signature");
-
-            if sig_vec.len() == std::mem::size_of::<Signature>() {
-                let sig = Signature::clone_from_slice(&sig_vec);
-                Ok(WalletCommand::Confirm(sig))
+            let sig = parse_signature(confirm_matches.value_of("signature").unwrap())?;
+            Ok(WalletCommand::Confirm(sig))
+        }
+        ("send-timestamp", Some(timestamp_matches)) => {
+            let dt = match timestamp_matches.value_of("date") {
+                Some(date) => parse_date(date)?,
+                None => Utc::now(),
+            };
+            Ok(WalletCommand::SendTimestamp(dt))
+        }
+        ("send-signature", Some(signature_matches)) => {
+            let sig = parse_signature(signature_matches.value_of("signature").unwrap())?;
+            Ok(WalletCommand::SendSignature(sig))
+        }
+        ("pending", Some(_pending_matches)) => Ok(WalletCommand::Pending),
+        ("build", Some(build_matches)) => {
+            let tokens = build_matches.value_of("tokens").unwrap().parse()?;
+            let from = if build_matches.is_present("from") {
+                parse_pubkey(build_matches.value_of("from").unwrap())?
             } else {
-                display_actions();
-                Err(WalletError::BadParameter("Invalid signature".to_string()))
-            }
+                id.pubkey()
+            };
+            let to = parse_pubkey(build_matches.value_of("to").unwrap())?;
+            Ok(WalletCommand::Build(tokens, from, to))
+        }
+        ("sign", Some(sign_matches)) => {
+            let tx = read_transaction(sign_matches.value_of("transaction"))?;
+            Ok(WalletCommand::Sign(tx))
+        }
+        ("submit", Some(submit_matches)) => {
+            let tx = read_transaction(submit_matches.value_of("transaction"))?;
+            Ok(WalletCommand::Submit(tx))
         }
         ("balance", Some(_balance_matches)) => Ok(WalletCommand::Balance),
         ("address", Some(_address_matches)) => Ok(WalletCommand::Address),
@@ -254,19 +450,10 @@ fn process_command(
                 tokens, config.drone_addr
             );
             let previous_balance = client.poll_get_balance(&config.id.pubkey())?;
-            request_airdrop(&config.drone_addr, &config.id, tokens as u64)?;
-
-            // TODO: return airdrop Result from Drone instead of polling the
-            //       network
-            let mut current_balance = previous_balance;
-            for _ in 0..20 {
-                sleep(Duration::from_millis(500));
-                current_balance = client.poll_get_balance(&config.id.pubkey())?;
-                if previous_balance != current_balance {
-                    break;
-                }
-                println!(".");
-            }
+            let sig = request_airdrop(&config.drone_addr, &config.id, tokens as u64)?;
+            println!("Airdrop confirmed: {}", bs58::encode(sig).into_string());
+
+            let current_balance = client.poll_get_balance(&config.id.pubkey())?;
             println!("Your balance is: {:?}", current_balance);
             if current_balance - previous_balance != tokens {
                 Err("Airdrop failed!")?;
@@ -278,6 +465,51 @@ fn process_command(
             let sig = client.transfer(tokens, &config.id, to, &last_id)?;
             println!("{}", bs58::encode(sig).into_string());
         }
+        // Lock tokens in a contract; its signature identifies it to the witnesses
+        WalletCommand::PayOnCondition(tokens, to, ref condition, cancelable) => {
+            let budget = if cancelable {
+                Budget::new_cancelable_payment(condition.clone(), config.id.pubkey(), tokens, to)
+            } else {
+                Budget::After(condition.clone(), Payment { tokens, to })
+            };
+            let last_id = client.get_last_id();
+            let tx = Transaction::new_budget(&config.id, budget, tokens, last_id);
+            client.transfer_signed(&tx)?;
+            println!("{}", bs58::encode(tx.sig).into_string());
+        }
+        WalletCommand::SendTimestamp(dt) => {
+            let last_id = client.get_last_id();
+            let tx = Transaction::new_timestamp(&config.id, dt, last_id);
+            client.transfer_signed(&tx)?;
+            println!("{}", bs58::encode(tx.sig).into_string());
+        }
+        WalletCommand::SendSignature(sig) => {
+            let last_id = client.get_last_id();
+            let tx = Transaction::new_signature(&config.id, sig, last_id);
+            client.transfer_signed(&tx)?;
+            println!("{}", bs58::encode(tx.sig).into_string());
+        }
+        // List every pending payment, a page of them at a time
+        WalletCommand::Pending => {
+            let mut after = None;
+            loop {
+                let contracts = client.get_pending_contracts(&config.id.pubkey(), after)?;
+                if contracts.is_empty() && after.is_none() {
+                    println!("No pending payments");
+                }
+                for (sig, plan) in &contracts {
+                    println!(
+                        "{}: {}",
+                        bs58::encode(sig).into_string(),
+                        describe_plan(plan)
+                    );
+                }
+                if contracts.len() < MAX_PENDING_CONTRACTS {
+                    break;
+                }
+                after = contracts.last().map(|(sig, _)| *sig);
+            }
+        }
         // Confirm the last client transaction by signature
         WalletCommand::Confirm(sig) => {
             if client.check_signature(&sig) {
@@ -286,6 +518,44 @@ fn process_command(
                 println!("Not found");
             }
         }
+        // Build a payment for a keypair that's kept offline
+        WalletCommand::Build(tokens, from, to) => {
+            let last_id = client.get_last_id();
+            let tx = Transaction::new_unsigned(from, to, tokens, last_id);
+            println!("{}", encode_transaction(&tx));
+        }
+        // Sign a payment without touching the network, once its owner has
+        // checked it's the one they meant to make
+        WalletCommand::Sign(ref tx) => {
+            if tx.from != config.id.pubkey() {
+                Err(WalletError::BadParameter(
+                    "Transaction is not from your keypair".to_string(),
+                ))?;
+            }
+            eprintln!("From:        {}", bs58::encode(tx.from).into_string());
+            eprintln!("Instruction: {}", describe_instruction(&tx.instruction));
+            eprintln!("Fee:         {} tokens", tx.fee);
+            eprintln!("Last ID:     {}", bs58::encode(tx.last_id).into_string());
+            eprintln!(
+                "Submit it within {} entries of its last ID, or the leader rejects it.",
+                MAX_ENTRY_IDS
+            );
+            if !confirm("Sign this transaction? [y/N] ")? {
+                Err("Transaction not signed")?;
+            }
+            let mut tx = tx.clone();
+            tx.sign(&config.id);
+            println!("{}", encode_transaction(&tx));
+        }
+        WalletCommand::Submit(ref tx) => {
+            if !tx.verify_sig() {
+                Err(WalletError::BadParameter(
+                    "Transaction is not signed".to_string(),
+                ))?;
+            }
+            client.transfer_signed(tx)?;
+            println!("{}", bs58::encode(tx.sig).into_string());
+        }
     }
     Ok(())
 }
@@ -298,9 +568,140 @@ fn display_actions() {
     println!("  airdrop   Request a batch of tokens");
     println!("  pay       Send tokens to a public key");
     println!("  confirm   Confirm your last payment by signature");
+    println!("  send-timestamp  Witness the time for payments waiting on it");
+    println!("  send-signature  Approve or cancel a payment by its signature");
+    println!("  pending   List the payments waiting on a witness");
+    println!("  build     Build a payment to sign offline");
+    println!("  sign      Sign a built payment with your keypair");
+    println!("  submit    Submit a signed payment");
     println!();
 }
 
+fn parse_pubkey(s: &str) -> Result<PublicKey, WalletError> {
+    let pubkey_vec = bs58::decode(s)
+        .into_vec()
+        .map_err(|_| WalletError::BadParameter("Invalid public key".to_string()))?;
+    if pubkey_vec.len() != std::mem::size_of::<PublicKey>() {
+        display_actions();
+        Err(WalletError::BadParameter("Invalid public key".to_string()))?;
+    }
+    Ok(PublicKey::clone_from_slice(&pubkey_vec))
+}
+
+fn parse_signature(s: &str) -> Result<Signature, WalletError> {
+    let sig_vec = bs58::decode(s)
+        .into_vec()
+        .map_err(|_| WalletError::BadParameter("Invalid signature".to_string()))?;
+    if sig_vec.len() != std::mem::size_of::<Signature>() {
+        display_actions();
+        Err(WalletError::BadParameter("Invalid signature".to_string()))?;
+    }
+    Ok(Signature::clone_from_slice(&sig_vec))
+}
+
+fn parse_date(s: &str) -> Result<DateTime<Utc>, WalletError> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|_| WalletError::BadParameter(format!("Invalid RFC 3339 date: {}", s)))
+}
+
+fn describe_payment(payment: &Payment) -> String {
+    format!(
+        "{} tokens to {}",
+        payment.tokens,
+        bs58::encode(payment.to).into_string()
+    )
+}
+
+fn describe_condition(condition: &Condition) -> String {
+    match condition {
+        Condition::Timestamp(dt, pubkey) => format!(
+            "after {} per {}",
+            dt.to_rfc3339(),
+            bs58::encode(pubkey).into_string()
+        ),
+        Condition::Signature(pubkey) => {
+            format!("once signed by {}", bs58::encode(pubkey).into_string())
+        }
+    }
+}
+
+fn describe_plan(plan: &Plan) -> String {
+    match plan {
+        Plan::Budget(Budget::Pay(payment)) => describe_payment(payment),
+        Plan::Budget(Budget::After(condition, payment)) => format!(
+            "{} {}",
+            describe_payment(payment),
+            describe_condition(condition)
+        ),
+        Plan::Budget(Budget::Or((cond_a, payment_a), (cond_b, payment_b))) => format!(
+            "{} {}, or {} {}",
+            describe_payment(payment_a),
+            describe_condition(cond_a),
+            describe_payment(payment_b),
+            describe_condition(cond_b)
+        ),
+    }
+}
+
+fn describe_instruction(instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::NewContract(contract) => format!(
+            "lock {} tokens to pay {}",
+            contract.tokens,
+            describe_plan(&contract.plan)
+        ),
+        Instruction::ApplyTimestamp(dt) => format!("witness the time {}", dt.to_rfc3339()),
+        Instruction::ApplySignature(sig) => format!(
+            "approve or cancel the payment {}",
+            bs58::encode(sig).into_string()
+        ),
+        Instruction::NewVote(_) => "vote".to_string(),
+        Instruction::NewStorageProof(proof) => format!(
+            "prove storage of the segment at entry height {}",
+            proof.entry_height
+        ),
+        Instruction::ApproveStorageProofs(sigs) => {
+            format!("approve {} storage proofs", sigs.len())
+        }
+        Instruction::SetHashesPerTick(hashes_per_tick) => {
+            format!("set {} hashes per tick", hashes_per_tick)
+        }
+    }
+}
+
+/// Ask `prompt` on the terminal, since the transaction may be read from stdin
+fn confirm(prompt: &str) -> io::Result<bool> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    tty.write_all(prompt.as_bytes())?;
+    let mut answer = String::new();
+    BufReader::new(&tty).read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+/// Transactions move between the steps of an offline payment as base58-encoded bincode
+fn encode_transaction(tx: &Transaction) -> String {
+    let data = serialize(tx).expect("serialize Transaction in fn encode_transaction");
+    bs58::encode(data).into_string()
+}
+
+/// Decode the transaction `encoded`, or one read from stdin if it's `None`
+fn read_transaction(encoded: Option<&str>) -> Result<Transaction, Box<error::Error>> {
+    let encoded = match encoded {
+        Some(encoded) => encoded.to_string(),
+        None => {
+            let mut buffer = String::new();
+            io::stdin().read_to_string(&mut buffer)?;
+            buffer
+        }
+    };
+    let data = bs58::decode(encoded.trim())
+        .into_vec()
+        .map_err(|_| WalletError::BadParameter("Invalid transaction".to_string()))?;
+    Ok(deserialize(&data)?)
+}
+
 fn read_leader(path: &str) -> Result<Config, WalletError> {
     let file = File::open(path.to_string()).or_else(|err| {
         Err(WalletError::BadParameter(format!(
@@ -318,8 +719,8 @@ fn read_leader(path: &str) -> Result<Config, WalletError> {
 }
 
 fn mk_client(r: &NodeInfo) -> io::Result<ThinClient> {
-    let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    let transactions_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    let requests_socket = udp_bind(0).unwrap();
+    let transactions_socket = udp_bind(0).unwrap();
     requests_socket
         .set_read_timeout(Some(Duration::new(1, 0)))
         .unwrap();
@@ -332,20 +733,30 @@ fn mk_client(r: &NodeInfo) -> io::Result<ThinClient> {
     ))
 }
 
+/// Ask the drone for an airdrop and wait until the leader confirmed it
+/// # Returns
+/// The signature of the airdrop transaction
 fn request_airdrop(
     drone_addr: &SocketAddr,
     id: &KeyPair,
     tokens: u64,
-) -> Result<(), Box<error::Error>> {
+) -> Result<Signature, Box<error::Error>> {
     let mut stream = TcpStream::connect(drone_addr)?;
-    let req = DroneRequest::GetAirdrop {
+    let req = DroneRequest::GetConfirmedAirdrop {
         airdrop_request_amount: tokens,
         client_public_key: id.pubkey(),
     };
     let tx = serialize(&req).expect("serialize drone request");
-    stream.write_all(&tx).unwrap();
-    // TODO: add timeout to this function, in case of unresponsive drone
-    Ok(())
+    stream.write_all(&tx)?;
+    // the drone waits up to CONFIRMATION_TIMEOUT for the leader
+    stream.set_read_timeout(Some(Duration::new(CONFIRMATION_TIMEOUT + 10, 0)))?;
+    let mut buf = [0u8; 512];
+    let len = stream.read(&mut buf)?;
+    match deserialize(&buf[..len])? {
+        DroneResponse::Confirmed(sig) => Ok(sig),
+        DroneResponse::Sent(sig) => Err(DroneError::NotConfirmed(sig))?,
+        DroneResponse::Error(e) => Err(e)?,
+    }
 }
 
 fn main() -> Result<(), Box<error::Error>> {
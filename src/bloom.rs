@@ -0,0 +1,124 @@
+//! The `bloom` module provides a Bloom filter, a compact set that answers whether it holds an item
+//! with no false negatives and a tunable rate of false positives.
+
+use rand::{thread_rng, RngCore};
+use std::cmp;
+use std::f64::consts::LN_2;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Bloom {
+    /// one hash function is seeded with each key
+    keys: Vec<u64>,
+    bits: Vec<u64>,
+    num_bits: u64,
+}
+
+impl Bloom {
+    pub fn new(num_bits: usize, keys: Vec<u64>) -> Self {
+        let num_bits = cmp::max(num_bits, 1);
+        Bloom {
+            keys,
+            bits: vec![0; (num_bits + 63) / 64],
+            num_bits: num_bits as u64,
+        }
+    }
+
+    /// A filter with random keys sized to hold `num_items` with a false
+    /// positive rate of `false_rate`, using no more than `max_bits`.
+    pub fn random(num_items: usize, false_rate: f64, max_bits: usize) -> Self {
+        let num_items = cmp::max(num_items, 1) as f64;
+        let num_bits = (-num_items * false_rate.ln() / (LN_2 * LN_2)).ceil() as usize;
+        let num_bits = cmp::max(cmp::min(num_bits, max_bits), 1);
+        let num_keys = cmp::max((num_bits as f64 / num_items * LN_2).round() as usize, 1);
+        let mut rng = thread_rng();
+        let keys = (0..num_keys).map(|_| rng.next_u64()).collect();
+        Self::new(num_bits, keys)
+    }
+
+    fn pos(&self, item: &[u8], key: u64) -> u64 {
+        // FNV-1a seeded with the key, and murmur3's finalizer to spread
+        // the few bytes of a short item across all the bits
+        let mut hash = FNV_OFFSET_BASIS ^ key;
+        for b in item {
+            hash ^= u64::from(*b);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^= hash >> 33;
+        hash % self.num_bits
+    }
+
+    pub fn add(&mut self, item: &[u8]) {
+        for i in 0..self.keys.len() {
+            let pos = self.pos(item, self.keys[i]);
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.keys.iter().all(|key| {
+            let pos = self.pos(item, *key);
+            self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0
+        })
+    }
+
+    /// Forget every item, keeping the keys.
+    pub fn clear(&mut self) {
+        for b in &mut self.bits {
+            *b = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::{deserialize, serialize};
+    use bloom::Bloom;
+
+    #[test]
+    fn test_bloom_add_contains() {
+        let mut bloom = Bloom::random(100, 0.1, 8 * 1024);
+        let items: Vec<_> = (0..100u64).map(|i| [i as u8, (i >> 8) as u8, 7]).collect();
+        for item in &items {
+            bloom.add(item);
+        }
+        assert!(items.iter().all(|item| bloom.contains(item)));
+
+        let false_positives = (100..1100u64)
+            .filter(|i| bloom.contains(&[*i as u8, (*i >> 8) as u8, 7]))
+            .count();
+        assert!(false_positives < 200, "{}", false_positives);
+
+        bloom.clear();
+        assert!(items.iter().all(|item| !bloom.contains(item)));
+    }
+
+    #[test]
+    fn test_bloom_size() {
+        let bloom = Bloom::random(1000, 0.1, 8 * 1024);
+        // ln(0.1) / ln(2)^2 bits and ln(2) of that many hashes per item
+        assert_eq!(bloom.num_bits, 4793);
+        assert_eq!(bloom.keys.len(), 3);
+
+        let bloom = Bloom::random(1000, 0.1, 1024);
+        assert_eq!(bloom.num_bits, 1024);
+        assert_eq!(bloom.keys.len(), 1);
+
+        let bloom = Bloom::random(0, 0.1, 1024);
+        assert_eq!(bloom.num_bits, 5);
+    }
+
+    #[test]
+    fn test_bloom_serialize() {
+        let mut bloom = Bloom::random(10, 0.1, 1024);
+        bloom.add(b"hello");
+        let bloom: Bloom = deserialize(&serialize(&bloom).unwrap()).unwrap();
+        assert!(bloom.contains(b"hello"));
+    }
+}
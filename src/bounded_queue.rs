@@ -0,0 +1,326 @@
+//! The `bounded_queue` module provides a channel that holds a fixed number of
+//! transactions. When its receiver falls behind, the channel sheds queued items
+//! by a `ShedPolicy` instead of growing until the node runs out of memory.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvTimeoutError, SendError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShedPolicy {
+    /// Drop the items that have waited the longest
+    DropOldest,
+    /// Drop the items whose transactions pay the lowest fee
+    DropLowestFee,
+}
+
+/// An item of a bounded channel
+pub trait QueueItem {
+    /// The number of transactions the item holds, which the capacity is counted in
+    fn num_transactions(&self) -> usize;
+
+    /// The fee the item's transactions pay, which `ShedPolicy::DropLowestFee` sheds by
+    fn fee(&self) -> i64 {
+        0
+    }
+}
+
+struct Queued<T> {
+    item: T,
+    num_transactions: usize,
+    fee: i64,
+}
+
+struct Queue<T> {
+    items: VecDeque<Queued<T>>,
+    num_transactions: usize,
+    num_senders: usize,
+    receiver: bool,
+}
+
+struct Shared<T> {
+    queue: Mutex<Queue<T>>,
+    ready: Condvar,
+    capacity: usize,
+    policy: ShedPolicy,
+}
+
+pub struct BoundedSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct BoundedReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// A channel that holds up to `capacity` transactions and sheds by `policy`
+pub fn bounded_channel<T: QueueItem>(
+    capacity: usize,
+    policy: ShedPolicy,
+) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            items: VecDeque::new(),
+            num_transactions: 0,
+            num_senders: 1,
+            receiver: true,
+        }),
+        ready: Condvar::new(),
+        capacity,
+        policy,
+    });
+    (
+        BoundedSender {
+            shared: shared.clone(),
+        },
+        BoundedReceiver { shared },
+    )
+}
+
+impl<T: QueueItem> BoundedSender<T> {
+    /// Queue `item`, returning the items shed to make room for it so that
+    /// their buffers can be recycled. Under `ShedPolicy::DropLowestFee` that
+    /// may be `item` itself. An item is always queued into an empty channel.
+    pub fn send(&self, item: T) -> Result<Vec<T>, SendError<T>> {
+        // finding the fee can mean deserializing every transaction, so it's
+        // done before the receiver is locked out
+        let num_transactions = item.num_transactions();
+        let fee = match self.shared.policy {
+            ShedPolicy::DropOldest => 0,
+            ShedPolicy::DropLowestFee => item.fee(),
+        };
+        let mut queue = self.shared.queue.lock().expect("'queue' lock in send");
+        if !queue.receiver {
+            return Err(SendError(item));
+        }
+
+        // pick every victim before shedding any, so that nothing is shed for an
+        // item that is shed itself
+        let mut order: Vec<_> = (0..queue.items.len()).collect();
+        if self.shared.policy == ShedPolicy::DropLowestFee {
+            order.sort_by_key(|&i| queue.items[i].fee);
+        }
+        let mut victims = vec![];
+        let mut remaining = queue.num_transactions;
+        for i in order {
+            if remaining + num_transactions <= self.shared.capacity {
+                break;
+            }
+            if self.shared.policy == ShedPolicy::DropLowestFee && queue.items[i].fee >= fee {
+                return Ok(vec![item]);
+            }
+            remaining -= queue.items[i].num_transactions;
+            victims.push(i);
+        }
+
+        let mut items: Vec<_> = queue.items.drain(..).map(Some).collect();
+        let shed = victims
+            .into_iter()
+            .map(|i| items[i].take().unwrap().item)
+            .collect();
+        queue.items = items.into_iter().flat_map(|queued| queued).collect();
+        queue.num_transactions = remaining + num_transactions;
+        queue.items.push_back(Queued {
+            item,
+            num_transactions,
+            fee,
+        });
+        self.shared.ready.notify_one();
+        Ok(shed)
+    }
+}
+
+impl<T> BoundedSender<T> {
+    /// The number of transactions queued
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().num_transactions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.shared.capacity
+    }
+}
+
+impl<T> Clone for BoundedSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.queue.lock().unwrap().num_senders += 1;
+        BoundedSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for BoundedSender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut queue) = self.shared.queue.lock() {
+            queue.num_senders -= 1;
+        }
+        self.shared.ready.notify_all();
+    }
+}
+
+impl<T> BoundedReceiver<T> {
+    fn pop(queue: &mut Queue<T>) -> Option<T> {
+        let queued = queue.items.pop_front()?;
+        queue.num_transactions -= queued.num_transactions;
+        Some(queued.item)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut queue = self.shared.queue.lock().expect("'queue' lock in try_recv");
+        match Self::pop(&mut queue) {
+            Some(item) => Ok(item),
+            None if queue.num_senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.shared
+            .queue
+            .lock()
+            .expect("'queue' lock in recv_timeout");
+        loop {
+            if let Some(item) = Self::pop(&mut queue) {
+                return Ok(item);
+            }
+            if queue.num_senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            queue = self.shared
+                .ready
+                .wait_timeout(queue, deadline - now)
+                .expect("'ready' wait in recv_timeout")
+                .0;
+        }
+    }
+
+    /// The number of transactions queued
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().num_transactions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for BoundedReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut queue) = self.shared.queue.lock() {
+            queue.receiver = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bounded_queue::{bounded_channel, QueueItem, ShedPolicy};
+    use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    /// `.0` transactions paying a fee of `.1`
+    #[derive(Debug, PartialEq)]
+    struct Batch(usize, i64);
+
+    impl QueueItem for Batch {
+        fn num_transactions(&self) -> usize {
+            self.0
+        }
+        fn fee(&self) -> i64 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let (sender, receiver) = bounded_channel(10, ShedPolicy::DropOldest);
+        assert_eq!(sender.send(Batch(4, 3)).unwrap(), vec![]);
+        assert_eq!(sender.send(Batch(4, 2)).unwrap(), vec![]);
+        assert_eq!(sender.send(Batch(4, 1)).unwrap(), vec![Batch(4, 3)]);
+        assert_eq!(sender.len(), 8);
+        assert_eq!(
+            sender.send(Batch(10, 0)).unwrap(),
+            vec![Batch(4, 2), Batch(4, 1)]
+        );
+        assert!(sender.is_full());
+
+        // an item larger than the capacity still gets through an empty channel
+        assert_eq!(sender.send(Batch(20, 0)).unwrap(), vec![Batch(10, 0)]);
+        assert_eq!(receiver.try_recv(), Ok(Batch(20, 0)));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_drop_lowest_fee() {
+        let (sender, receiver) = bounded_channel(10, ShedPolicy::DropLowestFee);
+        assert_eq!(sender.send(Batch(4, 2)).unwrap(), vec![]);
+        assert_eq!(sender.send(Batch(4, 1)).unwrap(), vec![]);
+        assert_eq!(sender.send(Batch(4, 3)).unwrap(), vec![Batch(4, 1)]);
+
+        // the new item is shed when nothing queued pays less
+        assert_eq!(sender.send(Batch(4, 2)).unwrap(), vec![Batch(4, 2)]);
+        assert_eq!(receiver.try_recv(), Ok(Batch(4, 2)));
+        assert_eq!(receiver.try_recv(), Ok(Batch(4, 3)));
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn test_drop_lowest_fee_all_or_nothing() {
+        let (sender, receiver) = bounded_channel(10, ShedPolicy::DropLowestFee);
+        assert_eq!(sender.send(Batch(5, 1)).unwrap(), vec![]);
+        assert_eq!(sender.send(Batch(5, 3)).unwrap(), vec![]);
+
+        // making room would take the batch paying more too, so only the new
+        // one is shed, not the one paying less along with it
+        assert_eq!(sender.send(Batch(8, 2)).unwrap(), vec![Batch(8, 2)]);
+        assert_eq!(sender.len(), 10);
+
+        // all the victims pay less, so they make room
+        assert_eq!(
+            sender.send(Batch(10, 4)).unwrap(),
+            vec![Batch(5, 1), Batch(5, 3)]
+        );
+        assert_eq!(sender.send(Batch(5, 5)).unwrap(), vec![Batch(10, 4)]);
+        assert_eq!(receiver.try_recv(), Ok(Batch(5, 5)));
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (sender, receiver) = bounded_channel(10, ShedPolicy::DropOldest);
+        let other = sender.clone();
+        let hdl = spawn(move || {
+            other.send(Batch(1, 0)).unwrap();
+        });
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Ok(Batch(1, 0))
+        );
+        hdl.join().unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        drop(sender);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+
+        let (sender, receiver) = bounded_channel(10, ShedPolicy::DropOldest);
+        drop(receiver);
+        assert!(sender.send(Batch(1, 0)).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! The `broadcast_tree` module arranges the validators into the tree that carries each of the
+//! leader's blobs through the network.
+//!
+//! The leader sits at layer 0 and sends a blob to as many validators as its fanout. Those form
+//! layer 1, and each of them retransmits the blob to as many validators of the next layer as its
+//! own fanout, and so on. Every node advertises its fanout in its `NodeInfo`.
+//!
+//! Validators are ordered by the stakes they had when the bank's stake epoch began, so the ones
+//! with the most at stake get the blobs first and the order doesn't shift as balances change.
+//! Validators with the same stake are shuffled by a seed derived from the leader id and the blob's
+//! group of `BLOBS_PER_TREE` blobs, which spreads the work of retransmitting across them. Every
+//! node with the same view of the network computes the same tree for a blob, and only needs to
+//! arrange it once for the whole group.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use crdt::NodeInfo;
+use hash::{extend_and_hash, hash, Hash};
+use signature::PublicKey;
+use std::cmp;
+
+/// The number of nodes a node sends each blob on to, unless it is configured otherwise
+pub const DEFAULT_FANOUT: u64 = 16;
+
+/// Consecutive blobs that are sent down the same tree
+pub const BLOBS_PER_TREE: u64 = 64;
+
+pub struct BroadcastTree {
+    leader_id: PublicKey,
+    leader_fanout: usize,
+    /// the group of blobs the tree is for, their index divided by `BLOBS_PER_TREE`
+    group: u64,
+    /// validators in the order they fill the layers
+    nodes: Vec<NodeInfo>,
+}
+
+/// A node that asks for no children still takes one, so that nobody is
+/// left without a parent.
+fn fanout(v: &NodeInfo) -> usize {
+    cmp::max(v.fanout, 1) as usize
+}
+
+fn seed(leader_id: &PublicKey, group: u64) -> Hash {
+    let mut data = leader_id.to_vec();
+    data.write_u64::<LittleEndian>(group)
+        .expect("write_u64 in fn seed");
+    hash(&data)
+}
+
+impl BroadcastTree {
+    /// Arrange `peers`, the validators paired with their stakes, into the tree
+    /// for the blob at `index` from the leader `leader_id`.
+    pub fn new(
+        leader_id: PublicKey,
+        leader_fanout: u64,
+        peers: Vec<(NodeInfo, u64)>,
+        index: u64,
+    ) -> Self {
+        let group = index / BLOBS_PER_TREE;
+        let seed = seed(&leader_id, group);
+        let mut keyed: Vec<_> = peers
+            .into_iter()
+            .filter(|(v, _)| v.id != leader_id)
+            .map(|(v, stake)| (stake, extend_and_hash(&seed, &v.id), v))
+            .collect();
+        keyed.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        BroadcastTree {
+            leader_id,
+            leader_fanout: cmp::max(leader_fanout, 1) as usize,
+            group,
+            nodes: keyed.into_iter().map(|(_, _, v)| v).collect(),
+        }
+    }
+
+    /// Whether this is the tree for the blob at `index` from `leader_id`.
+    pub fn is_for(&self, leader_id: &PublicKey, index: u64) -> bool {
+        self.leader_id == *leader_id && self.group == index / BLOBS_PER_TREE
+    }
+
+    /// The nodes `id` sends the blob to, none if it isn't in the tree.
+    pub fn children(&self, id: &PublicKey) -> &[NodeInfo] {
+        let (start, fanout) = if *id == self.leader_id {
+            (0, self.leader_fanout)
+        } else {
+            match self.nodes.iter().position(|v| v.id == *id) {
+                Some(pos) => {
+                    let start = self.nodes[..pos]
+                        .iter()
+                        .fold(self.leader_fanout, |start, v| start + fanout(v));
+                    (start, fanout(&self.nodes[pos]))
+                }
+                None => return &[],
+            }
+        };
+        let start = cmp::min(start, self.nodes.len());
+        let end = cmp::min(start + fanout, self.nodes.len());
+        &self.nodes[start..end]
+    }
+
+    /// The layer `id` is in, 0 for the leader.
+    pub fn layer(&self, id: &PublicKey) -> Option<usize> {
+        if *id == self.leader_id {
+            return Some(0);
+        }
+        let pos = self.nodes.iter().position(|v| v.id == *id)?;
+        let mut layer = 1;
+        let mut layer_start = 0;
+        let mut layer_len = self.leader_fanout;
+        while pos >= layer_start + layer_len {
+            let next_len = self.nodes[layer_start..layer_start + layer_len]
+                .iter()
+                .map(fanout)
+                .sum();
+            layer_start += layer_len;
+            layer_len = next_len;
+            layer += 1;
+        }
+        Some(layer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use broadcast_tree::{BroadcastTree, BLOBS_PER_TREE, DEFAULT_FANOUT};
+    use crdt::NodeInfo;
+    use signature::{KeyPair, KeyPairUtil};
+    use std::collections::HashSet;
+
+    fn nodes(num: usize, fanout: u64) -> Vec<NodeInfo> {
+        (0..num)
+            .map(|i| {
+                let addr = ([127, 0, 0, 1], 8000 + 10 * i as u16).into();
+                let mut v = NodeInfo::new_leader_with_pubkey(KeyPair::new().pubkey(), &addr);
+                v.fanout = fanout;
+                v
+            })
+            .collect()
+    }
+
+    fn peers(validators: &[NodeInfo]) -> Vec<(NodeInfo, u64)> {
+        validators.iter().map(|v| (v.clone(), 1)).collect()
+    }
+
+    /// Every validator is reached exactly once, and only from the layer above it
+    #[test]
+    fn test_tree_reaches_everyone_once() {
+        let leader = KeyPair::new().pubkey();
+        let validators = nodes(100, 3);
+        let tree = BroadcastTree::new(leader, 4, peers(&validators), 7);
+        let mut reached = HashSet::new();
+        let mut queue = vec![leader];
+        while let Some(id) = queue.pop() {
+            let layer = tree.layer(&id).unwrap();
+            for child in tree.children(&id) {
+                assert!(reached.insert(child.id));
+                assert_eq!(tree.layer(&child.id), Some(layer + 1));
+                queue.push(child.id);
+            }
+        }
+        assert_eq!(reached.len(), validators.len());
+        assert_eq!(tree.children(&leader).len(), 4);
+        // the first three layers hold 4 + 12 + 36 validators, the rest are in the fourth
+        assert_eq!(
+            validators.iter().filter_map(|v| tree.layer(&v.id)).max(),
+            Some(4)
+        );
+        assert!(tree.children(&KeyPair::new().pubkey()).is_empty());
+    }
+
+    #[test]
+    fn test_tree_is_deterministic() {
+        let leader = KeyPair::new().pubkey();
+        let validators = nodes(50, DEFAULT_FANOUT);
+        let mut reversed = peers(&validators);
+        reversed.reverse();
+        let a = BroadcastTree::new(leader, DEFAULT_FANOUT, peers(&validators), 3);
+        let b = BroadcastTree::new(leader, DEFAULT_FANOUT, reversed, 3);
+        assert_eq!(a.nodes, b.nodes);
+
+        // the blobs of a group share a tree, the next group is sent down another
+        assert!(a.is_for(&leader, BLOBS_PER_TREE - 1));
+        assert!(!a.is_for(&leader, BLOBS_PER_TREE));
+        let c = BroadcastTree::new(leader, DEFAULT_FANOUT, peers(&validators), BLOBS_PER_TREE);
+        assert_ne!(a.nodes, c.nodes);
+    }
+
+    #[test]
+    fn test_tree_stake_order() {
+        let leader = KeyPair::new().pubkey();
+        let validators = nodes(20, 2);
+        let peers = validators
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.clone(), if i % 5 == 0 { 1000 } else { 1 }))
+            .collect();
+        let tree = BroadcastTree::new(leader, 4, peers, 0);
+        let layer_1: Vec<_> = tree.children(&leader).iter().map(|v| v.id).collect();
+        for (i, v) in validators.iter().enumerate() {
+            assert_eq!(layer_1.contains(&v.id), i % 5 == 0);
+        }
+    }
+
+    #[test]
+    fn test_tree_skips_leader() {
+        let validators = nodes(10, 1);
+        let leader = validators[0].id;
+        let tree = BroadcastTree::new(leader, 0, peers(&validators), 0);
+        assert_eq!(tree.nodes.len(), 9);
+        // a fanout of zero still sends the blob on
+        assert_eq!(tree.children(&leader).len(), 1);
+        assert_eq!(tree.layer(&tree.nodes[8].id), Some(9));
+    }
+}
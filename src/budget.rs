@@ -29,6 +29,13 @@ impl Condition {
             _ => false,
         }
     }
+
+    /// Return the `PublicKey` whose `Witness` satisfies this Condition.
+    pub fn pubkey(&self) -> &PublicKey {
+        match self {
+            Condition::Timestamp(_, pubkey) | Condition::Signature(pubkey) => pubkey,
+        }
+    }
 }
 
 /// A data type reprsenting a payment plan.
@@ -74,9 +81,20 @@ impl Budget {
         from: PublicKey,
         tokens: i64,
         to: PublicKey,
+    ) -> Self {
+        Self::new_cancelable_payment(Condition::Timestamp(dt, from), from, tokens, to)
+    }
+
+    /// Create a budget that pays `tokens` to `to` once `condition` is satisfied
+    /// unless cancelled by `from`, who gets the tokens back.
+    pub fn new_cancelable_payment(
+        condition: Condition,
+        from: PublicKey,
+        tokens: i64,
+        to: PublicKey,
     ) -> Self {
         Budget::Or(
-            (Condition::Timestamp(dt, from), Payment { tokens, to }),
+            (condition, Payment { tokens, to }),
             (Condition::Signature(from), Payment { tokens, to: from }),
         )
     }
@@ -113,6 +131,17 @@ impl PaymentPlan for Budget {
             mem::replace(self, Budget::Pay(payment));
         }
     }
+
+    /// Return true if `pubkey` is paid by the budget or may witness it.
+    fn is_party(&self, pubkey: &PublicKey) -> bool {
+        match self {
+            Budget::Pay(payment) => payment.to == *pubkey,
+            Budget::After(cond, payment) => cond.pubkey() == pubkey || payment.to == *pubkey,
+            Budget::Or(a, b) => [a, b]
+                .iter()
+                .any(|(cond, payment)| cond.pubkey() == pubkey || payment.to == *pubkey),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -196,4 +225,40 @@ mod tests {
         budget.apply_witness(&Witness::Signature, &from);
         assert_eq!(budget, Budget::new_payment(42, from));
     }
+
+    #[test]
+    fn test_cancelable_payment() {
+        let from = KeyPair::new().pubkey();
+        let witness = KeyPair::new().pubkey();
+        let to = KeyPair::new().pubkey();
+
+        let condition = Condition::Signature(witness);
+        let mut budget = Budget::new_cancelable_payment(condition.clone(), from, 42, to);
+        budget.apply_witness(&Witness::Signature, &witness);
+        assert_eq!(budget, Budget::new_payment(42, to));
+
+        let mut budget = Budget::new_cancelable_payment(condition, from, 42, to);
+        budget.apply_witness(&Witness::Signature, &from);
+        assert_eq!(budget, Budget::new_payment(42, from));
+    }
+
+    #[test]
+    fn test_is_party() {
+        let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let from = KeyPair::new().pubkey();
+        let witness = KeyPair::new().pubkey();
+        let to = KeyPair::new().pubkey();
+
+        let budget = Budget::new_future_payment(dt, witness, 42, to);
+        assert!(budget.is_party(&witness));
+        assert!(budget.is_party(&to));
+        assert!(!budget.is_party(&from));
+
+        let condition = Condition::Timestamp(dt, witness);
+        let budget = Budget::new_cancelable_payment(condition, from, 42, to);
+        assert!(budget.is_party(&from));
+        assert!(budget.is_party(&witness));
+        assert!(budget.is_party(&to));
+        assert!(!budget.is_party(&KeyPair::new().pubkey()));
+    }
 }
@@ -5,6 +5,7 @@ use result::Result;
 use signature::PublicKey;
 use std;
 use std::collections::HashMap;
+use std::str::FromStr;
 
 pub const DEFAULT_WEIGHT: u32 = 1;
 
@@ -12,6 +13,35 @@ pub trait ChooseGossipPeerStrategy {
     fn choose_peer<'a>(&self, options: Vec<&'a NodeInfo>) -> Result<&'a NodeInfo>;
 }
 
+/// Which `ChooseGossipPeerStrategy` a node picks its gossip peers with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GossipStrategy {
+    /// `ChooseRandomPeerStrategy`
+    Random,
+    /// `ChooseWeightedPeerStrategy`, weighing rumors by the stake of the
+    /// validators spreading them
+    Weighted,
+}
+
+impl Default for GossipStrategy {
+    fn default() -> Self {
+        GossipStrategy::Weighted
+    }
+}
+
+impl FromStr for GossipStrategy {
+    type Err = String;
+
+    /// Parse "random" or "weighted".
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(GossipStrategy::Random),
+            "weighted" => Ok(GossipStrategy::Weighted),
+            _ => Err(format!("invalid gossip strategy: {}", s)),
+        }
+    }
+}
+
 pub struct ChooseRandomPeerStrategy<'a> {
     random: &'a Fn() -> u64,
 }
@@ -51,6 +81,14 @@ impl<'a> ChooseGossipPeerStrategy for ChooseRandomPeerStrategy<'a> {
 // observed update_index from the last direct communication validator "v", and
 // rumor_v(i) is the rumored update_index of validator "v" propagated by fellow validator "i".
 
+// The weight is then scaled by the peer's own stake s(v), out of the stake S of all the
+// options, n of them:
+//
+//  w' = w * (1 + n * s(v) / S)
+//
+// so that a peer holding an average stake is picked twice as often as one without any,
+// which is still picked now and then to hear from validators that only just joined.
+
 // This could be a problem if there are validators with large stakes lying about their
 // observed updates. There could also be a problem in network partitions, or even just
 // when certain validators are disproportionately active, where we hear more rumors about
@@ -67,7 +105,7 @@ pub struct ChooseWeightedPeerStrategy<'a> {
     // "i" in the inner map, i.e. look up external_liveness[v][i]
     external_liveness: &'a HashMap<PublicKey, HashMap<PublicKey, u64>>,
     // A function returning the size of the stake for a particular validator, corresponds
-    // to stake(i) in the formula above.
+    // to stake(i) and s(v) in the formulas above.
     get_stake: &'a Fn(PublicKey) -> f64,
 }
 
@@ -126,6 +164,11 @@ impl<'a> ChooseWeightedPeerStrategy<'a> {
             }
         });
 
+        // Rumors only from validators without stake carry no weight
+        if total_stake <= 0.0 {
+            return DEFAULT_WEIGHT;
+        }
+
         let weighted_vote = relevant_votes.iter().fold(0.0, |sum, &(stake, vote)| {
             if vote < last_seen_index {
                 // This should never happen because we maintain the invariant that the indexes
@@ -177,9 +220,24 @@ impl<'a> ChooseGossipPeerStrategy for ChooseWeightedPeerStrategy<'a> {
             Err(CrdtError::NoPeers)?;
         }
 
+        let stakes: Vec<_> = options
+            .iter()
+            .map(|peer| (self.get_stake)(peer.id))
+            .collect();
+        let total_stake: f64 = stakes.iter().sum();
+        let num_options = options.len() as f64;
+
         let mut weighted_peers = vec![];
-        for peer in options {
-            let weight = self.calculate_weighted_remote_index(peer.id);
+        for (peer, stake) in options.into_iter().zip(stakes) {
+            let mut weight = self.calculate_weighted_remote_index(peer.id);
+            if total_stake > 0.0 {
+                let scaled = f64::from(weight) * (1.0 + num_options * stake / total_stake);
+                weight = if scaled >= f64::from(std::u32::MAX) {
+                    std::u32::MAX
+                } else {
+                    scaled as u32
+                };
+            }
             weighted_peers.push(Weighted { weight, item: peer });
         }
 
@@ -190,7 +248,7 @@ impl<'a> ChooseGossipPeerStrategy for ChooseWeightedPeerStrategy<'a> {
 
 #[cfg(test)]
 mod tests {
-    use choose_gossip_peer_strategy::{ChooseWeightedPeerStrategy, DEFAULT_WEIGHT};
+    use choose_gossip_peer_strategy::{ChooseWeightedPeerStrategy, GossipStrategy, DEFAULT_WEIGHT};
     use logger;
     use signature::{KeyPair, KeyPairUtil, PublicKey};
     use std;
@@ -330,4 +388,43 @@ mod tests {
         // If nobody has seen a newer update then revert to default
         assert_eq!(result, DEFAULT_WEIGHT);
     }
+
+    #[test]
+    fn test_stake_weighted_rumors() {
+        logger::setup();
+
+        let key1 = KeyPair::new().pubkey();
+        let staked = KeyPair::new().pubkey();
+        let unstaked = KeyPair::new().pubkey();
+
+        let mut remote: HashMap<PublicKey, u64> = HashMap::new();
+        let mut external_liveness: HashMap<PublicKey, HashMap<PublicKey, u64>> = HashMap::new();
+        remote.insert(key1, 0);
+        let mut rumors: HashMap<PublicKey, u64> = HashMap::new();
+        rumors.insert(staked, 30);
+        rumors.insert(unstaked, 1000);
+        external_liveness.insert(key1, rumors);
+
+        // Only the staked validator's rumor counts
+        let get_stake = |id: PublicKey| if id == staked { 100.0 } else { 0.0 };
+        let weighted_strategy =
+            ChooseWeightedPeerStrategy::new(&remote, &external_liveness, &get_stake);
+        let result = weighted_strategy.calculate_weighted_remote_index(key1);
+        assert_eq!(result, 30 + DEFAULT_WEIGHT);
+
+        // Nor does anyone's, if nobody has stake
+        let get_stake = |_id: PublicKey| 0.0;
+        let weighted_strategy =
+            ChooseWeightedPeerStrategy::new(&remote, &external_liveness, &get_stake);
+        let result = weighted_strategy.calculate_weighted_remote_index(key1);
+        assert_eq!(result, DEFAULT_WEIGHT);
+    }
+
+    #[test]
+    fn test_gossip_strategy_from_str() {
+        assert_eq!("random".parse(), Ok(GossipStrategy::Random));
+        assert_eq!("weighted".parse(), Ok(GossipStrategy::Weighted));
+        assert!("fastest".parse::<GossipStrategy>().is_err());
+        assert_eq!(GossipStrategy::default(), GossipStrategy::Weighted);
+    }
 }
@@ -11,26 +11,39 @@
 //! * layer 1 - As many nodes as we can fit
 //! * layer 2 - Everyone else, if layer 1 is `2^10`, layer 2 should be able to fit `2^20` number of nodes.
 //!
-//! Bank needs to provide an interface for us to query the stake weight
-
-use bincode::{deserialize, serialize};
-use byteorder::{LittleEndian, ReadBytesExt};
-use choose_gossip_peer_strategy::{ChooseGossipPeerStrategy, ChooseWeightedPeerStrategy};
+//! Each group of blobs travels down its own `BroadcastTree`, so a node only retransmits them to
+//! its children. The tree is ordered by the stakes validators had when the bank's stake epoch began.
+//!
+//! Gossip peers are weighted by the stakes the `Bank` holds for each validator. A node pulls the
+//! entries it is missing from a peer by sending it a `Bloom` filter of the ones it has.
+
+use bank::Bank;
+use bincode::{deserialize, serialize, serialized_size};
+use bloom::Bloom;
+use broadcast_tree::{BroadcastTree, DEFAULT_FANOUT};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use choose_gossip_peer_strategy::{
+    ChooseGossipPeerStrategy, ChooseRandomPeerStrategy, ChooseWeightedPeerStrategy, GossipStrategy,
+};
 use counter::Counter;
 use hash::Hash;
-use packet::{to_blob, Blob, BlobRecycler, SharedBlob, BLOB_SIZE};
+use ledger::Block;
+use net_utils::{bind_addr, is_global, is_ipv6, send_addr, unspecified_ip};
+use packet::{to_blob, Blob, BlobRecycler, SharedBlob, BLOB_DATA_SIZE, BLOB_SIZE};
 use pnet_datalink as datalink;
 use rand::{thread_rng, RngCore};
 use rayon::prelude::*;
 use result::{Error, Result};
+use segmented_ledger::SegmentedLedger;
 use signature::{KeyPair, KeyPairUtil, PublicKey};
 use std;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::io::Cursor;
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{sleep, Builder, JoinHandle};
 use std::time::Duration;
 use streamer::{BlobReceiver, BlobSender, Window};
@@ -44,6 +57,15 @@ const GOSSIP_PURGE_MILLIS: u64 = 15000;
 /// minimum membership table size before we start purging dead nodes
 const MIN_TABLE_SIZE: usize = 2;
 
+/// repair requests waiting for a ledger read before more are dropped
+const MAX_LEDGER_REQUESTS: usize = 64;
+
+/// share of the entries a node has that the bloom filter in its pull requests
+/// wrongly says it has, so they're only sent to it the next time around
+const PULL_BLOOM_FALSE_RATE: f64 = 0.1;
+/// leave at least half of the pull request's blob for the rest of the request
+const PULL_BLOOM_MAX_BITS: usize = BLOB_DATA_SIZE / 2 * 8;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum CrdtError {
     NoPeers,
@@ -54,7 +76,7 @@ pub enum CrdtError {
 }
 
 pub fn parse_port_or_addr(optstr: Option<String>) -> SocketAddr {
-    let daddr: SocketAddr = "0.0.0.0:8000".parse().expect("default socket address");
+    let daddr = bind_addr(8000);
     if let Some(addrstr) = optstr {
         if let Ok(port) = addrstr.parse() {
             let mut addr = daddr;
@@ -70,28 +92,39 @@ pub fn parse_port_or_addr(optstr: Option<String>) -> SocketAddr {
     }
 }
 
+/// The first global IPv4 address and, with the `ipv6` feature, the first
+/// global IPv6 address of this machine's interfaces
+fn get_ip_addrs() -> (Option<IpAddr>, Option<IpAddr>) {
+    let ips: Vec<_> = datalink::interfaces()
+        .into_iter()
+        .flat_map(|iface| iface.ips)
+        .map(|p| p.ip())
+        .filter(is_global)
+        .collect();
+    let v4 = ips.iter().find(|ip| ip.is_ipv4()).cloned();
+    let v6 = if cfg!(feature = "ipv6") {
+        ips.iter().find(|ip| ip.is_ipv6()).cloned()
+    } else {
+        None
+    };
+    (v4, v6)
+}
+
+/// The address to advertise this machine at, IPv4 unless it only has IPv6
 pub fn get_ip_addr() -> Option<IpAddr> {
-    for iface in datalink::interfaces() {
-        for p in iface.ips {
-            if !p.ip().is_loopback() && !p.ip().is_multicast() {
-                match p.ip() {
-                    IpAddr::V4(addr) => {
-                        if !addr.is_link_local() {
-                            return Some(p.ip());
-                        }
-                    }
-                    IpAddr::V6(_addr) => {
-                        // Select an ipv6 address if the config is selected
-                        #[cfg(feature = "ipv6")]
-                        {
-                            return Some(p.ip());
-                        }
-                    }
-                }
-            }
-        }
+    let (v4, v6) = get_ip_addrs();
+    v4.or(v6)
+}
+
+/// The address to advertise besides `ip`, this machine's global address in
+/// the other family
+pub fn get_alt_ip_addr(ip: &IpAddr) -> Option<IpAddr> {
+    let (v4, v6) = get_ip_addrs();
+    if ip.is_ipv4() {
+        v6
+    } else {
+        v4
     }
-    None
 }
 
 /// Structure to be replicated by the network
@@ -108,6 +141,9 @@ pub struct ContactInfo {
     /// repair address, we use this to jump ahead of the packets
     /// destined to the replciate_addr
     pub tvu_window: SocketAddr,
+    /// the node's address in the other address family, if it has one, where
+    /// it listens on the same ports
+    pub alt_ip: Option<IpAddr>,
     /// if this struture changes update this value as well
     /// Always update `NodeInfo` version too
     /// This separate version for addresses allows us to use the `Vote`
@@ -116,10 +152,43 @@ pub struct ContactInfo {
     pub version: u64,
 }
 
+impl ContactInfo {
+    /// Whether the node has an address in the family `ipv6`
+    fn has_family(&self, ipv6: bool) -> bool {
+        self.ncp.is_ipv6() == ipv6 || self.alt_ip.map_or(false, |ip| ip.is_ipv6() == ipv6)
+    }
+
+    /// These addresses as the node `me` reaches them, moved to the alternate
+    /// address when `me` has no address in the family of the primary one
+    pub fn reachable_from(&self, me: &ContactInfo) -> ContactInfo {
+        let mut contact_info = self.clone();
+        match self.alt_ip {
+            Some(ip) if !me.has_family(self.ncp.is_ipv6()) && me.has_family(ip.is_ipv6()) => {
+                for addr in &mut [
+                    &mut contact_info.ncp,
+                    &mut contact_info.tvu,
+                    &mut contact_info.rpu,
+                    &mut contact_info.tpu,
+                    &mut contact_info.tvu_window,
+                ] {
+                    if !addr.ip().is_unspecified() {
+                        addr.set_ip(ip);
+                    }
+                }
+            }
+            _ => (),
+        }
+        contact_info
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct LedgerState {
     /// last verified hash that was submitted to the leader
     pub last_id: Hash,
+    /// share of the leader's blobs that didn't reach this node with the
+    /// broadcast, in parts per million, once it has been measured
+    pub blob_loss: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -131,6 +200,8 @@ pub struct NodeInfo {
     pub contact_info: ContactInfo,
     /// current leader identity
     pub leader_id: PublicKey,
+    /// how many nodes this node retransmits each blob to in the broadcast tree
+    pub fanout: u64,
     /// information about the state of the ledger
     ledger_state: LedgerState,
 }
@@ -141,6 +212,14 @@ fn make_debug_id(buf: &[u8]) -> u64 {
         .expect("rdr.read_u64 in fn debug_id")
 }
 
+/// What the bloom filter of a pull request holds for an entry, its id and version
+fn pull_item(v: &NodeInfo) -> Vec<u8> {
+    let mut item = v.id.to_vec();
+    item.write_u64::<LittleEndian>(v.version)
+        .expect("write_u64 in fn pull_item");
+    item
+}
+
 impl NodeInfo {
     pub fn new(
         id: PublicKey,
@@ -159,11 +238,14 @@ impl NodeInfo {
                 rpu,
                 tpu,
                 tvu_window,
+                alt_ip: None,
                 version: 0,
             },
             leader_id: PublicKey::default(),
+            fanout: DEFAULT_FANOUT,
             ledger_state: LedgerState {
                 last_id: Hash::default(),
+                blob_loss: None,
             },
         }
     }
@@ -265,10 +347,20 @@ pub struct Crdt {
     /// last time we heard from anyone getting a message fro this public key
     /// these are rumers and shouldn't be trusted directly
     external_liveness: HashMap<PublicKey, HashMap<PublicKey, u64>>,
+    /// ledger to serve repair requests from once they fall out of the window
+    ledger: Option<SegmentedLedger>,
+    /// queue of repair requests for the `ledger_repair` thread to read from the ledger
+    ledger_requests: Option<SyncSender<(NodeInfo, u64)>>,
+    /// bank to look up the stake of each validator in
+    bank: Option<Arc<Bank>>,
+    /// the tree the last retransmitted blob went down, reused for the rest of its
+    /// group while the stake epoch it was ordered in lasts
+    retransmit_tree: Mutex<Option<(u64, Arc<BroadcastTree>)>>,
+    gossip_strategy: GossipStrategy,
 }
 // TODO These messages should be signed, and go through the gpu pipeline for spam filtering
 #[derive(Serialize, Deserialize, Debug)]
-enum Protocol {
+pub enum Protocol {
     /// forward your own latest data structure when requesting an update
     /// this doesn't update the `remote` update index, but it allows the
     /// recepient of this request to add knowledge of this node to the network
@@ -280,6 +372,14 @@ enum Protocol {
     /// ask for a missing index
     /// (my replicated data to keep alive, missing window index)
     RequestWindowIndex(NodeInfo, u64),
+    /// ask for the entries I don't have yet
+    /// (filter of the `pull_item` of every entry I have, my replicated data)
+    PullRequest(Bloom, NodeInfo),
+    /// ask which address this request came from; the answer only goes back
+    /// there, so requests with a forged source can't be used to flood a host
+    RequestIpEcho,
+    /// the address a `RequestIpEcho` came from
+    IpEcho(SocketAddr),
 }
 
 impl Crdt {
@@ -316,6 +416,11 @@ impl Crdt {
             external_liveness: HashMap::new(),
             me: me.id,
             update_index: 1,
+            ledger: None,
+            ledger_requests: None,
+            bank: None,
+            retransmit_tree: Mutex::new(None),
+            gossip_strategy: GossipStrategy::default(),
         };
         g.local.insert(me.id, g.update_index);
         g.table.insert(me.id, me);
@@ -324,6 +429,18 @@ impl Crdt {
     pub fn debug_id(&self) -> u64 {
         make_debug_id(&self.me)
     }
+    pub fn set_ledger(&mut self, ledger: SegmentedLedger) {
+        self.ledger = Some(ledger);
+    }
+    pub fn ledger(&self) -> Option<&SegmentedLedger> {
+        self.ledger.as_ref()
+    }
+    pub fn set_bank(&mut self, bank: Arc<Bank>) {
+        self.bank = Some(bank);
+    }
+    pub fn set_gossip_strategy(&mut self, gossip_strategy: GossipStrategy) {
+        self.gossip_strategy = gossip_strategy;
+    }
     pub fn my_data(&self) -> &NodeInfo {
         &self.table[&self.me]
     }
@@ -344,6 +461,43 @@ impl Crdt {
         self.insert(&me);
     }
 
+    /// A validator's stake as of the start of the bank's stake epoch, which
+    /// every node with the same ledger agrees on, however it learned of the
+    /// validator. Without a bank every validator has the same stake.
+    fn tree_stake(&self, id: PublicKey) -> u64 {
+        match self.bank {
+            Some(ref bank) => bank.epoch_stake(&id),
+            None => 1,
+        }
+    }
+
+    /// The stake epoch the broadcast tree is ordered in
+    fn stake_epoch(&self) -> u64 {
+        self.bank.as_ref().map_or(0, |bank| bank.stake_epoch())
+    }
+
+    /// Publish the blob loss this node measured, for the leader to size its
+    /// erasure sets with
+    pub fn set_blob_loss(&mut self, loss_ppm: u32) {
+        let mut me = self.my_data().clone();
+        if me.ledger_state.blob_loss == Some(loss_ppm) {
+            return;
+        }
+        me.ledger_state.blob_loss = Some(loss_ppm);
+        me.version += 1;
+        self.insert(&me);
+    }
+
+    /// The worst blob loss any other node has reported, so that every
+    /// validator can recover the leader's erasure sets
+    pub fn blob_loss(&self) -> Option<u32> {
+        self.table
+            .values()
+            .filter(|v| v.id != self.me)
+            .filter_map(|v| v.ledger_state.blob_loss)
+            .max()
+    }
+
     pub fn get_external_liveness_entry(&self, key: &PublicKey) -> Option<&HashMap<PublicKey, u64>> {
         self.external_liveness.get(key)
     }
@@ -519,18 +673,15 @@ impl Crdt {
 
         Ok(())
     }
-    /// compute broadcast table
-    /// # Remarks
-    pub fn compute_broadcast_table(&self) -> Vec<NodeInfo> {
-        let live: Vec<_> = self.alive.iter().collect();
-        //thread_rng().shuffle(&mut live);
+    /// The validators that blobs from `leader_id` are broadcast to, paired with
+    /// their stakes
+    fn broadcast_peers(&self, leader_id: PublicKey) -> Vec<(NodeInfo, u64)> {
         let daddr = "0.0.0.0:0".parse().unwrap();
         let me = &self.table[&self.me];
-        let cloned_table: Vec<NodeInfo> = live.iter()
-            .map(|x| &self.table[x.0])
+        self.table
+            .values()
             .filter(|v| {
-                if me.id == v.id {
-                    //filter myself
+                if leader_id == v.id || v.id == PublicKey::default() {
                     false
                 } else if v.contact_info.tvu == daddr {
                     trace!(
@@ -540,18 +691,30 @@ impl Crdt {
                     );
                     false
                 } else {
-                    trace!(
-                        "{:x}:broadcast node {:x} {}",
-                        me.debug_id(),
-                        v.debug_id(),
-                        v.contact_info.tvu
-                    );
                     true
                 }
             })
-            .cloned()
-            .collect();
-        cloned_table
+            .map(|v| {
+                let mut v = v.clone();
+                v.contact_info = v.contact_info.reachable_from(&me.contact_info);
+                let stake = self.tree_stake(v.id);
+                (v, stake)
+            })
+            .collect()
+    }
+
+    /// The fanout of the leader `leader_id`, as far as this node knows it
+    fn leader_fanout(&self, leader_id: PublicKey) -> u64 {
+        self.table
+            .get(&leader_id)
+            .map_or(DEFAULT_FANOUT, |v| v.fanout)
+    }
+
+    /// compute broadcast table
+    /// # Remarks
+    /// The leader's broadcast tree for each blob is built from these nodes.
+    pub fn compute_broadcast_table(&self) -> Vec<(NodeInfo, u64)> {
+        self.broadcast_peers(self.me)
     }
 
     /// broadcast messages from the leader to layer 1 nodes
@@ -559,7 +722,7 @@ impl Crdt {
     /// We need to avoid having obj locked while doing any io, such as the `send_to`
     pub fn broadcast(
         me: &NodeInfo,
-        broadcast_table: &[NodeInfo],
+        broadcast_table: &[(NodeInfo, u64)],
         window: &Window,
         s: &UdpSocket,
         transmit_index: &mut u64,
@@ -573,46 +736,59 @@ impl Crdt {
         trace!("broadcast nodes {}", broadcast_table.len());
 
         // enumerate all the blobs in the window, those are the indices
-        // transmit them to nodes, starting from a different node
+        // transmit them to the first layer of each blob's tree
         let mut orders = Vec::new();
+        let mut tree: Option<BroadcastTree> = None;
         let window_l = window.write().unwrap();
         for i in *transmit_index..received_index {
             let is = i as usize;
             let k = is % window_l.len();
             assert!(window_l[k].is_some());
 
-            let pos = is % broadcast_table.len();
-            orders.push((window_l[k].clone(), &broadcast_table[pos]));
+            if !tree.as_ref().map_or(false, |t| t.is_for(&me.id, i)) {
+                let peers = broadcast_table.to_vec();
+                tree = Some(BroadcastTree::new(me.id, me.fanout, peers, i));
+            }
+            let layer_1 = tree.as_ref().unwrap().children(&me.id).to_vec();
+            orders.push((window_l[k].clone(), layer_1));
         }
 
         trace!("broadcast orders table {}", orders.len());
+        let ipv6 = is_ipv6(s);
         let errs: Vec<_> = orders
             .into_iter()
-            .map(|(b, v)| {
-                // only leader should be broadcasting
-                assert!(me.leader_id != v.id);
+            .map(|(b, layer_1)| {
                 let bl = b.unwrap();
                 let blob = bl.read().expect("blob read lock in streamer::broadcast");
-                //TODO profile this, may need multiple sockets for par_iter
-                trace!(
-                    "{:x}: BROADCAST idx: {} sz: {} to {:x},{} coding: {}",
-                    me.debug_id(),
-                    blob.get_index().unwrap(),
-                    blob.meta.size,
-                    v.debug_id(),
-                    v.contact_info.tvu,
-                    blob.is_coding()
-                );
                 assert!(blob.meta.size < BLOB_SIZE);
-                let e = s.send_to(&blob.data[..blob.meta.size], &v.contact_info.tvu);
-                trace!(
-                    "{:x}: done broadcast {} to {:x} {}",
-                    me.debug_id(),
-                    blob.meta.size,
-                    v.debug_id(),
-                    v.contact_info.tvu
-                );
-                e
+                let mut result = Ok(0);
+                for v in layer_1 {
+                    // only leader should be broadcasting
+                    assert!(me.leader_id != v.id);
+                    //TODO profile this, may need multiple sockets for par_iter
+                    trace!(
+                        "{:x}: BROADCAST idx: {} sz: {} to {:x},{} coding: {}",
+                        me.debug_id(),
+                        blob.get_index().unwrap(),
+                        blob.meta.size,
+                        v.debug_id(),
+                        v.contact_info.tvu,
+                        blob.is_coding()
+                    );
+                    let addr = send_addr(ipv6, &v.contact_info.tvu);
+                    let e = s.send_to(&blob.data[..blob.meta.size], &addr);
+                    trace!(
+                        "{:x}: done broadcast {} to {:x} {}",
+                        me.debug_id(),
+                        blob.meta.size,
+                        v.debug_id(),
+                        v.contact_info.tvu
+                    );
+                    if e.is_err() {
+                        result = e;
+                    }
+                }
+                result
             })
             .collect();
         trace!("broadcast results {}", errs.len());
@@ -626,50 +802,36 @@ impl Crdt {
         Ok(())
     }
 
-    /// retransmit messages from the leader to layer 1 nodes
+    /// retransmit messages from the leader to this node's children in the
+    /// blob's broadcast tree
     /// # Remarks
     /// We need to avoid having obj locked while doing any io, such as the `send_to`
     pub fn retransmit(obj: &Arc<RwLock<Self>>, blob: &SharedBlob, s: &UdpSocket) -> Result<()> {
-        let (me, table): (NodeInfo, Vec<NodeInfo>) = {
+        let rblob = blob.read().unwrap();
+        let index = rblob.get_index()?;
+        let (me, orders): (NodeInfo, Vec<NodeInfo>) = {
             // copy to avoid locking during IO
             let s = obj.read().expect("'obj' read lock in pub fn retransmit");
-            (s.table[&s.me].clone(), s.table.values().cloned().collect())
+            let me = s.table[&s.me].clone();
+            let tree = s.retransmit_tree(me.leader_id, index);
+            let orders = tree.children(&me.id).to_vec();
+            (me, orders)
         };
-        blob.write()
-            .unwrap()
-            .set_id(me.id)
-            .expect("set_id in pub fn retransmit");
-        let rblob = blob.read().unwrap();
-        let daddr = "0.0.0.0:0".parse().unwrap();
-        let orders: Vec<_> = table
-            .iter()
-            .filter(|v| {
-                if me.id == v.id {
-                    false
-                } else if me.leader_id == v.id {
-                    trace!("skip retransmit to leader {:?}", v.id);
-                    false
-                } else if v.contact_info.tvu == daddr {
-                    trace!("skip nodes that are not listening {:?}", v.id);
-                    false
-                } else {
-                    true
-                }
-            })
-            .collect();
         trace!("retransmit orders {}", orders.len());
+        let ipv6 = is_ipv6(s);
         let errs: Vec<_> = orders
             .par_iter()
             .map(|v| {
                 debug!(
                     "{:x}: retransmit blob {} to {:x}",
                     me.debug_id(),
-                    rblob.get_index().unwrap(),
+                    index,
                     v.debug_id(),
                 );
                 //TODO profile this, may need multiple sockets for par_iter
                 assert!(rblob.meta.size < BLOB_SIZE);
-                s.send_to(&rblob.data[..rblob.meta.size], &v.contact_info.tvu)
+                let addr = send_addr(ipv6, &v.contact_info.tvu);
+                s.send_to(&rblob.data[..rblob.meta.size], &addr)
             })
             .collect();
         for e in errs {
@@ -682,6 +844,23 @@ impl Crdt {
         Ok(())
     }
 
+    /// The tree blob `index` from `leader_id` is retransmitted down, built
+    /// once for each group of blobs
+    fn retransmit_tree(&self, leader_id: PublicKey, index: u64) -> Arc<BroadcastTree> {
+        let stake_epoch = self.stake_epoch();
+        let mut cached = self.retransmit_tree.lock().unwrap();
+        if let Some((epoch, ref tree)) = *cached {
+            if epoch == stake_epoch && tree.is_for(&leader_id, index) {
+                return tree.clone();
+            }
+        }
+        let peers = self.broadcast_peers(leader_id);
+        let leader_fanout = self.leader_fanout(leader_id);
+        let tree = Arc::new(BroadcastTree::new(leader_id, leader_fanout, peers, index));
+        *cached = Some((stake_epoch, tree.clone()));
+        tree
+    }
+
     // max number of nodes that we could be converged to
     pub fn convergence(&self) -> u64 {
         let max = self.remote.values().len() as u64 + 1;
@@ -692,9 +871,13 @@ impl Crdt {
         thread_rng().next_u64()
     }
 
-    // TODO: fill in with real implmentation once staking is implemented
-    fn get_stake(_id: PublicKey) -> f64 {
-        1.0
+    /// A validator's stake in the bank, the balance of its account once it
+    /// has voted. Without a bank every validator has the same stake.
+    fn get_stake(&self, id: PublicKey) -> u64 {
+        match self.bank {
+            Some(ref bank) => bank.stake(&id),
+            None => 1,
+        }
     }
 
     fn get_updates_since(&self, v: u64) -> (PublicKey, u64, Vec<NodeInfo>) {
@@ -709,6 +892,65 @@ impl Crdt {
         (id, ups, data)
     }
 
+    /// The entries that the requester's `bloom` says it doesn't have
+    fn get_updates_missing(&self, bloom: &Bloom) -> (PublicKey, u64, Vec<NodeInfo>) {
+        let data = self.table
+            .values()
+            .filter(|x| x.id != PublicKey::default() && !bloom.contains(&pull_item(x)))
+            .cloned()
+            .collect();
+        (self.me, self.update_index, data)
+    }
+
+    /// Split the response to a request for updates into as many
+    /// `ReceiveUpdates` messages as it takes for each to fit in a blob.
+    fn split_updates(
+        from: PublicKey,
+        update_index: u64,
+        data: Vec<NodeInfo>,
+        external_liveness: Vec<(PublicKey, u64)>,
+    ) -> Vec<Protocol> {
+        let empty = Protocol::ReceiveUpdates(from, update_index, vec![], vec![]);
+        let empty_size = serialized_size(&empty).expect("serialized_size of ReceiveUpdates");
+        let liveness_size = serialized_size(&(from, update_index)).expect("serialized_size");
+        let mut rsps = Vec::new();
+        let mut size = empty_size;
+        let mut chunk = Vec::new();
+        let mut liveness_chunk = Vec::new();
+        for v in data {
+            let v_size = serialized_size(&v).expect("serialized_size of NodeInfo");
+            if size + v_size > BLOB_DATA_SIZE as u64 {
+                let chunk = std::mem::replace(&mut chunk, Vec::new());
+                rsps.push(Protocol::ReceiveUpdates(from, update_index, chunk, vec![]));
+                size = empty_size;
+            }
+            chunk.push(v);
+            size += v_size;
+        }
+        for l in external_liveness {
+            if size + liveness_size > BLOB_DATA_SIZE as u64 {
+                let chunk = std::mem::replace(&mut chunk, Vec::new());
+                let liveness_chunk = std::mem::replace(&mut liveness_chunk, Vec::new());
+                rsps.push(Protocol::ReceiveUpdates(
+                    from,
+                    update_index,
+                    chunk,
+                    liveness_chunk,
+                ));
+                size = empty_size;
+            }
+            liveness_chunk.push(l);
+            size += liveness_size;
+        }
+        rsps.push(Protocol::ReceiveUpdates(
+            from,
+            update_index,
+            chunk,
+            liveness_chunk,
+        ));
+        rsps
+    }
+
     pub fn window_index_request(&self, ix: u64) -> Result<(SocketAddr, Vec<u8>)> {
         let daddr = "0.0.0.0:0".parse().unwrap();
         let valid: Vec<_> = self.table
@@ -719,18 +961,19 @@ impl Crdt {
             Err(CrdtError::NoPeers)?;
         }
         let n = (Self::random() as usize) % valid.len();
-        let addr = valid[n].contact_info.ncp;
+        let me = &self.table[&self.me];
+        let addr = valid[n].contact_info.reachable_from(&me.contact_info).ncp;
         let req = Protocol::RequestWindowIndex(self.table[&self.me].clone(), ix);
         let out = serialize(&req)?;
         Ok((addr, out))
     }
 
-    /// Create a random gossip request
+    /// Create a gossip request to a peer picked with this node's `GossipStrategy`
     /// # Returns
     /// (A,B)
     /// * A - Address to send to
-    /// * B - RequestUpdates protocol message
-    fn gossip_request(&self) -> Result<(SocketAddr, Protocol)> {
+    /// * B - PullRequest protocol message
+    pub fn gossip_request(&self) -> Result<(SocketAddr, Protocol)> {
         let options: Vec<_> = self.table
             .values()
             .filter(|v| {
@@ -740,13 +983,16 @@ impl Crdt {
             })
             .collect();
 
-        let choose_peer_strategy = ChooseWeightedPeerStrategy::new(
-            &self.remote,
-            &self.external_liveness,
-            &Self::get_stake,
-        );
-
-        let choose_peer_result = choose_peer_strategy.choose_peer(options);
+        let choose_peer_result = match self.gossip_strategy {
+            GossipStrategy::Random => {
+                ChooseRandomPeerStrategy::new(&Self::random).choose_peer(options)
+            }
+            GossipStrategy::Weighted => {
+                let get_stake = |id| self.get_stake(id) as f64;
+                ChooseWeightedPeerStrategy::new(&self.remote, &self.external_liveness, &get_stake)
+                    .choose_peer(options)
+            }
+        };
 
         if let Err(Error::CrdtError(CrdtError::NoPeers)) = &choose_peer_result {
             trace!(
@@ -757,16 +1003,21 @@ impl Crdt {
         };
         let v = choose_peer_result?;
 
-        let remote_update_index = *self.remote.get(&v.id).unwrap_or(&0);
-        let req = Protocol::RequestUpdates(remote_update_index, self.table[&self.me].clone());
+        let mut bloom = Bloom::random(self.table.len(), PULL_BLOOM_FALSE_RATE, PULL_BLOOM_MAX_BITS);
+        for x in self.table.values() {
+            bloom.add(&pull_item(x));
+        }
+        let me = &self.table[&self.me];
+        let addr = v.contact_info.reachable_from(&me.contact_info).ncp;
+        let req = Protocol::PullRequest(bloom, me.clone());
         trace!(
             "created gossip request from {:x} to {:x} {}",
             self.debug_id(),
             v.debug_id(),
-            v.contact_info.ncp
+            addr
         );
 
-        Ok((v.contact_info.ncp, req))
+        Ok((addr, req))
     }
 
     pub fn new_vote(&mut self, last_id: Hash) -> Result<(Vote, SocketAddr)> {
@@ -779,7 +1030,8 @@ impl Crdt {
             contact_info_version: me.contact_info.version,
         };
         self.insert(&me);
-        Ok((vote, leader.contact_info.tpu))
+        let leader = leader.contact_info.reachable_from(&me.contact_info);
+        Ok((vote, leader.tpu))
     }
 
     /// At random pick a node and try to get updated changes from them
@@ -788,7 +1040,6 @@ impl Crdt {
         blob_sender: &BlobSender,
         blob_recycler: &BlobRecycler,
     ) -> Result<()> {
-        //TODO we need to keep track of stakes and weight the selection by stake size
         //TODO cache sockets
 
         // Lock the object only to do this operation and not for any longer
@@ -797,8 +1048,6 @@ impl Crdt {
             .expect("'obj' read lock in fn run_gossip")
             .gossip_request()?;
 
-        // TODO this will get chatty, so we need to first ask for number of updates since
-        // then only ask for specific data that we dont have
         let blob = to_blob(req, remote_gossip_addr, blob_recycler)?;
         let mut q: VecDeque<SharedBlob> = VecDeque::new();
         q.push_back(blob);
@@ -914,6 +1163,7 @@ impl Crdt {
     }
     fn run_window_request(
         window: &Window,
+        ledger_requests: Option<&SyncSender<(NodeInfo, u64)>>,
         me: &NodeInfo,
         from: &NodeInfo,
         ix: u64,
@@ -973,23 +1223,157 @@ impl Crdt {
             );
         }
 
+        // With erasure coding the window indices also count coding blobs, so they
+        // aren't entry heights and the ledger can't tell which entry was asked for.
+        if cfg!(feature = "erasure") {
+            return None;
+        }
+        if let Some(ledger_requests) = ledger_requests {
+            match ledger_requests.try_send((from.clone(), ix)) {
+                Ok(()) => {
+                    inc_new_counter!("crdt-window-request-ledger-queued", 1);
+                }
+                Err(TrySendError::Full(_)) => {
+                    inc_new_counter!("crdt-window-request-ledger-dropped", 1);
+                }
+                Err(TrySendError::Disconnected(_)) => (),
+            }
+        }
         None
     }
 
+    /// Serve a repair request that missed the window from the ledger's segments.
+    /// Requests for heights that have been pruned are refused.
+    fn run_ledger_request(
+        ledger: &SegmentedLedger,
+        from: &NodeInfo,
+        ix: u64,
+        blob_recycler: &BlobRecycler,
+    ) -> Option<SharedBlob> {
+        let entry = match ledger.get_entry(ix) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                inc_new_counter!("crdt-window-request-pruned", 1);
+                info!(
+                    "refusing RequestWindowIndex {} from {:x}, not in ledger",
+                    ix,
+                    from.debug_id()
+                );
+                return None;
+            }
+            Err(e) => {
+                warn!("ledger read for RequestWindowIndex {} failed: {:?}", ix, e);
+                return None;
+            }
+        };
+        let mut blobs = VecDeque::new();
+        [entry].to_blobs(blob_recycler, &mut blobs);
+        let out = blobs.pop_front()?;
+        {
+            let mut outblob = out.write().unwrap();
+            outblob.set_index(ix).expect("blob set_index");
+            // the requester's id keeps it from retransmitting the response
+            outblob.set_id(from.id).expect("blob set_id");
+            outblob.meta.set_addr(&from.contact_info.tvu_window);
+        }
+        inc_new_counter!("crdt-window-request-ledger", 1);
+        Some(out)
+    }
+
     //TODO we should first coalesce all the requests
-    fn handle_blob(
+    pub fn handle_blob(
         obj: &Arc<RwLock<Self>>,
         window: &Window,
         blob_recycler: &BlobRecycler,
         blob: &Blob,
-    ) -> Option<SharedBlob> {
+    ) -> Vec<SharedBlob> {
         match deserialize(&blob.data[..blob.meta.size]) {
+            // only the blob knows where the request came from
+            Ok(Protocol::RequestIpEcho) => Crdt::handle_ip_echo(blob.meta.addr(), blob_recycler),
             Ok(request) => Crdt::handle_protocol(request, obj, window, blob_recycler),
             Err(_) => {
                 warn!("deserialize crdt packet failed");
-                None
+                vec![]
+            }
+        }
+    }
+
+    /// Tell `from` the address its request came from. Like every answer from
+    /// `listen`, it goes out the gossip socket the request came in on.
+    fn handle_ip_echo(from: SocketAddr, blob_recycler: &BlobRecycler) -> Vec<SharedBlob> {
+        inc_new_counter!("crdt-ip_echo", 1);
+        trace!("RequestIpEcho from {}", from);
+        to_blob(Protocol::IpEcho(from), from, blob_recycler)
+            .into_iter()
+            .collect()
+    }
+
+    /// Answer a request for updates from `from_rd` with the ones `get_updates` picks
+    fn handle_pull<F>(
+        obj: &Arc<RwLock<Self>>,
+        from_rd: NodeInfo,
+        blob_recycler: &BlobRecycler,
+        get_updates: F,
+    ) -> Vec<SharedBlob>
+    where
+        F: Fn(&Self) -> (PublicKey, u64, Vec<NodeInfo>),
+    {
+        let me = obj.read().unwrap();
+        let addr = from_rd
+            .contact_info
+            .reachable_from(&me.table[&me.me].contact_info)
+            .ncp;
+        if addr == me.table[&me.me].contact_info.ncp {
+            warn!(
+                "RequestUpdates ignored, I'm talking to myself: me={:x} remoteme={:x}",
+                me.debug_id(),
+                make_debug_id(&from_rd.id)
+            );
+            inc_new_counter!("crdt-window-request-loopback", 1);
+            return vec![];
+        }
+        // only lock for these two calls, dont lock during IO `sock.send_to` or `sock.recv_from`
+        let (from, ups, data) = get_updates(&*me);
+        let external_liveness = me.remote.iter().map(|(k, v)| (*k, *v)).collect();
+        drop(me);
+        let len = data.len();
+        trace!("get updates response {}", len);
+        {
+            let mut me = obj.write().unwrap();
+            me.insert(&from_rd);
+            me.update_liveness(from_rd.id);
+        }
+        if len < 1 {
+            let me = obj.read().unwrap();
+            trace!(
+                "no updates me {:x} ix {} for {:x}",
+                me.debug_id(),
+                me.update_index,
+                from_rd.debug_id()
+            );
+            return vec![];
+        }
+        let rsps = Self::split_updates(from, ups, data, external_liveness);
+        inc_new_counter!("crdt-pull-response-blobs", rsps.len());
+        let mut blobs = Vec::with_capacity(rsps.len());
+        for rsp in rsps {
+            match to_blob(rsp, addr, &blob_recycler) {
+                Ok(r) => blobs.push(r),
+                Err(_) => {
+                    warn!("to_blob failed");
+                    return vec![];
+                }
             }
         }
+        trace!(
+            "sending updates me {:x} len {} in {} blobs to {:x} {}",
+            obj.read().unwrap().debug_id(),
+            len,
+            blobs.len(),
+            from_rd.debug_id(),
+            addr,
+        );
+        blobs
     }
 
     fn handle_protocol(
@@ -997,56 +1381,18 @@ impl Crdt {
         obj: &Arc<RwLock<Self>>,
         window: &Window,
         blob_recycler: &BlobRecycler,
-    ) -> Option<SharedBlob> {
+    ) -> Vec<SharedBlob> {
         match request {
             // TODO sigverify these
             Protocol::RequestUpdates(v, from_rd) => {
-                let addr = from_rd.contact_info.ncp;
-                trace!("RequestUpdates {} from {}", v, addr);
-                let me = obj.read().unwrap();
-                if addr == me.table[&me.me].contact_info.ncp {
-                    warn!(
-                        "RequestUpdates ignored, I'm talking to myself: me={:x} remoteme={:x}",
-                        me.debug_id(),
-                        make_debug_id(&from_rd.id)
-                    );
-                    inc_new_counter!("crdt-window-request-loopback", 1);
-                    return None;
-                }
-                // only lock for these two calls, dont lock during IO `sock.send_to` or `sock.recv_from`
-                let (from, ups, data) = me.get_updates_since(v);
-                let external_liveness = me.remote.iter().map(|(k, v)| (*k, *v)).collect();
-                drop(me);
-                trace!("get updates since response {} {}", v, data.len());
-                let len = data.len();
-                let rsp = Protocol::ReceiveUpdates(from, ups, data, external_liveness);
-                {
-                    let mut me = obj.write().unwrap();
-                    me.insert(&from_rd);
-                    me.update_liveness(from_rd.id);
-                }
-                if len < 1 {
-                    let me = obj.read().unwrap();
-                    trace!(
-                        "no updates me {:x} ix {} since {}",
-                        me.debug_id(),
-                        me.update_index,
-                        v
-                    );
-                    None
-                } else if let Ok(r) = to_blob(rsp, addr, &blob_recycler) {
-                    trace!(
-                        "sending updates me {:x} len {} to {:x} {}",
-                        obj.read().unwrap().debug_id(),
-                        len,
-                        from_rd.debug_id(),
-                        addr,
-                    );
-                    Some(r)
-                } else {
-                    warn!("to_blob failed");
-                    None
-                }
+                trace!("RequestUpdates {} from {}", v, from_rd.contact_info.ncp);
+                Self::handle_pull(obj, from_rd, blob_recycler, |me| me.get_updates_since(v))
+            }
+            Protocol::PullRequest(bloom, from_rd) => {
+                trace!("PullRequest from {}", from_rd.contact_info.ncp);
+                Self::handle_pull(obj, from_rd, blob_recycler, |me| {
+                    me.get_updates_missing(&bloom)
+                })
             }
             Protocol::ReceiveUpdates(from, update_index, data, external_liveness) => {
                 trace!(
@@ -1058,8 +1404,14 @@ impl Crdt {
                 obj.write()
                     .expect("'obj' write lock in ReceiveUpdates")
                     .apply_updates(from, update_index, &data, &external_liveness);
-                None
+                vec![]
+            }
+            Protocol::RequestIpEcho => {
+                warn!("RequestIpEcho without the address it came from");
+                vec![]
             }
+            // answers to our own requests are read off their sockets
+            Protocol::IpEcho(_) => vec![],
             Protocol::RequestWindowIndex(from, ix) => {
                 //TODO this doesn't depend on CRDT module, can be moved
                 //but we are using the listen thread to service these request
@@ -1081,9 +1433,25 @@ impl Crdt {
                         ix,
                     );
                     inc_new_counter!("crdt-window-request-address-eq", 1);
-                    return None;
+                    return vec![];
                 }
-                Self::run_window_request(&window, &me, &from, ix, blob_recycler)
+                let from = NodeInfo {
+                    contact_info: from.contact_info.reachable_from(&me.contact_info),
+                    ..from
+                };
+                let ledger_requests = {
+                    let me = obj.read().unwrap();
+                    me.ledger.as_ref().and(me.ledger_requests.clone())
+                };
+                Self::run_window_request(
+                    &window,
+                    ledger_requests.as_ref(),
+                    &me,
+                    &from,
+                    ix,
+                    blob_recycler,
+                ).into_iter()
+                    .collect()
             }
         }
     }
@@ -1103,7 +1471,7 @@ impl Crdt {
             reqs.append(&mut more);
         }
         let resp: VecDeque<_> = reqs.iter()
-            .filter_map(|b| Self::handle_blob(obj, window, blob_recycler, &b.read().unwrap()))
+            .flat_map(|b| Self::handle_blob(obj, window, blob_recycler, &b.read().unwrap()))
             .collect();
         response_sender.send(resp)?;
         while let Some(r) = reqs.pop_front() {
@@ -1111,6 +1479,44 @@ impl Crdt {
         }
         Ok(())
     }
+    /// Serve the repair requests queued by `listen` from the ledger, so that
+    /// reading it doesn't hold up the listen thread.
+    fn run_ledger_repair(
+        obj: &Arc<RwLock<Self>>,
+        blob_recycler: &BlobRecycler,
+        requests: &Receiver<(NodeInfo, u64)>,
+        response_sender: &BlobSender,
+    ) -> Result<()> {
+        let timeout = Duration::new(1, 0);
+        let (from, ix) = requests.recv_timeout(timeout)?;
+        let ledger = obj.read().unwrap().ledger.clone();
+        if let Some(ledger) = ledger {
+            if let Some(blob) = Self::run_ledger_request(&ledger, &from, ix, blob_recycler) {
+                let mut resp = VecDeque::new();
+                resp.push_back(blob);
+                response_sender.send(resp)?;
+            }
+        }
+        Ok(())
+    }
+    pub fn ledger_repair(
+        obj: Arc<RwLock<Self>>,
+        blob_recycler: BlobRecycler,
+        response_sender: BlobSender,
+        exit: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        let (sender, requests) = sync_channel(MAX_LEDGER_REQUESTS);
+        obj.write().unwrap().ledger_requests = Some(sender);
+        Builder::new()
+            .name("solana-ledger-repair".to_string())
+            .spawn(move || loop {
+                let _ = Self::run_ledger_repair(&obj, &blob_recycler, &requests, &response_sender);
+                if exit.load(Ordering::Relaxed) {
+                    return;
+                }
+            })
+            .unwrap()
+    }
     pub fn listen(
         obj: Arc<RwLock<Self>>,
         window: Window,
@@ -1151,6 +1557,8 @@ pub struct Sockets {
     pub requests: UdpSocket,
     pub replicate: UdpSocket,
     pub transaction: UdpSocket,
+    /// TCP listener on the same port as `transaction`, for transactions too large for a packet
+    pub transaction_stream: TcpListener,
     pub respond: UdpSocket,
     pub broadcast: UdpSocket,
     pub repair: UdpSocket,
@@ -1167,17 +1575,35 @@ impl TestNode {
         let pubkey = KeyPair::new().pubkey();
         Self::new_localhost_with_pubkey(pubkey)
     }
+    /// A node on the IPv6 loopback address
+    pub fn new_localhost_ipv6() -> Self {
+        let pubkey = KeyPair::new().pubkey();
+        Self::new_with_ip(pubkey, IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)))
+    }
+    /// Bind a UDP socket and a TCP listener that share one port at `ip`
+    fn udp_tcp_bind(ip: IpAddr) -> (UdpSocket, TcpListener) {
+        loop {
+            let udp = UdpSocket::bind((ip, 0)).unwrap();
+            if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()) {
+                return (udp, tcp);
+            }
+        }
+    }
     pub fn new_localhost_with_pubkey(pubkey: PublicKey) -> Self {
-        let transaction = UdpSocket::bind("127.0.0.1:0").unwrap();
-        let gossip = UdpSocket::bind("127.0.0.1:0").unwrap();
-        let replicate = UdpSocket::bind("127.0.0.1:0").unwrap();
-        let requests = UdpSocket::bind("127.0.0.1:0").unwrap();
-        let repair = UdpSocket::bind("127.0.0.1:0").unwrap();
-
-        let gossip_send = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let respond = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let broadcast = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let retransmit = UdpSocket::bind("0.0.0.0:0").unwrap();
+        Self::new_with_ip(pubkey, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)))
+    }
+    fn new_with_ip(pubkey: PublicKey, ip: IpAddr) -> Self {
+        let (transaction, transaction_stream) = Self::udp_tcp_bind(ip);
+        let gossip = UdpSocket::bind((ip, 0)).unwrap();
+        let replicate = UdpSocket::bind((ip, 0)).unwrap();
+        let requests = UdpSocket::bind((ip, 0)).unwrap();
+        let repair = UdpSocket::bind((ip, 0)).unwrap();
+
+        let any = unspecified_ip(ip.is_ipv6());
+        let gossip_send = UdpSocket::bind((any, 0)).unwrap();
+        let respond = UdpSocket::bind((any, 0)).unwrap();
+        let broadcast = UdpSocket::bind((any, 0)).unwrap();
+        let retransmit = UdpSocket::bind((any, 0)).unwrap();
         let data = NodeInfo::new(
             pubkey,
             gossip.local_addr().unwrap(),
@@ -1194,6 +1620,7 @@ impl TestNode {
                 requests,
                 replicate,
                 transaction,
+                transaction_stream,
                 respond,
                 broadcast,
                 repair,
@@ -1218,6 +1645,7 @@ impl TestNode {
         local_repair_addr.set_port(data.contact_info.tvu_window.port());
 
         let transaction = UdpSocket::bind(local_transactions_addr).unwrap();
+        let transaction_stream = TcpListener::bind(local_transactions_addr).unwrap();
         let gossip = UdpSocket::bind(local_gossip_addr).unwrap();
         let replicate = UdpSocket::bind(local_replicate_addr).unwrap();
         let repair = UdpSocket::bind(local_repair_addr).unwrap();
@@ -1228,9 +1656,10 @@ impl TestNode {
         // response Udp packet correctly back to the requester.
         let respond = requests.try_clone().unwrap();
 
-        let gossip_send = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let broadcast = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let retransmit = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let any = unspecified_ip(bind_addr.is_ipv6());
+        let gossip_send = UdpSocket::bind((any, 0)).unwrap();
+        let broadcast = UdpSocket::bind((any, 0)).unwrap();
+        let retransmit = UdpSocket::bind((any, 0)).unwrap();
         TestNode {
             data,
             sockets: Sockets {
@@ -1239,6 +1668,7 @@ impl TestNode {
                 requests,
                 replicate,
                 transaction,
+                transaction_stream,
                 respond,
                 broadcast,
                 repair,
@@ -1254,18 +1684,28 @@ mod tests {
         parse_port_or_addr, Crdt, CrdtError, NodeInfo, Protocol, GOSSIP_PURGE_MILLIS,
         GOSSIP_SLEEP_MILLIS, MIN_TABLE_SIZE,
     };
-    use hash::Hash;
+    use bank::Bank;
+    use bincode::{deserialize, serialize, serialized_size};
+    use broadcast_tree::{BLOBS_PER_TREE, DEFAULT_FANOUT};
+    use choose_gossip_peer_strategy::GossipStrategy;
+    use entry::Entry;
+    use entry_writer::EntryWriter;
+    use hash::{hash, Hash};
     use logger;
-    use packet::BlobRecycler;
+    use mint::Mint;
+    use packet::{to_blob, BlobRecycler, BLOB_DATA_SIZE};
     use result::Error;
+    use segmented_ledger::{SegmentConfig, SegmentedLedger};
     use signature::{KeyPair, KeyPairUtil, PublicKey};
+    use std::fs;
+    use std::net::SocketAddr;
     use std::sync::atomic::{AtomicBool, Ordering};
-    use std::sync::mpsc::channel;
+    use std::sync::mpsc::{channel, sync_channel};
     use std::sync::{Arc, RwLock};
     use std::thread::sleep;
     use std::time::Duration;
     use streamer::default_window;
-    use transaction::Vote;
+    use transaction::{Transaction, Vote};
 
     #[test]
     fn test_parse_port_or_addr() {
@@ -1276,6 +1716,27 @@ mod tests {
         let p3 = parse_port_or_addr(None);
         assert_eq!(p3.port(), 8000);
     }
+    #[test]
+    fn test_reachable_from() {
+        let v4 = NodeInfo::new_leader(&"10.0.0.1:8000".parse().unwrap()).contact_info;
+        let v6 = NodeInfo::new_leader(&"[2001:db8::1]:8000".parse().unwrap()).contact_info;
+        let mut dual = NodeInfo::new_leader(&"10.0.0.2:8000".parse().unwrap()).contact_info;
+        dual.alt_ip = Some("2001:db8::2".parse().unwrap());
+
+        // nodes that share the primary family use it
+        assert_eq!(dual.reachable_from(&v4), dual);
+        assert_eq!(dual.reachable_from(&dual), dual);
+
+        // an IPv6 only node reaches the dual stack one at its IPv6 address, on the same ports
+        let ci = dual.reachable_from(&v6);
+        assert_eq!(ci.ncp, "[2001:db8::2]:8001".parse().unwrap());
+        assert_eq!(ci.tpu, "[2001:db8::2]:8000".parse().unwrap());
+        assert_eq!(ci.tvu_window, "[2001:db8::2]:8004".parse().unwrap());
+
+        // and neither address helps when the families don't meet
+        assert_eq!(v6.reachable_from(&v4), v6);
+    }
+
     #[test]
     fn test_bad_address() {
         let d1 = NodeInfo::new(
@@ -1726,6 +2187,51 @@ mod tests {
         assert!(one && two);
     }
 
+    /// A validator at `addr` that voted with `stake` tokens from the mint
+    fn new_voter(bank: &Bank, mint: &Mint, stake: i64, addr: &str) -> NodeInfo {
+        let keypair = KeyPair::new();
+        bank.transfer(stake, &mint.keypair(), keypair.pubkey(), mint.last_id())
+            .unwrap();
+        let vote = Vote {
+            version: 0,
+            contact_info_version: 0,
+        };
+        let vote_tx = Transaction::new_vote(&keypair, vote, mint.last_id(), 0);
+        bank.process_transaction(&vote_tx).unwrap();
+        NodeInfo::new_leader_with_pubkey(keypair.pubkey(), &addr.parse().unwrap())
+    }
+
+    #[test]
+    fn gossip_request_strategies() {
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let unstaked = NodeInfo::new_leader(&"127.0.0.3:1234".parse().unwrap());
+        let mint = Mint::new(1000);
+        let bank = Bank::new(&mint);
+        let staked = new_voter(&bank, &mint, 500, "127.0.0.2:1234");
+        bank.transfer(200, &mint.keypair(), unstaked.id, mint.last_id())
+            .unwrap();
+        let mut crdt = Crdt::new(me).expect("Crdt::new");
+        crdt.set_bank(Arc::new(bank));
+        crdt.insert(&staked);
+        crdt.insert(&unstaked);
+        // only the tokens of an account that voted are at stake
+        assert_eq!(crdt.get_stake(staked.id), 500);
+        assert_eq!(crdt.get_stake(unstaked.id), 0);
+
+        let count = |crdt: &Crdt| {
+            (0..1000)
+                .filter(|_| crdt.gossip_request().unwrap().0 == staked.contact_info.ncp)
+                .count()
+        };
+        // the weighted strategy picks the staked peer about three times as
+        // often, the random one about as often as the other
+        crdt.set_gossip_strategy(GossipStrategy::Weighted);
+        assert!(count(&crdt) > 650);
+        crdt.set_gossip_strategy(GossipStrategy::Random);
+        let random_count = count(&crdt);
+        assert!(random_count > 350 && random_count < 650);
+    }
+
     #[test]
     fn purge_test() {
         logger::setup();
@@ -1804,18 +2310,18 @@ mod tests {
             "127.0.0.1:1238".parse().unwrap(),
         );
         let recycler = BlobRecycler::default();
-        let rv = Crdt::run_window_request(&window, &me, &me, 0, &recycler);
+        let rv = Crdt::run_window_request(&window, None, &me, &me, 0, &recycler);
         assert!(rv.is_none());
         let out = recycler.allocate();
         out.write().unwrap().meta.size = 200;
         window.write().unwrap()[0] = Some(out);
-        let rv = Crdt::run_window_request(&window, &me, &me, 0, &recycler);
+        let rv = Crdt::run_window_request(&window, None, &me, &me, 0, &recycler);
         assert!(rv.is_some());
         let v = rv.unwrap();
         //test we copied the blob
         assert_eq!(v.read().unwrap().meta.size, 200);
         let len = window.read().unwrap().len() as u64;
-        let rv = Crdt::run_window_request(&window, &me, &me, len, &recycler);
+        let rv = Crdt::run_window_request(&window, None, &me, &me, len, &recycler);
         assert!(rv.is_none());
     }
 
@@ -1832,7 +2338,7 @@ mod tests {
         let recycler = BlobRecycler::default();
 
         // Simulate handling a repair request from mock_peer
-        let rv = Crdt::run_window_request(&window, &me, &mock_peer, 0, &recycler);
+        let rv = Crdt::run_window_request(&window, None, &me, &mock_peer, 0, &recycler);
         assert!(rv.is_none());
         let blob = recycler.allocate();
         let blob_size = 200;
@@ -1842,7 +2348,7 @@ mod tests {
         let num_requests: u32 = 64;
         for i in 0..num_requests {
             let shared_blob =
-                Crdt::run_window_request(&window, &me, &mock_peer, 0, &recycler).unwrap();
+                Crdt::run_window_request(&window, None, &me, &mock_peer, 0, &recycler).unwrap();
             let blob = shared_blob.read().unwrap();
             // Test we copied the blob
             assert_eq!(blob.meta.size, blob_size);
@@ -1855,6 +2361,196 @@ mod tests {
             assert_eq!(blob.get_id().unwrap(), id);
         }
     }
+    /// test window requests that miss the window are queued for the ledger, and
+    /// dropped once the queue is full
+    #[test]
+    #[cfg(not(feature = "erasure"))]
+    fn run_window_request_queues_ledger_request() {
+        let window = default_window();
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let mock_peer = NodeInfo::new_leader(&"127.0.0.1:1235".parse().unwrap());
+        let recycler = BlobRecycler::default();
+        let (sender, receiver) = sync_channel(1);
+
+        let rv = Crdt::run_window_request(&window, Some(&sender), &me, &mock_peer, 1, &recycler);
+        assert!(rv.is_none());
+        let rv = Crdt::run_window_request(&window, Some(&sender), &me, &mock_peer, 2, &recycler);
+        assert!(rv.is_none());
+        assert_eq!(receiver.try_recv().unwrap(), (mock_peer.clone(), 1));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    /// test repair requests are served from the ledger by its own thread
+    #[test]
+    fn ledger_repair() {
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let mock_peer = NodeInfo::new_leader(&"127.0.0.1:1235".parse().unwrap());
+        let recycler = BlobRecycler::default();
+
+        let dir = "target/test_crdt_ledger_repair";
+        let _ = fs::remove_dir_all(dir);
+        let config = SegmentConfig {
+            entries_per_segment: 1,
+            ..SegmentConfig::default()
+        };
+        let ledger = SegmentedLedger::open(dir, config).unwrap();
+        let mint = Mint::new(1);
+        let mut entries = mint.create_entries();
+        let tick = Entry::new_tick(0, &mint.last_id());
+        entries.push(tick);
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries.clone()).unwrap();
+        }
+
+        let mut crdt = Crdt::new(me).unwrap();
+        crdt.set_ledger(ledger.clone());
+        let obj = Arc::new(RwLock::new(crdt));
+        let exit = Arc::new(AtomicBool::new(false));
+        let (response_sender, response_receiver) = channel();
+        let t_repair =
+            Crdt::ledger_repair(obj.clone(), recycler.clone(), response_sender, exit.clone());
+        let ledger_requests = obj.read().unwrap().ledger_requests.clone().unwrap();
+
+        ledger_requests.send((mock_peer.clone(), 1)).unwrap();
+        let mut blobs = response_receiver.recv().unwrap();
+        let blob = blobs.pop_front().unwrap();
+        {
+            let blob = blob.read().unwrap();
+            assert_eq!(blob.get_index().unwrap(), 1);
+            assert_eq!(blob.get_id().unwrap(), mock_peer.id);
+            assert_eq!(blob.meta.addr(), mock_peer.contact_info.tvu_window);
+            let entry: Entry = deserialize(blob.data()).unwrap();
+            assert_eq!(entry, entries[1]);
+        }
+
+        // heights that have been pruned are refused
+        assert_eq!(ledger.prune(3).unwrap(), 2);
+        assert!(Crdt::run_ledger_request(&ledger, &mock_peer, 1, &recycler).is_none());
+        exit.store(true, Ordering::Relaxed);
+        t_repair.join().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_blob_loss() {
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let mut crdt = Crdt::new(me.clone()).unwrap();
+        assert_eq!(crdt.blob_loss(), None);
+
+        // our own loss isn't counted, it's for the leader to read
+        crdt.set_blob_loss(500);
+        assert_eq!(crdt.my_data().version, 1);
+        crdt.set_blob_loss(500);
+        assert_eq!(crdt.my_data().version, 1);
+        assert_eq!(crdt.blob_loss(), None);
+
+        for loss in &[1_000, 20_000] {
+            let mut v = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+            v.ledger_state.blob_loss = Some(*loss);
+            crdt.insert(&v);
+        }
+        assert_eq!(crdt.blob_loss(), Some(20_000));
+    }
+
+    #[test]
+    fn test_broadcast_peers() {
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let mut crdt = Crdt::new(me.clone()).unwrap();
+        let leader = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+        let peer = NodeInfo::new_leader(&"127.0.0.3:1234".parse().unwrap());
+        crdt.insert(&leader);
+        crdt.insert(&peer);
+        crdt.insert(&NodeInfo::new_unspecified());
+        crdt.insert(&NodeInfo::new_entry_point(
+            "127.0.0.4:1234".parse().unwrap(),
+        ));
+
+        // validators retransmitting a blob are in its tree too
+        let mut ids: Vec<_> = crdt.broadcast_peers(leader.id)
+            .into_iter()
+            .map(|(v, stake)| {
+                assert_eq!(stake, 1);
+                v.id
+            })
+            .collect();
+        ids.sort();
+        let mut expected = vec![me.id, peer.id];
+        expected.sort();
+        assert_eq!(ids, expected);
+
+        let table = crdt.compute_broadcast_table();
+        assert_eq!(table.len(), 2);
+        assert!(table.iter().all(|(v, _)| v.id != me.id));
+        assert_eq!(crdt.leader_fanout(leader.id), DEFAULT_FANOUT);
+    }
+
+    #[test]
+    fn test_tree_stakes_agree() {
+        let mint = Mint::new(1000);
+        let bank = Arc::new(Bank::new(&mint));
+        let leader = new_voter(&bank, &mint, 100, "127.0.0.1:1234");
+        let mut nodes: Vec<_> = (0..8)
+            .map(|i| new_voter(&bank, &mint, 1 + i % 3, &format!("127.0.0.{}:1234", i + 2)))
+            .collect();
+        nodes.push(NodeInfo::new_leader(&"127.0.0.10:1234".parse().unwrap()));
+        for v in &mut nodes {
+            v.fanout = 2;
+        }
+        let me0 = nodes[0].clone();
+        let me1 = nodes[1].clone();
+        let others: Vec<_> = nodes[2..].to_vec();
+
+        // one node knows the bank before it knows the others, the other only after
+        // it learned of them in the opposite order and through a change of leader
+        let mut crdt0 = Crdt::new(me0.clone()).unwrap();
+        crdt0.set_bank(bank.clone());
+        crdt0.set_leader(leader.id);
+        crdt0.insert(&leader);
+        crdt0.insert(&me1);
+        for v in &others {
+            crdt0.insert(v);
+        }
+        let mut crdt1 = Crdt::new(me1.clone()).unwrap();
+        for v in others.iter().rev() {
+            crdt1.insert(v);
+        }
+        crdt1.set_leader(KeyPair::new().pubkey());
+        crdt1.set_bank(bank.clone());
+        crdt1.insert(&me0);
+        crdt1.insert(&leader);
+        crdt1.set_leader(leader.id);
+
+        let children = |crdt: &Crdt, index: u64, id: &PublicKey| -> Vec<PublicKey> {
+            let tree = crdt.retransmit_tree(leader.id, index);
+            tree.children(id).iter().map(|v| v.id).collect()
+        };
+        let assert_agree = |index: u64| {
+            for id in nodes.iter().map(|v| v.id).chain(Some(leader.id)) {
+                assert_eq!(children(&crdt0, index, &id), children(&crdt1, index, &id));
+            }
+        };
+        assert_agree(0);
+
+        // once the first stake epoch starts, the largest stakes come first
+        let mut i = 0u64;
+        while bank.stake_epoch() == 0 {
+            bank.register_entry_id(&hash(&serialize(&i).unwrap()));
+            i += 1;
+        }
+        assert_agree(0);
+        let first = children(&crdt0, 0, &leader.id)[0];
+        assert_eq!(bank.epoch_stake(&first), 3);
+
+        // and stay put when stakes change during the epoch
+        bank.transfer(100, &mint.keypair(), nodes[0].id, bank.last_id())
+            .unwrap();
+        assert_eq!(bank.stake(&nodes[0].id), 101);
+        let first = children(&crdt0, BLOBS_PER_TREE, &leader.id)[0];
+        assert_eq!(bank.epoch_stake(&first), 3);
+        assert_agree(BLOBS_PER_TREE);
+    }
+
     /// TODO: This is obviously the wrong way to do this. Need to implement leader selection,
     /// delete this test after leader selection is correctly implemented
     #[test]
@@ -1902,10 +2598,10 @@ mod tests {
         let obj = Arc::new(RwLock::new(crdt));
 
         let request = Protocol::RequestUpdates(1, node.clone());
-        assert!(Crdt::handle_protocol(request, &obj, &window, &recycler).is_none());
+        assert!(Crdt::handle_protocol(request, &obj, &window, &recycler).is_empty());
 
         let request = Protocol::RequestUpdates(1, node_with_same_addr.clone());
-        assert!(Crdt::handle_protocol(request, &obj, &window, &recycler).is_none());
+        assert!(Crdt::handle_protocol(request, &obj, &window, &recycler).is_empty());
 
         let request = Protocol::RequestUpdates(1, node_with_diff_addr.clone());
         Crdt::handle_protocol(request, &obj, &window, &recycler);
@@ -1918,4 +2614,94 @@ mod tests {
         assert!(!me.alive.contains_key(&node_with_same_addr.id));
         assert!(me.alive[&node_with_diff_addr.id] > 0);
     }
+
+    #[test]
+    fn split_updates_fit_in_blobs() {
+        let from = KeyPair::new().pubkey();
+        let data: Vec<_> = (0..1000)
+            .map(|i| NodeInfo::new_leader(&SocketAddr::from(([127, 0, 0, 1], i))))
+            .collect();
+        let liveness: Vec<_> = data.iter().map(|v| (v.id, 1)).collect();
+        let rsps = Crdt::split_updates(from, 7, data.clone(), liveness.clone());
+        assert!(rsps.len() > 1);
+        let mut all_data = vec![];
+        let mut all_liveness = vec![];
+        for rsp in rsps {
+            assert!(serialized_size(&rsp).unwrap() <= BLOB_DATA_SIZE as u64);
+            if let Protocol::ReceiveUpdates(id, ix, mut data, mut liveness) = rsp {
+                assert_eq!((id, ix), (from, 7));
+                all_data.append(&mut data);
+                all_liveness.append(&mut liveness);
+            } else {
+                panic!("not ReceiveUpdates");
+            }
+        }
+        assert_eq!(all_data, data);
+        assert_eq!(all_liveness, liveness);
+
+        let rsps = Crdt::split_updates(from, 7, vec![], vec![]);
+        assert_eq!(rsps.len(), 1);
+    }
+
+    /// A pull request is only answered with the entries the requester is missing
+    #[test]
+    fn pull_request_missing_updates() {
+        logger::setup();
+        let window = default_window();
+        let recycler = BlobRecycler::default();
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let requester = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+        let mut crdt = Crdt::new(me.clone()).unwrap();
+        let mut requester_crdt = Crdt::new(requester.clone()).unwrap();
+        requester_crdt.insert(&me);
+        for i in 0..500 {
+            let v = NodeInfo::new_leader(&SocketAddr::from(([127, 0, 1, 1], i)));
+            crdt.insert(&v);
+            if i % 2 == 0 {
+                requester_crdt.insert(&v);
+            }
+        }
+        let obj = Arc::new(RwLock::new(crdt));
+        let requester_obj = Arc::new(RwLock::new(requester_crdt));
+
+        let (addr, req) = requester_obj.read().unwrap().gossip_request().unwrap();
+        let req = to_blob(req, addr, &recycler).unwrap();
+        let rsps = Crdt::handle_blob(&obj, &window, &recycler, &req.read().unwrap());
+        let mut num_sent = 0;
+        for rsp in &rsps {
+            let rsp = rsp.read().unwrap();
+            match deserialize(&rsp.data[..rsp.meta.size]).unwrap() {
+                Protocol::ReceiveUpdates(_, _, data, _) => num_sent += data.len(),
+                _ => panic!("not ReceiveUpdates"),
+            }
+            Crdt::handle_blob(&requester_obj, &window, &recycler, &rsp);
+        }
+        // the 250 the requester doesn't have, but few of the 252 it does
+        assert!(num_sent >= 250 && num_sent < 350, "{}", num_sent);
+
+        let table = &obj.read().unwrap().table;
+        let requester_table = &requester_obj.read().unwrap().table;
+        assert!(table.keys().all(|id| requester_table.contains_key(id)));
+    }
+
+    #[test]
+    fn ip_echo_request() {
+        let window = default_window();
+        let recycler = BlobRecycler::default();
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let obj = Arc::new(RwLock::new(Crdt::new(me).unwrap()));
+        let from: SocketAddr = "127.0.0.5:1234".parse().unwrap();
+
+        let req = to_blob(Protocol::RequestIpEcho, from, &recycler).unwrap();
+        let rsps = Crdt::handle_blob(&obj, &window, &recycler, &req.read().unwrap());
+
+        // one answer, to the address the request came from and nowhere else
+        assert_eq!(rsps.len(), 1);
+        let rsp = rsps[0].read().unwrap();
+        assert_eq!(rsp.meta.addr(), from);
+        match deserialize(&rsp.data[..rsp.meta.size]).unwrap() {
+            Protocol::IpEcho(addr) => assert_eq!(addr, from),
+            _ => panic!("not IpEcho"),
+        }
+    }
 }
@@ -1,22 +1,46 @@
 //! The `drone` module provides an object for launching a Solana Drone,
 //! which is the custodian of any remaining tokens in a mint.
 //! The Solana Drone builds and send airdrop transactions,
-//! checking requests against a request cap for a given time time_slice
-//! and (to come) an IP rate limit.
+//! checking requests against a request cap for a given time time_slice,
+//! and against a token bucket for each client IP and each recipient key.
+//! The buckets can be saved to a file now and then, so restarting the drone
+//! doesn't refill them.
+//!
+//! The drone answers each request with a `DroneResponse`, which carries the
+//! signature of the airdrop transaction or the reason there is none. A client
+//! may ask the drone to wait until the leader confirms the transaction before
+//! answering.
 
 use influx_db_client as influxdb;
 use metrics;
-use signature::{KeyPair, PublicKey};
+use net_utils::udp_bind;
+use serde_json;
+use signature::{KeyPair, PublicKey, Signature};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
 use std::io;
 use std::io::{Error, ErrorKind};
-use std::net::{IpAddr, SocketAddr, UdpSocket};
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use thin_client::ThinClient;
+use timing::{duration_as_ms, timestamp};
 use transaction::Transaction;
 
 pub const TIME_SLICE: u64 = 60;
 pub const REQUEST_CAP: u64 = 1_000_000;
 pub const DRONE_PORT: u16 = 9900;
+/// Seconds over which a client IP or recipient key gets its quota, unless configured otherwise
+pub const QUOTA_PERIOD: u64 = 3600;
+/// Tokens a client IP or recipient key gets per `QUOTA_PERIOD`, unless configured
+/// otherwise: one client-demo airdrop, against the `REQUEST_CAP` every `TIME_SLICE`
+/// that all clients share
+pub const QUOTA_TOKENS: u64 = 500_000;
+/// Seconds to wait for the leader to confirm an airdrop before giving up
+pub const CONFIRMATION_TIMEOUT: u64 = 30;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum DroneRequest {
@@ -24,11 +48,332 @@ pub enum DroneRequest {
         airdrop_request_amount: u64,
         client_public_key: PublicKey,
     },
+    /// A `GetAirdrop` answered only once the leader confirmed the airdrop
+    /// transaction. It's a variant of its own, so the requests of clients that
+    /// don't know about it read as they always did.
+    GetConfirmedAirdrop {
+        airdrop_request_amount: u64,
+        client_public_key: PublicKey,
+    },
+}
+
+impl DroneRequest {
+    /// The tokens asked for and the key to send them to
+    pub fn airdrop(&self) -> (u64, PublicKey) {
+        match *self {
+            DroneRequest::GetAirdrop {
+                airdrop_request_amount,
+                client_public_key,
+            }
+            | DroneRequest::GetConfirmedAirdrop {
+                airdrop_request_amount,
+                client_public_key,
+            } => (airdrop_request_amount, client_public_key),
+        }
+    }
+
+    /// Whether the client waits for the leader to confirm the airdrop
+    pub fn confirm(&self) -> bool {
+        match *self {
+            DroneRequest::GetAirdrop { .. } => false,
+            DroneRequest::GetConfirmedAirdrop { .. } => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DroneError {
+    /// the drone handed out all the tokens it may in this time slice
+    RequestCapReached,
+    /// the client IP used up its quota
+    IpRateLimited,
+    /// the recipient key used up its quota
+    KeyRateLimited,
+    /// the request couldn't be read
+    BadRequest,
+    /// the airdrop transaction couldn't be sent to the leader
+    SendFailed(String),
+    /// the leader didn't confirm the airdrop transaction in time
+    NotConfirmed(Signature),
+}
+
+impl fmt::Display for DroneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DroneError::RequestCapReached => write!(f, "request limit reached for this time slice"),
+            DroneError::IpRateLimited => write!(f, "airdrop quota of this address used up"),
+            DroneError::KeyRateLimited => write!(f, "airdrop quota of this key used up"),
+            DroneError::BadRequest => write!(f, "bad request"),
+            DroneError::SendFailed(e) => write!(f, "airdrop failed: {}", e),
+            DroneError::NotConfirmed(sig) => write!(f, "airdrop {:?} wasn't confirmed", sig),
+        }
+    }
+}
+
+impl ::std::error::Error for DroneError {
+    fn description(&self) -> &str {
+        "drone error"
+    }
+}
+
+/// The drone's answer to a `DroneRequest`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DroneResponse {
+    /// the airdrop transaction with this signature was sent to the leader
+    Sent(Signature),
+    /// the leader confirmed the airdrop transaction with this signature
+    Confirmed(Signature),
+    Error(DroneError),
+}
+
+/// Poll the leader behind `client` until it confirms the transaction
+/// `signature`, for up to `timeout`. Each poll waits no longer than the read
+/// timeout of the client's requests socket, so an unreachable leader can't
+/// hold it up past `timeout` either.
+pub fn confirm_airdrop(
+    client: &mut ThinClient,
+    signature: Signature,
+    timeout: Duration,
+) -> DroneResponse {
+    let now = Instant::now();
+    loop {
+        match client.try_check_signature(&signature) {
+            Ok(true) => return DroneResponse::Confirmed(signature),
+            Ok(false) => (),
+            Err(e) => debug!("airdrop {:?} confirmation: {}", signature, e),
+        }
+        if now.elapsed() > timeout {
+            return DroneResponse::Error(DroneError::NotConfirmed(signature));
+        }
+        sleep(Duration::from_millis(100));
+    }
+}
+
+/// How many tokens a client may get per `period`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub tokens: u64,
+    pub period: Duration,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota {
+            tokens: QUOTA_TOKENS,
+            period: Duration::new(QUOTA_PERIOD, 0),
+        }
+    }
+}
+
+/// The tokens a client may still get, refilled at the rate of its quota
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct TokenBucket {
+    tokens: f64,
+    /// timestamp in milliseconds of the last refill
+    last_refill: u64,
+}
+
+impl TokenBucket {
+    fn full(quota: &Quota, now: u64) -> Self {
+        TokenBucket {
+            tokens: quota.tokens as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, quota: &Quota, now: u64) {
+        let elapsed = now.saturating_sub(self.last_refill) as f64;
+        let rate = quota.tokens as f64 / duration_as_ms(&quota.period) as f64;
+        self.tokens = (self.tokens + elapsed * rate).min(quota.tokens as f64);
+        self.last_refill = now;
+    }
+
+    fn is_full(&self, quota: &Quota) -> bool {
+        self.tokens >= quota.tokens as f64
+    }
+
+    fn refund(&mut self, quota: &Quota, amount: u64) {
+        self.tokens = (self.tokens + amount as f64).min(quota.tokens as f64);
+    }
+}
+
+/// What the rate limiter saves to its state file, buckets that aren't full
+#[derive(Serialize, Deserialize, Default)]
+struct RateLimitState {
+    ips: Vec<(IpAddr, TokenBucket)>,
+    keys: Vec<(PublicKey, TokenBucket)>,
+}
+
+/// Per client IP and per recipient key token buckets
+pub struct RateLimiter {
+    ip_quota: Quota,
+    key_quota: Quota,
+    /// client IPs that no quota applies to
+    allowed_ips: HashSet<IpAddr>,
+    ips: HashMap<IpAddr, TokenBucket>,
+    keys: HashMap<PublicKey, TokenBucket>,
+    /// file the buckets are saved to
+    state_path: Option<PathBuf>,
+    /// whether the buckets changed since they were last saved
+    changed: bool,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Quota::default(), Quota::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(ip_quota: Quota, key_quota: Quota) -> Self {
+        RateLimiter {
+            ip_quota,
+            key_quota,
+            allowed_ips: HashSet::new(),
+            ips: HashMap::new(),
+            keys: HashMap::new(),
+            state_path: None,
+            changed: false,
+        }
+    }
+
+    /// A rate limiter that saves its buckets to `path`, starting from the
+    /// ones saved there if the file exists.
+    pub fn with_state(ip_quota: Quota, key_quota: Quota, path: &Path) -> io::Result<Self> {
+        let mut limiter = Self::new(ip_quota, key_quota);
+        if path.exists() {
+            let state: RateLimitState = serde_json::from_reader(File::open(path)?)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            limiter.ips = state.ips.into_iter().collect();
+            limiter.keys = state.keys.into_iter().collect();
+        }
+        limiter.state_path = Some(path.to_path_buf());
+        Ok(limiter)
+    }
+
+    pub fn allow_ip(&mut self, ip: IpAddr) {
+        self.allowed_ips.insert(ip);
+    }
+
+    fn ip_bucket(&mut self, ip: IpAddr, now: u64) -> &mut TokenBucket {
+        let quota = self.ip_quota;
+        let bucket = self
+            .ips
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::full(&quota, now));
+        bucket.refill(&quota, now);
+        bucket
+    }
+
+    fn key_bucket(&mut self, key: PublicKey, now: u64) -> &mut TokenBucket {
+        let quota = self.key_quota;
+        let bucket = self
+            .keys
+            .entry(key)
+            .or_insert_with(|| TokenBucket::full(&quota, now));
+        bucket.refill(&quota, now);
+        bucket
+    }
+
+    /// Whether `ip` may get `amount` tokens for `key` at the time `now`
+    pub fn check(
+        &mut self,
+        ip: IpAddr,
+        key: PublicKey,
+        amount: u64,
+        now: u64,
+    ) -> Result<(), DroneError> {
+        if self.allowed_ips.contains(&ip) {
+            return Ok(());
+        }
+        if self.ip_bucket(ip, now).tokens < amount as f64 {
+            return Err(DroneError::IpRateLimited);
+        }
+        if self.key_bucket(key, now).tokens < amount as f64 {
+            return Err(DroneError::KeyRateLimited);
+        }
+        Ok(())
+    }
+
+    /// Take `amount` tokens that `check` allowed out of the buckets
+    pub fn take(&mut self, ip: IpAddr, key: PublicKey, amount: u64, now: u64) {
+        if self.allowed_ips.contains(&ip) {
+            return;
+        }
+        self.ip_bucket(ip, now).tokens -= amount as f64;
+        self.key_bucket(key, now).tokens -= amount as f64;
+        self.changed = true;
+    }
+
+    /// Put back `amount` tokens that `take` took for an airdrop that failed
+    pub fn refund(&mut self, ip: IpAddr, key: PublicKey, amount: u64, now: u64) {
+        if self.allowed_ips.contains(&ip) {
+            return;
+        }
+        let (ip_quota, key_quota) = (self.ip_quota, self.key_quota);
+        self.ip_bucket(ip, now).refund(&ip_quota, amount);
+        self.key_bucket(key, now).refund(&key_quota, amount);
+        self.changed = true;
+    }
+
+    /// Write the buckets that aren't full at the time `now` to the state file,
+    /// if they changed since the last save
+    pub fn save(&mut self, now: u64) -> io::Result<()> {
+        let path = match &self.state_path {
+            Some(path) if self.changed => path.clone(),
+            _ => return Ok(()),
+        };
+        let (ip_quota, key_quota) = (self.ip_quota, self.key_quota);
+        for bucket in self.ips.values_mut() {
+            bucket.refill(&ip_quota, now);
+        }
+        for bucket in self.keys.values_mut() {
+            bucket.refill(&key_quota, now);
+        }
+        // a full bucket is no different from a missing one
+        self.ips.retain(|_, bucket| !bucket.is_full(&ip_quota));
+        self.keys.retain(|_, bucket| !bucket.is_full(&key_quota));
+        let state = RateLimitState {
+            ips: self.ips.iter().map(|(k, v)| (*k, v.clone())).collect(),
+            keys: self.keys.iter().map(|(k, v)| (*k, v.clone())).collect(),
+        };
+        let tmp_path = path.with_extension("tmp");
+        serde_json::to_writer(File::create(&tmp_path)?, &state)
+            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+        fs::rename(&tmp_path, &path)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// Send the airdrop transaction of `req` from `mint_keypair` to the leader
+/// behind `client`
+/// # Returns
+/// The signature of the transaction
+pub fn send_airdrop(
+    client: &mut ThinClient,
+    mint_keypair: &KeyPair,
+    req: DroneRequest,
+) -> Result<Signature, io::Error> {
+    let last_id = client.get_last_id();
+
+    let (request_amount, client_public_key) = req.airdrop();
+    info!(
+        "Requesting airdrop of {} to {:?}",
+        request_amount, client_public_key
+    );
+    let tx = Transaction::new(
+        mint_keypair,
+        client_public_key,
+        request_amount as i64,
+        last_id,
+    );
+    client.transfer_signed(&tx).map(|_| tx.sig)
 }
 
 pub struct Drone {
-    mint_keypair: KeyPair,
-    ip_cache: Vec<IpAddr>,
+    mint_keypair: Arc<KeyPair>,
+    pub rate_limiter: RateLimiter,
     _airdrop_addr: SocketAddr,
     transactions_addr: SocketAddr,
     requests_addr: SocketAddr,
@@ -55,8 +400,8 @@ impl Drone {
             None => REQUEST_CAP,
         };
         Drone {
-            mint_keypair,
-            ip_cache: Vec::new(),
+            mint_keypair: Arc::new(mint_keypair),
+            rate_limiter: RateLimiter::default(),
             _airdrop_addr,
             transactions_addr,
             requests_addr,
@@ -74,77 +419,107 @@ impl Drone {
         self.request_current = 0;
     }
 
-    pub fn add_ip_to_cache(&mut self, ip: IpAddr) {
-        self.ip_cache.push(ip);
+    /// Check a request from the client at `ip` against the limits and, if it
+    /// is within them, count it against them. An airdrop that isn't sent after
+    /// all is given back with `cancel`.
+    pub fn reserve(&mut self, ip: IpAddr, req: DroneRequest) -> Result<(), DroneError> {
+        let (airdrop_request_amount, client_public_key) = req.airdrop();
+        let now = timestamp();
+        let result = if !self.check_request_limit(airdrop_request_amount) {
+            Err(DroneError::RequestCapReached)
+        } else {
+            self.rate_limiter
+                .check(ip, client_public_key, airdrop_request_amount, now)
+        };
+        let decision = match &result {
+            Ok(()) if self.rate_limiter.allowed_ips.contains(&ip) => "allow_listed",
+            Ok(()) => "allowed",
+            Err(DroneError::RequestCapReached) => "request_cap",
+            Err(DroneError::IpRateLimited) => "ip_limited",
+            Err(_) => "key_limited",
+        };
+        info!("airdrop request from {}: {}", ip, decision);
+        metrics::submit(
+            influxdb::Point::new("drone")
+                .add_tag("op", influxdb::Value::String("rate_limit".to_string()))
+                .add_tag("decision", influxdb::Value::String(decision.to_string()))
+                .add_field(
+                    "request_amount",
+                    influxdb::Value::Integer(airdrop_request_amount as i64),
+                )
+                .to_owned(),
+        );
+        result?;
+
+        self.rate_limiter
+            .take(ip, client_public_key, airdrop_request_amount, now);
+        self.request_current += airdrop_request_amount;
+        metrics::submit(
+            influxdb::Point::new("drone")
+                .add_tag("op", influxdb::Value::String("airdrop".to_string()))
+                .add_field(
+                    "request_amount",
+                    influxdb::Value::Integer(airdrop_request_amount as i64),
+                )
+                .add_field(
+                    "request_current",
+                    influxdb::Value::Integer(self.request_current as i64),
+                )
+                .to_owned(),
+        );
+        Ok(())
     }
 
-    pub fn clear_ip_cache(&mut self) {
-        self.ip_cache.clear();
+    /// Give back the limits `reserve` counted `req` against
+    pub fn cancel(&mut self, ip: IpAddr, req: DroneRequest) {
+        let (airdrop_request_amount, client_public_key) = req.airdrop();
+        self.rate_limiter
+            .refund(ip, client_public_key, airdrop_request_amount, timestamp());
+        self.request_current = self.request_current.saturating_sub(airdrop_request_amount);
     }
 
-    pub fn check_rate_limit(&mut self, ip: IpAddr) -> Result<IpAddr, IpAddr> {
-        // [WIP] This is placeholder code for a proper rate limiter.
-        // Right now it will only allow one total drone request per IP
-        if self.ip_cache.contains(&ip) {
-            // Add proper error handling here
-            Err(ip)
-        } else {
-            self.add_ip_to_cache(ip);
-            Ok(ip)
+    /// Check a request from the client at `ip` against the limits of `drone`,
+    /// and send the airdrop if it is within them. The drone is only locked to
+    /// check the limits, not while the airdrop is sent to the leader.
+    pub fn handle_request(drone: &Mutex<Drone>, ip: IpAddr, req: DroneRequest) -> DroneResponse {
+        let (mint_keypair, mut client) = {
+            let mut drone = drone.lock().unwrap();
+            if let Err(e) = drone.reserve(ip, req) {
+                return DroneResponse::Error(e);
+            }
+            (drone.mint_keypair.clone(), drone.client())
+        };
+        match send_airdrop(&mut client, &mint_keypair, req) {
+            Ok(signature) => DroneResponse::Sent(signature),
+            Err(e) => {
+                drone.lock().unwrap().cancel(ip, req);
+                DroneResponse::Error(DroneError::SendFailed(e.to_string()))
+            }
         }
     }
 
-    pub fn send_airdrop(&mut self, req: DroneRequest) -> Result<usize, io::Error> {
-        let tx: Transaction;
-        let request_amount: u64;
-        let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let transactions_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    /// A client of the leader the drone sends its airdrops to
+    pub fn client(&self) -> ThinClient {
+        let requests_socket = udp_bind(0).unwrap();
+        // resend requests whose answers got lost
+        requests_socket
+            .set_read_timeout(Some(Duration::new(1, 0)))
+            .unwrap();
+        let transactions_socket = udp_bind(0).unwrap();
 
-        let mut client = ThinClient::new(
+        ThinClient::new(
             self.requests_addr,
             requests_socket,
             self.transactions_addr,
             transactions_socket,
-        );
-        let last_id = client.get_last_id();
+        )
+    }
 
-        match req {
-            DroneRequest::GetAirdrop {
-                airdrop_request_amount,
-                client_public_key,
-            } => {
-                info!(
-                    "Requesting airdrop of {} to {:?}",
-                    airdrop_request_amount, client_public_key
-                );
-                request_amount = airdrop_request_amount;
-                tx = Transaction::new(
-                    &self.mint_keypair,
-                    client_public_key,
-                    airdrop_request_amount as i64,
-                    last_id,
-                );
-            }
-        }
-        if self.check_request_limit(request_amount) {
-            self.request_current += request_amount;
-            metrics::submit(
-                influxdb::Point::new("drone")
-                    .add_tag("op", influxdb::Value::String("airdrop".to_string()))
-                    .add_field(
-                        "request_amount",
-                        influxdb::Value::Integer(request_amount as i64),
-                    )
-                    .add_field(
-                        "request_current",
-                        influxdb::Value::Integer(self.request_current as i64),
-                    )
-                    .to_owned(),
-            );
-            client.transfer_signed(&tx)
-        } else {
-            Err(Error::new(ErrorKind::Other, "token limit reached"))
-        }
+    /// Send the airdrop transaction of `req` to the leader, whatever the limits
+    /// # Returns
+    /// The signature of the transaction
+    pub fn send_airdrop(&self, req: DroneRequest) -> Result<Signature, io::Error> {
+        send_airdrop(&mut self.client(), &self.mint_keypair, req)
     }
 }
 
@@ -157,17 +532,24 @@ impl Drop for Drone {
 #[cfg(test)]
 mod tests {
     use bank::Bank;
+    use bincode::{deserialize, serialize};
+    use choose_gossip_peer_strategy::GossipStrategy;
     use crdt::{get_ip_addr, TestNode};
-    use drone::{Drone, DroneRequest, REQUEST_CAP, TIME_SLICE};
+    use drone::{
+        confirm_airdrop, Drone, DroneError, DroneRequest, DroneResponse, Quota, RateLimiter,
+        REQUEST_CAP, TIME_SLICE,
+    };
     use fullnode::FullNode;
     use logger;
     use mint::Mint;
     use service::Service;
-    use signature::{KeyPair, KeyPairUtil};
+    use signature::{KeyPair, KeyPairUtil, Signature};
+    use std::fs;
     use std::io::sink;
     use std::net::{SocketAddr, UdpSocket};
+    use std::path::PathBuf;
     use std::sync::atomic::{AtomicBool, Ordering};
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
     use std::thread::sleep;
     use std::time::Duration;
     use thin_client::ThinClient;
@@ -206,36 +588,162 @@ mod tests {
         assert_eq!(drone.request_current, 0);
     }
 
+    fn quota(tokens: u64) -> Quota {
+        Quota {
+            tokens,
+            period: Duration::new(100, 0),
+        }
+    }
+
     #[test]
-    fn test_add_ip_to_cache() {
-        let keypair = KeyPair::new();
-        let mut addr: SocketAddr = "0.0.0.0:9900".parse().unwrap();
-        addr.set_ip(get_ip_addr().unwrap());
-        let transactions_addr = "0.0.0.0:0".parse().unwrap();
-        let requests_addr = "0.0.0.0:0".parse().unwrap();
-        let mut drone = Drone::new(keypair, addr, transactions_addr, requests_addr, None, None);
-        let ip = "127.0.0.1".parse().expect("create IpAddr from string");
-        assert_eq!(drone.ip_cache.len(), 0);
-        drone.add_ip_to_cache(ip);
-        assert_eq!(drone.ip_cache.len(), 1);
-        assert!(drone.ip_cache.contains(&ip));
+    fn test_rate_limiter_quotas() {
+        let mut limiter = RateLimiter::new(quota(100), quota(50));
+        let ip = "10.0.0.1".parse().unwrap();
+        let key = KeyPair::new().pubkey();
+        assert_eq!(limiter.check(ip, key, 40, 0), Ok(()));
+        limiter.take(ip, key, 40, 0);
+        assert_eq!(
+            limiter.check(ip, key, 20, 0),
+            Err(DroneError::KeyRateLimited)
+        );
+
+        // another key from the same address runs into the address's quota
+        let other_key = KeyPair::new().pubkey();
+        limiter.take(ip, other_key, 50, 0);
+        assert_eq!(
+            limiter.check(ip, other_key, 20, 0),
+            Err(DroneError::IpRateLimited)
+        );
+        assert_eq!(limiter.check(ip, key, 10, 0), Ok(()));
+
+        // the buckets refill at the rate of the quota, a tenth in a tenth of the period
+        assert_eq!(limiter.check(ip, key, 14, 10_000), Ok(()));
+        assert_eq!(
+            limiter.check(ip, key, 30, 10_000),
+            Err(DroneError::IpRateLimited)
+        );
+        assert_eq!(limiter.check(ip, key, 50, 1_000_000), Ok(()));
+    }
+
+    #[test]
+    fn test_rate_limiter_allow_list() {
+        let mut limiter = RateLimiter::new(quota(10), quota(10));
+        let ip = "10.0.0.1".parse().unwrap();
+        let key = KeyPair::new().pubkey();
+        assert_eq!(
+            limiter.check(ip, key, 20, 0),
+            Err(DroneError::IpRateLimited)
+        );
+        limiter.allow_ip(ip);
+        assert_eq!(limiter.check(ip, key, 20, 0), Ok(()));
+        limiter.take(ip, key, 20, 0);
+        assert_eq!(limiter.check(ip, key, 20, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_rate_limiter_state() {
+        let path = PathBuf::from("target/test_drone_rate_limiter_state.json");
+        let _ = fs::remove_file(&path);
+        let ip = "10.0.0.1".parse().unwrap();
+        let key = KeyPair::new().pubkey();
+        {
+            let mut limiter = RateLimiter::with_state(quota(100), quota(100), &path).unwrap();
+            limiter.take(ip, key, 90, 0);
+            limiter.save(0).unwrap();
+        }
+        // a restarted drone remembers what was handed out
+        let mut limiter = RateLimiter::with_state(quota(100), quota(100), &path).unwrap();
+        assert_eq!(
+            limiter.check(ip, key, 20, 0),
+            Err(DroneError::IpRateLimited)
+        );
+        assert_eq!(limiter.check(ip, key, 10, 0), Ok(()));
+
+        // full buckets aren't kept
+        limiter.take(ip, key, 0, 1_000_000);
+        limiter.save(1_000_000).unwrap();
+        assert!(limiter.ips.is_empty());
+        assert!(limiter.keys.is_empty());
+        fs::remove_file(&path).unwrap();
     }
 
     #[test]
-    fn test_clear_ip_cache() {
+    fn test_handle_request_limits() {
         let keypair = KeyPair::new();
-        let mut addr: SocketAddr = "0.0.0.0:9900".parse().unwrap();
-        addr.set_ip(get_ip_addr().unwrap());
+        let addr = "0.0.0.0:9900".parse().unwrap();
         let transactions_addr = "0.0.0.0:0".parse().unwrap();
         let requests_addr = "0.0.0.0:0".parse().unwrap();
-        let mut drone = Drone::new(keypair, addr, transactions_addr, requests_addr, None, None);
-        let ip = "127.0.0.1".parse().expect("create IpAddr from string");
-        assert_eq!(drone.ip_cache.len(), 0);
-        drone.add_ip_to_cache(ip);
-        assert_eq!(drone.ip_cache.len(), 1);
-        drone.clear_ip_cache();
-        assert_eq!(drone.ip_cache.len(), 0);
-        assert!(drone.ip_cache.is_empty());
+        let mut drone = Drone::new(
+            keypair,
+            addr,
+            transactions_addr,
+            requests_addr,
+            None,
+            Some(100),
+        );
+        drone.rate_limiter = RateLimiter::new(quota(10), quota(10));
+        let ip = "10.0.0.1".parse().unwrap();
+        let req = |amount| DroneRequest::GetConfirmedAirdrop {
+            airdrop_request_amount: amount,
+            client_public_key: KeyPair::new().pubkey(),
+        };
+
+        // an airdrop counts against the limits until it's cancelled
+        let airdrop = req(10);
+        assert_eq!(drone.reserve(ip, airdrop), Ok(()));
+        assert_eq!(drone.request_current, 10);
+        assert_eq!(drone.reserve(ip, req(1)), Err(DroneError::IpRateLimited));
+        drone.cancel(ip, airdrop);
+        assert_eq!(drone.request_current, 0);
+
+        // refused requests are answered without a word to the leader
+        let drone = Mutex::new(drone);
+        assert_eq!(
+            Drone::handle_request(&drone, ip, req(200)),
+            DroneResponse::Error(DroneError::RequestCapReached)
+        );
+        assert_eq!(
+            Drone::handle_request(&drone, ip, req(20)),
+            DroneResponse::Error(DroneError::IpRateLimited)
+        );
+    }
+
+    #[test]
+    fn test_drone_request_compat() {
+        // a request from a client that only knows `GetAirdrop`
+        let key = KeyPair::new().pubkey();
+        let bytes = serialize(&(0u32, 50u64, key)).unwrap();
+        let req: DroneRequest = deserialize(&bytes).unwrap();
+        assert_eq!(req.airdrop(), (50, key));
+        assert!(!req.confirm());
+
+        let req = DroneRequest::GetConfirmedAirdrop {
+            airdrop_request_amount: 50,
+            client_public_key: key,
+        };
+        let req: DroneRequest = deserialize(&serialize(&req).unwrap()).unwrap();
+        assert_eq!(req.airdrop(), (50, key));
+        assert!(req.confirm());
+    }
+
+    #[test]
+    fn test_confirm_airdrop_unreachable() {
+        let leader = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = leader.local_addr().unwrap();
+        let requests_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        requests_socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let transactions_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = ThinClient::new(addr, requests_socket, addr, transactions_socket);
+
+        // the leader never answers, and the drone gives up in time
+        let sig = Signature::default();
+        let response = confirm_airdrop(&mut client, sig, Duration::from_millis(300));
+        assert_eq!(
+            response,
+            DroneResponse::Error(DroneError::NotConfirmed(sig))
+        );
     }
 
     #[test]
@@ -283,13 +791,15 @@ mod tests {
             leader,
             exit.clone(),
             sink(),
+            None,
+            GossipStrategy::default(),
         );
         //TODO: this seems unstable
         sleep(Duration::from_millis(900));
 
         let mut addr: SocketAddr = "0.0.0.0:9900".parse().expect("bind to drone socket");
         addr.set_ip(get_ip_addr().expect("drone get_ip_addr"));
-        let mut drone = Drone::new(
+        let drone = Drone::new(
             alice.keypair(),
             addr,
             leader_data.contact_info.tpu,
@@ -302,15 +812,14 @@ mod tests {
             airdrop_request_amount: 50,
             client_public_key: bob_pubkey,
         };
-        let bob_result = drone.send_airdrop(bob_req).expect("send airdrop test");
-        assert!(bob_result > 0);
+        let bob_sig = drone.send_airdrop(bob_req).expect("send airdrop test");
 
         let carlos_req = DroneRequest::GetAirdrop {
             airdrop_request_amount: 5_000_000,
             client_public_key: carlos_pubkey,
         };
-        let carlos_result = drone.send_airdrop(carlos_req).expect("send airdrop test");
-        assert!(carlos_result > 0);
+        let carlos_sig = drone.send_airdrop(carlos_req).expect("send airdrop test");
+        assert_ne!(bob_sig, carlos_sig);
 
         let requests_socket = UdpSocket::bind("0.0.0.0:0").expect("drone bind to requests socket");
         let transactions_socket =
@@ -331,6 +840,10 @@ mod tests {
         info!("TPS request balance: {:?}", carlos_balance);
         assert_eq!(carlos_balance.unwrap(), TPS_BATCH);
 
+        // once the balance shows, the leader confirms the signature right away
+        let response = confirm_airdrop(&mut drone.client(), bob_sig, Duration::new(1, 0));
+        assert_eq!(response, DroneResponse::Confirmed(bob_sig));
+
         exit.store(true, Ordering::Relaxed);
         server.join().unwrap();
     }
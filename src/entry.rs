@@ -2,12 +2,20 @@
 //! unique ID that is the hash of the Entry before it, plus the hash of the
 //! transactions within it. Entries cannot be reordered, and its field `num_hashes`
 //! represents an approximate amount of time since the last Entry was created.
+//! An Entry after a long run of hashes carries the hashes at every `CHECKPOINT_HASHES`
+//! along the way, so that the run can be verified on as many cores as it has segments.
+//! An Entry can also mix external 32-byte values into the hash chain, which proves
+//! that they existed before its ID was generated.
 use bincode::serialized_size;
 use hash::{extend_and_hash, hash, Hash};
 use packet::BLOB_DATA_SIZE;
 use rayon::prelude::*;
+use std::iter;
 use transaction::Transaction;
 
+/// The number of hashes between the checkpoints of an Entry
+pub const CHECKPOINT_HASHES: u64 = 64 * 1024;
+
 /// Each Entry contains three pieces of data. The `num_hashes` field is the number
 /// of hashes performed since the previous entry.  The `id` field is the result
 /// of hashing `id` from the previous entry `num_hashes` times.  The `transactions`
@@ -31,11 +39,19 @@ pub struct Entry {
     /// The SHA-256 hash `num_hashes` after the previous Entry ID.
     pub id: Hash,
 
+    /// The hashes `CHECKPOINT_HASHES`, twice that, and so on after the previous
+    /// Entry ID, short of `id`. Optional, since each only speeds up verification.
+    pub checkpoints: Vec<Hash>,
+
     /// An unordered list of transactions that were observed before the Entry ID was
     /// generated. The may have been observed before a previous Entry ID but were
     /// pushed back into this list to ensure deterministic interpretation of the ledger.
     pub transactions: Vec<Transaction>,
 
+    /// External values, such as storage samples or oracle data, hashed into the
+    /// Entry ID after the transactions.
+    pub mixins: Vec<Hash>,
+
     /// Indication that:
     ///  1. the next Entry in the ledger has transactions that can potentially
     ///       be verified in parallel with these transactions
@@ -56,11 +72,13 @@ impl Entry {
         has_more: bool,
     ) -> Self {
         let num_hashes = cur_hashes + if transactions.is_empty() { 0 } else { 1 };
-        let id = next_hash(start_hash, 0, &transactions);
+        let id = next_hash(start_hash, 0, &transactions, &[]);
         let entry = Entry {
             num_hashes,
             id,
+            checkpoints: vec![],
             transactions,
+            mixins: vec![],
             has_more,
             pad: [0, 0, 0],
         };
@@ -68,11 +86,44 @@ impl Entry {
         entry
     }
 
+    /// Creates the next Entry `num_hashes` after `start_hash`, mixing `mixins`
+    /// into its ID.
+    pub fn new_mixins(start_hash: &Hash, cur_hashes: u64, mixins: Vec<Hash>) -> Self {
+        let num_hashes = cur_hashes + if mixins.is_empty() { 0 } else { 1 };
+        let id = next_hash(start_hash, 0, &[], &mixins);
+        let entry = Entry {
+            num_hashes,
+            id,
+            checkpoints: vec![],
+            transactions: vec![],
+            mixins,
+            has_more: false,
+            pad: [0, 0, 0],
+        };
+        assert!(serialized_size(&entry).unwrap() <= BLOB_DATA_SIZE as u64);
+        entry
+    }
+
     pub fn will_fit(transactions: Vec<Transaction>) -> bool {
         serialized_size(&Entry {
             num_hashes: 0,
             id: Hash::default(),
+            checkpoints: vec![],
             transactions,
+            mixins: vec![],
+            has_more: false,
+            pad: [0, 0, 0],
+        }).unwrap() <= BLOB_DATA_SIZE as u64
+    }
+
+    /// Same as `will_fit`, for an Entry of `mixins`.
+    pub fn will_fit_mixins(mixins: Vec<Hash>) -> bool {
+        serialized_size(&Entry {
+            num_hashes: 0,
+            id: Hash::default(),
+            checkpoints: vec![],
+            transactions: vec![],
+            mixins,
             has_more: false,
             pad: [0, 0, 0],
         }).unwrap() <= BLOB_DATA_SIZE as u64
@@ -98,17 +149,41 @@ impl Entry {
         Entry {
             num_hashes,
             id: *id,
+            checkpoints: vec![],
             transactions: vec![],
+            mixins: vec![],
             has_more: false,
             pad: [0, 0, 0],
         }
     }
 
+    /// Whether the Entry only marks the passing of time, recording neither
+    /// transactions nor mixins.
+    pub fn is_tick(&self) -> bool {
+        self.transactions.is_empty() && self.mixins.is_empty()
+    }
+
     /// Verifies self.id is the result of hashing a `start_hash` `self.num_hashes` times.
-    /// If the transaction is not a Tick, then hash that as well.
+    /// If the Entry is not a Tick, then hash its transactions and mixins as well.
     pub fn verify(&self, start_hash: &Hash) -> bool {
-        self.transactions.par_iter().all(|tx| tx.verify_plan())
-            && self.id == next_hash(start_hash, self.num_hashes, &self.transactions)
+        self.transactions.par_iter().all(|tx| tx.verify_plan()) && self.verify_hashes(start_hash)
+    }
+
+    /// Verifies the hashes from `start_hash` to self.id, the segment up to
+    /// each checkpoint in parallel with the others.
+    fn verify_hashes(&self, start_hash: &Hash) -> bool {
+        let checkpoint_hashes = self.checkpoints.len() as u64 * CHECKPOINT_HASHES;
+        if !self.checkpoints.is_empty() && checkpoint_hashes >= self.num_hashes {
+            return false;
+        }
+        let starts: Vec<_> = iter::once(start_hash).chain(&self.checkpoints).collect();
+        let last = starts[self.checkpoints.len()];
+        let last_hashes = self.num_hashes - checkpoint_hashes;
+        starts
+            .par_iter()
+            .zip(&self.checkpoints)
+            .all(|(start, checkpoint)| next_hash(start, CHECKPOINT_HASHES, &[], &[]) == *checkpoint)
+            && self.id == next_hash(last, last_hashes, &self.transactions, &self.mixins)
     }
 }
 
@@ -117,11 +192,21 @@ fn add_transaction_data(hash_data: &mut Vec<u8>, tx: &Transaction) {
     hash_data.extend_from_slice(&tx.sig);
 }
 
+fn add_mixin_data(hash_data: &mut Vec<u8>, mixin: &Hash) {
+    hash_data.push(1u8);
+    hash_data.extend_from_slice(mixin);
+}
+
 /// Creates the hash `num_hashes` after `start_hash`. If the transaction contains
 /// a signature, the final hash will be a hash of both the previous ID and
-/// the signature.  If num_hashes is zero and there's no transaction data,
-///  start_hash is returned.
-fn next_hash(start_hash: &Hash, num_hashes: u64, transactions: &[Transaction]) -> Hash {
+/// the signature, and likewise the mixins.  If num_hashes is zero and there's no
+/// transaction or mixin data, start_hash is returned.
+fn next_hash(
+    start_hash: &Hash,
+    num_hashes: u64,
+    transactions: &[Transaction],
+    mixins: &[Hash],
+) -> Hash {
     let mut id = *start_hash;
     for _ in 1..num_hashes {
         id = hash(&id);
@@ -132,6 +217,9 @@ fn next_hash(start_hash: &Hash, num_hashes: u64, transactions: &[Transaction]) -
     for tx in transactions {
         add_transaction_data(&mut hash_data, tx);
     }
+    for mixin in mixins {
+        add_mixin_data(&mut hash_data, mixin);
+    }
 
     if !hash_data.is_empty() {
         extend_and_hash(&id, &hash_data)
@@ -147,8 +235,10 @@ pub fn next_entry(start_hash: &Hash, num_hashes: u64, transactions: Vec<Transact
     assert!(num_hashes > 0 || transactions.is_empty());
     Entry {
         num_hashes,
-        id: next_hash(start_hash, num_hashes, &transactions),
+        id: next_hash(start_hash, num_hashes, &transactions, &[]),
+        checkpoints: vec![],
         transactions,
+        mixins: vec![],
         has_more: false,
         pad: [0, 0, 0],
     }
@@ -207,6 +297,24 @@ mod tests {
         assert!(!e0.verify(&zero));
     }
 
+    #[test]
+    fn test_mixins_verify() {
+        let zero = Hash::default();
+        let one = hash(&zero);
+        let entry = Entry::new_mixins(&zero, 0, vec![one]);
+        assert_eq!(entry.num_hashes, 1);
+        assert!(!entry.is_tick());
+        assert!(entry.verify(&zero));
+        assert_ne!(entry.id, next_entry(&zero, 1, vec![]).id);
+
+        // a different or a missing mixin fails verification
+        let mut bad = entry.clone();
+        bad.mixins = vec![zero];
+        assert!(!bad.verify(&zero));
+        bad.mixins = vec![];
+        assert!(!bad.verify(&zero));
+    }
+
     #[test]
     fn test_next_entry() {
         let zero = Hash::default();
@@ -222,7 +330,7 @@ mod tests {
         let tx0 = Transaction::new_timestamp(&keypair, Utc::now(), zero);
         let entry0 = next_entry(&zero, 1, vec![tx0.clone()]);
         assert_eq!(entry0.num_hashes, 1);
-        assert_eq!(entry0.id, next_hash(&zero, 1, &vec![tx0]));
+        assert_eq!(entry0.id, next_hash(&zero, 1, &vec![tx0], &[]));
     }
 
     #[test]
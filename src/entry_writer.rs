@@ -1,11 +1,19 @@
 //! The `entry_writer` module helps implement the TPU's write stage. It
 //! writes entries to the given writer, which is typically a file or
-//! stdout, and then sends the Entry to its output channel.
+//! stdout, and then sends the Entry to its output channel. It also owns
+//! the on-disk ledger format: a `LedgerWriter` makes appends durable and
+//! `recover_ledger` repairs a ledger left behind by a crash.
 
-use bank::Bank;
+use bank::{Bank, VERIFY_BLOCK_SIZE};
 use entry::Entry;
+use hash::Hash;
+use ledger::Block;
 use serde_json;
-use std::io::{self, BufRead, Cursor, Error, ErrorKind, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Error, ErrorKind, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 pub struct EntryWriter<'a, W> {
     bank: &'a Bank,
@@ -50,6 +58,200 @@ impl<'a, W: Write> EntryWriter<'a, W> {
     }
 }
 
+/// How often a `LedgerWriter` forces appended entries to stable storage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// Leave it to the OS. A crash may lose any number of recent entries.
+    Never,
+    /// fsync on every flush, i.e. after every entry.
+    Always,
+    /// fsync on the first flush after the interval has elapsed since the last one.
+    Interval(Duration),
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        SyncPolicy::Interval(Duration::from_millis(100))
+    }
+}
+
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    /// Parse "never", "always" or an interval in milliseconds.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(SyncPolicy::Never),
+            "always" => Ok(SyncPolicy::Always),
+            ms => ms
+                .parse()
+                .map(|ms| SyncPolicy::Interval(Duration::from_millis(ms)))
+                .map_err(|_| format!("invalid sync policy: {}", s)),
+        }
+    }
+}
+
+/// A ledger file opened for appending. `flush` hands the buffered bytes to the OS
+/// and then syncs them according to its `SyncPolicy`.
+pub struct LedgerWriter {
+    writer: BufWriter<File>,
+    policy: SyncPolicy,
+    last_sync: Instant,
+}
+
+impl LedgerWriter {
+    /// Open the ledger at `path` for appending, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P, policy: SyncPolicy) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(LedgerWriter {
+            writer: BufWriter::new(file),
+            policy,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Flush and fsync regardless of the policy.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for LedgerWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.policy {
+            SyncPolicy::Never => self.writer.flush(),
+            SyncPolicy::Always => self.sync(),
+            SyncPolicy::Interval(interval) => {
+                if self.last_sync.elapsed() >= interval {
+                    self.sync()
+                } else {
+                    self.writer.flush()
+                }
+            }
+        }
+    }
+}
+
+impl Drop for LedgerWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            warn!("failed to sync ledger on close: {:?}", e);
+        }
+    }
+}
+
+/// An entry read during recovery along with the offset of its record.
+struct Record {
+    entry: Entry,
+    start: u64,
+}
+
+/// Verify `records` against `last_id` and return the index of the first one that
+/// doesn't continue the PoH chain.
+fn first_unverified(records: &[Record], last_id: &Hash) -> Option<usize> {
+    let entries: Vec<Entry> = records.iter().map(|r| r.entry.clone()).collect();
+    if entries.verify(last_id) {
+        return None;
+    }
+    let mut id = *last_id;
+    for (i, entry) in entries.iter().enumerate() {
+        if !entry.verify(&id) {
+            return Some(i);
+        }
+        id = entry.id;
+    }
+    None
+}
+
+/// Repair the ledger at `path` after a crash and return the number of good entries.
+/// A record is good if it is a complete line that parses as an `Entry` and continues
+/// the PoH chain from the record before it. The first entry starts the chain, so it
+/// must verify against its own id as the genesis entry does. Bad records at the tail
+/// of the file are a torn write and are truncated. A bad record followed by a good
+/// one is corruption and is returned as an error.
+pub fn recover_ledger<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    recover_ledger_after(path, None)
+}
+
+/// Like `recover_ledger`, for a ledger file that continues the chain from `prev_id`,
+/// the id of the entry before its first record. `None` means the file starts at
+/// genesis.
+pub fn recover_ledger_after<P: AsRef<Path>>(path: P, prev_id: Option<Hash>) -> io::Result<u64> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut torn = None;
+    let mut count = 0;
+    {
+        let mut reader = BufReader::new(&file);
+        let mut pending: Vec<Record> = Vec::with_capacity(VERIFY_BLOCK_SIZE);
+        let mut last_id = prev_id;
+        let mut pos = 0;
+        let mut line = vec![];
+        loop {
+            line.clear();
+            let len = reader.read_until(b'\n', &mut line)?;
+            let eof = len == 0;
+            if !eof {
+                let start = pos;
+                pos += len as u64;
+                let entry = if line.ends_with(b"\n") {
+                    serde_json::from_slice::<Entry>(&line[..len - 1]).ok()
+                } else {
+                    None
+                };
+                match (entry, torn) {
+                    (Some(entry), None) => pending.push(Record { entry, start }),
+                    (Some(_), Some(bad)) => return Err(corrupted(bad)),
+                    (None, None) => torn = Some(start),
+                    (None, Some(_)) => (),
+                }
+            }
+            if pending.len() == VERIFY_BLOCK_SIZE || (eof && !pending.is_empty()) {
+                let start_id = last_id.unwrap_or(pending[0].entry.id);
+                match first_unverified(&pending, &start_id) {
+                    None => {
+                        count += pending.len() as u64;
+                        last_id = Some(pending[pending.len() - 1].entry.id);
+                    }
+                    Some(i) => {
+                        if i + 1 < pending.len() {
+                            return Err(corrupted(pending[i].start));
+                        }
+                        count += i as u64;
+                        torn = Some(pending[i].start);
+                    }
+                }
+                pending.clear();
+            }
+            if eof {
+                break;
+            }
+        }
+    }
+    if let Some(offset) = torn {
+        warn!(
+            "truncating torn ledger record at byte {}, {} good entries",
+            offset, count
+        );
+        file.set_len(offset)?;
+        file.sync_all()?;
+    }
+    Ok(count)
+}
+
+fn corrupted(offset: u64) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("ledger corrupted at byte {}", offset),
+    )
+}
+
 /// Parse a string containing an Entry.
 pub fn read_entry(s: &str) -> io::Result<Entry> {
     serde_json::from_str(s).map_err(|e| Error::new(ErrorKind::Other, e.to_string()))
@@ -81,9 +283,33 @@ mod tests {
     use mint::Mint;
     use packet::BLOB_DATA_SIZE;
     use signature::{KeyPair, KeyPairUtil};
+    use std::fs::{self, File};
+    use std::io::Read;
     use std::str;
     use transaction::Transaction;
 
+    /// Write a ledger with the mint's entries plus a few more and return its path.
+    fn tmp_ledger(name: &str, mint: &Mint) -> (String, Vec<Entry>) {
+        let path = format!("target/test_entry_writer_{}.log", name);
+        let mut entries = mint.create_entries();
+        let keypair = KeyPair::new();
+        for i in 0..3 {
+            let last_id = entries.last().unwrap().id;
+            let tx = Transaction::new(&mint.keypair(), keypair.pubkey(), 1 + i, last_id);
+            entries.extend(ledger::next_entries(&last_id, 0, vec![tx]));
+        }
+        let _ = fs::remove_file(&path);
+        let mut writer = LedgerWriter::open(&path, SyncPolicy::Always).unwrap();
+        EntryWriter::write_entries(&mut writer, entries.clone()).unwrap();
+        (path, entries)
+    }
+
+    fn read_ledger(path: &str) -> Vec<Entry> {
+        let mut s = String::new();
+        File::open(path).unwrap().read_to_string(&mut s).unwrap();
+        read_entries_from_str(&s).unwrap()
+    }
+
     #[test]
     fn test_dont_register_partial_entries() {
         let mint = Mint::new(1);
@@ -122,4 +348,118 @@ mod tests {
         let entries = read_entries_from_str(str::from_utf8(&buf).unwrap()).unwrap();
         assert_eq!(entries, mint.create_entries());
     }
+
+    #[test]
+    fn test_sync_policy_from_str() {
+        assert_eq!("never".parse(), Ok(SyncPolicy::Never));
+        assert_eq!("always".parse(), Ok(SyncPolicy::Always));
+        assert_eq!(
+            "250".parse(),
+            Ok(SyncPolicy::Interval(Duration::from_millis(250)))
+        );
+        assert!("sometimes".parse::<SyncPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_sync_policy_writer() {
+        let mint = Mint::new(1);
+        for (name, policy) in vec![
+            ("never", SyncPolicy::Never),
+            ("always", SyncPolicy::Always),
+            ("interval", SyncPolicy::default()),
+        ] {
+            let path = format!("target/test_entry_writer_sync_{}.log", name);
+            let _ = fs::remove_file(&path);
+            {
+                let mut writer = LedgerWriter::open(&path, policy).unwrap();
+                EntryWriter::write_entries(&mut writer, mint.create_entries()).unwrap();
+            }
+            assert_eq!(read_ledger(&path), mint.create_entries());
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_recover_ledger_intact() {
+        let mint = Mint::new(10);
+        let (path, entries) = tmp_ledger("intact", &mint);
+        assert_eq!(recover_ledger(&path).unwrap(), entries.len() as u64);
+        assert_eq!(read_ledger(&path), entries);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover_ledger_torn_tail() {
+        let mint = Mint::new(10);
+        let (path, entries) = tmp_ledger("torn_tail", &mint);
+        let len = fs::metadata(&path).unwrap().len();
+
+        // chop the last record in half, as a crash mid-write would
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 20).unwrap();
+        assert_eq!(recover_ledger(&path).unwrap(), entries.len() as u64 - 1);
+        assert_eq!(read_ledger(&path), &entries[..entries.len() - 1]);
+
+        // the ledger can be appended to again after recovery
+        {
+            let mut writer = LedgerWriter::open(&path, SyncPolicy::Always).unwrap();
+            EntryWriter::write_entries(&mut writer, entries[entries.len() - 1..].to_vec())
+                .unwrap();
+        }
+        assert_eq!(recover_ledger(&path).unwrap(), entries.len() as u64);
+        assert_eq!(read_ledger(&path), entries);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover_ledger_bad_chain_tail() {
+        let mint = Mint::new(10);
+        let (path, entries) = tmp_ledger("bad_chain_tail", &mint);
+
+        // a complete record that doesn't follow from the one before it
+        let bogus = Entry::new_tick(0, &mint.last_id());
+        {
+            let mut writer = LedgerWriter::open(&path, SyncPolicy::Always).unwrap();
+            EntryWriter::write_entries(&mut writer, vec![bogus]).unwrap();
+        }
+        assert_eq!(recover_ledger(&path).unwrap(), entries.len() as u64);
+        assert_eq!(read_ledger(&path), entries);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover_ledger_first_record() {
+        let mint = Mint::new(10);
+        let (path, entries) = tmp_ledger("first_record", &mint);
+
+        // a file that doesn't start at genesis only verifies against the id before it
+        let mut buf = vec![];
+        EntryWriter::write_entries(&mut buf, entries[1..].to_vec()).unwrap();
+        fs::write(&path, &buf).unwrap();
+        assert!(recover_ledger(&path).is_err());
+        assert_eq!(
+            recover_ledger_after(&path, Some(entries[0].id)).unwrap(),
+            entries.len() as u64 - 1
+        );
+        assert!(recover_ledger_after(&path, Some(Hash::default())).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover_ledger_corrupted() {
+        let mint = Mint::new(10);
+        let (path, _) = tmp_ledger("corrupted", &mint);
+
+        // garbage followed by good records is not a torn write
+        let mut contents = vec![];
+        File::open(&path)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents.insert(0, b'x');
+        fs::write(&path, &contents).unwrap();
+        assert!(recover_ledger(&path).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), contents.len() as u64);
+        fs::remove_file(&path).unwrap();
+    }
 }
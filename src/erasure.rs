@@ -1,12 +1,25 @@
 // Support erasure coding
+//   Blocks are coded by the pure-Rust `reed_solomon` backend, or by the C
+//   jerasure library when built with the `jerasure` feature
+//
+//   The blob stream is cut into erasure sets of NUM_CODED blobs, the last few
+//   of each set being coding blobs. The leader picks how many from the blob
+//   loss validators report, and every blob of a set carries that number in its
+//   header so the set can be decoded from whichever of its blobs arrive.
 
 use packet::{BlobRecycler, SharedBlob, BLOB_HEADER_SIZE};
+use std::cmp;
+use std::mem;
 use std::result;
 
-//TODO(sakridge) pick these values
+/// Number of blobs in an erasure set, data and coding
 pub const NUM_CODED: usize = 20;
-pub const MAX_MISSING: usize = 4;
-const NUM_DATA: usize = NUM_CODED - MAX_MISSING;
+/// Coding blobs per set until validators have reported any loss
+pub const DEFAULT_NUM_CODING: usize = 4;
+pub const MIN_NUM_CODING: usize = 1;
+pub const MAX_NUM_CODING: usize = NUM_CODED / 2;
+/// Sets carry this many coding blobs for every blob expected to be lost
+const LOSS_MARGIN: f64 = 2.0;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ErasureError {
@@ -18,167 +31,97 @@ pub enum ErasureError {
 
 pub type Result<T> = result::Result<T, ErasureError>;
 
-// k = number of data devices
-// m = number of coding devices
-// w = word size
-
-extern "C" {
-    fn jerasure_matrix_encode(
-        k: i32,
-        m: i32,
-        w: i32,
-        matrix: *const i32,
-        data_ptrs: *const *const u8,
-        coding_ptrs: *const *mut u8,
-        size: i32,
-    );
-    fn jerasure_matrix_decode(
-        k: i32,
-        m: i32,
-        w: i32,
-        matrix: *const i32,
-        row_k_ones: i32,
-        erasures: *const i32,
-        data_ptrs: *const *mut u8,
-        coding_ptrs: *const *const u8,
-        size: i32,
-    ) -> i32;
-    fn galois_single_divide(a: i32, b: i32, w: i32) -> i32;
+/// Coding is done over GF(2^w) with this word size in bits
+pub const ERASURE_W: i32 = 32;
+
+#[cfg(feature = "jerasure")]
+pub use jerasure::{decode_blocks, generate_coding_blocks};
+#[cfg(not(feature = "jerasure"))]
+pub use reed_solomon::{decode_blocks, generate_coding_blocks};
+
+/// How many blobs of each erasure set the leader sends as coding blobs
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodingRate {
+    /// coding blobs in the set being broadcast
+    num_coding: usize,
+    /// coding blobs in the sets started from now on
+    next_num_coding: usize,
 }
 
-fn get_matrix(m: i32, k: i32, w: i32) -> Vec<i32> {
-    let mut matrix = vec![0; (m * k) as usize];
-    for i in 0..m {
-        for j in 0..k {
-            unsafe {
-                matrix[(i * k + j) as usize] = galois_single_divide(1, i ^ (m + j), w);
-            }
+impl Default for CodingRate {
+    fn default() -> Self {
+        CodingRate {
+            num_coding: DEFAULT_NUM_CODING,
+            next_num_coding: DEFAULT_NUM_CODING,
         }
     }
-    matrix
 }
 
-pub const ERASURE_W: i32 = 32;
-
-// Generate coding blocks into coding
-//   There are some alignment restrictions, blocks should be aligned by 16 bytes
-//   which means their size should be >= 16 bytes
-pub fn generate_coding_blocks(coding: &mut [&mut [u8]], data: &[&[u8]]) -> Result<()> {
-    if data.len() == 0 {
-        return Ok(());
-    }
-    let m = coding.len() as i32;
-    let block_len = data[0].len();
-    let matrix: Vec<i32> = get_matrix(m, data.len() as i32, ERASURE_W);
-    let mut coding_arg = Vec::new();
-    let mut data_arg = Vec::new();
-    for block in data {
-        if block_len != block.len() {
-            trace!(
-                "data block size incorrect {} expected {}",
-                block.len(),
-                block_len
-            );
-            return Err(ErasureError::InvalidBlockSize);
-        }
-        data_arg.push(block.as_ptr());
-    }
-    for mut block in coding {
-        if block_len != block.len() {
-            trace!(
-                "coding block size incorrect {} expected {}",
-                block.len(),
-                block_len
-            );
-            return Err(ErasureError::InvalidBlockSize);
-        }
-        coding_arg.push(block.as_mut_ptr());
+impl CodingRate {
+    pub fn num_coding(&self) -> usize {
+        self.num_coding
     }
 
-    unsafe {
-        jerasure_matrix_encode(
-            data.len() as i32,
-            m,
-            ERASURE_W,
-            matrix.as_ptr(),
-            data_arg.as_ptr(),
-            coding_arg.as_ptr(),
-            data[0].len() as i32,
-        );
+    /// Size the sets started from now on for a blob loss, in parts per million
+    /// of the blobs sent. A set already being broadcast keeps its size.
+    pub fn set_loss(&mut self, loss_ppm: u32) {
+        let expected_lost = f64::from(loss_ppm) * NUM_CODED as f64 / 1_000_000.0;
+        let num_coding = (expected_lost * LOSS_MARGIN).ceil() as usize;
+        self.next_num_coding = cmp::min(cmp::max(num_coding, MIN_NUM_CODING), MAX_NUM_CODING);
     }
-    Ok(())
-}
 
-// Recover data + coding blocks into data blocks
-//   data: array of blocks to recover into
-//   coding: arry of coding blocks
-//   erasures: list of indices in data where blocks should be recovered
-pub fn decode_blocks(data: &mut [&mut [u8]], coding: &[&[u8]], erasures: &[i32]) -> Result<()> {
-    if data.len() == 0 {
-        return Ok(());
-    }
-    let block_len = data[0].len();
-    let matrix: Vec<i32> = get_matrix(coding.len() as i32, data.len() as i32, ERASURE_W);
-
-    // generate coding pointers, blocks should be the same size
-    let mut coding_arg: Vec<*const u8> = Vec::new();
-    for x in coding.iter() {
-        if x.len() != block_len {
-            return Err(ErasureError::InvalidBlockSize);
-        }
-        coding_arg.push(x.as_ptr());
+    fn start_set(&mut self) {
+        self.num_coding = self.next_num_coding;
     }
+}
 
-    // generate data pointers, blocks should be the same size
-    let mut data_arg: Vec<*mut u8> = Vec::new();
-    for x in data.iter_mut() {
-        if x.len() != block_len {
-            return Err(ErasureError::InvalidBlockSize);
-        }
-        data_arg.push(x.as_mut_ptr());
+// The number of coding blobs in the set `blob` belongs to, None if its header
+// is out of bounds
+fn blob_num_coding(blob: &SharedBlob) -> Option<usize> {
+    let num_coding = blob.read().unwrap().get_num_coding().ok()? as usize;
+    if num_coding < MIN_NUM_CODING || num_coding > MAX_NUM_CODING {
+        return None;
     }
-    unsafe {
-        let ret = jerasure_matrix_decode(
-            data.len() as i32,
-            coding.len() as i32,
-            ERASURE_W,
-            matrix.as_ptr(),
-            0,
-            erasures.as_ptr(),
-            data_arg.as_ptr(),
-            coding_arg.as_ptr(),
-            data[0].len() as i32,
-        );
-        trace!("jerasure_matrix_decode ret: {}", ret);
-        for x in data[erasures[0] as usize][0..8].iter() {
-            trace!("{} ", x)
-        }
-        trace!("");
-        if ret < 0 {
-            return Err(ErasureError::DecodeError);
-        }
-    }
-    Ok(())
+    Some(num_coding)
 }
 
-// Allocate some coding blobs and insert into the blobs array
-pub fn add_coding_blobs(recycler: &BlobRecycler, blobs: &mut Vec<SharedBlob>, consumed: u64) {
+// Allocate some coding blobs and insert into the blobs array, and mark every
+//   blob with the size of its set's coding
+pub fn add_coding_blobs(
+    recycler: &BlobRecycler,
+    blobs: &mut Vec<SharedBlob>,
+    consumed: u64,
+    rate: &mut CodingRate,
+) {
     let mut added = 0;
-    let blobs_len = blobs.len() as u64;
-    for i in consumed..consumed + blobs_len {
-        let is = i as usize;
-        if is != 0 && ((is + MAX_MISSING) % NUM_CODED) == 0 {
-            for _ in 0..MAX_MISSING {
-                trace!("putting coding at {}", (i - consumed));
+    let mut pos = 0;
+    while pos < blobs.len() {
+        let is = consumed as usize + pos;
+        if is % NUM_CODED == 0 {
+            rate.start_set();
+        }
+        let num_coding = rate.num_coding;
+        if is % NUM_CODED == NUM_CODED - num_coding {
+            for _ in 0..num_coding {
+                trace!("putting coding at {}", pos);
                 let new_blob = recycler.allocate();
-                let new_blob_clone = new_blob.clone();
-                let mut new_blob_l = new_blob_clone.write().unwrap();
-                new_blob_l.set_size(0);
-                new_blob_l.set_coding().unwrap();
-                drop(new_blob_l);
-                blobs.insert((i - consumed) as usize, new_blob);
+                {
+                    let mut new_blob_l = new_blob.write().unwrap();
+                    new_blob_l.set_size(0);
+                    new_blob_l.set_coding().unwrap();
+                    new_blob_l.set_num_coding(num_coding as u32).unwrap();
+                }
+                blobs.insert(pos, new_blob);
                 added += 1;
             }
+            pos += num_coding;
+        } else {
+            blobs[pos]
+                .write()
+                .unwrap()
+                .set_num_coding(num_coding as u32)
+                .unwrap();
+            pos += 1;
         }
     }
     info!(
@@ -206,14 +149,24 @@ pub fn generate_coding(
             let mut coding_locks = Vec::new();
             let mut coding_ptrs: Vec<&mut [u8]> = Vec::new();
 
+            // the set's last blob is a coding blob, and knows how many others are
+            let num_coding = match window[i % window.len()] {
+                Some(ref b) => blob_num_coding(b).ok_or(ErasureError::EncodeError)?,
+                None => {
+                    trace!("coding block is null @ {}", i % window.len());
+                    return Ok(());
+                }
+            };
+            let num_data = NUM_CODED - num_coding;
+
             info!(
                 "generate_coding start: {} end: {} consumed: {} num_blobs: {}",
                 block_start,
-                block_start + NUM_DATA,
+                block_start + num_data,
                 consumed,
                 num_blobs
             );
-            for i in block_start..block_start + NUM_DATA {
+            for i in block_start..block_start + num_data {
                 let n = i % window.len();
                 trace!("window[{}] = {:?}", n, window[n]);
                 if window[n].is_none() {
@@ -241,7 +194,7 @@ pub fn generate_coding(
             }
 
             // generate coding ptr array
-            let coding_start = block_start + NUM_DATA;
+            let coding_start = block_start + num_data;
             let coding_end = block_start + NUM_CODED;
             for i in coding_start..coding_end {
                 let n = i % window.len();
@@ -276,7 +229,7 @@ pub fn generate_coding(
                 "consumed: {} data: {}:{} coding: {}:{}",
                 consumed,
                 block_start,
-                block_start + NUM_DATA,
+                block_start + num_data,
                 coding_start,
                 coding_end
             );
@@ -314,10 +267,22 @@ pub fn recover(
         if i > 100 {
             break;
         }
+        let coding_end = block_start + NUM_CODED;
+        // any blob of the set knows how many of the set are coding blobs
+        let num_coding = (block_start..coding_end)
+            .filter_map(|i| window[i % window.len()].as_ref().and_then(blob_num_coding))
+            .next();
+        let num_coding = match num_coding {
+            Some(num_coding) => num_coding,
+            None => {
+                block_start += NUM_CODED;
+                continue;
+            }
+        };
+        let num_data = NUM_CODED - num_coding;
         let mut data_missing = 0;
         let mut coded_missing = 0;
-        let coding_start = block_start + NUM_DATA;
-        let coding_end = block_start + NUM_CODED;
+        let coding_start = block_start + num_data;
         trace!(
             "recover: block_start: {} coding_start: {} coding_end: {}",
             block_start,
@@ -341,7 +306,7 @@ pub fn recover(
             );
         }
         if data_missing > 0 {
-            if (data_missing + coded_missing) <= MAX_MISSING {
+            if (data_missing + coded_missing) <= num_coding {
                 debug!(
                     "2: recovering: data: {} coding: {}",
                     data_missing, coded_missing
@@ -355,7 +320,7 @@ pub fn recover(
                     let j = i % window.len();
                     let mut b = &mut window[j];
                     if b.is_some() {
-                        if i >= NUM_DATA && size.is_none() {
+                        if i >= coding_start && size.is_none() {
                             let bl = b.clone().unwrap();
                             size = Some(bl.read().unwrap().meta.size - BLOB_HEADER_SIZE);
                         }
@@ -387,7 +352,7 @@ pub fn recover(
                     let mut coding_ptrs: Vec<&[u8]> = Vec::new();
                     let mut data_ptrs: Vec<&mut [u8]> = Vec::new();
                     for (i, l) in locks.iter_mut().enumerate() {
-                        if i >= NUM_DATA {
+                        if i >= num_data {
                             trace!("pushing coding: {}", i);
                             coding_ptrs.push(&l.data()[..size.unwrap()]);
                         } else {
@@ -402,7 +367,8 @@ pub fn recover(
                     );
                     decode_blocks(data_ptrs.as_mut_slice(), &coding_ptrs, &erasures)?;
                 }
-                for i in &erasures[..erasures.len() - 1] {
+                let erased = &erasures[..erasures.len() - 1];
+                for i in erased.iter().filter(|i| (**i as usize) < num_data) {
                     let idx = *i as usize;
                     let data_size = locks[idx].get_data_size().unwrap() - BLOB_HEADER_SIZE as u64;
                     locks[idx].meta = meta.clone().unwrap();
@@ -414,6 +380,14 @@ pub fn recover(
                         locks[idx].data()[0]
                     );
                 }
+                drop(locks);
+                // only data is decoded, missing coding blobs stay missing
+                for i in erased.iter().filter(|i| (**i as usize) >= num_data) {
+                    let j = (block_start + *i as usize) % window.len();
+                    if let Some(b) = mem::replace(&mut window[j], None) {
+                        re.recycle(b);
+                    }
+                }
             }
         }
         block_start += NUM_CODED;
@@ -425,11 +399,17 @@ pub fn recover(
 mod test {
     use crdt;
     use erasure;
+    use erasure::{CodingRate, DEFAULT_NUM_CODING, MAX_NUM_CODING, MIN_NUM_CODING, NUM_CODED};
+    #[cfg(feature = "jerasure")]
+    use jerasure;
     use logger;
     use packet::{BlobRecycler, SharedBlob, BLOB_HEADER_SIZE};
+    #[cfg(feature = "jerasure")]
+    use reed_solomon;
     use signature::KeyPair;
     use signature::KeyPairUtil;
-    use std::sync::{Arc, RwLock};
+
+    const NUM_DATA: usize = NUM_CODED - DEFAULT_NUM_CODING;
 
     #[test]
     pub fn test_coding() {
@@ -507,6 +487,7 @@ mod test {
         blob_recycler: &BlobRecycler,
         offset: usize,
         num_blobs: usize,
+        num_coding: usize,
     ) -> (Vec<Option<SharedBlob>>, usize) {
         let mut window = vec![None; 32];
         let mut blobs = Vec::new();
@@ -520,7 +501,11 @@ mod test {
             }
             blobs.push(b_);
         }
-        erasure::add_coding_blobs(blob_recycler, &mut blobs, offset as u64);
+        let mut rate = CodingRate {
+            num_coding,
+            next_num_coding: num_coding,
+        };
+        erasure::add_coding_blobs(blob_recycler, &mut blobs, offset as u64, &mut rate);
         let blobs_len = blobs.len();
 
         let d = crdt::NodeInfo::new(
@@ -547,8 +532,9 @@ mod test {
 
         // Generate a window
         let offset = 1;
-        let num_blobs = erasure::NUM_DATA + 2;
-        let (mut window, blobs_len) = generate_window(data_len, &blob_recycler, 0, num_blobs);
+        let num_blobs = NUM_DATA + 2;
+        let (mut window, blobs_len) =
+            generate_window(data_len, &blob_recycler, 0, num_blobs, DEFAULT_NUM_CODING);
         println!("** after-gen-window:");
         print_window(&window);
 
@@ -583,40 +569,161 @@ mod test {
         assert_eq!(window_l2.get_index().unwrap(), erase_offset as u64);
     }
 
-    //TODO This needs to be reworked
     #[test]
-    #[ignore]
     pub fn test_window_recover() {
         logger::setup();
-        let blob_recycler = BlobRecycler::default();
-        let offset = 4;
         let data_len = 16;
-        let num_blobs = erasure::NUM_DATA + 2;
-        let (mut window, blobs_len) = generate_window(data_len, &blob_recycler, offset, num_blobs);
-        println!("** after-gen:");
-        print_window(&window);
+        let blob_recycler = BlobRecycler::default();
+        let offset = 1;
+        let num_blobs = NUM_DATA + 2;
+        let (mut window, blobs_len) =
+            generate_window(data_len, &blob_recycler, 0, num_blobs, DEFAULT_NUM_CODING);
         assert!(erasure::generate_coding(&mut window, offset, blobs_len).is_ok());
         println!("** after-coding:");
         print_window(&window);
-        let refwindow = window[offset + 1].clone();
-        window[offset + 1] = None;
-        window[offset + 2] = None;
-        window[offset + erasure::NUM_CODED + 3] = None;
-        window[offset + (2 * erasure::NUM_CODED) + 0] = None;
-        window[offset + (2 * erasure::NUM_CODED) + 1] = None;
-        window[offset + (2 * erasure::NUM_CODED) + 2] = None;
-        let window_l0 = &(window[offset + (3 * erasure::NUM_CODED)]).clone().unwrap();
-        window_l0.write().unwrap().data[0] = 55;
+
+        // Erase as many data blobs as the coding can restore
+        let erased = [1, 2, 7, NUM_DATA - 1];
+        assert_eq!(erased.len(), DEFAULT_NUM_CODING);
+        let refwindow: Vec<_> = erased.iter().map(|i| window[*i].clone().unwrap()).collect();
+        for i in &erased {
+            window[*i] = None;
+        }
         println!("** after-nulling:");
         print_window(&window);
         assert!(erasure::recover(&blob_recycler, &mut window, offset, offset + blobs_len).is_ok());
         println!("** after-restore:");
         print_window(&window);
-        let window_l = window[offset + 1].clone().unwrap();
-        let ref_l = refwindow.clone().unwrap();
-        assert_eq!(
-            window_l.read().unwrap().data()[..data_len],
-            ref_l.read().unwrap().data()[..data_len]
-        );
+        for (i, ref_l) in erased.iter().zip(&refwindow) {
+            let window_l = window[*i].clone().unwrap();
+            let window_l2 = window_l.read().unwrap();
+            let ref_l2 = ref_l.read().unwrap();
+            assert_eq!(
+                window_l2.data[..(data_len + BLOB_HEADER_SIZE)],
+                ref_l2.data[..(data_len + BLOB_HEADER_SIZE)]
+            );
+            assert_eq!(window_l2.meta.size, ref_l2.meta.size);
+            assert_eq!(window_l2.get_index().unwrap(), *i as u64);
+        }
+
+        // One more hole than the coding covers is left alone
+        let erased = [1, 2, 3, 7, NUM_DATA - 1];
+        for i in &erased {
+            window[*i] = None;
+        }
+        assert!(erasure::recover(&blob_recycler, &mut window, offset, offset + blobs_len).is_ok());
+        for i in &erased {
+            assert!(window[*i].is_none());
+        }
+    }
+
+    #[test]
+    pub fn test_window_recover_num_coding() {
+        logger::setup();
+        let data_len = 16;
+        let blob_recycler = BlobRecycler::default();
+        let offset = 1;
+        let num_data = NUM_CODED - MAX_NUM_CODING;
+        let (mut window, blobs_len) =
+            generate_window(data_len, &blob_recycler, 0, num_data + 2, MAX_NUM_CODING);
+        assert!(erasure::generate_coding(&mut window, offset, blobs_len).is_ok());
+        for i in num_data..NUM_CODED {
+            assert!(window[i].as_ref().unwrap().read().unwrap().is_coding());
+        }
+
+        // A set with more coding blobs restores more holes, here all but one
+        // of its data blobs and one of its coding blobs
+        let erased: Vec<_> = (1..num_data).collect();
+        let refwindow: Vec<_> = erased.iter().map(|i| window[*i].clone().unwrap()).collect();
+        for i in &erased {
+            window[*i] = None;
+        }
+        window[num_data] = None;
+        assert!(erasure::recover(&blob_recycler, &mut window, offset, offset + blobs_len).is_ok());
+        assert!(window[num_data].is_none());
+        for (i, ref_l) in erased.iter().zip(&refwindow) {
+            let window_l = window[*i].clone().unwrap();
+            let window_l2 = window_l.read().unwrap();
+            let ref_l2 = ref_l.read().unwrap();
+            assert_eq!(
+                window_l2.data[..(data_len + BLOB_HEADER_SIZE)],
+                ref_l2.data[..(data_len + BLOB_HEADER_SIZE)]
+            );
+            assert_eq!(window_l2.get_num_coding().unwrap(), MAX_NUM_CODING as u32);
+        }
+    }
+
+    #[test]
+    pub fn test_coding_rate() {
+        let mut rate = CodingRate::default();
+        assert_eq!(rate.num_coding(), DEFAULT_NUM_CODING);
+        rate.set_loss(0);
+        rate.start_set();
+        assert_eq!(rate.num_coding(), MIN_NUM_CODING);
+        rate.set_loss(100_000);
+        rate.start_set();
+        assert_eq!(rate.num_coding(), 4);
+        rate.set_loss(1_000_000);
+        rate.start_set();
+        assert_eq!(rate.num_coding(), MAX_NUM_CODING);
+    }
+
+    #[test]
+    pub fn test_add_coding_blobs_rate_change() {
+        let blob_recycler = BlobRecycler::default();
+        let mut blobs: Vec<_> = (0..30).map(|_| blob_recycler.allocate()).collect();
+        let mut rate = CodingRate::default();
+        rate.set_loss(1_000_000);
+
+        // The set under way keeps its size, the next one takes the new rate
+        erasure::add_coding_blobs(&blob_recycler, &mut blobs, 5, &mut rate);
+        assert_eq!(blobs.len(), 30 + DEFAULT_NUM_CODING + MAX_NUM_CODING);
+        for (pos, b) in blobs.iter().enumerate() {
+            let ix = 5 + pos;
+            let (set_coding, set_data) = if ix < NUM_CODED {
+                (DEFAULT_NUM_CODING, NUM_DATA)
+            } else {
+                (MAX_NUM_CODING, NUM_CODED - MAX_NUM_CODING)
+            };
+            let b = b.read().unwrap();
+            assert_eq!(b.get_num_coding().unwrap(), set_coding as u32);
+            assert_eq!(b.is_coding(), ix % NUM_CODED >= set_data);
+        }
+    }
+
+    #[cfg(feature = "jerasure")]
+    #[test]
+    pub fn test_backends_agree() {
+        let block_len = 64;
+        let vs: Vec<Vec<u8>> = (0..NUM_DATA)
+            .map(|i| (0..block_len).map(|k| (k * 7 + i) as u8).collect())
+            .collect();
+        let v_slices: Vec<_> = vs.iter().map(|x| x.as_slice()).collect();
+
+        let mut rs_coding = vec![vec![0u8; block_len]; DEFAULT_NUM_CODING];
+        let mut jerasure_coding = rs_coding.clone();
+        {
+            let mut slices: Vec<_> = rs_coding.iter_mut().map(|x| x.as_mut_slice()).collect();
+            reed_solomon::generate_coding_blocks(&mut slices, &v_slices).unwrap();
+        }
+        {
+            let mut slices: Vec<_> = jerasure_coding
+                .iter_mut()
+                .map(|x| x.as_mut_slice())
+                .collect();
+            jerasure::generate_coding_blocks(&mut slices, &v_slices).unwrap();
+        }
+        assert_eq!(rs_coding, jerasure_coding);
+
+        // jerasure's coding blocks decode with the pure-Rust backend
+        let mut damaged = vs.clone();
+        damaged[0] = vec![0; block_len];
+        damaged[3] = vec![0; block_len];
+        {
+            let coding_slices: Vec<_> = jerasure_coding.iter().map(|x| x.as_slice()).collect();
+            let mut slices: Vec<_> = damaged.iter_mut().map(|x| x.as_mut_slice()).collect();
+            reed_solomon::decode_blocks(&mut slices, &coding_slices, &[0, 3, -1]).unwrap();
+        }
+        assert_eq!(damaged, vs);
     }
 }
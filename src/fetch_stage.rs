@@ -1,13 +1,129 @@
 //! The `fetch_stage` batches input from a UDP socket and sends it to a channel.
+//! Packets wait in a queue per source address and are batched round-robin across
+//! the sources, so a flood from one address sheds that address's packets rather
+//! than everyone's. The channel holds `FETCH_QUEUE_CAPACITY` packets, and the
+//! stage stops batching while it's full. Should the stage itself fall behind,
+//! the sockets' receivers shed their oldest batches beyond
+//! `RECEIVE_QUEUE_CAPACITY` packets.
 
-use packet::PacketRecycler;
+use bounded_queue::{bounded_channel, BoundedReceiver, BoundedSender, QueueItem, ShedPolicy};
+use counter::Counter;
+use packet::{Packet, PacketRecycler, SharedPackets, NUM_PACKETS};
+use result::{Error, Result};
 use service::Service;
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::channel;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::net::{IpAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
-use std::thread::{self, JoinHandle};
-use streamer::{self, PacketReceiver};
+use std::thread::{self, Builder, JoinHandle};
+use std::time::Duration;
+use streamer;
+
+/// Packets the channel to the next stage holds
+pub const FETCH_QUEUE_CAPACITY: usize = 64 * NUM_PACKETS;
+
+/// Packets waiting in the source queues before the longest queue is shed
+pub const MAX_QUEUED_PACKETS: usize = 64 * NUM_PACKETS;
+
+/// Packets received from the sockets that wait for the stage to queue them
+pub const RECEIVE_QUEUE_CAPACITY: usize = 64 * NUM_PACKETS;
+
+/// milliseconds the stage waits for packets before batching what it has
+const FETCH_TIMEOUT_MS: u64 = 10;
+
+impl QueueItem for SharedPackets {
+    fn num_transactions(&self) -> usize {
+        self.read().unwrap().packets.len()
+    }
+}
+
+/// Packets queued by source address and taken round-robin across the sources
+#[derive(Default)]
+pub struct FairQueue {
+    queues: HashMap<IpAddr, VecDeque<Packet>>,
+    /// the sources with queued packets, in the order they're taken from
+    order: VecDeque<IpAddr>,
+    /// the sources with queued packets by the length of their queue, so the
+    /// longest is found without a scan over every source
+    by_len: BTreeSet<(usize, IpAddr)>,
+    len: usize,
+}
+
+impl FairQueue {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Record that the queue of `ip` went from `old_len` packets to `new_len`
+    fn resize(&mut self, ip: IpAddr, old_len: usize, new_len: usize) {
+        self.by_len.remove(&(old_len, ip));
+        if new_len > 0 {
+            self.by_len.insert((new_len, ip));
+        }
+    }
+
+    /// Queue `packet`, shedding the oldest packet of the longest queue if
+    /// more than `max_len` are queued. Returns whether a packet was shed.
+    pub fn push(&mut self, packet: Packet, max_len: usize) -> bool {
+        let ip = packet.meta.addr().ip();
+        let queue_len = {
+            let order = &mut self.order;
+            let queue = self.queues.entry(ip).or_insert_with(|| {
+                order.push_back(ip);
+                VecDeque::new()
+            });
+            queue.push_back(packet);
+            queue.len()
+        };
+        self.resize(ip, queue_len - 1, queue_len);
+        self.len += 1;
+        if self.len <= max_len {
+            return false;
+        }
+        let (longest_len, longest) = *self.by_len.iter().next_back().unwrap();
+        self.queues.get_mut(&longest).unwrap().pop_front();
+        self.resize(longest, longest_len, longest_len - 1);
+        self.len -= 1;
+        true
+    }
+
+    /// Take up to `num` packets, one from each source in turn
+    pub fn take(&mut self, num: usize) -> Vec<Packet> {
+        let mut packets = vec![];
+        while packets.len() < num {
+            let ip = match self.order.pop_front() {
+                Some(ip) => ip,
+                None => break,
+            };
+            let (popped, queue_len) = {
+                let queue = self.queues.get_mut(&ip).unwrap();
+                let popped = match queue.pop_front() {
+                    Some(packet) => {
+                        packets.push(packet);
+                        true
+                    }
+                    None => false,
+                };
+                (popped, queue.len())
+            };
+            if popped {
+                self.resize(ip, queue_len + 1, queue_len);
+            }
+            if queue_len == 0 {
+                self.queues.remove(&ip);
+            } else {
+                self.order.push_back(ip);
+            }
+        }
+        self.len -= packets.len();
+        packets
+    }
+}
 
 pub struct FetchStage {
     exit: Arc<AtomicBool>,
@@ -19,19 +135,20 @@ impl FetchStage {
         socket: UdpSocket,
         exit: Arc<AtomicBool>,
         packet_recycler: &PacketRecycler,
-    ) -> (Self, PacketReceiver) {
+    ) -> (Self, BoundedReceiver<SharedPackets>) {
         Self::new_multi_socket(vec![socket], exit, packet_recycler)
     }
     pub fn new_multi_socket(
         sockets: Vec<UdpSocket>,
         exit: Arc<AtomicBool>,
         packet_recycler: &PacketRecycler,
-    ) -> (Self, PacketReceiver) {
-        let (packet_sender, packet_receiver) = channel();
-        let thread_hdls: Vec<_> = sockets
+    ) -> (Self, BoundedReceiver<SharedPackets>) {
+        let (packet_sender, packet_receiver) =
+            bounded_channel(RECEIVE_QUEUE_CAPACITY, ShedPolicy::DropOldest);
+        let mut thread_hdls: Vec<_> = sockets
             .into_iter()
             .map(|socket| {
-                streamer::receiver(
+                streamer::bounded_receiver(
                     socket,
                     exit.clone(),
                     packet_recycler.clone(),
@@ -40,7 +157,76 @@ impl FetchStage {
             })
             .collect();
 
-        (FetchStage { exit, thread_hdls }, packet_receiver)
+        let (queue_sender, queue_receiver) =
+            bounded_channel(FETCH_QUEUE_CAPACITY, ShedPolicy::DropOldest);
+        let recycler = packet_recycler.clone();
+        let thread_hdl = Builder::new()
+            .name("solana-fetch-stage".to_string())
+            .spawn(move || {
+                let mut fair_queue = FairQueue::default();
+                loop {
+                    if let Err(e) = Self::fair_queue(
+                        &mut fair_queue,
+                        &packet_receiver,
+                        &queue_sender,
+                        &recycler,
+                    ) {
+                        match e {
+                            Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                            _ => error!("{:?}", e),
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        thread_hdls.push(thread_hdl);
+
+        (FetchStage { exit, thread_hdls }, queue_receiver)
+    }
+
+    /// Queue the packets received by source, and batch them into `queue_sender`
+    /// while it has room.
+    fn fair_queue(
+        fair_queue: &mut FairQueue,
+        packet_receiver: &BoundedReceiver<SharedPackets>,
+        queue_sender: &BoundedSender<SharedPackets>,
+        recycler: &PacketRecycler,
+    ) -> Result<()> {
+        let timer = Duration::from_millis(FETCH_TIMEOUT_MS);
+        let mut received = vec![];
+        match packet_receiver.recv_timeout(timer) {
+            Ok(msgs) => received.push(msgs),
+            // the queued packets still go out while the sources are quiet
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(e) => Err(e)?,
+        }
+        while let Ok(msgs) = packet_receiver.try_recv() {
+            received.push(msgs);
+        }
+        let mut num_shed = 0;
+        for msgs in received {
+            for packet in &msgs.read().unwrap().packets {
+                if fair_queue.push(packet.clone(), MAX_QUEUED_PACKETS) {
+                    num_shed += 1;
+                }
+            }
+            recycler.recycle(msgs);
+        }
+        if num_shed > 0 {
+            inc_new_counter!("fetch_stage-shed", num_shed);
+        }
+
+        while !fair_queue.is_empty() && !queue_sender.is_full() {
+            let msgs = recycler.allocate();
+            msgs.write().unwrap().packets = fair_queue.take(NUM_PACKETS);
+            for shed in queue_sender.send(msgs)? {
+                inc_new_counter!("fetch_stage-shed", shed.num_transactions());
+                recycler.recycle(shed);
+            }
+        }
+        inc_new_counter!("fetch_stage-fair_queue_depth", fair_queue.len());
+        inc_new_counter!("fetch_stage-queue_depth", queue_sender.len());
+        Ok(())
     }
 
     pub fn close(&self) {
@@ -60,3 +246,66 @@ impl Service for FetchStage {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fetch_stage::FairQueue;
+    use packet::Packet;
+    use std::net::SocketAddr;
+
+    fn packet_from(addr: &SocketAddr, tag: u8) -> Packet {
+        let mut packet = Packet::default();
+        packet.meta.set_addr(addr);
+        packet.data[0] = tag;
+        packet
+    }
+
+    #[test]
+    fn test_fair_queue() {
+        let flood: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        let client: SocketAddr = "127.0.0.2:8000".parse().unwrap();
+        let mut fair_queue = FairQueue::default();
+        for i in 0..10 {
+            assert!(!fair_queue.push(packet_from(&flood, i), 12));
+        }
+        assert!(!fair_queue.push(packet_from(&client, 100), 12));
+        assert!(!fair_queue.push(packet_from(&client, 101), 12));
+
+        // the flood's oldest packets are shed, not the client's
+        assert!(fair_queue.push(packet_from(&flood, 10), 12));
+        assert!(fair_queue.push(packet_from(&client, 102), 12));
+        assert_eq!(fair_queue.len(), 12);
+
+        let tags: Vec<_> = fair_queue.take(6).iter().map(|p| p.data[0]).collect();
+        assert_eq!(tags, vec![2, 100, 3, 101, 4, 102]);
+        let tags: Vec<_> = fair_queue.take(10).iter().map(|p| p.data[0]).collect();
+        assert_eq!(tags, vec![5, 6, 7, 8, 9, 10]);
+        assert!(fair_queue.is_empty());
+        assert!(fair_queue.take(1).is_empty());
+    }
+
+    #[test]
+    fn test_fair_queue_many_sources() {
+        let flood: SocketAddr = "10.0.0.1:8000".parse().unwrap();
+        let mut fair_queue = FairQueue::default();
+        for i in 0..10 {
+            assert!(!fair_queue.push(packet_from(&flood, i), 1000));
+        }
+        for i in 0..990u32 {
+            let client = SocketAddr::new([127, 0, (i >> 8) as u8, i as u8].into(), 8000);
+            assert!(!fair_queue.push(packet_from(&client, 100), 1000));
+        }
+        // only the flood is shed, every client keeps its packet
+        for i in 0..9 {
+            assert!(fair_queue.push(packet_from(&flood, 10 + i), 1000));
+        }
+        let packets = fair_queue.take(2000);
+        assert_eq!(packets.len(), 1000);
+        let flooded: Vec<_> = packets
+            .iter()
+            .filter(|p| p.meta.addr() == flood)
+            .map(|p| p.data[0])
+            .collect();
+        assert_eq!(flooded, vec![9, 10, 11, 12, 13, 14, 15, 16, 17, 18]);
+    }
+}
@@ -0,0 +1,259 @@
+//! The `forward_stage` lets a validator accept transactions on its `tpu` address,
+//! although only the leader runs a `Tpu`. The packets are batched by a
+//! `FetchStage`, which fair-queues them by source and sheds them under load, and
+//! each batch is relayed to the `tpu` address of whichever node `Crdt` knows as
+//! the leader at the time. Clients can submit to any node they know, and their
+//! submissions follow the leader when it changes.
+//!
+//! Only transactions with a valid signature that weren't forwarded recently are
+//! relayed, so a validator can't be used to push garbage or replays at the
+//! leader. Forwarded packets are sent from the validator's own `tpu` address,
+//! which tags them: packets arriving from another node's `tpu` address are never
+//! forwarded again, so two validators that each take the other for the leader
+//! don't bounce transactions between them. The leader fair-queues forwarded
+//! traffic as coming from the validator, which has already given each of its
+//! clients a fair share.
+
+use bounded_queue::BoundedReceiver;
+use counter::Counter;
+use crdt::Crdt;
+use fetch_stage::FetchStage;
+use net_utils::canonical_addr;
+use packet::{PacketRecycler, SharedPackets};
+use result::{Error, Result};
+use service::Service;
+use sigverify::{self, SigDedup, DEDUP_CAPACITY};
+use std::collections::HashSet;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, RwLock};
+use std::thread::{self, Builder, JoinHandle};
+use std::time::Duration;
+
+pub struct ForwardStage {
+    fetch_stage: FetchStage,
+    thread_hdl: JoinHandle<()>,
+}
+
+impl ForwardStage {
+    /// Forward the transactions received on `transaction_socket` to the leader
+    /// in `crdt`. They are sent from `transaction_socket` too.
+    pub fn new(
+        crdt: Arc<RwLock<Crdt>>,
+        transaction_socket: UdpSocket,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        sigverify::init();
+        let packet_recycler = PacketRecycler::default();
+        let send_socket = transaction_socket
+            .try_clone()
+            .expect("transaction socket clone in ForwardStage::new");
+        let (fetch_stage, packet_receiver) =
+            FetchStage::new(transaction_socket, exit, &packet_recycler);
+        let thread_hdl = Builder::new()
+            .name("solana-forward-stage".to_string())
+            .spawn(move || {
+                let mut dedup = SigDedup::new(DEDUP_CAPACITY);
+                loop {
+                    if let Err(e) = Self::forward(
+                        &crdt,
+                        &packet_receiver,
+                        &send_socket,
+                        &packet_recycler,
+                        &mut dedup,
+                    ) {
+                        match e {
+                            Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                            Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                            _ => error!("{:?}", e),
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        ForwardStage {
+            fetch_stage,
+            thread_hdl,
+        }
+    }
+
+    /// The `tpu` address of the leader, unless this node is the leader or
+    /// doesn't know where it is
+    fn leader_tpu(crdt: &RwLock<Crdt>) -> Option<SocketAddr> {
+        let crdt = crdt.read().expect("'crdt' read lock in fn leader_tpu");
+        let me = crdt.my_data();
+        let leader = crdt.leader_data()?;
+        if leader.id == me.id {
+            return None;
+        }
+        let addr = leader.contact_info.reachable_from(&me.contact_info).tpu;
+        if addr.ip().is_unspecified() || addr.port() == 0 {
+            return None;
+        }
+        Some(addr)
+    }
+
+    /// The `tpu` addresses of the other nodes, which forwarded packets come from
+    fn forwarder_addrs(crdt: &RwLock<Crdt>) -> HashSet<SocketAddr> {
+        let crdt = crdt.read().expect("'crdt' read lock in fn forwarder_addrs");
+        let mut addrs = HashSet::new();
+        for v in crdt.table.values().filter(|v| v.id != crdt.me) {
+            let tpu = v.contact_info.tpu;
+            addrs.insert(canonical_addr(&tpu));
+            if let Some(ip) = v.contact_info.alt_ip {
+                addrs.insert(canonical_addr(&SocketAddr::new(ip, tpu.port())));
+            }
+        }
+        addrs
+    }
+
+    /// Drop the packets in `msgs` that were forwarded by another node, repeat
+    /// a transaction forwarded recently or fail signature verification
+    fn filter(crdt: &RwLock<Crdt>, msgs: &SharedPackets, dedup: &mut SigDedup) {
+        let forwarders = Self::forwarder_addrs(crdt);
+        {
+            let mut msgs = msgs.write().unwrap();
+            let len = msgs.packets.len();
+            msgs.packets
+                .retain(|packet| !forwarders.contains(&packet.meta.addr()));
+            let num_forwarded = len - msgs.packets.len();
+            if num_forwarded > 0 {
+                inc_new_counter!("forward_stage-already_forwarded", num_forwarded);
+            }
+        }
+        dedup.dedup_packets(&[msgs.clone()]);
+        let batch = vec![msgs.clone()];
+        let mut vers = sigverify::ed25519_verify(&batch).remove(0).into_iter();
+        let mut msgs = msgs.write().unwrap();
+        let len = msgs.packets.len();
+        msgs.packets.retain(|_| vers.next() == Some(1));
+        let num_invalid = len - msgs.packets.len();
+        if num_invalid > 0 {
+            inc_new_counter!("forward_stage-invalid_signature", num_invalid);
+        }
+    }
+
+    fn forward(
+        crdt: &RwLock<Crdt>,
+        packet_receiver: &BoundedReceiver<SharedPackets>,
+        socket: &UdpSocket,
+        recycler: &PacketRecycler,
+        dedup: &mut SigDedup,
+    ) -> Result<()> {
+        let timer = Duration::new(1, 0);
+        let msgs = packet_receiver.recv_timeout(timer)?;
+        Self::filter(crdt, &msgs, dedup);
+        let len = msgs.read().unwrap().packets.len();
+        let result = match Self::leader_tpu(crdt) {
+            Some(_) if len == 0 => Ok(()),
+            Some(addr) => {
+                for packet in &mut msgs.write().unwrap().packets {
+                    packet.meta.set_addr(&addr);
+                }
+                let result = msgs.read().unwrap().send_to(socket);
+                if result.is_ok() {
+                    inc_new_counter!("forward_stage-forwarded", len);
+                }
+                result
+            }
+            None => {
+                inc_new_counter!("forward_stage-no_leader", len);
+                Ok(())
+            }
+        };
+        recycler.recycle(msgs);
+        result
+    }
+
+    pub fn close(&self) {
+        self.fetch_stage.close();
+    }
+}
+
+impl Service for ForwardStage {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        let mut thread_hdls = self.fetch_stage.thread_hdls();
+        thread_hdls.push(self.thread_hdl);
+        thread_hdls
+    }
+
+    fn join(self) -> thread::Result<()> {
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::serialize;
+    use crdt::{Crdt, NodeInfo, TestNode};
+    use forward_stage::ForwardStage;
+    use hash::Hash;
+    use service::Service;
+    use signature::{KeyPair, KeyPairUtil};
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::time::Duration;
+    use transaction::Transaction;
+
+    fn transaction() -> Vec<u8> {
+        let tx = Transaction::new(&KeyPair::new(), KeyPair::new().pubkey(), 1, Hash::default());
+        serialize(&tx).unwrap()
+    }
+
+    #[test]
+    fn test_forward_to_leader() {
+        let validator = TestNode::new_localhost();
+        let validator_tpu = validator.data.contact_info.tpu;
+        let leader_tpu = UdpSocket::bind("127.0.0.1:0").unwrap();
+        leader_tpu
+            .set_read_timeout(Some(Duration::new(5, 0)))
+            .unwrap();
+        let addr = leader_tpu.local_addr().unwrap();
+        let leader = NodeInfo::new(KeyPair::new().pubkey(), addr, addr, addr, addr, addr);
+
+        // a validator that takes this one for the leader
+        let peer_tpu = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = peer_tpu.local_addr().unwrap();
+        let peer = NodeInfo::new(KeyPair::new().pubkey(), addr, addr, addr, addr, addr);
+
+        let mut crdt = Crdt::new(validator.data).unwrap();
+        crdt.insert(&leader);
+        crdt.insert(&peer);
+        crdt.set_leader(leader.id);
+        let exit = Arc::new(AtomicBool::new(false));
+        let forward_stage = ForwardStage::new(
+            Arc::new(RwLock::new(crdt)),
+            validator.sockets.transaction,
+            exit.clone(),
+        );
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tx0 = transaction();
+        let tx1 = transaction();
+        // forwarded by the peer already
+        peer_tpu.send_to(&transaction(), &validator_tpu).unwrap();
+        client.send_to(b"transaction", &validator_tpu).unwrap();
+        client.send_to(&tx0, &validator_tpu).unwrap();
+        client.send_to(&tx0, &validator_tpu).unwrap();
+        client.send_to(&tx1, &validator_tpu).unwrap();
+
+        let mut buf = [0u8; 1024];
+        for tx in &[tx0, tx1] {
+            let (len, from) = leader_tpu.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], &tx[..]);
+            assert_eq!(from, validator_tpu);
+        }
+        leader_tpu
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(leader_tpu.recv_from(&mut buf).is_err());
+
+        exit.store(true, Ordering::Relaxed);
+        forward_stage.join().unwrap();
+    }
+}
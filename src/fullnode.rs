@@ -1,45 +1,128 @@
 //! The `fullnode` module hosts all the fullnode microservices.
 
 use bank::Bank;
+use choose_gossip_peer_strategy::GossipStrategy;
 use crdt::{Crdt, NodeInfo, TestNode};
 use entry::Entry;
-use entry_writer;
+use entry_writer::{self, LedgerWriter, SyncPolicy};
+use forward_stage::ForwardStage;
+use keystore::{read_passphrase, Keystore, KeystoreError};
 use ledger::Block;
 use ncp::Ncp;
 use packet::BlobRecycler;
+use record_stage::MixinSender;
 use rpu::Rpu;
+use segmented_ledger::{SegmentConfig, SegmentedLedger};
 use service::Service;
 use signature::{KeyPair, KeyPairUtil};
 use std::collections::VecDeque;
-use std::fs::{File, OpenOptions};
+use std::error;
+use std::fs::File;
 use std::io::{stdin, stdout, BufReader};
 use std::io::{Read, Write};
 use std::net::SocketAddr;
+use std::result;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread::{JoinHandle, Result};
 use std::time::Duration;
-use streamer;
+use streamer::{self, WINDOW_SIZE};
 use tpu::Tpu;
 use tvu::Tvu;
 use untrusted::Input;
 
+/// Recover the ledger at `path` from any torn write and open it for reading
+/// from the start and for appending after the last good entry.
+fn open_ledger(path: String, policy: SyncPolicy) -> (Box<Read>, Box<Write + Send>) {
+    info!("recovering ledger...");
+    let good = entry_writer::recover_ledger(&path).expect("recovering ledger file");
+    info!("recovered {} ledger entries", good);
+    (
+        Box::new(File::open(path.clone()).expect("opening ledger file")),
+        Box::new(LedgerWriter::open(path, policy).expect("opening ledger file")),
+    )
+}
+
+/// Replay a whole ledger from genesis into a new bank. Returns the bank, the
+/// ledger's height and its tail.
+fn process_ledger(infile: Box<Read>) -> (Bank, u64, Vec<Entry>) {
+    let bank = Bank::default();
+    let reader = BufReader::new(infile);
+    let entries = entry_writer::read_entries(reader).map(|e| e.expect("failed to parse entry"));
+
+    info!("processing ledger...");
+    let (entry_height, ledger_tail) = bank.process_ledger(entries).expect("process_ledger");
+    (bank, entry_height, ledger_tail)
+}
+
+/// Restore the bank from the latest snapshot in a segmented ledger, or from
+/// genesis if there is none, and replay the entries after it. The replayed
+/// bank is then snapshotted, which lets the segments below it be pruned. From
+/// then on the ledger's writer takes the snapshots as segments fill.
+fn process_segmented_ledger(
+    dir: String,
+    config: SegmentConfig,
+) -> (Bank, u64, Vec<Entry>, SegmentedLedger) {
+    info!("opening ledger segments...");
+    let ledger = SegmentedLedger::open(dir, config).expect("opening ledger directory");
+    let (bank, entry_height) = match ledger.load_snapshot().expect("loading bank snapshot") {
+        Some(snapshot) => {
+            let snapshot_height = snapshot.entry_height;
+            info!("restoring bank from snapshot at height {}", snapshot_height);
+            let bank = Bank::from_snapshot(snapshot);
+            let reader = BufReader::new(
+                ledger
+                    .reader_from(snapshot_height)
+                    .expect("reading ledger segments"),
+            );
+            let entries =
+                entry_writer::read_entries(reader).map(|e| e.expect("failed to parse entry"));
+            info!("processing ledger...");
+            let entry_height = bank.process_ledger_from(snapshot_height, entries)
+                .expect("process_ledger_from");
+            (bank, entry_height)
+        }
+        None => {
+            let reader = ledger.reader().expect("reading ledger segments");
+            let (bank, entry_height, _) = process_ledger(reader);
+            (bank, entry_height)
+        }
+    };
+    let ledger_tail = ledger
+        .tail(entry_height, WINDOW_SIZE)
+        .expect("reading ledger tail");
+    ledger
+        .save_snapshot(&bank.snapshot(entry_height))
+        .expect("saving bank snapshot");
+    (bank, entry_height, ledger_tail, ledger)
+}
+
 //use std::time::Duration;
 pub struct FullNode {
     exit: Arc<AtomicBool>,
     thread_hdls: Vec<JoinHandle<()>>,
+    mixin_sender: Option<MixinSender>,
 }
 
 pub enum LedgerFile {
     StdInOut,
+    /// A ledger file synced with the default `SyncPolicy`
     Path(String),
+    /// A ledger file synced with the given `SyncPolicy`
+    SyncedPath(String, SyncPolicy),
+    /// A directory of ledger segments
+    Segmented(String, SegmentConfig),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 /// Fullnode configuration to be stored in file
 pub struct Config {
     pub node_info: NodeInfo,
+    /// empty if the keypair is kept in `keystore` instead
+    #[serde(default)]
     pkcs8: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keystore: Option<Keystore>,
 }
 
 /// Structure to be replicated by the network
@@ -49,11 +132,41 @@ impl Config {
             KeyPair::from_pkcs8(Input::from(&pkcs8)).expect("from_pkcs8 in fullnode::Config new");
         let pubkey = keypair.pubkey();
         let node_info = NodeInfo::new_leader_with_pubkey(pubkey, bind_addr);
-        Config { node_info, pkcs8 }
+        Config {
+            node_info,
+            pkcs8,
+            keystore: None,
+        }
     }
-    pub fn keypair(&self) -> KeyPair {
-        KeyPair::from_pkcs8(Input::from(&self.pkcs8))
-            .expect("from_pkcs8 in fullnode::Config keypair")
+
+    /// A Config whose keypair is encrypted under `passphrase`
+    pub fn new_encrypted(bind_addr: &SocketAddr, pkcs8: &[u8], passphrase: &str) -> Self {
+        let mut config = Self::new(bind_addr, pkcs8.to_vec());
+        config.pkcs8 = vec![];
+        config.keystore = Some(Keystore::encrypt(pkcs8, passphrase));
+        config
+    }
+
+    /// The node's keypair, decrypted with `passphrase` if it's encrypted
+    pub fn keypair_with_passphrase(
+        &self,
+        passphrase: &str,
+    ) -> result::Result<KeyPair, KeystoreError> {
+        let pkcs8 = match self.keystore {
+            Some(ref keystore) => keystore.decrypt(passphrase)?,
+            None => self.pkcs8.clone(),
+        };
+        KeyPair::from_pkcs8(Input::from(&pkcs8)).map_err(|_| KeystoreError::BadKeystore)
+    }
+
+    /// The node's keypair, decrypted with the passphrase from
+    /// `keystore::read_passphrase` if it's encrypted
+    pub fn keypair(&self) -> result::Result<KeyPair, Box<error::Error>> {
+        let passphrase = match self.keystore {
+            Some(_) => read_passphrase("Passphrase for the fullnode keypair: ")?,
+            None => String::new(),
+        };
+        Ok(self.keypair_with_passphrase(&passphrase)?)
     }
 }
 
@@ -64,27 +177,33 @@ impl FullNode {
         ledger: LedgerFile,
         keypair_for_validator: Option<KeyPair>,
         network_entry_for_validator: Option<SocketAddr>,
+        gossip_strategy: GossipStrategy,
     ) -> FullNode {
         info!("creating bank...");
-        let bank = Bank::default();
-        let (infile, outfile): (Box<Read>, Box<Write + Send>) = match ledger {
-            LedgerFile::Path(path) => (
-                Box::new(File::open(path.clone()).expect("opening ledger file")),
-                Box::new(
-                    OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(path)
-                        .expect("opening ledger file"),
-                ),
-            ),
-            LedgerFile::StdInOut => (Box::new(stdin()), Box::new(stdout())),
+        let (bank, entry_height, ledger_tail, outfile, repair_ledger) = match ledger {
+            LedgerFile::Path(path) => {
+                let (infile, outfile) = open_ledger(path, SyncPolicy::default());
+                let (bank, entry_height, ledger_tail) = process_ledger(infile);
+                (bank, entry_height, ledger_tail, outfile, None)
+            }
+            LedgerFile::SyncedPath(path, policy) => {
+                let (infile, outfile) = open_ledger(path, policy);
+                let (bank, entry_height, ledger_tail) = process_ledger(infile);
+                (bank, entry_height, ledger_tail, outfile, None)
+            }
+            LedgerFile::Segmented(dir, config) => {
+                let (bank, entry_height, ledger_tail, ledger) =
+                    process_segmented_ledger(dir, config);
+                let writer: Box<Write + Send> =
+                    Box::new(ledger.writer().expect("opening ledger segment"));
+                (bank, entry_height, ledger_tail, writer, Some(ledger))
+            }
+            LedgerFile::StdInOut => {
+                let (bank, entry_height, ledger_tail) = process_ledger(Box::new(stdin()));
+                let writer: Box<Write + Send> = Box::new(stdout());
+                (bank, entry_height, ledger_tail, writer, None)
+            }
         };
-        let reader = BufReader::new(infile);
-        let entries = entry_writer::read_entries(reader).map(|e| e.expect("failed to parse entry"));
-
-        info!("processing ledger...");
-        let (entry_height, ledger_tail) = bank.process_ledger(entries).expect("process_ledger");
         // entry_height is the network-wide agreed height of the ledger.
         //  initialize it from the input ledger
         info!("processed {} ledger...", entry_height);
@@ -112,6 +231,8 @@ impl FullNode {
                 node,
                 &network_entry_point,
                 exit.clone(),
+                repair_ledger,
+                gossip_strategy,
             );
             info!(
                 "validator ready... local request address: {} (advertising {}) connected to: {}",
@@ -130,6 +251,8 @@ impl FullNode {
                 node,
                 exit.clone(),
                 outfile,
+                repair_ledger,
+                gossip_strategy,
             );
             info!(
                 "leader ready... local request address: {} (advertising {})",
@@ -191,6 +314,8 @@ impl FullNode {
         node: TestNode,
         exit: Arc<AtomicBool>,
         writer: W,
+        repair_ledger: Option<SegmentedLedger>,
+        gossip_strategy: GossipStrategy,
     ) -> Self {
         let bank = Arc::new(bank);
         let mut thread_hdls = vec![];
@@ -203,16 +328,24 @@ impl FullNode {
         thread_hdls.extend(rpu.thread_hdls());
 
         let blob_recycler = BlobRecycler::default();
-        let crdt = Arc::new(RwLock::new(Crdt::new(node.data).expect("Crdt::new")));
+        let mut crdt = Crdt::new(node.data).expect("Crdt::new");
+        if let Some(ledger) = repair_ledger {
+            crdt.set_ledger(ledger);
+        }
+        crdt.set_bank(bank.clone());
+        crdt.set_gossip_strategy(gossip_strategy);
+        let crdt = Arc::new(RwLock::new(crdt));
         let (tpu, blob_receiver) = Tpu::new(
             &bank,
             &crdt,
             tick_duration,
             node.sockets.transaction,
+            node.sockets.transaction_stream,
             &blob_recycler,
             exit.clone(),
             writer,
         );
+        let mixin_sender = tpu.mixin_sender();
         thread_hdls.extend(tpu.thread_hdls());
         let window = FullNode::new_window(ledger_tail, entry_height, &crdt, &blob_recycler);
         let ncp = Ncp::new(
@@ -234,10 +367,15 @@ impl FullNode {
         );
         thread_hdls.extend(vec![t_broadcast]);
 
-        FullNode { exit, thread_hdls }
+        FullNode {
+            exit,
+            thread_hdls,
+            mixin_sender: Some(mixin_sender),
+        }
     }
 
-    /// Create a server instance acting as a validator.
+    /// Create a server instance acting as a validator. Transactions sent to
+    /// its TPU address are forwarded to the leader by a `ForwardStage`.
     ///
     /// ```text
     ///               .-------------------------------.
@@ -274,6 +412,8 @@ impl FullNode {
         node: TestNode,
         entry_point: &NodeInfo,
         exit: Arc<AtomicBool>,
+        repair_ledger: Option<SegmentedLedger>,
+        gossip_strategy: GossipStrategy,
     ) -> Self {
         let bank = Arc::new(bank);
         let mut thread_hdls = vec![];
@@ -285,7 +425,13 @@ impl FullNode {
         );
         thread_hdls.extend(rpu.thread_hdls());
 
-        let crdt = Arc::new(RwLock::new(Crdt::new(node.data).expect("Crdt::new")));
+        let mut crdt = Crdt::new(node.data).expect("Crdt::new");
+        if let Some(ledger) = repair_ledger {
+            crdt.set_ledger(ledger);
+        }
+        crdt.set_bank(bank.clone());
+        crdt.set_gossip_strategy(gossip_strategy);
+        let crdt = Arc::new(RwLock::new(crdt));
         crdt.write()
             .expect("'crdt' write lock before insert() in pub fn replicate")
             .insert(&entry_point);
@@ -313,9 +459,21 @@ impl FullNode {
             node.sockets.retransmit,
             exit.clone(),
         );
+        let forward_stage = ForwardStage::new(crdt, node.sockets.transaction, exit.clone());
         thread_hdls.extend(tvu.thread_hdls());
         thread_hdls.extend(ncp.thread_hdls());
-        FullNode { exit, thread_hdls }
+        thread_hdls.extend(forward_stage.thread_hdls());
+        FullNode {
+            exit,
+            thread_hdls,
+            mixin_sender: None,
+        }
+    }
+
+    /// A handle for mixing external values into the PoH stream, if this node
+    /// is the leader. Drop it before joining the node.
+    pub fn mixin_sender(&self) -> Option<MixinSender> {
+        self.mixin_sender.clone()
     }
 
     //used for notifying many nodes in parallel to exit
@@ -344,14 +502,41 @@ impl Service for FullNode {
 #[cfg(test)]
 mod tests {
     use bank::Bank;
+    use choose_gossip_peer_strategy::GossipStrategy;
     use crdt::TestNode;
-    use fullnode::FullNode;
+    use fullnode::{Config, FullNode};
+    use keystore::KeystoreError;
     use mint::Mint;
+    use serde_json;
     use service::Service;
     use signature::{KeyPair, KeyPairUtil};
     use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
     #[test]
+    fn test_config_encrypted() {
+        let mint = Mint::new(1);
+        let bind_addr = "127.0.0.1:8000".parse().unwrap();
+        let config = Config::new_encrypted(&bind_addr, &mint.pkcs8, "hunter2");
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains("keystore"));
+        let config: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.node_info.id, mint.pubkey());
+
+        assert_eq!(
+            config.keypair_with_passphrase("hunter2").unwrap().pubkey(),
+            mint.pubkey()
+        );
+        assert_eq!(
+            config.keypair_with_passphrase("hunter3").err(),
+            Some(KeystoreError::BadPassphrase)
+        );
+
+        let config = Config::new(&bind_addr, mint.pkcs8.clone());
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("keystore"));
+        assert_eq!(config.keypair().unwrap().pubkey(), mint.pubkey());
+    }
+    #[test]
     fn validator_exit() {
         let kp = KeyPair::new();
         let tn = TestNode::new_localhost_with_pubkey(kp.pubkey());
@@ -359,7 +544,17 @@ mod tests {
         let bank = Bank::new(&alice);
         let exit = Arc::new(AtomicBool::new(false));
         let entry = tn.data.clone();
-        let v = FullNode::new_validator(kp, bank, 0, None, tn, &entry, exit);
+        let v = FullNode::new_validator(
+            kp,
+            bank,
+            0,
+            None,
+            tn,
+            &entry,
+            exit,
+            None,
+            GossipStrategy::default(),
+        );
         v.exit();
         v.join().unwrap();
     }
@@ -373,7 +568,17 @@ mod tests {
                 let bank = Bank::new(&alice);
                 let exit = Arc::new(AtomicBool::new(false));
                 let entry = tn.data.clone();
-                FullNode::new_validator(kp, bank, 0, None, tn, &entry, exit)
+                FullNode::new_validator(
+                    kp,
+                    bank,
+                    0,
+                    None,
+                    tn,
+                    &entry,
+                    exit,
+                    None,
+                    GossipStrategy::default(),
+                )
             })
             .collect();
         //each validator can exit in parallel to speed many sequential calls to `join`
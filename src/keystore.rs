@@ -0,0 +1,241 @@
+//! The `keystore` module encrypts keypair files under a passphrase. The key is
+//! stretched from the passphrase by PBKDF2-HMAC-SHA256 and seals the PKCS#8 bytes
+//! with ChaCha20-Poly1305, so a wrong passphrase or a tampered file fails to
+//! open rather than yielding a bad keypair. Services unlock their keystore
+//! without a terminal by setting `PASSPHRASE_ENV`, or `PASSPHRASE_FD_ENV` to a
+//! file descriptor the passphrase can be read from, once per process.
+
+use libc;
+use ring::aead::{self, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, pbkdf2};
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::{Once, ONCE_INIT};
+
+/// The environment variable holding the passphrase of a keystore
+pub const PASSPHRASE_ENV: &str = "SOLANA_KEYPAIR_PASSPHRASE";
+
+/// The environment variable holding a file descriptor to read the passphrase from
+pub const PASSPHRASE_FD_ENV: &str = "SOLANA_KEYPAIR_PASSPHRASE_FD";
+
+/// PBKDF2 rounds of a new keystore
+const KDF_ITERATIONS: u32 = 100_000;
+
+const SALT_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeystoreError {
+    /// the passphrase is wrong, or the keystore was tampered with
+    BadPassphrase,
+    /// the keystore's parameters are malformed
+    BadKeystore,
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeystoreError::BadPassphrase => write!(f, "wrong passphrase for keystore"),
+            KeystoreError::BadKeystore => write!(f, "malformed keystore"),
+        }
+    }
+}
+
+impl error::Error for KeystoreError {
+    fn description(&self) -> &str {
+        "keystore error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+/// A PKCS#8 keypair sealed under a passphrase
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Keystore {
+    /// PBKDF2-HMAC-SHA256 rounds
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    /// The ChaCha20-Poly1305 ciphertext and tag of the PKCS#8 bytes
+    pub ciphertext: Vec<u8>,
+}
+
+/// A keypair file holds either plain PKCS#8 bytes or a `Keystore`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum KeypairFile {
+    Plain(Vec<u8>),
+    Encrypted(Keystore),
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut key = vec![0u8; CHACHA20_POLY1305.key_len()];
+    pbkdf2::derive(
+        &digest::SHA256,
+        iterations,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    key
+}
+
+impl Keystore {
+    /// Seal `pkcs8` under `passphrase`
+    pub fn encrypt(pkcs8: &[u8], passphrase: &str) -> Self {
+        let rnd = SystemRandom::new();
+        let mut salt = vec![0u8; SALT_LEN];
+        rnd.fill(&mut salt).expect("fill salt in Keystore::encrypt");
+        let mut nonce = vec![0u8; CHACHA20_POLY1305.nonce_len()];
+        rnd.fill(&mut nonce)
+            .expect("fill nonce in Keystore::encrypt");
+
+        let key = derive_key(passphrase, &salt, KDF_ITERATIONS);
+        let key = SealingKey::new(&CHACHA20_POLY1305, &key).expect("SealingKey in encrypt");
+        let tag_len = CHACHA20_POLY1305.tag_len();
+        let mut ciphertext = pkcs8.to_vec();
+        ciphertext.resize(pkcs8.len() + tag_len, 0);
+        let len = aead::seal_in_place(&key, &nonce, &[], &mut ciphertext, tag_len)
+            .expect("seal_in_place in Keystore::encrypt");
+        ciphertext.truncate(len);
+        Keystore {
+            iterations: KDF_ITERATIONS,
+            salt,
+            nonce,
+            ciphertext,
+        }
+    }
+
+    /// The PKCS#8 bytes sealed under `passphrase`
+    pub fn decrypt(&self, passphrase: &str) -> Result<Vec<u8>, KeystoreError> {
+        if self.iterations == 0 || self.nonce.len() != CHACHA20_POLY1305.nonce_len() {
+            return Err(KeystoreError::BadKeystore);
+        }
+        let key = derive_key(passphrase, &self.salt, self.iterations);
+        let key = OpeningKey::new(&CHACHA20_POLY1305, &key).expect("OpeningKey in decrypt");
+        let mut ciphertext = self.ciphertext.clone();
+        let pkcs8 = aead::open_in_place(&key, &self.nonce, &[], 0, &mut ciphertext)
+            .map_err(|_| KeystoreError::BadPassphrase)?;
+        Ok(pkcs8.to_vec())
+    }
+}
+
+fn strip_newline(mut line: String) -> String {
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    line
+}
+
+/// Prompt for a passphrase on the terminal, without echoing it
+fn prompt_passphrase(prompt: &str) -> io::Result<String> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    tty.write_all(prompt.as_bytes())?;
+    let fd = tty.as_raw_fd();
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    let is_terminal = unsafe { libc::tcgetattr(fd, &mut termios) } == 0;
+    if is_terminal {
+        let mut silent = termios;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    }
+    let mut line = String::new();
+    let result = BufReader::new(&tty).read_line(&mut line);
+    if is_terminal {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) };
+    }
+    tty.write_all(b"\n")?;
+    result?;
+    Ok(strip_newline(line))
+}
+
+/// Read the passphrase from the file descriptor named by `fd`, closing it.
+fn read_passphrase_fd(fd: &str) -> Result<String, String> {
+    let fd: i32 = fd
+        .parse()
+        .map_err(|e| format!("{}: {}", PASSPHRASE_FD_ENV, e))?;
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let mut passphrase = String::new();
+    file.read_to_string(&mut passphrase)
+        .map_err(|e| format!("{}: {}", PASSPHRASE_FD_ENV, e))?;
+    Ok(strip_newline(passphrase))
+}
+
+/// The passphrase from the file descriptor in `PASSPHRASE_FD_ENV`. The
+/// descriptor is read and closed by the first call, later calls get a copy.
+fn fd_passphrase(fd: &str) -> Result<String, String> {
+    static INIT: Once = ONCE_INIT;
+    static mut PASSPHRASE: Option<Result<String, String>> = None;
+    unsafe {
+        INIT.call_once(|| PASSPHRASE = Some(read_passphrase_fd(fd)));
+        match PASSPHRASE {
+            Some(ref passphrase) => passphrase.clone(),
+            None => panic!("Failed to read the passphrase descriptor"),
+        }
+    }
+}
+
+/// The passphrase from `PASSPHRASE_ENV`, from the file descriptor in
+/// `PASSPHRASE_FD_ENV`, or else typed at a `prompt` on the terminal
+pub fn read_passphrase(prompt: &str) -> Result<String, Box<error::Error>> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    if let Ok(fd) = env::var(PASSPHRASE_FD_ENV) {
+        return Ok(fd_passphrase(&fd)?);
+    }
+    Ok(prompt_passphrase(prompt)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use keystore::*;
+    use serde_json;
+    use signature::KeyPair;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let rnd = SystemRandom::new();
+        let pkcs8 = KeyPair::generate_pkcs8(&rnd).unwrap().to_vec();
+        let keystore = Keystore::encrypt(&pkcs8, "hunter2");
+        assert_ne!(&keystore.ciphertext[..pkcs8.len()], &pkcs8[..]);
+        assert_eq!(keystore.decrypt("hunter2"), Ok(pkcs8.clone()));
+        assert_eq!(
+            keystore.decrypt("hunter3"),
+            Err(KeystoreError::BadPassphrase)
+        );
+
+        let mut tampered = keystore.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(
+            tampered.decrypt("hunter2"),
+            Err(KeystoreError::BadPassphrase)
+        );
+    }
+
+    #[test]
+    fn test_keypair_file() {
+        let pkcs8 = KeyPair::generate_pkcs8(&SystemRandom::new())
+            .unwrap()
+            .to_vec();
+        let plain = serde_json::to_string(&pkcs8).unwrap();
+        assert_eq!(
+            serde_json::from_str::<KeypairFile>(&plain).unwrap(),
+            KeypairFile::Plain(pkcs8.clone())
+        );
+
+        let keystore = Keystore::encrypt(&pkcs8, "hunter2");
+        let encrypted = serde_json::to_string(&keystore).unwrap();
+        assert_eq!(
+            serde_json::from_str::<KeypairFile>(&encrypted).unwrap(),
+            KeypairFile::Encrypted(keystore)
+        );
+    }
+}
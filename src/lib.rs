@@ -12,37 +12,50 @@ pub mod counter;
 pub mod bank;
 pub mod banking_stage;
 pub mod blob_fetch_stage;
+pub mod bloom;
+pub mod bounded_queue;
+pub mod broadcast_tree;
 pub mod budget;
 pub mod choose_gossip_peer_strategy;
 pub mod crdt;
 pub mod drone;
 pub mod entry;
 pub mod entry_writer;
-#[cfg(feature = "erasure")]
 pub mod erasure;
 pub mod fetch_stage;
+pub mod forward_stage;
 pub mod fullnode;
 pub mod hash;
+#[cfg(feature = "jerasure")]
+pub mod jerasure;
+pub mod keystore;
 pub mod ledger;
 pub mod logger;
 pub mod metrics;
 pub mod mint;
+pub mod mmsg;
+pub mod mnemonic;
 pub mod nat;
 pub mod ncp;
+pub mod net_utils;
 pub mod packet;
 pub mod payment_plan;
 pub mod record_stage;
 pub mod recorder;
+pub mod reed_solomon;
 pub mod replicate_stage;
+pub mod replicator;
 pub mod request;
 pub mod request_processor;
 pub mod request_stage;
 pub mod result;
 pub mod rpu;
+pub mod segmented_ledger;
 pub mod service;
 pub mod signature;
 pub mod sigverify;
 pub mod sigverify_stage;
+pub mod stream_fetch_stage;
 pub mod streamer;
 pub mod thin_client;
 pub mod timing;
@@ -52,9 +65,11 @@ pub mod tvu;
 pub mod voting;
 pub mod window_stage;
 pub mod write_stage;
+extern crate aes;
 extern crate bincode;
 extern crate byteorder;
 extern crate chrono;
+extern crate flate2;
 extern crate generic_array;
 extern crate itertools;
 extern crate libc;
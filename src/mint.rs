@@ -12,6 +12,9 @@ pub struct Mint {
     pub pkcs8: Vec<u8>,
     pubkey: PublicKey,
     pub tokens: i64,
+    /// The number of hashes in every tick, if the ledger fixes one
+    #[serde(default)]
+    pub hashes_per_tick: Option<u64>,
 }
 
 impl Mint {
@@ -23,6 +26,7 @@ impl Mint {
             pkcs8,
             pubkey,
             tokens,
+            hashes_per_tick: None,
         }
     }
 
@@ -53,7 +57,12 @@ impl Mint {
     pub fn create_transactions(&self) -> Vec<Transaction> {
         let keypair = self.keypair();
         let tx = Transaction::new(&keypair, self.pubkey(), self.tokens, self.seed());
-        vec![tx]
+        let mut transactions = vec![tx];
+        if let Some(hashes_per_tick) = self.hashes_per_tick {
+            let tx = Transaction::new_hashes_per_tick(&keypair, hashes_per_tick, self.seed());
+            transactions.push(tx);
+        }
+        transactions
     }
 
     pub fn create_entries(&self) -> Vec<Entry> {
@@ -82,6 +91,20 @@ mod tests {
         assert_eq!(transactions.next(), None);
     }
 
+    #[test]
+    fn test_create_transactions_hashes_per_tick() {
+        let mut mint = Mint::new(100);
+        mint.hashes_per_tick = Some(1_000);
+        let transactions = mint.create_transactions();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(
+            transactions[1].instruction,
+            Instruction::SetHashesPerTick(1_000)
+        );
+        assert_eq!(transactions[1].from, mint.pubkey());
+        assert!(transactions[1].verify_signature());
+    }
+
     #[test]
     fn test_verify_entries() {
         let entries = Mint::new(100).create_entries();
@@ -0,0 +1,338 @@
+//! The `mmsg` module batches UDP socket I/O. On Linux it fills and drains whole
+//! packet buffers with the `recvmmsg`/`sendmmsg` syscalls; on other platforms it
+//! falls back to one `recv_from`/`send_to` per datagram.
+
+use net_utils::{is_ipv6, send_addr};
+use packet::Packet;
+use std::cmp;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+/// Maximum number of datagrams read by a single `recv_mmsg` call
+pub const NUM_RCVMMSGS: usize = 128;
+
+/// Maximum number of datagrams written by a single `sendmmsg` syscall, kernels
+/// refuse vectors longer than `UIO_MAXIOV`
+pub const NUM_SNDMMSGS: usize = 1024;
+
+/// Portable receive. Blocks for the first datagram according to the socket's
+/// settings, then switches the socket to non-blocking and reads until it would
+/// block or `packets` is full.
+pub fn recv_each(socket: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize> {
+    let mut i = 0;
+    let count = cmp::min(NUM_RCVMMSGS, packets.len());
+    for p in packets.iter_mut().take(count) {
+        p.meta.size = 0;
+        match socket.recv_from(&mut p.data) {
+            Err(_) if i > 0 => break,
+            Err(e) => return Err(e),
+            Ok((nrecv, from)) => {
+                p.meta.size = nrecv;
+                p.meta.set_addr(&from);
+                if i == 0 {
+                    socket.set_nonblocking(true)?;
+                }
+            }
+        }
+        i += 1;
+    }
+    Ok(i)
+}
+
+/// Portable send, one `send_to` per datagram. Returns the number of datagrams sent.
+pub fn send_each(socket: &UdpSocket, packets: &[(&[u8], SocketAddr)]) -> io::Result<usize> {
+    let ipv6 = is_ipv6(socket);
+    for (data, addr) in packets {
+        socket.send_to(data, send_addr(ipv6, addr))?;
+    }
+    Ok(packets.len())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv_mmsg(socket: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize> {
+    recv_each(socket, packets)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_mmsg(socket: &UdpSocket, packets: &[(&[u8], SocketAddr)]) -> io::Result<usize> {
+    send_each(socket, packets)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use libc::{
+        c_int, c_void, in6_addr, in_addr, iovec, mmsghdr, sa_family_t, sockaddr_in, sockaddr_in6,
+        sockaddr_storage, socklen_t, AF_INET, AF_INET6,
+    };
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+    pub fn empty_iovec() -> iovec {
+        iovec {
+            iov_base: 0 as *mut c_void,
+            iov_len: 0,
+        }
+    }
+
+    pub fn empty_mmsghdr() -> mmsghdr {
+        unsafe { mem::zeroed() }
+    }
+
+    pub fn empty_sockaddr() -> sockaddr_storage {
+        unsafe { mem::zeroed() }
+    }
+
+    pub fn to_sockaddr(addr: &SocketAddr, storage: &mut sockaddr_storage) -> socklen_t {
+        match *addr {
+            SocketAddr::V4(ref a) => {
+                let sin = storage as *mut sockaddr_storage as *mut sockaddr_in;
+                unsafe {
+                    (*sin).sin_family = AF_INET as sa_family_t;
+                    (*sin).sin_port = a.port().to_be();
+                    (*sin).sin_addr = in_addr {
+                        s_addr: u32::from(*a.ip()).to_be(),
+                    };
+                }
+                mem::size_of::<sockaddr_in>() as socklen_t
+            }
+            SocketAddr::V6(ref a) => {
+                let sin6 = storage as *mut sockaddr_storage as *mut sockaddr_in6;
+                unsafe {
+                    (*sin6).sin6_family = AF_INET6 as sa_family_t;
+                    (*sin6).sin6_port = a.port().to_be();
+                    (*sin6).sin6_flowinfo = a.flowinfo();
+                    (*sin6).sin6_addr = in6_addr {
+                        s6_addr: a.ip().octets(),
+                    };
+                    (*sin6).sin6_scope_id = a.scope_id();
+                }
+                mem::size_of::<sockaddr_in6>() as socklen_t
+            }
+        }
+    }
+
+    pub fn from_sockaddr(storage: &sockaddr_storage) -> Option<SocketAddr> {
+        match c_int::from(storage.ss_family) {
+            AF_INET => {
+                let sin = unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    ip,
+                    u16::from_be(sin.sin_port),
+                )))
+            }
+            AF_INET6 => {
+                let sin6 =
+                    unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in6) };
+                let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    ip,
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Read up to `NUM_RCVMMSGS` datagrams into `packets` with one `recvmmsg` syscall.
+/// Blocks for the first datagram according to the socket's settings, then returns
+/// whatever else is already queued.
+#[cfg(target_os = "linux")]
+pub fn recv_mmsg(socket: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize> {
+    use self::linux::{empty_iovec, empty_mmsghdr, empty_sockaddr, from_sockaddr};
+    use libc::{self, c_void, socklen_t, MSG_WAITFORONE};
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    let count = cmp::min(NUM_RCVMMSGS, packets.len());
+    let mut hdrs = vec![empty_mmsghdr(); count];
+    let mut iovs = vec![empty_iovec(); count];
+    let mut addrs = vec![empty_sockaddr(); count];
+    let addrlen = mem::size_of::<libc::sockaddr_storage>() as socklen_t;
+
+    for i in 0..count {
+        packets[i].meta.size = 0;
+        iovs[i].iov_base = packets[i].data.as_mut_ptr() as *mut c_void;
+        iovs[i].iov_len = packets[i].data.len();
+        hdrs[i].msg_hdr.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut c_void;
+        hdrs[i].msg_hdr.msg_namelen = addrlen;
+        hdrs[i].msg_hdr.msg_iov = &mut iovs[i];
+        hdrs[i].msg_hdr.msg_iovlen = 1;
+    }
+
+    let npkts = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            hdrs.as_mut_ptr(),
+            count as _,
+            MSG_WAITFORONE as _,
+            ptr::null_mut(),
+        )
+    };
+    if npkts < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let npkts = npkts as usize;
+    for i in 0..npkts {
+        packets[i].meta.size = hdrs[i].msg_len as usize;
+        if let Some(from) = from_sockaddr(&addrs[i]) {
+            packets[i].meta.set_addr(&from);
+        }
+    }
+    Ok(npkts)
+}
+
+/// Send every `(data, addr)` pair with as few `sendmmsg` syscalls as the kernel
+/// allows. Returns the number of datagrams sent.
+#[cfg(target_os = "linux")]
+pub fn send_mmsg(socket: &UdpSocket, packets: &[(&[u8], SocketAddr)]) -> io::Result<usize> {
+    use self::linux::{empty_iovec, empty_mmsghdr, empty_sockaddr, to_sockaddr};
+    use libc::{self, c_void};
+    use std::os::unix::io::AsRawFd;
+
+    let ipv6 = is_ipv6(socket);
+    let mut total = 0;
+    for chunk in packets.chunks(NUM_SNDMMSGS) {
+        let mut hdrs = vec![empty_mmsghdr(); chunk.len()];
+        let mut iovs = vec![empty_iovec(); chunk.len()];
+        let mut addrs = vec![empty_sockaddr(); chunk.len()];
+
+        for (i, (data, addr)) in chunk.iter().enumerate() {
+            iovs[i].iov_base = data.as_ptr() as *mut c_void;
+            iovs[i].iov_len = data.len();
+            hdrs[i].msg_hdr.msg_namelen = to_sockaddr(&send_addr(ipv6, addr), &mut addrs[i]);
+            hdrs[i].msg_hdr.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut c_void;
+            hdrs[i].msg_hdr.msg_iov = &mut iovs[i];
+            hdrs[i].msg_hdr.msg_iovlen = 1;
+        }
+
+        // sendmmsg may stop short of the whole vector, keep going from where it left off
+        let mut sent = 0;
+        while sent < chunk.len() {
+            let n = unsafe {
+                libc::sendmmsg(
+                    socket.as_raw_fd(),
+                    hdrs[sent..].as_mut_ptr(),
+                    (chunk.len() - sent) as _,
+                    0,
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "sendmmsg sent nothing",
+                ));
+            }
+            sent += n as usize;
+        }
+        total += sent;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use mmsg::{recv_each, recv_mmsg, send_each, send_mmsg, NUM_RCVMMSGS};
+    use packet::{Packet, PACKET_DATA_SIZE};
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    fn sockets() -> (UdpSocket, UdpSocket) {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        reader.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        (reader, sender)
+    }
+
+    #[test]
+    pub fn test_send_recv_mmsg() {
+        let (reader, sender) = sockets();
+        let addr = reader.local_addr().unwrap();
+        let data: Vec<_> = (0..10).map(|i| vec![i as u8; PACKET_DATA_SIZE]).collect();
+        let msgs: Vec<_> = data.iter().map(|d| (&d[..], addr)).collect();
+        assert_eq!(send_mmsg(&sender, &msgs).unwrap(), 10);
+
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+        let mut recv = 0;
+        while recv < 10 {
+            recv += recv_mmsg(&reader, &mut packets[recv..]).unwrap();
+        }
+        assert_eq!(recv, 10);
+        for (i, p) in packets.iter().take(recv).enumerate() {
+            assert_eq!(p.meta.size, PACKET_DATA_SIZE);
+            assert_eq!(p.meta.addr(), sender.local_addr().unwrap());
+            assert_eq!(p.data[0], i as u8);
+        }
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_max() {
+        let (reader, sender) = sockets();
+        let addr = reader.local_addr().unwrap();
+        let data = [0u8; PACKET_DATA_SIZE];
+        let msgs = vec![(&data[..], addr); NUM_RCVMMSGS + 10];
+        assert_eq!(send_mmsg(&sender, &msgs).unwrap(), NUM_RCVMMSGS + 10);
+
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS * 2];
+        let mut recv = 0;
+        while recv < NUM_RCVMMSGS + 10 {
+            let n = recv_mmsg(&reader, &mut packets[recv..]).unwrap();
+            assert!(n <= NUM_RCVMMSGS);
+            recv += n;
+        }
+        assert_eq!(recv, NUM_RCVMMSGS + 10);
+    }
+
+    #[test]
+    pub fn test_send_recv_each() {
+        let (reader, sender) = sockets();
+        let addr = reader.local_addr().unwrap();
+        let data = [7u8; 64];
+        let msgs = vec![(&data[..], addr); 5];
+        assert_eq!(send_each(&sender, &msgs).unwrap(), 5);
+
+        let mut packets = vec![Packet::default(); 5];
+        let mut recv = 0;
+        while recv < 5 {
+            reader.set_nonblocking(false).unwrap();
+            recv += recv_each(&reader, &mut packets[recv..]).unwrap();
+        }
+        for p in &packets {
+            assert_eq!(p.meta.size, 64);
+            assert_eq!(p.data[0], 7);
+        }
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_timeout() {
+        let (reader, _sender) = sockets();
+        let mut packets = vec![Packet::default(); 1];
+        assert!(recv_mmsg(&reader, &mut packets).is_err());
+    }
+
+    #[cfg(all(feature = "ipv6", test))]
+    #[test]
+    pub fn test_send_recv_mmsg_ipv6() {
+        let reader = UdpSocket::bind("[::1]:0").expect("bind");
+        reader.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+        let sender = UdpSocket::bind("[::1]:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let data = [1u8; 32];
+        let msgs = vec![(&data[..], addr); 3];
+        assert_eq!(send_mmsg(&sender, &msgs).unwrap(), 3);
+
+        let mut packets = vec![Packet::default(); 3];
+        let mut recv = 0;
+        while recv < 3 {
+            recv += recv_mmsg(&reader, &mut packets[recv..]).unwrap();
+        }
+        assert_eq!(packets[0].meta.addr(), sender.local_addr().unwrap());
+    }
+}
@@ -0,0 +1,317 @@
+//! The `mnemonic` module encodes a key seed as a BIP-39 phrase of English words,
+//! which is easier to back up than a keypair file, and derives any number of
+//! child keypairs from the seed of such a phrase. Children are derived along the
+//! hardened path `m/44'/501'/index'` by SLIP-0010 for Ed25519, so an exchange
+//! can hand out a deposit address per index while keeping one phrase.
+
+use byteorder::{BigEndian, ByteOrder};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+use signature::KeyPair;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use untrusted::Input;
+
+/// The BIP-39 English word list, in order
+const WORDS: &str = include_str!("mnemonic_words.txt");
+
+const BITS_PER_WORD: usize = 11;
+
+/// PBKDF2 rounds that stretch a phrase into its seed
+const SEED_ITERATIONS: u32 = 2048;
+
+/// The coin type of the derivation path, as registered in SLIP-0044
+const COIN_TYPE: u32 = 501;
+
+const HARDENED: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MnemonicError {
+    /// the word isn't in the word list
+    InvalidWord(String),
+    /// phrases are 12, 15, 18, 21 or 24 words long
+    InvalidLength(usize),
+    /// the phrase was mistyped
+    InvalidChecksum,
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MnemonicError::InvalidWord(word) => write!(f, "unknown word in phrase: {}", word),
+            MnemonicError::InvalidLength(len) => write!(f, "phrase of {} words", len),
+            MnemonicError::InvalidChecksum => write!(f, "phrase checksum mismatch"),
+        }
+    }
+}
+
+impl error::Error for MnemonicError {
+    fn description(&self) -> &str {
+        "mnemonic error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+fn word_list() -> Vec<&'static str> {
+    WORDS.lines().collect()
+}
+
+fn check_length(num_words: usize) -> Result<(), MnemonicError> {
+    if num_words < 12 || num_words > 24 || num_words % 3 != 0 {
+        return Err(MnemonicError::InvalidLength(num_words));
+    }
+    Ok(())
+}
+
+/// A new random phrase of `num_words` words, one of 12, 15, 18, 21 or 24
+pub fn generate_mnemonic(num_words: usize) -> Result<String, MnemonicError> {
+    check_length(num_words)?;
+    let mut entropy = vec![0u8; num_words * 4 / 3];
+    SystemRandom::new()
+        .fill(&mut entropy)
+        .expect("fill entropy in generate_mnemonic");
+    Ok(entropy_to_mnemonic(&entropy))
+}
+
+/// The phrase for `entropy`, which is 16, 20, 24, 28 or 32 bytes long. A word
+/// encodes 11 bits of the entropy followed by the leading bits of its SHA-256.
+pub fn entropy_to_mnemonic(entropy: &[u8]) -> String {
+    assert!(entropy.len() >= 16 && entropy.len() <= 32 && entropy.len() % 4 == 0);
+    let checksum = digest::digest(&digest::SHA256, entropy).as_ref()[0];
+    let num_entropy_bits = entropy.len() * 8;
+    let bit = |i: usize| {
+        let byte = if i < num_entropy_bits {
+            entropy[i / 8]
+        } else {
+            checksum
+        };
+        (byte >> (7 - i % 8)) & 1
+    };
+    let words = word_list();
+    let num_words = entropy.len() * 3 / 4;
+    let phrase: Vec<_> = (0..num_words)
+        .map(|w| {
+            let index = (0..BITS_PER_WORD).fold(0, |index, b| {
+                (index << 1) | bit(w * BITS_PER_WORD + b) as usize
+            });
+            words[index]
+        })
+        .collect();
+    phrase.join(" ")
+}
+
+/// The entropy `phrase` encodes, if its words and checksum are valid
+pub fn mnemonic_to_entropy(phrase: &str) -> Result<Vec<u8>, MnemonicError> {
+    let words = word_list();
+    let indexes = phrase
+        .split_whitespace()
+        .map(|word| {
+            words
+                .binary_search(&word)
+                .map_err(|_| MnemonicError::InvalidWord(word.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let num_words = indexes.len();
+    check_length(num_words)?;
+
+    let bit =
+        |i: usize| (indexes[i / BITS_PER_WORD] >> (BITS_PER_WORD - 1 - i % BITS_PER_WORD)) & 1;
+    let num_entropy_bits = num_words * BITS_PER_WORD * 32 / 33;
+    let entropy: Vec<u8> = (0..num_entropy_bits / 8)
+        .map(|byte| (0..8).fold(0, |acc, b| (acc << 1) | bit(byte * 8 + b) as u8))
+        .collect();
+
+    let num_checksum_bits = num_words / 3;
+    let checksum =
+        (0..num_checksum_bits).fold(0, |acc, b| (acc << 1) | bit(num_entropy_bits + b) as u8);
+    let entropy_hash = digest::digest(&digest::SHA256, &entropy);
+    let expected = entropy_hash.as_ref()[0] >> (8 - num_checksum_bits);
+    if checksum != expected {
+        return Err(MnemonicError::InvalidChecksum);
+    }
+    Ok(entropy)
+}
+
+/// The 64-byte seed of `phrase`, stretched with the optional `passphrase`
+pub fn mnemonic_to_seed(phrase: &str, passphrase: &str) -> Result<Vec<u8>, MnemonicError> {
+    mnemonic_to_entropy(phrase)?;
+    let normalized: Vec<_> = phrase.split_whitespace().collect();
+    let salt = format!("mnemonic{}", passphrase);
+    let mut seed = vec![0u8; 64];
+    pbkdf2::derive(
+        &digest::SHA512,
+        SEED_ITERATIONS,
+        salt.as_bytes(),
+        normalized.join(" ").as_bytes(),
+        &mut seed,
+    );
+    Ok(seed)
+}
+
+/// The key and chain code of an HMAC-SHA512 output
+fn split_hmac(key: &[u8], data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let signature = hmac::sign(&hmac::SigningKey::new(&digest::SHA512, key), data);
+    let (key, chain_code) = signature.as_ref().split_at(32);
+    (key.to_vec(), chain_code.to_vec())
+}
+
+/// The Ed25519 private key seed at the hardened `path` below the master key of
+/// `seed`, derived by SLIP-0010
+pub fn derive_private_key(seed: &[u8], path: &[u32]) -> Vec<u8> {
+    let (mut key, mut chain_code) = split_hmac(b"ed25519 seed", seed);
+    for index in path {
+        let mut data = vec![0u8];
+        data.extend_from_slice(&key);
+        let mut index_bytes = [0u8; 4];
+        BigEndian::write_u32(&mut index_bytes, index | HARDENED);
+        data.extend_from_slice(&index_bytes);
+        let (child_key, child_chain_code) = split_hmac(&chain_code, &data);
+        key = child_key;
+        chain_code = child_chain_code;
+    }
+    key
+}
+
+/// The PKCS#8 v2 document of an Ed25519 key is this prefix, the private key
+/// seed, this middle and the public key
+const PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+const PKCS8_MIDDLE: [u8; 5] = [0xa1, 0x23, 0x03, 0x21, 0x00];
+
+/// The PKCS#8 document of the child keypair `index` of `seed`, which is written
+/// to keypair files like any other
+pub fn derive_pkcs8(seed: &[u8], index: u32) -> Vec<u8> {
+    let private_key = derive_private_key(seed, &[44, COIN_TYPE, index]);
+    let keypair = KeyPair::from_seed_unchecked(Input::from(&private_key))
+        .expect("from_seed_unchecked in derive_pkcs8");
+    let mut pkcs8 = PKCS8_PREFIX.to_vec();
+    pkcs8.extend_from_slice(&private_key);
+    pkcs8.extend_from_slice(&PKCS8_MIDDLE);
+    pkcs8.extend_from_slice(keypair.public_key_bytes());
+    pkcs8
+}
+
+/// The child keypair `index` of `seed`
+pub fn derive_keypair(seed: &[u8], index: u32) -> KeyPair {
+    let pkcs8 = derive_pkcs8(seed, index);
+    KeyPair::from_pkcs8(Input::from(&pkcs8)).expect("from_pkcs8 in derive_keypair")
+}
+
+/// Read a phrase from the file at `path`, or from stdin if it's "-"
+pub fn read_mnemonic(path: &str) -> Result<String, Box<error::Error>> {
+    let mut phrase = String::new();
+    if path == "-" {
+        io::stdin().read_to_string(&mut phrase)?;
+    } else {
+        File::open(path)?.read_to_string(&mut phrase)?;
+    }
+    mnemonic_to_entropy(&phrase)?;
+    Ok(phrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use mnemonic::*;
+    use signature::KeyPairUtil;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len() / 2)
+            .map(|i| u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_mnemonic_vectors() {
+        // from the BIP-39 reference vectors
+        let entropy = [0u8; 16];
+        let phrase = entropy_to_mnemonic(&entropy);
+        assert_eq!(
+            phrase,
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+        );
+        assert_eq!(mnemonic_to_entropy(&phrase), Ok(entropy.to_vec()));
+        assert_eq!(
+            mnemonic_to_seed(&phrase, "TREZOR").unwrap(),
+            from_hex(
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+                 1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+            )
+        );
+
+        let entropy = [0xffu8; 32];
+        let phrase = entropy_to_mnemonic(&entropy);
+        assert!(phrase.ends_with("zoo zoo vote"));
+        assert_eq!(mnemonic_to_entropy(&phrase), Ok(entropy.to_vec()));
+    }
+
+    #[test]
+    fn test_mnemonic_errors() {
+        let phrase = generate_mnemonic(24).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+        assert!(mnemonic_to_entropy(&phrase).is_ok());
+        assert_eq!(generate_mnemonic(13), Err(MnemonicError::InvalidLength(13)));
+
+        let phrase = "abandon ".repeat(12);
+        assert_eq!(
+            mnemonic_to_entropy(&phrase),
+            Err(MnemonicError::InvalidChecksum)
+        );
+        let phrase = format!("{}about", "abandon ".repeat(10));
+        assert_eq!(
+            mnemonic_to_entropy(&phrase),
+            Err(MnemonicError::InvalidLength(11))
+        );
+        let phrase = format!("{}solana", "abandon ".repeat(11));
+        assert_eq!(
+            mnemonic_to_entropy(&phrase),
+            Err(MnemonicError::InvalidWord("solana".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_derive_private_key() {
+        // from the SLIP-0010 Ed25519 test vectors
+        let seed = from_hex("000102030405060708090a0b0c0d0e0f");
+        assert_eq!(
+            derive_private_key(&seed, &[]),
+            from_hex("2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7")
+        );
+        assert_eq!(
+            derive_private_key(&seed, &[0, 1]),
+            from_hex("b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2")
+        );
+    }
+
+    #[test]
+    fn test_derive_keypair_vector() {
+        let phrase = format!("{}about", "abandon ".repeat(11));
+        let seed = mnemonic_to_seed(&phrase, "").unwrap();
+        assert_eq!(
+            derive_private_key(&seed, &[44, COIN_TYPE, 0]),
+            from_hex("ec252c5d95bcf80a4b22df119cedd4ae1aed07364578e8d43bdfa0435625dbd1")
+        );
+        assert_eq!(
+            derive_keypair(&seed, 0).pubkey().to_vec(),
+            from_hex("e9b6062841bb977ad21de71ec961900633c26f21384e015b014a637a61499547")
+        );
+    }
+
+    #[test]
+    fn test_derive_keypair() {
+        let seed = mnemonic_to_seed(&generate_mnemonic(12).unwrap(), "").unwrap();
+        assert_eq!(
+            derive_keypair(&seed, 0).pubkey(),
+            derive_keypair(&seed, 0).pubkey()
+        );
+        assert_ne!(
+            derive_keypair(&seed, 0).pubkey(),
+            derive_keypair(&seed, 1).pubkey()
+        );
+    }
+}
@@ -1,19 +1,27 @@
 //! The `nat` module assists with NAT traversal
+//!
+//! A node behind a NAT learns its public address by asking a peer that can see it, such as its
+//! gossip entry point, which address its packets arrive from. This is the "ip echo" exchange of
+//! `crdt::Protocol::RequestIpEcho` and `crdt::Protocol::IpEcho`. The answer only ever goes back
+//! to the socket that asked, so to check a port a node asks from the socket bound to it. It comes
+//! from the gossip port the request went to, which is all a port-restricted NAT lets back in.
 
-extern crate futures;
-extern crate p2p;
-extern crate rand;
 extern crate reqwest;
-extern crate tokio_core;
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
-
-use self::futures::Future;
-use self::p2p::UdpSocketExt;
+use bincode::{deserialize, serialize};
+use crdt::{get_ip_addr, Protocol};
+use net_utils::{is_ipv6, send_addr, udp_bind};
+use packet::BLOB_SIZE;
 use rand::{thread_rng, Rng};
-use std::env;
 use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::str;
+use std::time::Duration;
+
+/// How long to wait for each answer to an ip echo request
+const IP_ECHO_TIMEOUT_MILLIS: u64 = 1000;
+/// How many times to send an ip echo request before giving up
+const IP_ECHO_TRIES: usize = 3;
 
 /// A data type representing a public Udp socket
 pub struct UdpSocketPair {
@@ -22,8 +30,56 @@ pub struct UdpSocketPair {
     pub sender: UdpSocket,   // Locally bound socket to send via public address
 }
 
-/// Tries to determine the public IP address of this machine
-pub fn get_public_ip_addr() -> Result<IpAddr, String> {
+/// Wait on `socket` for an `IpEcho`, ignoring anything else that arrives.
+fn recv_ip_echo(socket: &UdpSocket, timeout: Duration) -> io::Result<SocketAddr> {
+    socket.set_read_timeout(Some(timeout))?;
+    let mut buf = vec![0u8; BLOB_SIZE];
+    let result = loop {
+        match socket.recv_from(&mut buf) {
+            Ok((size, _)) => {
+                if let Ok(Protocol::IpEcho(addr)) = deserialize(&buf[..size]) {
+                    break Ok(addr);
+                }
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    socket.set_read_timeout(None)?;
+    result
+}
+
+/// Ask the node with the gossip address `entry_point` which address the
+/// packets from `socket` arrive from. An answer also shows that `socket` can
+/// be reached from `entry_point`, at least while its NAT mapping lasts.
+pub fn ip_echo(socket: &UdpSocket, entry_point: &SocketAddr) -> io::Result<SocketAddr> {
+    let req = serialize(&Protocol::RequestIpEcho)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    let timeout = Duration::from_millis(IP_ECHO_TIMEOUT_MILLIS);
+    let mut tries = 0;
+    loop {
+        tries += 1;
+        socket.send_to(&req, send_addr(is_ipv6(socket), entry_point))?;
+        match recv_ip_echo(socket, timeout) {
+            Ok(addr) => return Ok(addr),
+            Err(e) => if tries >= IP_ECHO_TRIES {
+                return Err(e);
+            },
+        }
+    }
+}
+
+/// Tries to determine the public IP address of this machine by asking the
+/// node with the gossip address `entry_point`
+pub fn get_public_ip_addr(entry_point: &SocketAddr) -> Result<IpAddr, String> {
+    let socket = udp_bind(0).map_err(|err| err.to_string())?;
+    let addr = ip_echo(&socket, entry_point).map_err(|err| err.to_string())?;
+    Ok(addr.ip())
+}
+
+/// Tries to determine the public IP address of this machine with a public
+/// website. Only used when asked for, since it makes startup depend on a
+/// third party.
+pub fn get_public_ip_addr_http() -> Result<IpAddr, String> {
     let body = reqwest::get("http://ifconfig.co/ip")
         .map_err(|err| err.to_string())?
         .text()
@@ -41,9 +97,7 @@ pub fn udp_random_bind(start: u16, end: u16, tries: u32) -> io::Result<UdpSocket
         count += 1;
 
         let rand_port = thread_rng().gen_range(start, end);
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), rand_port);
-
-        match UdpSocket::bind(addr) {
+        match udp_bind(rand_port) {
             Result::Ok(val) => break Result::Ok(val),
             Result::Err(err) => if err.kind() != io::ErrorKind::AddrInUse || count >= tries {
                 return Err(err);
@@ -52,70 +106,90 @@ pub fn udp_random_bind(start: u16, end: u16, tries: u32) -> io::Result<UdpSocket
     }
 }
 
-/// Binds a private Udp address to a public address using UPnP if possible
-pub fn udp_public_bind(label: &str, startport: u16, endport: u16) -> UdpSocketPair {
-    let private_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0);
-
-    let mut core = tokio_core::reactor::Core::new().unwrap();
-    let handle = core.handle();
-    let mc = p2p::P2p::default();
-    let res = core.run({
-        tokio_core::net::UdpSocket::bind_public(&private_addr, &handle, &mc)
-            .map_err(|e| {
-                info!("Failed to bind public socket for {}: {}", label, e);
-            })
-            .and_then(|(socket, public_addr)| Ok((public_addr, socket.local_addr().unwrap())))
-    });
-
-    match res {
-        Ok((public_addr, local_addr)) => {
-            info!(
-                "Using local address {} mapped to UPnP public address {} for {}",
-                local_addr, public_addr, label
-            );
-
-            // NAT should now be forwarding inbound packets directed at
-            // |public_addr| to the local |receiver| socket...
-            let receiver = UdpSocket::bind(local_addr).unwrap();
-
-            // TODO: try to autodetect a broken NAT (issue #496)
-            let sender = if env::var("BROKEN_NAT").is_err() {
-                receiver.try_clone().unwrap()
-            } else {
-                // ... however for outbound packets, some NATs *will not* rewrite the
-                // source port from |receiver.local_addr().port()| to |public_addr.port()|.
-                // This is currently a problem when talking with a fullnode as it
-                // assumes it can send UDP packets back at the source.  This hits the
-                // NAT as a datagram for |receiver.local_addr().port()| on the NAT's public
-                // IP, which the NAT promptly discards.  As a short term hack, create a
-                // local UDP socket, |sender|, with the same port as |public_addr.port()|.
-                //
-                // TODO: Remove the |sender| socket and deal with the downstream changes to
-                //       the UDP signalling
-                let mut local_addr_sender = local_addr;
-                local_addr_sender.set_port(public_addr.port());
-                UdpSocket::bind(local_addr_sender).unwrap()
-            };
-
-            UdpSocketPair {
-                addr: public_addr,
-                receiver,
-                sender,
+/// Binds a private Udp address and learns the public address it maps to from
+/// the node with the gossip address `entry_point`. Falls back to this
+/// machine's own address if `entry_point` doesn't answer.
+pub fn udp_public_bind(
+    label: &str,
+    entry_point: &SocketAddr,
+    startport: u16,
+    endport: u16,
+) -> UdpSocketPair {
+    let sender = udp_random_bind(startport, endport, 5).unwrap();
+    let local_addr = sender.local_addr().unwrap();
+    let receiver = sender.try_clone().unwrap();
+
+    let pub_addr = match ip_echo(&sender, entry_point) {
+        Ok(pub_addr) => {
+            if pub_addr.port() != local_addr.port() {
+                warn!(
+                    "{} is mapped to {} by a NAT, is the port forwarded?",
+                    label, pub_addr
+                );
             }
+            pub_addr
         }
-        Err(_) => {
-            let sender = udp_random_bind(startport, endport, 5).unwrap();
-            let local_addr = sender.local_addr().unwrap();
-
-            let pub_ip = get_public_ip_addr().unwrap();
-            let pub_addr = SocketAddr::new(pub_ip, local_addr.port());
-
-            info!("Using source address {} for {}", pub_addr, label);
-            UdpSocketPair {
-                addr: pub_addr,
-                receiver: sender.try_clone().unwrap(),
-                sender,
-            }
+        Err(e) => {
+            let ip = get_ip_addr().unwrap_or_else(|| local_addr.ip());
+            warn!(
+                "no ip echo from {} for {}: {}, falling back to {}",
+                entry_point, label, e, ip
+            );
+            SocketAddr::new(ip, local_addr.port())
         }
+    };
+
+    info!(
+        "Using local address {} seen at {} for {}",
+        local_addr, pub_addr, label
+    );
+    UdpSocketPair {
+        addr: pub_addr,
+        receiver,
+        sender,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crdt::{Crdt, TestNode};
+    use nat::{get_public_ip_addr, ip_echo, udp_public_bind};
+    use ncp::Ncp;
+    use std::net::UdpSocket;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, RwLock};
+    use streamer::default_window;
+
+    #[test]
+    fn test_ip_echo() {
+        let exit = Arc::new(AtomicBool::new(false));
+        let tn = TestNode::new_localhost();
+        let entry_point = tn.data.contact_info.ncp;
+        let crdt = Arc::new(RwLock::new(Crdt::new(tn.data).unwrap()));
+        let ncp = Ncp::new(
+            &crdt,
+            default_window(),
+            tn.sockets.gossip,
+            tn.sockets.gossip_send,
+            exit,
+        ).unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = ip_echo(&socket, &entry_point).unwrap();
+        assert_eq!(addr, socket.local_addr().unwrap());
+
+        // nobody answers on a closed port
+        let closed_addr = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(ip_echo(&socket, &closed_addr).is_err());
+
+        // binding still works without an answer
+        let pair = udp_public_bind("test", &closed_addr, 8000, 10000);
+        assert_eq!(pair.addr.port(), pair.sender.local_addr().unwrap().port());
+
+        assert_eq!(get_public_ip_addr(&entry_point).unwrap(), entry_point.ip());
+        ncp.close().unwrap();
     }
 }
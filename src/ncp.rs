@@ -31,6 +31,15 @@ impl Ncp {
             &crdt.read().unwrap().me[..4],
             gossip_listen_socket.local_addr().unwrap()
         );
+        // answers to requests go out the socket they came in on, since a
+        // NAT in front of the requester may only let that address back in
+        let (listen_response_sender, listen_response_receiver) = channel();
+        let t_listen_responder = streamer::responder(
+            "ncp-listen",
+            gossip_listen_socket.try_clone()?,
+            blob_recycler.clone(),
+            listen_response_receiver,
+        );
         let t_receiver = streamer::blob_receiver(
             exit.clone(),
             blob_recycler.clone(),
@@ -49,11 +58,24 @@ impl Ncp {
             window,
             blob_recycler.clone(),
             request_receiver,
+            listen_response_sender,
+            exit.clone(),
+        );
+        let t_ledger_repair = Crdt::ledger_repair(
+            crdt.clone(),
+            blob_recycler.clone(),
             response_sender.clone(),
             exit.clone(),
         );
         let t_gossip = Crdt::gossip(crdt.clone(), blob_recycler, response_sender, exit.clone());
-        let thread_hdls = vec![t_receiver, t_responder, t_listen, t_gossip];
+        let thread_hdls = vec![
+            t_receiver,
+            t_responder,
+            t_listen_responder,
+            t_listen,
+            t_ledger_repair,
+            t_gossip,
+        ];
         Ok(Ncp { exit, thread_hdls })
     }
 
@@ -78,10 +100,14 @@ impl Service for Ncp {
 
 #[cfg(test)]
 mod tests {
-    use crdt::{Crdt, TestNode};
+    use bincode::{deserialize, serialize};
+    use crdt::{Crdt, Protocol, TestNode};
     use ncp::Ncp;
+    use packet::BLOB_SIZE;
+    use std::net::UdpSocket;
     use std::sync::atomic::AtomicBool;
     use std::sync::{Arc, RwLock};
+    use std::time::Duration;
 
     #[test]
     #[ignore]
@@ -101,4 +127,32 @@ mod tests {
         ).unwrap();
         d.close().expect("thread join");
     }
+
+    #[test]
+    fn test_ip_echo_source_port() {
+        let exit = Arc::new(AtomicBool::new(false));
+        let tn = TestNode::new_localhost();
+        let gossip_addr = tn.data.contact_info.ncp;
+        let crdt = Arc::new(RwLock::new(Crdt::new(tn.data.clone()).unwrap()));
+        let w = Arc::new(RwLock::new(vec![]));
+        let ncp = Ncp::new(&crdt, w, tn.sockets.gossip, tn.sockets.gossip_send, exit).unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let req = serialize(&Protocol::RequestIpEcho).unwrap();
+        socket.send_to(&req, gossip_addr).unwrap();
+
+        // a NAT only lets the answer through if it comes from the address
+        // the request went to
+        let mut buf = vec![0u8; BLOB_SIZE];
+        let (size, from) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(from, gossip_addr);
+        match deserialize(&buf[..size]).unwrap() {
+            Protocol::IpEcho(addr) => assert_eq!(addr, socket.local_addr().unwrap()),
+            _ => panic!("not IpEcho"),
+        }
+        ncp.close().expect("thread join");
+    }
 }
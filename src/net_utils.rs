@@ -0,0 +1,126 @@
+//! The `net_utils` module picks the address family of the node's sockets.
+//!
+//! Without the `ipv6` feature a node binds IPv4 sockets. With it, a node binds IPv6 sockets,
+//! which on a dual-stack host also reach IPv4 peers through IPv4-mapped IPv6 addresses such as
+//! `::ffff:10.0.0.1`. Peers are always known by their plain address, so addresses are mapped
+//! on the way out of an IPv6 socket and unmapped on the way in.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+
+/// The address that binds a socket of the family `ipv6` to every interface
+pub fn unspecified_ip(ipv6: bool) -> IpAddr {
+    if ipv6 {
+        IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0))
+    } else {
+        IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+    }
+}
+
+/// The address that binds `port` on every interface, in the family the node is built for
+pub fn bind_addr(port: u16) -> SocketAddr {
+    SocketAddr::new(unspecified_ip(cfg!(feature = "ipv6")), port)
+}
+
+/// Bind a Udp socket to `port` on every interface, 0 for any free port
+pub fn udp_bind(port: u16) -> io::Result<UdpSocket> {
+    UdpSocket::bind(bind_addr(port))
+}
+
+/// Whether `socket` is bound to IPv6, and so sends to IPv4 addresses only once they are mapped
+pub fn is_ipv6(socket: &UdpSocket) -> bool {
+    cfg!(feature = "ipv6") && socket.local_addr().map(|a| a.is_ipv6()).unwrap_or(false)
+}
+
+/// `addr` as a socket of the family `ipv6` sends to it
+pub fn send_addr(ipv6: bool, addr: &SocketAddr) -> SocketAddr {
+    match *addr {
+        SocketAddr::V4(v4) if ipv6 => {
+            SocketAddr::V6(SocketAddrV6::new(v4.ip().to_ipv6_mapped(), v4.port(), 0, 0))
+        }
+        _ => *addr,
+    }
+}
+
+/// `addr` with an IPv4-mapped IPv6 address turned back into the IPv4 address it maps
+pub fn canonical_addr(addr: &SocketAddr) -> SocketAddr {
+    if let SocketAddr::V6(v6) = *addr {
+        let s = v6.ip().segments();
+        if s[..5] == [0; 5] && s[5] == 0xffff {
+            let ip = Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8, (s[7] >> 8) as u8, s[7] as u8);
+            return SocketAddr::new(IpAddr::V4(ip), v6.port());
+        }
+    }
+    *addr
+}
+
+/// Whether hosts on other networks can reach `ip`
+pub fn is_global(ip: &IpAddr) -> bool {
+    if ip.is_loopback() || ip.is_multicast() || ip.is_unspecified() {
+        return false;
+    }
+    match *ip {
+        IpAddr::V4(v4) => !v4.is_link_local(),
+        // fe80::/10 is link local
+        IpAddr::V6(v6) => v6.segments()[0] & 0xffc0 != 0xfe80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use net_utils::{canonical_addr, is_global, send_addr};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_send_addr_canonical_addr() {
+        let v4: SocketAddr = "10.0.0.1:8000".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:10.0.0.1]:8000".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:8000".parse().unwrap();
+        assert_eq!(send_addr(false, &v4), v4);
+        assert_eq!(send_addr(true, &v4), mapped);
+        assert_eq!(send_addr(true, &v6), v6);
+        assert_eq!(canonical_addr(&mapped), v4);
+        assert_eq!(canonical_addr(&v4), v4);
+        assert_eq!(canonical_addr(&v6), v6);
+    }
+
+    #[test]
+    fn test_is_global() {
+        assert!(is_global(&"10.0.0.1".parse().unwrap()));
+        assert!(is_global(&"2001:db8::1".parse().unwrap()));
+        assert!(!is_global(&"127.0.0.1".parse().unwrap()));
+        assert!(!is_global(&"169.254.0.1".parse().unwrap()));
+        assert!(!is_global(&"::1".parse().unwrap()));
+        assert!(!is_global(&"fe80::1".parse().unwrap()));
+        assert!(!is_global(&"::".parse().unwrap()));
+    }
+
+    #[cfg(feature = "ipv6")]
+    #[test]
+    fn test_dual_stack_send_recv() {
+        use net_utils::{is_ipv6, udp_bind};
+        use std::net::UdpSocket;
+        use std::time::Duration;
+
+        let reader = UdpSocket::bind("127.0.0.1:0").unwrap();
+        reader.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+        let sender = udp_bind(0).unwrap();
+        assert!(is_ipv6(&sender));
+        let addr = reader.local_addr().unwrap();
+        sender
+            .send_to(&[7u8], send_addr(is_ipv6(&sender), &addr))
+            .unwrap();
+
+        let mut buf = [0u8; 1];
+        let (_, from) = reader.recv_from(&mut buf).unwrap();
+        assert_eq!(buf[0], 7);
+        assert!(from.is_ipv4());
+
+        // and the answer arrives at the IPv6 socket from a mapped address
+        reader.send_to(&[8u8], from).unwrap();
+        sender.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+        let (_, from) = sender.recv_from(&mut buf).unwrap();
+        assert_eq!(buf[0], 8);
+        assert_eq!(canonical_addr(&from), addr);
+    }
+}
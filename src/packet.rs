@@ -2,6 +2,8 @@
 use bincode::{deserialize, serialize};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use counter::Counter;
+use mmsg::{recv_mmsg, send_mmsg, NUM_RCVMMSGS};
+use net_utils::canonical_addr;
 use result::{Error, Result};
 use serde::Serialize;
 use signature::PublicKey;
@@ -79,8 +81,10 @@ impl Meta {
         }
     }
 
+    /// Stores an IPv4-mapped address as the IPv4 address it maps, the way
+    /// peers advertise it.
     pub fn set_addr(&mut self, a: &SocketAddr) {
-        match *a {
+        match canonical_addr(a) {
             SocketAddr::V4(v4) => {
                 let ip = v4.ip().octets();
                 self.addr[0] = u16::from(ip[0]);
@@ -88,6 +92,7 @@ impl Meta {
                 self.addr[2] = u16::from(ip[2]);
                 self.addr[3] = u16::from(ip[3]);
                 self.port = a.port();
+                self.v6 = false;
             }
             SocketAddr::V6(v6) => {
                 self.addr = v6.ip().segments();
@@ -196,30 +201,30 @@ impl Packets {
         //  * read until it fails
         //  * set it back to blocking before returning
         socket.set_nonblocking(false)?;
-        for p in &mut self.packets {
-            p.meta.size = 0;
-            trace!("receiving on {}", socket.local_addr().unwrap());
-            match socket.recv_from(&mut p.data) {
+        trace!("receiving on {}", socket.local_addr().unwrap());
+        loop {
+            match recv_mmsg(socket, &mut self.packets[i..]) {
                 Err(_) if i > 0 => {
-                    inc_new_counter!("packets-recv_count", 1);
-                    debug!("got {:?} messages on {}", i, socket.local_addr().unwrap());
                     break;
                 }
                 Err(e) => {
                     trace!("recv_from err {:?}", e);
                     return Err(Error::IO(e));
                 }
-                Ok((nrecv, from)) => {
-                    p.meta.size = nrecv;
-                    p.meta.set_addr(&from);
-                    trace!("got {} bytes from {}", nrecv, from);
+                Ok(npkts) => {
+                    trace!("got {} packets", npkts);
                     if i == 0 {
                         socket.set_nonblocking(true)?;
                     }
+                    i += npkts;
+                    if npkts != NUM_RCVMMSGS || i >= NUM_PACKETS {
+                        break;
+                    }
                 }
             }
-            i += 1;
         }
+        inc_new_counter!("packets-recv_count", 1);
+        debug!("got {:?} messages on {}", i, socket.local_addr().unwrap());
         Ok(i)
     }
     pub fn recv_from(&mut self, socket: &UdpSocket) -> Result<()> {
@@ -229,10 +234,11 @@ impl Packets {
         Ok(())
     }
     pub fn send_to(&self, socket: &UdpSocket) -> Result<()> {
-        for p in &self.packets {
-            let a = p.meta.addr();
-            socket.send_to(&p.data[..p.meta.size], &a)?;
-        }
+        let msgs: Vec<_> = self.packets
+            .iter()
+            .map(|p| (&p.data[..p.meta.size], p.meta.addr()))
+            .collect();
+        send_mmsg(socket, &msgs)?;
         Ok(())
     }
 }
@@ -297,6 +303,7 @@ const BLOB_INDEX_END: usize = size_of::<u64>();
 const BLOB_ID_END: usize = BLOB_INDEX_END + size_of::<usize>() + size_of::<PublicKey>();
 const BLOB_FLAGS_END: usize = BLOB_ID_END + size_of::<u32>();
 const BLOB_SIZE_END: usize = BLOB_FLAGS_END + size_of::<u64>();
+const BLOB_NUM_CODING_END: usize = BLOB_SIZE_END + size_of::<u32>();
 
 macro_rules! align {
     ($x:expr, $align:expr) => {
@@ -305,7 +312,7 @@ macro_rules! align {
 }
 
 pub const BLOB_FLAG_IS_CODING: u32 = 0x1;
-pub const BLOB_HEADER_SIZE: usize = align!(BLOB_SIZE_END, 64);
+pub const BLOB_HEADER_SIZE: usize = align!(BLOB_NUM_CODING_END, 64);
 
 impl Blob {
     pub fn get_index(&self) -> Result<u64> {
@@ -367,6 +374,20 @@ impl Blob {
         Ok(())
     }
 
+    /// number of coding blobs in this blob's erasure set, the rest are data blobs
+    pub fn get_num_coding(&self) -> Result<u32> {
+        let mut rdr = io::Cursor::new(&self.data[BLOB_SIZE_END..BLOB_NUM_CODING_END]);
+        let r = rdr.read_u32::<LittleEndian>()?;
+        Ok(r)
+    }
+
+    pub fn set_num_coding(&mut self, num_coding: u32) -> Result<()> {
+        let mut wtr = vec![];
+        wtr.write_u32::<LittleEndian>(num_coding)?;
+        self.data[BLOB_SIZE_END..BLOB_NUM_CODING_END].clone_from_slice(&wtr);
+        Ok(())
+    }
+
     pub fn data(&self) -> &[u8] {
         &self.data[BLOB_HEADER_SIZE..]
     }
@@ -418,20 +439,24 @@ impl Blob {
         Ok(v)
     }
     pub fn send_to(re: &BlobRecycler, socket: &UdpSocket, v: &mut SharedBlobs) -> Result<()> {
+        let res = {
+            let blobs: Vec<_> = v.iter()
+                .map(|r| r.read().expect("'r' read lock in pub fn send_to"))
+                .collect();
+            let msgs: Vec<_> = blobs
+                .iter()
+                .map(|p| (&p.data[..p.meta.size], p.meta.addr()))
+                .collect();
+            send_mmsg(socket, &msgs)
+        };
+        // nothing resends a failed batch, so every blob goes back either way
         while let Some(r) = v.pop_front() {
-            {
-                let p = r.read().expect("'r' read lock in pub fn send_to");
-                let a = p.meta.addr();
-                if let Err(e) = socket.send_to(&p.data[..p.meta.size], &a) {
-                    warn!(
-                        "error sending {} byte packet to {:?}: {:?}",
-                        p.meta.size, a, e
-                    );
-                    Err(e)?;
-                }
-            }
             re.recycle(r);
         }
+        if let Err(e) = res {
+            warn!("error sending blobs: {:?}", e);
+            Err(e)?;
+        }
         Ok(())
     }
 }
@@ -439,13 +464,14 @@ impl Blob {
 #[cfg(test)]
 mod tests {
     use packet::{
-        to_packets, Blob, BlobRecycler, Packet, PacketRecycler, Packets, Recycler, NUM_PACKETS,
+        to_packets, Blob, BlobRecycler, Meta, Packet, PacketRecycler, Packets, Recycler,
+        BLOB_HEADER_SIZE, NUM_PACKETS,
     };
     use request::Request;
     use std::collections::VecDeque;
     use std::io;
     use std::io::Write;
-    use std::net::UdpSocket;
+    use std::net::{SocketAddr, UdpSocket};
     use std::sync::Arc;
 
     #[test]
@@ -562,6 +588,22 @@ mod tests {
         r.recycle(rp);
     }
 
+    #[test]
+    pub fn blob_send_fail_recycles() {
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let r = BlobRecycler::default();
+        // port 0 is not a valid destination, so the send fails
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let p = r.allocate();
+        p.write().unwrap().meta.set_addr(&addr);
+        p.write().unwrap().meta.size = 1024;
+        let mut v = VecDeque::new();
+        v.push_back(p);
+        assert!(Blob::send_to(&r, &sender, &mut v).is_err());
+        assert_eq!(v.len(), 0);
+        assert_eq!(r.gc.lock().unwrap().len(), 1);
+    }
+
     #[cfg(all(feature = "ipv6", test))]
     #[test]
     pub fn blob_ipv6_send_recv() {
@@ -581,6 +623,22 @@ mod tests {
         r.recycle(rp);
     }
 
+    #[test]
+    pub fn meta_set_addr() {
+        let mut meta = Meta::default();
+        let v6: SocketAddr = "[2001:db8::1]:8000".parse().unwrap();
+        meta.set_addr(&v6);
+        assert_eq!(meta.addr(), v6);
+
+        // the same buffer holds an IPv4 address once it is recycled
+        let v4: SocketAddr = "10.0.0.1:8001".parse().unwrap();
+        meta.set_addr(&v4);
+        assert_eq!(meta.addr(), v4);
+
+        meta.set_addr(&"[::ffff:10.0.0.1]:8001".parse().unwrap());
+        assert_eq!(meta.addr(), v4);
+    }
+
     #[test]
     pub fn debug_trait() {
         write!(io::sink(), "{:?}", Packet::default()).unwrap();
@@ -597,4 +655,15 @@ mod tests {
         assert_eq!(b.get_index().unwrap(), <u64>::max_value());
     }
 
+    #[test]
+    pub fn blob_num_coding_test() {
+        let mut b = Blob::default();
+        b.set_index(7).unwrap();
+        b.set_size(16);
+        b.set_num_coding(5).unwrap();
+        assert_eq!(b.get_num_coding().unwrap(), 5);
+        assert_eq!(b.get_index().unwrap(), 7);
+        assert_eq!(b.get_data_size().unwrap(), 16 + BLOB_HEADER_SIZE as u64);
+        assert_eq!(BLOB_HEADER_SIZE, 64);
+    }
 }
@@ -37,4 +37,7 @@ pub trait PaymentPlan {
     /// Apply a witness to the payment plan to see if the plan can be reduced.
     /// If so, modify the plan in-place.
     fn apply_witness(&mut self, witness: &Witness, from: &PublicKey);
+
+    /// Return true if `pubkey` is paid by the plan or may witness it.
+    fn is_party(&self, pubkey: &PublicKey) -> bool;
 }
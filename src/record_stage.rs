@@ -8,6 +8,7 @@
 use entry::Entry;
 use hash::Hash;
 use recorder::Recorder;
+use result;
 use service::Service;
 use std::sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError};
 use std::thread::{self, Builder, JoinHandle};
@@ -18,6 +19,29 @@ use transaction::Transaction;
 pub enum Signal {
     Tick,
     Transactions(Vec<Transaction>),
+    /// External values to mix into the hash chain, such as storage samples
+    Mixins(Vec<Hash>),
+}
+
+/// A handle for mixing external values into the hash chain of a running
+/// RecordStage. It only sends `Signal::Mixins`, so unlike the stage's other
+/// senders it can't record transactions the bank hasn't processed.
+#[derive(Clone)]
+pub struct MixinSender {
+    signal_sender: Sender<Signal>,
+}
+
+impl MixinSender {
+    pub fn new(signal_sender: Sender<Signal>) -> Self {
+        MixinSender { signal_sender }
+    }
+
+    /// Mix `mixins` into the hash chain. The RecordStage keeps running while
+    /// any clone of this sender is alive.
+    pub fn send(&self, mixins: Vec<Hash>) -> result::Result<()> {
+        self.signal_sender.send(Signal::Mixins(mixins))?;
+        Ok(())
+    }
 }
 
 pub struct RecordStage {
@@ -78,18 +102,61 @@ impl RecordStage {
         (RecordStage { thread_hdl }, entry_receiver)
     }
 
+    /// Same as `RecordStage::new`, but will automatically produce a tick every
+    /// `hashes_per_tick` hashes, and only then. `hashes_since_tick` of those
+    /// were generated by the entries since the last tick before `start_hash`.
+    pub fn new_with_hashes_per_tick(
+        signal_receiver: Receiver<Signal>,
+        start_hash: &Hash,
+        hashes_per_tick: u64,
+        hashes_since_tick: u64,
+    ) -> (Self, Receiver<Vec<Entry>>) {
+        let (entry_sender, entry_receiver) = channel();
+        let start_hash = *start_hash;
+
+        let thread_hdl = Builder::new()
+            .name("solana-record-stage".to_string())
+            .spawn(move || {
+                let mut recorder = Recorder::new_with_hashes_per_tick(
+                    start_hash,
+                    hashes_per_tick,
+                    hashes_since_tick,
+                );
+                loop {
+                    if Self::try_process_pending_signals(
+                        &mut recorder,
+                        &signal_receiver,
+                        &entry_sender,
+                    ).is_err()
+                    {
+                        return;
+                    }
+                    recorder.hash();
+                    if let Some(entry) = recorder.tick_hashes() {
+                        if entry_sender.send(vec![entry]).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        (RecordStage { thread_hdl }, entry_receiver)
+    }
+
     fn process_signal(
         signal: Signal,
         recorder: &mut Recorder,
         sender: &Sender<Vec<Entry>>,
     ) -> Result<(), ()> {
-        let txs = if let Signal::Transactions(txs) = signal {
-            txs
-        } else {
-            vec![]
+        let entries = match signal {
+            Signal::Tick => recorder.record(vec![]),
+            Signal::Transactions(txs) => recorder.record(txs),
+            Signal::Mixins(mixins) => recorder.mix(mixins),
         };
-        let entries = recorder.record(txs);
-        sender.send(entries).or(Err(()))?;
+        if !entries.is_empty() {
+            sender.send(entries).or(Err(()))?;
+        }
         Ok(())
     }
 
@@ -124,6 +191,20 @@ impl RecordStage {
             };
         }
     }
+
+    fn try_process_pending_signals(
+        recorder: &mut Recorder,
+        receiver: &Receiver<Signal>,
+        sender: &Sender<Vec<Entry>>,
+    ) -> Result<(), ()> {
+        loop {
+            match receiver.try_recv() {
+                Ok(signal) => Self::process_signal(signal, recorder, sender)?,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(()),
+            };
+        }
+    }
 }
 
 impl Service for RecordStage {
@@ -139,6 +220,7 @@ impl Service for RecordStage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use hash::hash;
     use ledger::Block;
     use signature::{KeyPair, KeyPairUtil};
     use std::sync::mpsc::channel;
@@ -197,6 +279,21 @@ mod tests {
         assert_eq!(entries.len(), 1);
     }
 
+    #[test]
+    fn test_mixins() {
+        let (tx_sender, signal_receiver) = channel();
+        let zero = Hash::default();
+        let (_record_stage, entry_receiver) = RecordStage::new(signal_receiver, &zero);
+        let sample = hash(&zero);
+        let mixin_sender = MixinSender::new(tx_sender);
+        mixin_sender.send(vec![sample]).unwrap();
+        drop(mixin_sender);
+        let entries: Vec<_> = entry_receiver.iter().flat_map(|x| x).collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].mixins, vec![sample]);
+        assert!(entries.verify(&zero));
+    }
+
     #[test]
     fn test_clock() {
         let (tx_sender, tx_receiver) = channel();
@@ -212,4 +309,33 @@ mod tests {
         // Ensure the ID is not the seed.
         assert_ne!(entries[0].id, zero);
     }
+
+    #[test]
+    fn test_hashes_per_tick() {
+        let (tx_sender, tx_receiver) = channel();
+        let zero = Hash::default();
+        let (_record_stage, entry_receiver) =
+            RecordStage::new_with_hashes_per_tick(tx_receiver, &zero, 100, 0);
+        sleep(Duration::from_millis(100));
+        tx_sender.send(Signal::Tick).unwrap();
+        let keypair = KeyPair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, zero);
+        tx_sender.send(Signal::Transactions(vec![tx])).unwrap();
+        sleep(Duration::from_millis(100));
+        drop(tx_sender);
+        let entries: Vec<_> = entry_receiver.iter().flat_map(|x| x).collect();
+        assert!(entries.len() > 1);
+        assert_eq!(entries.iter().filter(|e| !e.is_tick()).count(), 1);
+        // every tick closes 100 hashes, not counting the one mixing in the transaction
+        let mut hashes_since_tick = 0;
+        for entry in &entries {
+            if entry.is_tick() {
+                assert_eq!(hashes_since_tick + entry.num_hashes, 100);
+                hashes_since_tick = 0;
+            } else {
+                hashes_since_tick += entry.num_hashes - 1;
+            }
+        }
+        assert!(entries.verify(&zero));
+    }
 }
@@ -1,16 +1,44 @@
 //! The `recorder` module provides an object for generating a Proof of History.
 //! It records Transaction items on behalf of its users.
 
-use entry::Entry;
+use entry::{Entry, CHECKPOINT_HASHES};
 use hash::{hash, Hash};
 use ledger;
+use record_stage::Signal;
+use std::mem;
+use std::sync::mpsc::channel;
 use std::time::{Duration, Instant};
 use transaction::Transaction;
 
+/// Hashes the calibration generates between looks at the clock
+const CALIBRATION_HASHES: u64 = 100;
+
 pub struct Recorder {
     last_hash: Hash,
     num_hashes: u64,
     num_ticks: u32,
+    /// the hash at every `CHECKPOINT_HASHES` since the last entry
+    checkpoints: Vec<Hash>,
+    /// if set, a Tick closes every `hashes_per_tick` hashes rather than a clock
+    hashes_per_tick: Option<u64>,
+    /// hashes generated since the last Tick, across the entries in between
+    hashes_since_tick: u64,
+}
+
+/// The number of hashes this node generates in `tick_duration`, to fix the
+/// hashes per tick of a new ledger with. The record stage checks for signals
+/// before every hash, so that's measured too.
+pub fn calibrate_hashes_per_tick(tick_duration: Duration) -> u64 {
+    let (_sender, receiver) = channel::<Signal>();
+    let mut recorder = Recorder::new(Hash::default());
+    let start_time = Instant::now();
+    while start_time.elapsed() < tick_duration {
+        for _ in 0..CALIBRATION_HASHES {
+            let _ = receiver.try_recv();
+            recorder.hash();
+        }
+    }
+    recorder.hashes_since_tick.max(1)
 }
 
 impl Recorder {
@@ -19,30 +47,234 @@ impl Recorder {
             last_hash,
             num_hashes: 0,
             num_ticks: 0,
+            checkpoints: vec![],
+            hashes_per_tick: None,
+            hashes_since_tick: 0,
+        }
+    }
+
+    /// A Recorder that closes a Tick every `hashes_per_tick` hashes, and only
+    /// then. `hashes_since_tick` hashes of the first Tick were generated before
+    /// `last_hash`, by whoever recorded the entries since the last Tick.
+    pub fn new_with_hashes_per_tick(
+        last_hash: Hash,
+        hashes_per_tick: u64,
+        hashes_since_tick: u64,
+    ) -> Self {
+        Recorder {
+            hashes_per_tick: Some(hashes_per_tick),
+            hashes_since_tick,
+            ..Self::new(last_hash)
         }
     }
 
     pub fn hash(&mut self) {
         self.last_hash = hash(&self.last_hash);
         self.num_hashes += 1;
+        self.hashes_since_tick += 1;
+        if self.num_hashes % CHECKPOINT_HASHES == 0 {
+            self.checkpoints.push(self.last_hash);
+        }
+    }
+
+    /// A Tick carrying the checkpoints of the hashes since the last entry
+    fn checkpoint_tick(&mut self) -> Entry {
+        let mut checkpoints = mem::replace(&mut self.checkpoints, vec![]);
+        if self.num_hashes % CHECKPOINT_HASHES == 0 {
+            // that checkpoint is the tick's id
+            checkpoints.pop();
+        }
+        let mut entry = Entry::new_mut(&mut self.last_hash, &mut self.num_hashes, vec![], false);
+        entry.checkpoints = checkpoints;
+        self.hashes_since_tick = 0;
+        entry
+    }
+
+    /// Before an entry that isn't a Tick, a Tick closing the hashes since the
+    /// last entry if they were many enough to need checkpoints. With a fixed
+    /// hashes per tick, only `tick_hashes` closes Ticks, so the checkpoints
+    /// are dropped instead.
+    fn close_checkpoints(&mut self) -> Vec<Entry> {
+        if self.hashes_per_tick.is_some() {
+            self.checkpoints.clear();
+            vec![]
+        } else if self.checkpoints.is_empty() {
+            vec![]
+        } else {
+            vec![self.checkpoint_tick()]
+        }
     }
 
+    /// Record `transactions`, or a Tick if there are none and the hashes per
+    /// tick aren't fixed.
     pub fn record(&mut self, transactions: Vec<Transaction>) -> Vec<Entry> {
-        ledger::next_entries_mut(&mut self.last_hash, &mut self.num_hashes, transactions)
+        if transactions.is_empty() {
+            if self.hashes_per_tick.is_some() {
+                return vec![];
+            }
+            return vec![self.checkpoint_tick()];
+        }
+        let mut entries = self.close_checkpoints();
+        entries.extend(ledger::next_entries_mut(
+            &mut self.last_hash,
+            &mut self.num_hashes,
+            transactions,
+        ));
+        entries
+    }
+
+    /// Mix `mixins` into the hash chain, recording them in Entries whose IDs
+    /// prove they existed before they were generated. Like transactions, the
+    /// mixins are split across as many Entries as it takes to fit each in a blob.
+    pub fn mix(&mut self, mixins: Vec<Hash>) -> Vec<Entry> {
+        if mixins.is_empty() {
+            return vec![];
+        }
+        let mut entries = self.close_checkpoints();
+        let mut chunk_len = mixins.len();
+        while !Entry::will_fit_mixins(mixins[0..chunk_len].to_vec()) {
+            chunk_len /= 2;
+        }
+        for chunk in mixins.chunks(chunk_len) {
+            let entry = Entry::new_mixins(&self.last_hash, self.num_hashes, chunk.to_vec());
+            self.last_hash = entry.id;
+            self.num_hashes = 0;
+            entries.push(entry);
+        }
+        entries
     }
 
     pub fn tick(&mut self, start_time: Instant, tick_duration: Duration) -> Option<Entry> {
         if start_time.elapsed() > tick_duration * (self.num_ticks + 1) {
             // TODO: don't let this overflow u32
             self.num_ticks += 1;
-            Some(Entry::new_mut(
-                &mut self.last_hash,
-                &mut self.num_hashes,
-                vec![],
-                false,
-            ))
+            Some(self.checkpoint_tick())
         } else {
             None
         }
     }
+
+    /// A Tick, once `hashes_per_tick` hashes have passed since the last Tick.
+    /// The hashes that mix transactions or mixins into the entries in between
+    /// don't count, so the time between Ticks doesn't depend on the load.
+    pub fn tick_hashes(&mut self) -> Option<Entry> {
+        match self.hashes_per_tick {
+            Some(hashes_per_tick) if self.hashes_since_tick >= hashes_per_tick => {
+                Some(self.checkpoint_tick())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entry::CHECKPOINT_HASHES;
+    use hash::{hash, Hash};
+    use ledger::Block;
+    use packet::BLOB_DATA_SIZE;
+    use recorder::{calibrate_hashes_per_tick, Recorder};
+    use signature::{KeyPair, KeyPairUtil};
+    use std::time::Duration;
+    use transaction::Transaction;
+
+    #[test]
+    fn test_record_checkpoints() {
+        let zero = Hash::default();
+        let mut recorder = Recorder::new(zero);
+        for _ in 0..2 * CHECKPOINT_HASHES {
+            recorder.hash();
+        }
+        let mut entries = recorder.record(vec![]);
+        assert_eq!(entries[0].num_hashes, 2 * CHECKPOINT_HASHES);
+        assert_eq!(entries[0].checkpoints.len(), 1);
+
+        for _ in 0..CHECKPOINT_HASHES + 1 {
+            recorder.hash();
+        }
+        let keypair = KeyPair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, zero);
+        let more = recorder.record(vec![tx]);
+        assert_eq!(more.len(), 2);
+        assert_eq!(more[0].checkpoints.len(), 1);
+        assert!(more[1].checkpoints.is_empty());
+        entries.extend(more);
+        assert!(entries.verify(&zero));
+
+        // a wrong checkpoint, or one past the id, fails verification
+        let mut bad = entries.clone();
+        bad[1].checkpoints = vec![zero];
+        assert!(!bad.verify(&zero));
+        let mut bad = entries.clone();
+        bad[2].checkpoints = vec![entries[2].id];
+        assert!(!bad.verify(&zero));
+    }
+
+    #[test]
+    fn test_record_hashes_per_tick() {
+        let zero = Hash::default();
+        let mut recorder = Recorder::new_with_hashes_per_tick(zero, 10, 0);
+        let mut entries = vec![];
+        for _ in 0..25 {
+            recorder.hash();
+            entries.extend(recorder.tick_hashes());
+        }
+        assert!(recorder.record(vec![]).is_empty());
+        assert_eq!(entries.len(), 2);
+
+        let keypair = KeyPair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, zero);
+        entries.extend(recorder.record(vec![tx]));
+        for _ in 0..10 {
+            recorder.hash();
+            entries.extend(recorder.tick_hashes());
+        }
+        assert_eq!(entries.len(), 4);
+        // the transaction entry took 5 of the tick's hashes, and one to mix it in
+        assert_eq!(entries[2].num_hashes, 6);
+        assert_eq!(entries[3].num_hashes, 5);
+        assert!(entries.verify(&zero));
+
+        // a recorder picking up 7 hashes into a tick closes it 3 hashes later
+        let mut recorder = Recorder::new_with_hashes_per_tick(zero, 10, 7);
+        for _ in 0..2 {
+            recorder.hash();
+            assert!(recorder.tick_hashes().is_none());
+        }
+        recorder.hash();
+        assert_eq!(recorder.tick_hashes().unwrap().num_hashes, 3);
+    }
+
+    #[test]
+    fn test_mix() {
+        let zero = Hash::default();
+        let mut recorder = Recorder::new(zero);
+        assert!(recorder.mix(vec![]).is_empty());
+        for _ in 0..CHECKPOINT_HASHES + 1 {
+            recorder.hash();
+        }
+        let sample = hash(&zero);
+        let entries = recorder.mix(vec![sample]);
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_tick());
+        assert_eq!(entries[1].mixins, vec![sample]);
+        assert!(entries.verify(&zero));
+
+        // more mixins than fit in a blob are split across entries
+        let last_id = entries[1].id;
+        let samples = vec![sample; BLOB_DATA_SIZE / sample.len() + 1];
+        let entries = recorder.mix(samples.clone());
+        assert!(entries.len() > 1);
+        let mut mixins = vec![];
+        for entry in &entries {
+            mixins.extend(entry.mixins.iter().cloned());
+        }
+        assert_eq!(mixins, samples);
+        assert!(entries.verify(&last_id));
+    }
+
+    #[test]
+    fn test_calibrate_hashes_per_tick() {
+        assert!(calibrate_hashes_per_tick(Duration::from_millis(10)) > 0);
+    }
 }
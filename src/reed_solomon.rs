@@ -0,0 +1,341 @@
+//! The `reed_solomon` module is a pure-Rust implementation of the Cauchy
+//! Reed-Solomon code behind the `erasure` module. It computes over GF(2^32)
+//! with the same field polynomial and coding matrix that jerasure uses with
+//! `w = 32`, so its coding blocks are interchangeable with the C backend's.
+
+use byteorder::{ByteOrder, LittleEndian};
+use erasure::{ErasureError, Result};
+
+/// x^32 + x^22 + x^2 + x + 1, gf-complete's default polynomial for w = 32
+const FIELD_POLY: u32 = 0x0040_0007;
+
+/// Blocks are coded a 32-bit word at a time
+const WORD_SIZE: usize = 4;
+
+/// Multiply in GF(2^32).
+pub fn gf_mul(mut a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        b >>= 1;
+        let carry = a & 0x8000_0000 != 0;
+        a <<= 1;
+        if carry {
+            a ^= FIELD_POLY;
+        }
+    }
+    product
+}
+
+/// Multiplicative inverse in GF(2^32), computed as a^(2^32 - 2).
+pub fn gf_inv(a: u32) -> u32 {
+    assert!(a != 0, "zero has no inverse");
+    let mut inverse = 1;
+    let mut base = a;
+    let mut exp: u64 = (1 << 32) - 2;
+    while exp != 0 {
+        if exp & 1 != 0 {
+            inverse = gf_mul(inverse, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    inverse
+}
+
+/// The `m` x `k` Cauchy coding matrix in row-major order. Entry (i, j) is
+/// 1 / (i + (m + j)), the matrix jerasure's `get_matrix` builds.
+pub fn coding_matrix(m: usize, k: usize) -> Vec<u32> {
+    let mut matrix = Vec::with_capacity(m * k);
+    for i in 0..m {
+        for j in 0..k {
+            matrix.push(gf_inv((i ^ (m + j)) as u32));
+        }
+    }
+    matrix
+}
+
+/// Multiplies words by a constant with one table lookup per byte.
+struct MulTable([[u32; 256]; WORD_SIZE]);
+
+impl MulTable {
+    fn new(c: u32) -> Self {
+        let mut table = [[0u32; 256]; WORD_SIZE];
+        for (byte, row) in table.iter_mut().enumerate() {
+            for bit in 0..8 {
+                let product = gf_mul(c, 1 << (8 * byte + bit));
+                let step = 1 << bit;
+                for b in step..2 * step {
+                    row[b] = row[b - step] ^ product;
+                }
+            }
+        }
+        MulTable(table)
+    }
+
+    fn mul(&self, x: u32) -> u32 {
+        let t = &self.0;
+        t[0][(x & 0xff) as usize]
+            ^ t[1][((x >> 8) & 0xff) as usize]
+            ^ t[2][((x >> 16) & 0xff) as usize]
+            ^ t[3][(x >> 24) as usize]
+    }
+
+    /// dst += c * src, a word at a time
+    fn mul_add_region(&self, dst: &mut [u8], src: &[u8]) {
+        for (d, s) in dst.chunks_mut(WORD_SIZE).zip(src.chunks(WORD_SIZE)) {
+            let word = LittleEndian::read_u32(d) ^ self.mul(LittleEndian::read_u32(s));
+            LittleEndian::write_u32(d, word);
+        }
+    }
+}
+
+fn check_block_sizes<I: Iterator<Item = usize>>(block_len: usize, lens: I) -> Result<()> {
+    if block_len % WORD_SIZE != 0 {
+        trace!(
+            "block size {} is not a multiple of {}",
+            block_len,
+            WORD_SIZE
+        );
+        return Err(ErasureError::InvalidBlockSize);
+    }
+    for len in lens {
+        if len != block_len {
+            trace!("block size incorrect {} expected {}", len, block_len);
+            return Err(ErasureError::InvalidBlockSize);
+        }
+    }
+    Ok(())
+}
+
+/// Invert the `n` x `n` row-major matrix `a` with Gauss-Jordan elimination.
+fn invert_matrix(mut a: Vec<u32>, n: usize) -> Result<Vec<u32>> {
+    let mut inverse = vec![0; n * n];
+    for i in 0..n {
+        inverse[i * n + i] = 1;
+    }
+    for col in 0..n {
+        let pivot = (col..n)
+            .find(|row| a[row * n + col] != 0)
+            .ok_or(ErasureError::DecodeError)?;
+        for j in 0..n {
+            a.swap(pivot * n + j, col * n + j);
+            inverse.swap(pivot * n + j, col * n + j);
+        }
+        let scale = gf_inv(a[col * n + col]);
+        for j in 0..n {
+            a[col * n + j] = gf_mul(a[col * n + j], scale);
+            inverse[col * n + j] = gf_mul(inverse[col * n + j], scale);
+        }
+        for row in (0..n).filter(|row| *row != col) {
+            let factor = a[row * n + col];
+            if factor == 0 {
+                continue;
+            }
+            for j in 0..n {
+                a[row * n + j] ^= gf_mul(factor, a[col * n + j]);
+                inverse[row * n + j] ^= gf_mul(factor, inverse[col * n + j]);
+            }
+        }
+    }
+    Ok(inverse)
+}
+
+/// Generate coding blocks into `coding` from `data`. Blocks must all be the
+/// same size, a multiple of 4 bytes.
+pub fn generate_coding_blocks(coding: &mut [&mut [u8]], data: &[&[u8]]) -> Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    let block_len = data[0].len();
+    let lens = data.iter().map(|b| b.len());
+    check_block_sizes(block_len, lens.chain(coding.iter().map(|b| b.len())))?;
+
+    let k = data.len();
+    let matrix = coding_matrix(coding.len(), k);
+    for (i, block) in coding.iter_mut().enumerate() {
+        for b in block.iter_mut() {
+            *b = 0;
+        }
+        for (j, src) in data.iter().enumerate() {
+            MulTable::new(matrix[i * k + j]).mul_add_region(block, src);
+        }
+    }
+    Ok(())
+}
+
+/// Recover the data blocks listed in `erasures` from the rest of `data` and
+/// `coding`. `erasures` holds indices into data followed by coding and ends
+/// with -1. Erased coding blocks are not regenerated.
+pub fn decode_blocks(data: &mut [&mut [u8]], coding: &[&[u8]], erasures: &[i32]) -> Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    let block_len = data[0].len();
+    let lens = data.iter().map(|b| b.len());
+    check_block_sizes(block_len, lens.chain(coding.iter().map(|b| b.len())))?;
+
+    let k = data.len();
+    let m = coding.len();
+    let erased: Vec<usize> = erasures
+        .iter()
+        .take_while(|e| **e >= 0)
+        .map(|e| *e as usize)
+        .collect();
+    if erased.iter().any(|e| *e >= k + m) {
+        return Err(ErasureError::DecodeError);
+    }
+    let lost: Vec<usize> = erased.iter().cloned().filter(|e| *e < k).collect();
+    if lost.is_empty() {
+        return Ok(());
+    }
+    let rows: Vec<usize> = (0..m)
+        .filter(|i| !erased.contains(&(k + i)))
+        .take(lost.len())
+        .collect();
+    if rows.len() < lost.len() {
+        return Err(ErasureError::NotEnoughBlocksToDecode);
+    }
+
+    // Subtract the surviving data out of each coding block, leaving only
+    // the lost data's contribution.
+    let matrix = coding_matrix(m, k);
+    let mut syndromes: Vec<Vec<u8>> = rows.iter().map(|row| coding[*row].to_vec()).collect();
+    for (syndrome, row) in syndromes.iter_mut().zip(&rows) {
+        for j in (0..k).filter(|j| !lost.contains(j)) {
+            MulTable::new(matrix[row * k + j]).mul_add_region(syndrome, &data[j]);
+        }
+    }
+
+    // Solve for the lost data with the inverse of the coding rows restricted
+    // to the lost columns. Any square submatrix of a Cauchy matrix is invertible.
+    let n = lost.len();
+    let mut sub = Vec::with_capacity(n * n);
+    for row in &rows {
+        for col in &lost {
+            sub.push(matrix[row * k + col]);
+        }
+    }
+    let inverse = invert_matrix(sub, n)?;
+    for (i, col) in lost.iter().enumerate() {
+        let block = &mut data[*col];
+        for b in block.iter_mut() {
+            *b = 0;
+        }
+        for (j, syndrome) in syndromes.iter().enumerate() {
+            MulTable::new(inverse[i * n + j]).mul_add_region(block, syndrome);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use erasure::ErasureError;
+    use rand::{thread_rng, Rng};
+    use reed_solomon::*;
+
+    #[test]
+    fn test_gf_arithmetic() {
+        assert_eq!(gf_mul(0, 12345), 0);
+        assert_eq!(gf_mul(1, 12345), 12345);
+        assert_eq!(gf_mul(2, 0x8000_0000), FIELD_POLY);
+        for a in &[1, 2, 3, 0xdead_beef, 0xffff_ffff] {
+            assert_eq!(gf_mul(*a, gf_inv(*a)), 1);
+            assert_eq!(gf_mul(*a, 0x1234_5678), gf_mul(0x1234_5678, *a));
+        }
+        let table = MulTable::new(0xdead_beef);
+        for x in &[0, 1, 0xff, 0x1234_5678, 0xffff_ffff] {
+            assert_eq!(table.mul(*x), gf_mul(0xdead_beef, *x));
+        }
+    }
+
+    #[test]
+    fn test_invert_matrix() {
+        let m = 4;
+        let k = 4;
+        let matrix = coding_matrix(m, k);
+        let inverse = invert_matrix(matrix.clone(), k).unwrap();
+        for i in 0..k {
+            for j in 0..k {
+                let dot = (0..k).fold(0, |acc, x| {
+                    acc ^ gf_mul(matrix[i * k + x], inverse[x * k + j])
+                });
+                assert_eq!(dot, if i == j { 1 } else { 0 });
+            }
+        }
+        assert!(invert_matrix(vec![0; 4], 2).is_err());
+    }
+
+    #[test]
+    fn test_decode_every_erasure_pattern() {
+        let k = 6;
+        let m = 3;
+        let block_len = 64;
+        let mut rng = thread_rng();
+        let data: Vec<Vec<u8>> = (0..k)
+            .map(|_| (0..block_len).map(|_| rng.gen()).collect())
+            .collect();
+        let mut coding: Vec<Vec<u8>> = vec![vec![0; block_len]; m];
+        {
+            let data_slices: Vec<&[u8]> = data.iter().map(|x| x.as_slice()).collect();
+            let mut coding_slices: Vec<&mut [u8]> =
+                coding.iter_mut().map(|x| x.as_mut_slice()).collect();
+            generate_coding_blocks(&mut coding_slices, &data_slices).unwrap();
+        }
+
+        // every set of up to m erased blocks, data or coding, is recoverable
+        for pattern in 0u32..(1 << (k + m)) {
+            let erased: Vec<i32> = (0..(k + m) as i32)
+                .filter(|i| pattern & (1 << i) != 0)
+                .collect();
+            if erased.len() > m {
+                continue;
+            }
+            let mut damaged = data.clone();
+            for e in erased.iter().filter(|e| (**e as usize) < k) {
+                damaged[*e as usize] = vec![0; block_len];
+            }
+            let mut erasures = erased.clone();
+            erasures.push(-1);
+            {
+                let coding_slices: Vec<&[u8]> = coding.iter().map(|x| x.as_slice()).collect();
+                let mut data_slices: Vec<&mut [u8]> =
+                    damaged.iter_mut().map(|x| x.as_mut_slice()).collect();
+                decode_blocks(&mut data_slices, &coding_slices, &erasures).unwrap();
+            }
+            assert_eq!(damaged, data, "erasures {:?}", erasures);
+        }
+    }
+
+    #[test]
+    fn test_decode_too_many_erasures() {
+        let mut data = vec![vec![0u8; 16]; 4];
+        let coding = vec![vec![0u8; 16]; 2];
+        let coding_slices: Vec<&[u8]> = coding.iter().map(|x| x.as_slice()).collect();
+        let mut data_slices: Vec<&mut [u8]> = data.iter_mut().map(|x| x.as_mut_slice()).collect();
+        assert_eq!(
+            decode_blocks(&mut data_slices, &coding_slices, &[0, 1, 4, -1]),
+            Err(ErasureError::NotEnoughBlocksToDecode)
+        );
+    }
+
+    #[test]
+    fn test_block_sizes() {
+        let data = vec![vec![0u8; 16], vec![0u8; 20]];
+        let data_slices: Vec<&[u8]> = data.iter().map(|x| x.as_slice()).collect();
+        let mut coding = vec![0u8; 16];
+        assert_eq!(
+            generate_coding_blocks(&mut [&mut coding[..]], &data_slices),
+            Err(ErasureError::InvalidBlockSize)
+        );
+        let data = vec![vec![0u8; 6]];
+        let data_slices: Vec<&[u8]> = data.iter().map(|x| x.as_slice()).collect();
+        assert_eq!(
+            generate_coding_blocks(&mut [&mut coding[..6]], &data_slices),
+            Err(ErasureError::InvalidBlockSize)
+        );
+    }
+}
@@ -4,22 +4,25 @@ use bank::Bank;
 use bincode::serialize;
 use counter::Counter;
 use crdt::Crdt;
+use entry::Entry;
 use ledger;
-use packet::BlobRecycler;
+use net_utils::udp_bind;
+use packet::{BlobRecycler, SharedBlob};
+use replicator::StorageProofVerifier;
 use result::{Error, Result};
 use service::Service;
 use signature::KeyPair;
 use std::collections::VecDeque;
-use std::net::UdpSocket;
+use std::net::SocketAddr;
 use std::sync::atomic::AtomicUsize;
-use std::sync::mpsc::channel;
 use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
 use std::sync::{Arc, RwLock};
 use std::thread::{self, Builder, JoinHandle};
 use std::time::Duration;
 use streamer::{responder, BlobReceiver, BlobSender};
 use timing;
-use transaction::Transaction;
+use transaction::{Instruction, Transaction};
 use voting::entries_to_votes;
 
 pub struct ReplicateStage {
@@ -28,7 +31,90 @@ pub struct ReplicateStage {
 
 const VOTE_TIMEOUT_MS: u64 = 1000;
 
+/// The most storage proofs waiting to be verified, later ones are dropped
+const MAX_QUEUED_STORAGE_PROOFS: usize = 64;
+
 impl ReplicateStage {
+    /// Queue the storage proofs in `entries` to be verified, dropping those that
+    /// don't fit so that replication never waits on them
+    fn queue_storage_proofs(entries: &[Entry], proof_sender: &SyncSender<Transaction>) {
+        for tx in entries.iter().flat_map(|entry| &entry.transactions) {
+            if let Instruction::NewStorageProof(_) = tx.instruction {
+                if proof_sender.try_send(tx.clone()).is_err() {
+                    inc_new_counter!("replicate-storage_proof_dropped", 1);
+                }
+            }
+        }
+    }
+
+    /// Verify the queued storage proofs against this node's ledger, and send the
+    /// leader an approval of those that hold
+    fn approve_storage_proofs(
+        keypair: &Arc<KeyPair>,
+        bank: &Arc<Bank>,
+        crdt: &Arc<RwLock<Crdt>>,
+        blob_recycler: &BlobRecycler,
+        verifier: &mut StorageProofVerifier,
+        proof_receiver: &Receiver<Transaction>,
+        vote_blob_sender: &BlobSender,
+    ) -> Result<()> {
+        let timer = Duration::new(1, 0);
+        let mut txs = vec![proof_receiver.recv_timeout(timer)?];
+        while let Ok(tx) = proof_receiver.try_recv() {
+            txs.push(tx);
+        }
+        let ledger = match crdt.read().unwrap().ledger() {
+            Some(ledger) => ledger.clone(),
+            None => return Ok(()),
+        };
+        let now = timing::timestamp();
+        let approvals: Vec<_> = txs
+            .iter()
+            .filter(|tx| {
+                let verified = verifier.verify(tx, &ledger, now);
+                if !verified {
+                    inc_new_counter!("replicate-storage_proof_rejected", 1);
+                }
+                verified
+            })
+            .map(|tx| tx.sig)
+            .collect();
+        if approvals.is_empty() {
+            return Ok(());
+        }
+        let addr = {
+            let rcrdt = crdt.read().unwrap();
+            let me = rcrdt.my_data().contact_info.clone();
+            rcrdt
+                .leader_data()
+                .map(|leader| leader.contact_info.reachable_from(&me).tpu)
+        };
+        if let Some(addr) = addr {
+            inc_new_counter!("replicate-storage_proofs_approved", approvals.len());
+            let tx = Transaction::new_storage_approval(&keypair, approvals, bank.last_id(), 0);
+            let blob = Self::tx_blob(blob_recycler, &tx, &addr)?;
+            vote_blob_sender.send(VecDeque::from(vec![blob]))?;
+        }
+        Ok(())
+    }
+
+    fn tx_blob(
+        blob_recycler: &BlobRecycler,
+        tx: &Transaction,
+        addr: &SocketAddr,
+    ) -> Result<SharedBlob> {
+        let shared_blob = blob_recycler.allocate();
+        {
+            let mut blob = shared_blob.write().unwrap();
+            let bytes = serialize(tx)?;
+            let len = bytes.len();
+            blob.data[..len].copy_from_slice(&bytes);
+            blob.meta.set_addr(addr);
+            blob.meta.size = len;
+        }
+        Ok(shared_blob)
+    }
+
     /// Process entry blobs, already in order
     fn replicate_requests(
         keypair: &Arc<KeyPair>,
@@ -37,6 +123,7 @@ impl ReplicateStage {
         blob_recycler: &BlobRecycler,
         window_receiver: &BlobReceiver,
         vote_blob_sender: &BlobSender,
+        proof_sender: &SyncSender<Transaction>,
         last_vote: &mut u64,
     ) -> Result<()> {
         let timer = Duration::new(1, 0);
@@ -56,6 +143,7 @@ impl ReplicateStage {
             "replicate-transactions",
             entries.iter().map(|x| x.transactions.len()).sum()
         );
+        Self::queue_storage_proofs(&entries, proof_sender);
         let res = bank.process_entries(entries);
         if res.is_err() {
             error!("process_entries {} {:?}", blobs_len, res);
@@ -64,22 +152,14 @@ impl ReplicateStage {
         let now = timing::timestamp();
         if now - *last_vote > VOTE_TIMEOUT_MS {
             let last_id = bank.last_id();
-            let shared_blob = blob_recycler.allocate();
             let (vote, addr) = {
                 let mut wcrdt = crdt.write().unwrap();
                 //TODO: doesn't seem like there is a synchronous call to get height and id
                 info!("replicate_stage {:?}", &last_id[..8]);
                 wcrdt.new_vote(last_id)
             }?;
-            {
-                let mut blob = shared_blob.write().unwrap();
-                let tx = Transaction::new_vote(&keypair, vote, last_id, 0);
-                let bytes = serialize(&tx)?;
-                let len = bytes.len();
-                blob.data[..len].copy_from_slice(&bytes);
-                blob.meta.set_addr(&addr);
-                blob.meta.size = len;
-            }
+            let tx = Transaction::new_vote(&keypair, vote, last_id, 0);
+            let shared_blob = Self::tx_blob(blob_recycler, &tx, &addr)?;
             inc_new_counter!("replicate-vote_sent", 1);
             *last_vote = now;
 
@@ -98,7 +178,7 @@ impl ReplicateStage {
         window_receiver: BlobReceiver,
     ) -> Self {
         let (vote_blob_sender, vote_blob_receiver) = channel();
-        let send = UdpSocket::bind("0.0.0.0:0").expect("bind");
+        let send = udp_bind(0).expect("bind");
         let t_responder = responder(
             "replicate_stage",
             send,
@@ -106,6 +186,38 @@ impl ReplicateStage {
             vote_blob_receiver,
         );
         let skeypair = Arc::new(keypair);
+        let (proof_sender, proof_receiver) = sync_channel(MAX_QUEUED_STORAGE_PROOFS);
+
+        let t_storage = {
+            let skeypair = skeypair.clone();
+            let bank = bank.clone();
+            let crdt = crdt.clone();
+            let blob_recycler = blob_recycler.clone();
+            let vote_blob_sender = vote_blob_sender.clone();
+            Builder::new()
+                .name("solana-storage-verifier".to_string())
+                .spawn(move || {
+                    let mut verifier = StorageProofVerifier::default();
+                    loop {
+                        if let Err(e) = Self::approve_storage_proofs(
+                            &skeypair,
+                            &bank,
+                            &crdt,
+                            &blob_recycler,
+                            &mut verifier,
+                            &proof_receiver,
+                            &vote_blob_sender,
+                        ) {
+                            match e {
+                                Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                                Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                                _ => error!("{:?}", e),
+                            }
+                        }
+                    }
+                })
+                .unwrap()
+        };
 
         let t_replicate = Builder::new()
             .name("solana-replicate-stage".to_string())
@@ -119,6 +231,7 @@ impl ReplicateStage {
                         &blob_recycler,
                         &window_receiver,
                         &vote_blob_sender,
+                        &proof_sender,
                         &mut timestamp,
                     ) {
                         match e {
@@ -131,7 +244,7 @@ impl ReplicateStage {
             })
             .unwrap();
         ReplicateStage {
-            thread_hdls: vec![t_responder, t_replicate],
+            thread_hdls: vec![t_responder, t_replicate, t_storage],
         }
     }
 }
@@ -0,0 +1,710 @@
+//! The `replicator` module implements the storage miner of RFC-003, a node that
+//! keeps a segment of the ledger and proves that it does.
+//!
+//! A replicator joins the network over gossip and fetches its segment with the
+//! same window requests validators use to repair their window, which the other
+//! nodes answer from their ledgers. It encrypts the segment in CBC mode with a
+//! key only it can derive, its signature of the id of the segment's first entry,
+//! and stores nothing else. Every so often it signs a recent `last_id`, hashes
+//! the blocks of the encrypted segment that signature picks, and submits the
+//! hash as a storage proof. Sampling is much faster than encrypting, so the
+//! proof can't be made in time without the encrypted segment at hand.
+//!
+//! Validators that hold the segment repeat the encryption and the sampling to
+//! verify a proof, and approve it so that the mint pays the replicator. They
+//! keep the last few segments they encrypted to check later proofs against, and
+//! verify at most one proof of each replicator every `MIN_PROOF_INTERVAL_MS`.
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockCipher, NewBlockCipher};
+use aes::Aes256;
+use bincode::{deserialize, serialize};
+use blob_fetch_stage::BlobFetchStage;
+use byteorder::{ByteOrder, LittleEndian};
+use counter::Counter;
+use crdt::{Crdt, NodeInfo, TestNode};
+use entry::Entry;
+use hash::{hash, Hash};
+use ledger::Block;
+use ncp::Ncp;
+use net_utils::{is_ipv6, send_addr, udp_bind};
+use packet::BlobRecycler;
+use segmented_ledger::SegmentedLedger;
+use service::Service;
+use signature::{KeyPair, PublicKey, Signature, SignatureUtil};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, RwLock};
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::{Duration, Instant};
+use streamer::{default_window, BlobReceiver};
+use thin_client::ThinClient;
+use transaction::{Instruction, StorageProof, Transaction};
+
+/// The number of entries in a segment, which starts at a multiple of it
+pub const ENTRIES_PER_SEGMENT: u64 = 1024;
+
+/// The bytes of the encrypted segment hashed for each sample
+pub const SAMPLE_SIZE: usize = 1024;
+
+/// The number of samples hashed into each proof
+pub const NUM_SAMPLES: usize = 32;
+
+/// How long a replicator waits between two proofs of its segment
+pub const PROOF_INTERVAL_MS: u64 = 30_000;
+
+/// How long a validator waits before verifying another proof of a replicator
+pub const MIN_PROOF_INTERVAL_MS: u64 = PROOF_INTERVAL_MS / 2;
+
+/// The most encrypted segments a `StorageProofVerifier` keeps
+const MAX_CACHED_SEGMENTS: usize = 4;
+
+const CIPHER_BLOCK_SIZE: usize = 16;
+
+/// How long to wait for answers to a round of window requests
+const FETCH_TIMEOUT_MS: u64 = 200;
+
+/// The most window requests sent in a round
+const MAX_FETCH_REQUESTS: usize = 256;
+
+fn sign(keypair: &KeyPair, data: &[u8]) -> Signature {
+    Signature::clone_from_slice(keypair.sign(data).as_ref())
+}
+
+/// The cipher and initialization vector of the key `identity`
+fn cipher(identity: &Signature) -> (Aes256, [u8; CIPHER_BLOCK_SIZE]) {
+    let key = hash(identity);
+    let mut iv = [0u8; CIPHER_BLOCK_SIZE];
+    iv.copy_from_slice(&hash(&key)[..CIPHER_BLOCK_SIZE]);
+    (Aes256::new(GenericArray::from_slice(&key[..])), iv)
+}
+
+/// The key `keypair` encrypts the segment starting with `first` with
+pub fn storage_identity(keypair: &KeyPair, first: &Entry) -> Signature {
+    sign(keypair, &first.id)
+}
+
+/// Encrypt `entries` in CBC mode with the key `identity`
+pub fn encrypt_segment(identity: &Signature, entries: &[Entry]) -> Vec<u8> {
+    let mut data = serialize(&entries).expect("serialize entries in encrypt_segment");
+    let len = (data.len() + CIPHER_BLOCK_SIZE - 1) / CIPHER_BLOCK_SIZE * CIPHER_BLOCK_SIZE;
+    data.resize(len, 0);
+    let (cipher, mut prev) = cipher(identity);
+    for block in data.chunks_mut(CIPHER_BLOCK_SIZE) {
+        for (b, p) in block.iter_mut().zip(prev.iter()) {
+            *b ^= *p;
+        }
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        prev.copy_from_slice(block);
+    }
+    data
+}
+
+/// Decrypt a segment `encrypt_segment` encrypted with the key `identity`
+pub fn decrypt_segment(identity: &Signature, encrypted: &[u8]) -> io::Result<Vec<Entry>> {
+    if encrypted.len() % CIPHER_BLOCK_SIZE != 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "segment isn't whole blocks",
+        ));
+    }
+    let mut data = encrypted.to_vec();
+    let (cipher, mut prev) = cipher(identity);
+    for block in data.chunks_mut(CIPHER_BLOCK_SIZE) {
+        let mut next = [0u8; CIPHER_BLOCK_SIZE];
+        next.copy_from_slice(block);
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+        for (b, p) in block.iter_mut().zip(prev.iter()) {
+            *b ^= *p;
+        }
+        prev = next;
+    }
+    deserialize(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+}
+
+/// The offsets of the samples `sample_seed` picks from an encrypted segment of `len` bytes
+fn sample_offsets(sample_seed: &Signature, len: usize) -> Vec<usize> {
+    let num_samples = cmp::max((len + SAMPLE_SIZE - 1) / SAMPLE_SIZE, 1) as u64;
+    let mut seed = hash(sample_seed);
+    (0..NUM_SAMPLES)
+        .map(|_| {
+            seed = hash(&seed);
+            (LittleEndian::read_u64(&seed[..8]) % num_samples) as usize * SAMPLE_SIZE
+        })
+        .collect()
+}
+
+/// The hash of the samples `sample_seed` picks from `encrypted`
+pub fn sample_hash(encrypted: &[u8], sample_seed: &Signature) -> Hash {
+    let mut samples = Vec::with_capacity(NUM_SAMPLES * SAMPLE_SIZE);
+    for offset in sample_offsets(sample_seed, encrypted.len()) {
+        let end = cmp::min(offset + SAMPLE_SIZE, encrypted.len());
+        samples.extend_from_slice(&encrypted[offset..end]);
+    }
+    hash(&samples)
+}
+
+/// Prove that `encrypted`, the segment at `entry_height` encrypted with the key
+/// `identity`, is stored, with samples picked by the signature of `last_id`
+pub fn new_storage_proof(
+    keypair: &KeyPair,
+    entry_height: u64,
+    identity: Signature,
+    encrypted: &[u8],
+    last_id: &Hash,
+) -> StorageProof {
+    let sample_seed = sign(keypair, last_id);
+    StorageProof {
+        entry_height,
+        identity,
+        sample_seed,
+        hash: sample_hash(encrypted, &sample_seed),
+    }
+}
+
+/// Verify the storage proof of `tx` against `entries`, the segment it claims
+pub fn verify_storage_proof(tx: &Transaction, entries: &[Entry]) -> bool {
+    let proof = match tx.instruction {
+        Instruction::NewStorageProof(ref proof) => proof,
+        _ => return false,
+    };
+    if proof.entry_height % ENTRIES_PER_SEGMENT != 0
+        || entries.len() as u64 != ENTRIES_PER_SEGMENT
+        || !proof.identity.verify(&tx.from, &entries[0].id)
+        || !proof.sample_seed.verify(&tx.from, &tx.last_id)
+    {
+        return false;
+    }
+    let encrypted = encrypt_segment(&proof.identity, entries);
+    sample_hash(&encrypted, &proof.sample_seed) == proof.hash
+}
+
+/// Verifies storage proofs against a node's ledger
+#[derive(Default)]
+pub struct StorageProofVerifier {
+    /// recently encrypted segments, with the id of their first entry, by the key
+    /// they were encrypted with and their entry height, oldest first
+    segments: VecDeque<((Signature, u64), Hash, Arc<Vec<u8>>)>,
+    /// when each replicator last had a proof verified
+    last_verified: HashMap<PublicKey, u64>,
+}
+
+impl StorageProofVerifier {
+    /// Verify the storage proof of `tx` at time `now` against the segment of
+    /// `ledger` it claims, unless its replicator had one verified less than
+    /// `MIN_PROOF_INTERVAL_MS` ago
+    pub fn verify(&mut self, tx: &Transaction, ledger: &SegmentedLedger, now: u64) -> bool {
+        let proof = match tx.instruction {
+            Instruction::NewStorageProof(ref proof) => proof,
+            _ => return false,
+        };
+        if proof.entry_height % ENTRIES_PER_SEGMENT != 0
+            || !proof.sample_seed.verify(&tx.from, &tx.last_id)
+        {
+            return false;
+        }
+        if let Some(last) = self.last_verified.get(&tx.from) {
+            if now < last + MIN_PROOF_INTERVAL_MS {
+                inc_new_counter!("replicator-storage_proof_too_soon", 1);
+                return false;
+            }
+        }
+        self.last_verified
+            .retain(|_, last| now < *last + MIN_PROOF_INTERVAL_MS);
+        self.last_verified.insert(tx.from, now);
+
+        match self.encrypted_segment(&tx.from, proof, ledger) {
+            Some(encrypted) => sample_hash(&encrypted, &proof.sample_seed) == proof.hash,
+            None => false,
+        }
+    }
+
+    /// The segment `proof` claims encrypted with its key, if the key is the
+    /// sender `from`'s for that segment
+    fn encrypted_segment(
+        &mut self,
+        from: &PublicKey,
+        proof: &StorageProof,
+        ledger: &SegmentedLedger,
+    ) -> Option<Arc<Vec<u8>>> {
+        let key = (proof.identity, proof.entry_height);
+        if let Some(&(_, first_id, ref encrypted)) = self.segments.iter().find(|s| s.0 == key) {
+            return if proof.identity.verify(from, &first_id) {
+                Some(encrypted.clone())
+            } else {
+                None
+            };
+        }
+        let entries = match ledger.get_entries(proof.entry_height, ENTRIES_PER_SEGMENT) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("reading segment {} failed: {:?}", proof.entry_height, e);
+                return None;
+            }
+        };
+        if entries.len() as u64 != ENTRIES_PER_SEGMENT
+            || !proof.identity.verify(from, &entries[0].id)
+        {
+            return None;
+        }
+        let encrypted = Arc::new(encrypt_segment(&proof.identity, &entries));
+        if self.segments.len() == MAX_CACHED_SEGMENTS {
+            self.segments.pop_front();
+        }
+        self.segments
+            .push_back((key, entries[0].id, encrypted.clone()));
+        Some(encrypted)
+    }
+}
+
+pub struct Replicator {
+    exit: Arc<AtomicBool>,
+    thread_hdls: Vec<JoinHandle<()>>,
+}
+
+impl Replicator {
+    /// Join the network at `entry_point`, fetch the segment starting at
+    /// `entry_height` and keep it encrypted in the file `store_path`, and
+    /// submit a proof of it to the leader every `PROOF_INTERVAL_MS`.
+    pub fn new(
+        keypair: KeyPair,
+        entry_height: u64,
+        mut node: TestNode,
+        entry_point: &NodeInfo,
+        store_path: &Path,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        assert_eq!(entry_height % ENTRIES_PER_SEGMENT, 0);
+        // a replicator only listens for the answers to its window requests
+        let daddr = "0.0.0.0:0".parse().unwrap();
+        node.data.contact_info.tvu = daddr;
+        node.data.contact_info.rpu = daddr;
+        node.data.contact_info.tpu = daddr;
+        let mut crdt = Crdt::new(node.data).expect("Crdt::new");
+        crdt.insert(entry_point);
+        let crdt = Arc::new(RwLock::new(crdt));
+
+        let ncp = Ncp::new(
+            &crdt,
+            default_window(),
+            node.sockets.gossip,
+            node.sockets.gossip_send,
+            exit.clone(),
+        ).expect("Ncp::new");
+        let blob_recycler = BlobRecycler::default();
+        let (fetch_stage, blob_receiver) =
+            BlobFetchStage::new(node.sockets.repair, exit.clone(), &blob_recycler);
+
+        let store_path = store_path.to_path_buf();
+        let t_replicator = {
+            let exit = exit.clone();
+            Builder::new()
+                .name("solana-replicator".to_string())
+                .spawn(move || {
+                    Self::run(
+                        &keypair,
+                        entry_height,
+                        &crdt,
+                        &blob_recycler,
+                        &blob_receiver,
+                        &store_path,
+                        &exit,
+                    )
+                })
+                .unwrap()
+        };
+
+        let mut thread_hdls = vec![t_replicator];
+        thread_hdls.extend(ncp.thread_hdls());
+        thread_hdls.extend(fetch_stage.thread_hdls());
+        Replicator { exit, thread_hdls }
+    }
+
+    fn run(
+        keypair: &KeyPair,
+        entry_height: u64,
+        crdt: &Arc<RwLock<Crdt>>,
+        blob_recycler: &BlobRecycler,
+        blob_receiver: &BlobReceiver,
+        store_path: &Path,
+        exit: &AtomicBool,
+    ) {
+        let identity = match Self::load(store_path) {
+            Some(identity) => identity,
+            None => {
+                let entries = match Self::fetch_segment(
+                    entry_height,
+                    crdt,
+                    blob_recycler,
+                    blob_receiver,
+                    exit,
+                ) {
+                    Some(entries) => entries,
+                    None => return,
+                };
+                let identity = storage_identity(keypair, &entries[0]);
+                let encrypted = encrypt_segment(&identity, &entries);
+                if let Err(e) = Self::store(store_path, &identity, &encrypted) {
+                    error!("storing segment {} failed: {:?}", entry_height, e);
+                    return;
+                }
+                info!("stored segment {} at {:?}", entry_height, store_path);
+                identity
+            }
+        };
+        let encrypted = match Self::read_encrypted(store_path) {
+            Ok(encrypted) => encrypted,
+            Err(e) => {
+                error!("reading segment {} failed: {:?}", entry_height, e);
+                return;
+            }
+        };
+
+        let mut leader: Option<(PublicKey, ThinClient)> = None;
+        while !exit.load(Ordering::Relaxed) {
+            let leader_data = crdt.read().unwrap().leader_data().cloned();
+            let leader_data = match leader_data {
+                Some(leader_data) => leader_data,
+                None => {
+                    sleep(Duration::from_millis(100));
+                    continue;
+                }
+            };
+            if leader.as_ref().map(|(id, _)| *id) != Some(leader_data.id) {
+                leader = Some((leader_data.id, Self::client(&leader_data)));
+            }
+            if let Some((_, ref mut client)) = leader {
+                let last_id = client.get_last_id();
+                let proof =
+                    new_storage_proof(keypair, entry_height, identity, &encrypted, &last_id);
+                let tx = Transaction::new_storage_proof(keypair, proof, last_id, 0);
+                match client.transfer_signed(&tx) {
+                    Ok(_) => inc_new_counter!("replicator-proof_sent", 1),
+                    Err(e) => warn!("sending storage proof failed: {:?}", e),
+                }
+            }
+            let now = Instant::now();
+            while !exit.load(Ordering::Relaxed)
+                && now.elapsed() < Duration::from_millis(PROOF_INTERVAL_MS)
+            {
+                sleep(Duration::from_millis(100));
+            }
+        }
+    }
+
+    /// Ask the peers for the entries of the segment at `entry_height` until
+    /// all of them have arrived. Returns `None` on exit.
+    fn fetch_segment(
+        entry_height: u64,
+        crdt: &Arc<RwLock<Crdt>>,
+        blob_recycler: &BlobRecycler,
+        blob_receiver: &BlobReceiver,
+        exit: &AtomicBool,
+    ) -> Option<Vec<Entry>> {
+        let socket = udp_bind(0).expect("bind in fn fetch_segment");
+        let ipv6 = is_ipv6(&socket);
+        let mut entries = vec![None; ENTRIES_PER_SEGMENT as usize];
+        let mut missing = entries.len();
+        let timeout = Duration::from_millis(FETCH_TIMEOUT_MS);
+        let mut last_round: Option<Instant> = None;
+        loop {
+            if exit.load(Ordering::Relaxed) {
+                return None;
+            }
+            if missing == 0 {
+                let segment: Vec<_> = entries.drain(..).map(|e| e.unwrap()).collect();
+                if segment[1..].verify(&segment[0].id) {
+                    return Some(segment);
+                }
+                // a peer answered with entries of another ledger
+                warn!("segment {} doesn't verify, fetching again", entry_height);
+                inc_new_counter!("replicator-fetch-bad_segment", 1);
+                entries.resize(ENTRIES_PER_SEGMENT as usize, None);
+                missing = entries.len();
+            }
+
+            if last_round.map_or(true, |t| t.elapsed() >= timeout) {
+                let requests: Vec<_> = (0..ENTRIES_PER_SEGMENT)
+                    .filter(|ix| entries[*ix as usize].is_none())
+                    .take(MAX_FETCH_REQUESTS)
+                    .filter_map(|ix| {
+                        crdt.read()
+                            .unwrap()
+                            .window_index_request(entry_height + ix)
+                            .ok()
+                    })
+                    .collect();
+                for (to, req) in requests {
+                    if let Err(e) = socket.send_to(&req, send_addr(ipv6, &to)) {
+                        warn!("window request to {} failed: {:?}", to, e);
+                    }
+                }
+                last_round = Some(Instant::now());
+            }
+
+            let mut blobs = match blob_receiver.recv_timeout(timeout) {
+                Ok(blobs) => blobs,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            };
+            while let Ok(mut more) = blob_receiver.try_recv() {
+                blobs.append(&mut more);
+            }
+            for blob in blobs {
+                let (ix, entry) = {
+                    let b = blob.read().unwrap();
+                    let entry = deserialize::<Entry>(&b.data()[..b.meta.size]);
+                    (b.get_index().ok(), entry.ok())
+                };
+                blob_recycler.recycle(blob);
+                if let (Some(ix), Some(entry)) = (ix, entry) {
+                    if ix >= entry_height && ix < entry_height + ENTRIES_PER_SEGMENT {
+                        let slot = &mut entries[(ix - entry_height) as usize];
+                        if slot.is_none() {
+                            *slot = Some(entry);
+                            missing -= 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The encrypted segment is stored after the identity that encrypted it
+    fn store(path: &Path, identity: &Signature, encrypted: &[u8]) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&identity[..])?;
+            file.write_all(encrypted)?;
+            file.sync_all()?;
+        }
+        fs::rename(tmp_path, path)
+    }
+
+    /// The identity of a segment stored before, if there is one
+    fn load(path: &Path) -> Option<Signature> {
+        let mut identity = [0u8; 64];
+        File::open(path)
+            .and_then(|mut file| file.read_exact(&mut identity))
+            .ok()?;
+        Some(Signature::clone_from_slice(&identity))
+    }
+
+    fn read_encrypted(path: &Path) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+        Ok(data.split_off(64))
+    }
+
+    fn client(leader: &NodeInfo) -> ThinClient {
+        let requests_socket = udp_bind(0).expect("bind in fn client");
+        requests_socket
+            .set_read_timeout(Some(Duration::new(1, 0)))
+            .expect("set_read_timeout in fn client");
+        let transactions_socket = udp_bind(0).expect("bind in fn client");
+        ThinClient::new(
+            leader.contact_info.rpu,
+            requests_socket,
+            leader.contact_info.tpu,
+            transactions_socket,
+        )
+    }
+
+    pub fn exit(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+    }
+
+    pub fn close(self) -> thread::Result<()> {
+        self.exit();
+        self.join()
+    }
+}
+
+impl Service for Replicator {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        self.thread_hdls
+    }
+
+    fn join(self) -> thread::Result<()> {
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crdt::{Crdt, TestNode};
+    use entry::{next_entry, Entry};
+    use entry_writer::{EntryWriter, SyncPolicy};
+    use hash::{hash, Hash};
+    use ncp::Ncp;
+    use replicator::{
+        decrypt_segment, encrypt_segment, new_storage_proof, storage_identity,
+        verify_storage_proof, Replicator, StorageProofVerifier, ENTRIES_PER_SEGMENT,
+        MIN_PROOF_INTERVAL_MS,
+    };
+    use segmented_ledger::{SegmentConfig, SegmentedLedger};
+    use service::Service;
+    use signature::{KeyPair, KeyPairUtil, SignatureUtil};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::thread::sleep;
+    use std::time::Duration;
+    use streamer::default_window;
+    use transaction::{StorageProof, Transaction};
+
+    fn make_segment(start_hash: &Hash) -> Vec<Entry> {
+        let mut id = *start_hash;
+        (0..ENTRIES_PER_SEGMENT)
+            .map(|_| {
+                let entry = next_entry(&id, 1, vec![]);
+                id = entry.id;
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_segment() {
+        let keypair = KeyPair::new();
+        let entries = make_segment(&Hash::default());
+        let identity = storage_identity(&keypair, &entries[0]);
+        let encrypted = encrypt_segment(&identity, &entries);
+        assert_eq!(decrypt_segment(&identity, &encrypted).unwrap(), entries);
+        assert!(decrypt_segment(&identity, &encrypted[1..]).is_err());
+
+        let other = storage_identity(&KeyPair::new(), &entries[0]);
+        assert_ne!(encrypt_segment(&other, &entries), encrypted);
+    }
+
+    #[test]
+    fn test_verify_storage_proof() {
+        let keypair = KeyPair::new();
+        let entries = make_segment(&Hash::default());
+        let identity = storage_identity(&keypair, &entries[0]);
+        let encrypted = encrypt_segment(&identity, &entries);
+        let last_id = hash(b"last_id");
+        let proof = new_storage_proof(&keypair, 0, identity, &encrypted, &last_id);
+        let tx = Transaction::new_storage_proof(&keypair, proof.clone(), last_id, 0);
+        assert!(verify_storage_proof(&tx, &entries));
+        assert!(!verify_storage_proof(&tx, &entries[1..]));
+        assert!(!verify_storage_proof(&tx, &make_segment(&last_id)));
+
+        // the samples are picked by the signature of the transaction's last_id
+        let tx = Transaction::new_storage_proof(&keypair, proof.clone(), Hash::default(), 0);
+        assert!(!verify_storage_proof(&tx, &entries));
+
+        // nobody proves another replicator's segment
+        let tx = Transaction::new_storage_proof(&KeyPair::new(), proof.clone(), last_id, 0);
+        assert!(!verify_storage_proof(&tx, &entries));
+
+        let proof = StorageProof {
+            hash: Hash::default(),
+            ..proof
+        };
+        let tx = Transaction::new_storage_proof(&keypair, proof, last_id, 0);
+        assert!(!verify_storage_proof(&tx, &entries));
+    }
+
+    #[test]
+    fn test_storage_proof_verifier() {
+        let dir = Path::new("target/test_storage_proof_verifier");
+        let _ = fs::remove_dir_all(dir);
+        let config = SegmentConfig {
+            sync_policy: SyncPolicy::Never,
+            ..SegmentConfig::default()
+        };
+        let ledger = SegmentedLedger::open(dir, config).unwrap();
+        let entries = make_segment(&Hash::default());
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries.clone()).unwrap();
+        }
+
+        let keypair = KeyPair::new();
+        let identity = storage_identity(&keypair, &entries[0]);
+        let encrypted = encrypt_segment(&identity, &entries);
+        let proof_tx = |last_id: &Hash| {
+            let proof = new_storage_proof(&keypair, 0, identity, &encrypted, last_id);
+            Transaction::new_storage_proof(&keypair, proof, *last_id, 0)
+        };
+        let mut verifier = StorageProofVerifier::default();
+        assert!(verifier.verify(&proof_tx(&hash(b"a")), &ledger, 0));
+
+        // one proof of a replicator is verified every interval
+        assert!(!verifier.verify(&proof_tx(&hash(b"b")), &ledger, 1));
+        let now = MIN_PROOF_INTERVAL_MS;
+        assert!(verifier.verify(&proof_tx(&hash(b"b")), &ledger, now));
+        // against the segment it encrypted the first time
+        assert_eq!(verifier.segments.len(), 1);
+
+        // nobody proves another replicator's segment, even once it's encrypted
+        let other = KeyPair::new();
+        let proof = new_storage_proof(&other, 0, identity, &encrypted, &hash(b"c"));
+        let tx = Transaction::new_storage_proof(&other, proof, hash(b"c"), 0);
+        assert!(!verifier.verify(&tx, &ledger, now));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    /// A replicator fetches its segment from a node's ledger
+    #[test]
+    fn test_replicator_fetch_segment() {
+        let dir = Path::new("target/test_replicator_fetch_segment");
+        let _ = fs::remove_dir_all(dir);
+        let config = SegmentConfig {
+            sync_policy: SyncPolicy::Never,
+            ..SegmentConfig::default()
+        };
+        let ledger = SegmentedLedger::open(dir, config).unwrap();
+        let entries = make_segment(&Hash::default());
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries.clone()).unwrap();
+        }
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let validator = TestNode::new_localhost();
+        let entry_point = validator.data.clone();
+        let mut crdt = Crdt::new(validator.data).unwrap();
+        crdt.set_ledger(ledger);
+        let ncp = Ncp::new(
+            &Arc::new(RwLock::new(crdt)),
+            default_window(),
+            validator.sockets.gossip,
+            validator.sockets.gossip_send,
+            exit.clone(),
+        ).unwrap();
+
+        let keypair = KeyPair::new();
+        let pubkey = keypair.pubkey();
+        let store_path = dir.join("segment");
+        let node = TestNode::new_localhost_with_pubkey(pubkey);
+        let replicator = Replicator::new(keypair, 0, node, &entry_point, &store_path, exit.clone());
+        for _ in 0..300 {
+            if store_path.exists() {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        let identity = Replicator::load(&store_path).expect("segment stored");
+        assert!(identity.verify(&pubkey, &entries[0].id));
+        let encrypted = Replicator::read_encrypted(&store_path).unwrap();
+        assert_eq!(decrypt_segment(&identity, &encrypted).unwrap(), entries);
+
+        replicator.close().unwrap();
+        exit.store(true, Ordering::Relaxed);
+        ncp.join().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -2,6 +2,12 @@
 
 use hash::Hash;
 use signature::{PublicKey, Signature};
+use transaction::Plan;
+
+/// The most pending contracts a `PendingContracts` response lists. At a few
+/// hundred bytes per contract that's some 8 KB, which fits in a response blob
+/// but takes several IP fragments; ask again `after` the last signature for more.
+pub const MAX_PENDING_CONTRACTS: usize = 32;
 
 #[cfg_attr(feature = "cargo-clippy", allow(large_enum_variant))]
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
@@ -10,6 +16,10 @@ pub enum Request {
     GetLastId,
     GetTransactionCount,
     GetSignature { signature: Signature },
+    GetPendingContracts {
+        key: PublicKey,
+        after: Option<Signature>,
+    },
 }
 
 impl Request {
@@ -25,4 +35,9 @@ pub enum Response {
     LastId { id: Hash },
     TransactionCount { transaction_count: u64 },
     SignatureStatus { signature_status: bool },
+    PendingContracts {
+        key: PublicKey,
+        after: Option<Signature>,
+        contracts: Vec<(Signature, Plan)>,
+    },
 }
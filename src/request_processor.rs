@@ -1,7 +1,7 @@
 //! The `request_processor` processes thin client Request messages.
 
 use bank::Bank;
-use request::{Request, Response};
+use request::{Request, Response, MAX_PENDING_CONTRACTS};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
@@ -46,6 +46,21 @@ impl RequestProcessor {
                 info!("Response::Signature {:?}", rsp);
                 Some(rsp)
             }
+            Request::GetPendingContracts { key, after } => {
+                let contracts =
+                    self.bank
+                        .pending_contracts(&key, after.as_ref(), MAX_PENDING_CONTRACTS);
+                let rsp = (
+                    Response::PendingContracts {
+                        key,
+                        after,
+                        contracts,
+                    },
+                    rsp_addr,
+                );
+                info!("Response::PendingContracts {:?}", rsp);
+                Some(rsp)
+            }
         }
     }
 
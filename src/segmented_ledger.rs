@@ -0,0 +1,761 @@
+//! The `segmented_ledger` module stores the ledger as a directory of segment
+//! files, each holding a fixed number of entries, instead of one ever-growing
+//! file. Segments that fall out of the live window are compressed into a cold
+//! archive directory, segments covered by a bank snapshot can be pruned, and the
+//! total size on disk can be capped. The writer takes a new snapshot every few
+//! segments, so a running node keeps pruning.
+
+use bank::{Bank, BankSnapshot};
+use bincode::{deserialize_from, serialize_into};
+use entry::Entry;
+use entry_writer::{read_entries, recover_ledger_after, LedgerWriter, SyncPolicy};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hash::Hash;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of entries in a segment unless configured otherwise
+pub const DEFAULT_ENTRIES_PER_SEGMENT: u64 = 16 * 1024;
+
+/// Number of recent segments kept uncompressed unless configured otherwise
+pub const DEFAULT_LIVE_SEGMENTS: usize = 4;
+
+/// Number of segments written between bank snapshots unless configured otherwise
+pub const DEFAULT_SNAPSHOT_SEGMENTS: u64 = 4;
+
+const SEGMENT_EXTENSION: &str = "ledger";
+const ARCHIVE_EXTENSION: &str = "ledger.gz";
+const SNAPSHOT_EXTENSION: &str = "snapshot";
+
+#[derive(Clone, Debug)]
+pub struct SegmentConfig {
+    /// entries written to a segment before starting the next one
+    pub entries_per_segment: u64,
+    /// most recent segments kept uncompressed in the ledger directory
+    pub live_segments: usize,
+    /// directory older segments are compressed into; without one they stay live
+    pub archive_dir: Option<PathBuf>,
+    /// bytes the live and archived segments may use together
+    pub max_bytes: Option<u64>,
+    /// segments written between the bank snapshots the writer takes; without
+    /// it only the snapshots saved by hand let segments be pruned
+    pub snapshot_segments: Option<u64>,
+    pub sync_policy: SyncPolicy,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        SegmentConfig {
+            entries_per_segment: DEFAULT_ENTRIES_PER_SEGMENT,
+            live_segments: DEFAULT_LIVE_SEGMENTS,
+            archive_dir: None,
+            max_bytes: None,
+            snapshot_segments: Some(DEFAULT_SNAPSHOT_SEGMENTS),
+            sync_policy: SyncPolicy::default(),
+        }
+    }
+}
+
+/// A segment file on disk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    /// height of the first entry in the segment
+    pub start: u64,
+    pub path: PathBuf,
+    pub archived: bool,
+    /// size of the file in bytes
+    pub len: u64,
+}
+
+impl Segment {
+    /// Open the segment for reading, decompressing it if it is archived.
+    pub fn open(&self) -> io::Result<Box<Read>> {
+        let file = BufReader::new(File::open(&self.path)?);
+        if self.archived {
+            Ok(Box::new(GzDecoder::new(file)))
+        } else {
+            Ok(Box::new(file))
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SegmentedLedger {
+    dir: PathBuf,
+    config: SegmentConfig,
+    /// entries in the last live segment when the ledger was opened
+    tail_entries: u64,
+}
+
+fn segment_name(start: u64, extension: &str) -> String {
+    format!("{:020}.{}", start, extension)
+}
+
+/// Return the start height of a segment file name ending in `extension`.
+fn parse_segment_name(name: &str, extension: &str) -> Option<u64> {
+    let mut parts = name.splitn(2, '.');
+    let start = parts.next()?.parse().ok()?;
+    if parts.next()? == extension {
+        Some(start)
+    } else {
+        None
+    }
+}
+
+fn list_segments(dir: &Path, extension: &str, archived: bool) -> io::Result<Vec<Segment>> {
+    let mut segments = vec![];
+    for dirent in fs::read_dir(dir)? {
+        let dirent = dirent?;
+        let name = dirent.file_name();
+        let start = match name.to_str().and_then(|n| parse_segment_name(n, extension)) {
+            Some(start) => start,
+            None => continue,
+        };
+        segments.push(Segment {
+            start,
+            path: dirent.path(),
+            archived,
+            len: dirent.metadata()?.len(),
+        });
+    }
+    segments.sort_by_key(|s| s.start);
+    Ok(segments)
+}
+
+impl SegmentedLedger {
+    /// Open the ledger in `dir`, creating it if needed, and recover the last
+    /// segment from any torn write.
+    pub fn open<P: AsRef<Path>>(dir: P, config: SegmentConfig) -> io::Result<Self> {
+        assert!(config.entries_per_segment > 0);
+        fs::create_dir_all(&dir)?;
+        if let Some(ref archive_dir) = config.archive_dir {
+            fs::create_dir_all(archive_dir)?;
+        }
+        let mut ledger = SegmentedLedger {
+            dir: dir.as_ref().to_path_buf(),
+            config,
+            tail_entries: 0,
+        };
+        if let Some(last) = ledger.live_segments()?.pop() {
+            let prev_id = ledger.prev_id(&last)?;
+            ledger.tail_entries = recover_ledger_after(&last.path, prev_id)?;
+        }
+        Ok(ledger)
+    }
+
+    /// Return the id of the entry before `segment`, read from the end of the
+    /// segment preceding it, or `None` if `segment` starts at genesis.
+    fn prev_id(&self, segment: &Segment) -> io::Result<Option<Hash>> {
+        if segment.start == 0 {
+            return Ok(None);
+        }
+        let segments = self.segments()?;
+        let prev = match segments.iter().rev().find(|s| s.start < segment.start) {
+            Some(prev) => prev,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("ledger segment before height {} is missing", segment.start),
+                ))
+            }
+        };
+        let mut prev_id = None;
+        for entry in read_entries(BufReader::new(prev.open()?)) {
+            prev_id = Some(entry?.id);
+        }
+        Ok(prev_id)
+    }
+
+    fn live_segments(&self) -> io::Result<Vec<Segment>> {
+        list_segments(&self.dir, SEGMENT_EXTENSION, false)
+    }
+
+    fn archived_segments(&self) -> io::Result<Vec<Segment>> {
+        match self.config.archive_dir {
+            Some(ref archive_dir) => list_segments(archive_dir, ARCHIVE_EXTENSION, true),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// All segments, archived and live, in height order.
+    pub fn segments(&self) -> io::Result<Vec<Segment>> {
+        let mut segments = self.archived_segments()?;
+        segments.extend(self.live_segments()?);
+        segments.sort_by_key(|s| s.start);
+        Ok(segments)
+    }
+
+    /// Return a reader over every entry from genesis. Fails if the oldest
+    /// segments have been pruned, since replay would then need a bank snapshot.
+    pub fn reader(&self) -> io::Result<Box<Read>> {
+        self.reader_from(0)
+    }
+
+    /// Return a reader over every entry from `height` on. Fails if the segment
+    /// holding `height` has been pruned.
+    pub fn reader_from(&self, height: u64) -> io::Result<Box<Read>> {
+        let segments = self.segments()?;
+        let first = match segments.iter().rposition(|s| s.start <= height) {
+            Some(first) => first,
+            None if segments.is_empty() && height == 0 => return Ok(Box::new(io::empty())),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("ledger pruned below height {}", height),
+                ))
+            }
+        };
+        let mut reader: Box<Read> = Box::new(io::empty());
+        let mut skip = height - segments[first].start;
+        for segment in &segments[first..] {
+            let mut segment_reader = BufReader::new(segment.open()?);
+            let mut line = vec![];
+            while skip > 0 && segment_reader.read_until(b'\n', &mut line)? > 0 {
+                line.clear();
+                skip -= 1;
+            }
+            reader = Box::new(reader.chain(segment_reader));
+        }
+        Ok(reader)
+    }
+
+    /// Read the up to `num` entries before `height` that are still on disk, the
+    /// tail a node needs to seed its window.
+    pub fn tail(&self, height: u64, num: u64) -> io::Result<Vec<Entry>> {
+        let oldest = match self.segments()?.first() {
+            Some(segment) => segment.start,
+            None => return Ok(vec![]),
+        };
+        let start = height.saturating_sub(num).max(oldest);
+        if start >= height {
+            return Ok(vec![]);
+        }
+        self.get_entries(start, height - start)
+    }
+
+    /// The height of the latest bank snapshot, if one has been saved.
+    pub fn snapshot_height(&self) -> io::Result<Option<u64>> {
+        Ok(list_segments(&self.dir, SNAPSHOT_EXTENSION, false)?
+            .pop()
+            .map(|s| s.start))
+    }
+
+    /// Load the latest bank snapshot, if one has been saved.
+    pub fn load_snapshot(&self) -> io::Result<Option<BankSnapshot>> {
+        let height = match self.snapshot_height()? {
+            Some(height) => height,
+            None => return Ok(None),
+        };
+        let path = self.dir.join(segment_name(height, SNAPSHOT_EXTENSION));
+        let reader = BufReader::new(File::open(&path)?);
+        let snapshot: BankSnapshot = deserialize_from(reader)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        if snapshot.entry_height != height {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("snapshot {:?} is at height {}", path, snapshot.entry_height),
+            ));
+        }
+        Ok(Some(snapshot))
+    }
+
+    /// Save a bank snapshot, replacing any older one, and prune the segments it
+    /// covers. The snapshot must match the entries written so far.
+    pub fn save_snapshot(&self, snapshot: &BankSnapshot) -> io::Result<()> {
+        let name = segment_name(snapshot.entry_height, SNAPSHOT_EXTENSION);
+        let tmp_path = self.dir.join(format!("{}.tmp", name));
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serialize_into(&mut writer, snapshot)
+                .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+            writer.into_inner()?.sync_all()?;
+        }
+        fs::rename(&tmp_path, self.dir.join(name))?;
+        for old in list_segments(&self.dir, SNAPSHOT_EXTENSION, false)? {
+            if old.start < snapshot.entry_height {
+                fs::remove_file(&old.path)?;
+            }
+        }
+        info!("saved bank snapshot at height {}", snapshot.entry_height);
+        self.prune(snapshot.entry_height)?;
+        Ok(())
+    }
+
+    /// Replay the entries below `height` onto the latest bank snapshot, or onto
+    /// a new bank from genesis if there is none, and save the result as the new
+    /// snapshot. The entries below `height` must have been synced.
+    pub fn snapshot_to(&self, height: u64) -> io::Result<()> {
+        let snapshot = self.load_snapshot()?;
+        let start = snapshot.as_ref().map_or(0, |s| s.entry_height);
+        if height <= start {
+            return Ok(());
+        }
+        let reader = BufReader::new(self.reader_from(start)?);
+        let mut error = None;
+        let result = {
+            let entries = read_entries(reader)
+                .take((height - start) as usize)
+                .scan((), |_, entry| entry.map_err(|e| error = Some(e)).ok());
+            match snapshot {
+                Some(snapshot) => {
+                    let bank = Bank::from_snapshot(snapshot);
+                    bank.process_ledger_from(start, entries)
+                        .map(|entry_height| (bank, entry_height))
+                }
+                None => {
+                    let bank = Bank::default();
+                    bank.process_ledger(entries)
+                        .map(|(entry_height, _)| (bank, entry_height))
+                }
+            }
+        };
+        if let Some(e) = error {
+            return Err(e);
+        }
+        let (bank, entry_height) =
+            result.map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)))?;
+        if entry_height != height {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("ledger ends at height {} before {}", entry_height, height),
+            ));
+        }
+        self.save_snapshot(&bank.snapshot(height))
+    }
+
+    /// Return a writer that appends after the last entry, starting a new
+    /// segment every `entries_per_segment` entries. Only one writer should be
+    /// open at a time.
+    pub fn writer(&self) -> io::Result<SegmentWriter> {
+        let (start, entries) = match self.segments()?.pop() {
+            Some(ref last) if last.archived => (last.start + self.config.entries_per_segment, 0),
+            Some(last) => (last.start, self.tail_entries),
+            None => (0, 0),
+        };
+        let mut writer = SegmentWriter {
+            ledger: self.clone(),
+            writer: LedgerWriter::open(
+                self.dir.join(segment_name(start, SEGMENT_EXTENSION)),
+                self.config.sync_policy,
+            )?,
+            start,
+            entries,
+        };
+        if entries >= self.config.entries_per_segment {
+            writer.roll()?;
+        }
+        Ok(writer)
+    }
+
+    /// Read the entry at `height`. Returns `None` if the segment holding it has
+    /// been pruned or the entry hasn't been written yet.
+    pub fn get_entry(&self, height: u64) -> io::Result<Option<Entry>> {
+        let segments = self.segments()?;
+        let segment = match segments.iter().rev().find(|s| s.start <= height) {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        let reader = BufReader::new(segment.open()?);
+        match read_entries(reader).nth((height - segment.start) as usize) {
+            Some(entry) => entry.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read up to `num` entries starting at `height`, fewer if the ledger ends
+    /// before them. Returns no entries if the segment holding `height` has been
+    /// pruned.
+    pub fn get_entries(&self, height: u64, num: u64) -> io::Result<Vec<Entry>> {
+        let segments = self.segments()?;
+        let first = match segments.iter().rposition(|s| s.start <= height) {
+            Some(first) => first,
+            None => return Ok(vec![]),
+        };
+        let mut entries = vec![];
+        let mut skip = height - segments[first].start;
+        for segment in &segments[first..] {
+            let reader = BufReader::new(segment.open()?);
+            for entry in read_entries(reader) {
+                if entries.len() as u64 == num {
+                    return Ok(entries);
+                }
+                if skip > 0 {
+                    skip -= 1;
+                } else {
+                    entries.push(entry?);
+                }
+            }
+            skip = 0;
+        }
+        Ok(entries)
+    }
+
+    /// Delete the segments whose entries all lie below `covered_height`, the
+    /// height a bank snapshot has been taken at. The last two segments are kept
+    /// so the one being written can be recovered against the chain before it.
+    /// Returns the number deleted.
+    pub fn prune(&self, covered_height: u64) -> io::Result<usize> {
+        let segments = self.segments()?;
+        let mut pruned = 0;
+        for pair in segments[..segments.len().saturating_sub(1)].windows(2) {
+            if pair[1].start > covered_height {
+                break;
+            }
+            info!("pruning ledger segment {:?}", pair[0].path);
+            fs::remove_file(&pair[0].path)?;
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    /// Compress the live segments beyond `live_segments` into the archive.
+    fn archive(&self) -> io::Result<()> {
+        let archive_dir = match self.config.archive_dir {
+            Some(ref archive_dir) => archive_dir,
+            None => return Ok(()),
+        };
+        let live = self.live_segments()?;
+        let keep = self.config.live_segments.max(1);
+        if live.len() <= keep {
+            return Ok(());
+        }
+        for segment in &live[..live.len() - keep] {
+            let name = segment_name(segment.start, ARCHIVE_EXTENSION);
+            let tmp_path = archive_dir.join(format!("{}.tmp", name));
+            {
+                let mut reader = File::open(&segment.path)?;
+                let mut encoder = GzEncoder::new(File::create(&tmp_path)?, Compression::default());
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?.sync_all()?;
+            }
+            fs::rename(&tmp_path, archive_dir.join(name))?;
+            fs::remove_file(&segment.path)?;
+            debug!("archived ledger segment {:?}", segment.path);
+        }
+        Ok(())
+    }
+
+    /// Delete the oldest segments until the ledger fits in `max_bytes`. Only
+    /// segments covered by the latest bank snapshot are deleted, so the ledger
+    /// can always be replayed, and the segment being written and the one before
+    /// it are kept.
+    fn enforce_cap(&self) -> io::Result<()> {
+        let max_bytes = match self.config.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return Ok(()),
+        };
+        let covered_height = self.snapshot_height()?.unwrap_or(0);
+        let segments = self.segments()?;
+        let mut total: u64 = segments.iter().map(|s| s.len).sum();
+        for pair in segments[..segments.len().saturating_sub(1)].windows(2) {
+            if total <= max_bytes || pair[1].start > covered_height {
+                break;
+            }
+            warn!(
+                "ledger over its {} byte cap, deleting segment {:?}",
+                max_bytes, pair[0].path
+            );
+            fs::remove_file(&pair[0].path)?;
+            total -= pair[0].len;
+        }
+        if total > max_bytes {
+            warn!(
+                "ledger over its {} byte cap, keeping {} bytes after snapshot height {}",
+                max_bytes, total, covered_height
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Appends entries to a `SegmentedLedger`. A segment is finished on the first
+/// flush after it holds `entries_per_segment` entries, which for an
+/// `EntryWriter` is right after the entry that fills it. Every
+/// `snapshot_segments` segments the writer then replays them onto the last
+/// bank snapshot to take a new one, which holds up writes while it runs.
+pub struct SegmentWriter {
+    ledger: SegmentedLedger,
+    writer: LedgerWriter,
+    /// height of the first entry in the current segment
+    start: u64,
+    /// complete entries written to the current segment
+    entries: u64,
+}
+
+impl SegmentWriter {
+    fn roll(&mut self) -> io::Result<()> {
+        self.writer.sync()?;
+        self.start += self.entries;
+        self.entries = 0;
+        let path = self.ledger.dir.join(segment_name(self.start, SEGMENT_EXTENSION));
+        self.writer = LedgerWriter::open(path, self.ledger.config.sync_policy)?;
+        self.ledger.archive()?;
+        if let Some(snapshot_segments) = self.ledger.config.snapshot_segments {
+            let covered_height = self.ledger.snapshot_height()?.unwrap_or(0);
+            let interval = snapshot_segments * self.ledger.config.entries_per_segment;
+            if self.start >= covered_height + interval {
+                self.ledger.snapshot_to(self.start)?;
+            }
+        }
+        self.ledger.enforce_cap()
+    }
+}
+
+impl Write for SegmentWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.entries += buf[..len].iter().filter(|b| **b == b'\n').count() as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.entries >= self.ledger.config.entries_per_segment {
+            self.roll()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bank::Bank;
+    use entry_writer::{read_entries_to_vec, EntryWriter};
+    use ledger::Block;
+    use mint::Mint;
+    use signature::{KeyPair, KeyPairUtil};
+    use transaction::Transaction;
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = PathBuf::from(format!("target/test_segmented_ledger_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn make_entries(mint: &Mint, num: usize) -> Vec<Entry> {
+        let mut entries = mint.create_entries();
+        let keypair = KeyPair::new();
+        while entries.len() < num {
+            let last_id = entries.last().unwrap().id;
+            let tx = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, last_id);
+            entries.push(Entry::new(&last_id, 0, vec![tx], false));
+        }
+        entries
+    }
+
+    fn snapshot_at(entries: &[Entry], height: usize) -> BankSnapshot {
+        let bank = Bank::default();
+        bank.process_ledger(entries[..height].to_vec()).unwrap();
+        bank.snapshot(height as u64)
+    }
+
+    fn config(entries_per_segment: u64) -> SegmentConfig {
+        SegmentConfig {
+            entries_per_segment,
+            snapshot_segments: None,
+            sync_policy: SyncPolicy::Never,
+            ..SegmentConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_segment_name() {
+        let name = segment_name(42, SEGMENT_EXTENSION);
+        assert_eq!(parse_segment_name(&name, SEGMENT_EXTENSION), Some(42));
+        assert_eq!(parse_segment_name(&name, ARCHIVE_EXTENSION), None);
+        let name = segment_name(42, ARCHIVE_EXTENSION);
+        assert_eq!(parse_segment_name(&name, ARCHIVE_EXTENSION), Some(42));
+        let tmp_name = format!("{}.tmp", name);
+        assert_eq!(parse_segment_name(&tmp_name, ARCHIVE_EXTENSION), None);
+        assert_eq!(parse_segment_name("foo.ledger", SEGMENT_EXTENSION), None);
+    }
+
+    #[test]
+    fn test_segmented_ledger_roll() {
+        let dir = tmp_dir("roll");
+        let mint = Mint::new(100);
+        let entries = make_entries(&mint, 10);
+        let ledger = SegmentedLedger::open(&dir, config(4)).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries[..6].to_vec()).unwrap();
+        }
+
+        // reopening resumes in the middle of the second segment
+        let ledger = SegmentedLedger::open(&dir, config(4)).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries[6..].to_vec()).unwrap();
+        }
+        let starts: Vec<_> = ledger.segments().unwrap().iter().map(|s| s.start).collect();
+        assert_eq!(starts, vec![0, 4, 8]);
+
+        let read = read_entries_to_vec(BufReader::new(ledger.reader().unwrap())).unwrap();
+        assert_eq!(read, entries);
+        assert!(read.verify(&read[0].id));
+        assert_eq!(ledger.get_entry(5).unwrap(), Some(entries[5].clone()));
+        assert_eq!(ledger.get_entry(10).unwrap(), None);
+        assert_eq!(ledger.get_entries(3, 6).unwrap(), entries[3..9].to_vec());
+        assert_eq!(ledger.get_entries(8, 6).unwrap(), entries[8..].to_vec());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segmented_ledger_recover_tail() {
+        let dir = tmp_dir("recover_tail");
+        let mint = Mint::new(100);
+        let entries = make_entries(&mint, 6);
+        let ledger = SegmentedLedger::open(&dir, config(4)).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries.clone()).unwrap();
+        }
+
+        // a tail segment that doesn't follow the segment before it is corrupt
+        let other = make_entries(&Mint::new(100), 6);
+        let mut buf = vec![];
+        EntryWriter::write_entries(&mut buf, other[4..].to_vec()).unwrap();
+        fs::write(dir.join(segment_name(4, SEGMENT_EXTENSION)), &buf).unwrap();
+        assert!(SegmentedLedger::open(&dir, config(4)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segmented_ledger_archive_and_prune() {
+        let dir = tmp_dir("archive");
+        let archive_dir = dir.join("archive");
+        let mint = Mint::new(100);
+        let entries = make_entries(&mint, 13);
+        let config = SegmentConfig {
+            live_segments: 1,
+            archive_dir: Some(archive_dir.clone()),
+            ..config(3)
+        };
+        let ledger = SegmentedLedger::open(&dir.join("live"), config).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries.clone()).unwrap();
+        }
+        let segments = ledger.segments().unwrap();
+        let archived: Vec<_> = segments.iter().map(|s| s.archived).collect();
+        assert_eq!(archived, vec![true, true, true, true, false]);
+
+        // archived segments still replay and serve repairs
+        let read = read_entries_to_vec(BufReader::new(ledger.reader().unwrap())).unwrap();
+        assert_eq!(read, entries);
+        assert_eq!(ledger.get_entry(4).unwrap(), Some(entries[4].clone()));
+
+        // a snapshot at height 7 covers the first two segments
+        assert_eq!(ledger.prune(7).unwrap(), 2);
+        assert_eq!(ledger.get_entry(4).unwrap(), None);
+        assert_eq!(ledger.get_entry(7).unwrap(), Some(entries[7].clone()));
+        assert!(ledger.reader().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segmented_ledger_snapshot() {
+        let dir = tmp_dir("snapshot");
+        let mint = Mint::new(100);
+        let entries = make_entries(&mint, 10);
+        let ledger = SegmentedLedger::open(&dir, config(2)).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries.clone()).unwrap();
+        }
+        assert_eq!(ledger.load_snapshot().unwrap(), None);
+
+        // a snapshot at height 7 prunes the segments below it
+        let snapshot = snapshot_at(&entries, 7);
+        ledger.save_snapshot(&snapshot).unwrap();
+        assert_eq!(ledger.snapshot_height().unwrap(), Some(7));
+        assert_eq!(ledger.load_snapshot().unwrap(), Some(snapshot));
+        let starts: Vec<_> = ledger.segments().unwrap().iter().map(|s| s.start).collect();
+        assert_eq!(starts, vec![6, 8, 10]);
+        assert!(ledger.reader().is_err());
+
+        // replay resumes from the snapshot, mid-segment
+        let read = read_entries_to_vec(BufReader::new(ledger.reader_from(7).unwrap())).unwrap();
+        assert_eq!(read, entries[7..].to_vec());
+        assert_eq!(ledger.tail(10, 3).unwrap(), entries[7..].to_vec());
+        assert_eq!(ledger.tail(10, 8).unwrap(), entries[6..].to_vec());
+
+        // a newer snapshot replaces the old one
+        ledger.save_snapshot(&snapshot_at(&entries, 10)).unwrap();
+        assert_eq!(ledger.snapshot_height().unwrap(), Some(10));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segmented_ledger_cap() {
+        let dir = tmp_dir("cap");
+        let mint = Mint::new(100);
+        let entries = make_entries(&mint, 12);
+        let ledger = SegmentedLedger::open(&dir, config(2)).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries[..4].to_vec()).unwrap();
+        }
+        ledger.save_snapshot(&snapshot_at(&entries, 4)).unwrap();
+
+        // a cap smaller than any segment
+        let config = SegmentConfig {
+            max_bytes: Some(1),
+            ..config(2)
+        };
+        let ledger = SegmentedLedger::open(&dir, config).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            EntryWriter::write_entries(&mut writer, entries[4..].to_vec()).unwrap();
+        }
+
+        // the cap only deletes segments the snapshot covers
+        let starts: Vec<_> = ledger.segments().unwrap().iter().map(|s| s.start).collect();
+        assert_eq!(starts, vec![4, 6, 8, 10, 12]);
+        assert_eq!(ledger.get_entry(2).unwrap(), None);
+        assert_eq!(ledger.get_entry(4).unwrap(), Some(entries[4].clone()));
+        assert_eq!(ledger.get_entry(11).unwrap(), Some(entries[11].clone()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segmented_ledger_snapshot_while_writing() {
+        let dir = tmp_dir("snapshot_while_writing");
+        let mint = Mint::new(100);
+        let entries = make_entries(&mint, 12);
+
+        // a cap of two full segments of transfers
+        let mut buf = vec![];
+        EntryWriter::write_entries(&mut buf, entries[2..6].to_vec()).unwrap();
+        let max_bytes = buf.len() as u64;
+        let config = SegmentConfig {
+            max_bytes: Some(max_bytes),
+            snapshot_segments: Some(1),
+            ..config(2)
+        };
+        let ledger = SegmentedLedger::open(&dir, config).unwrap();
+        {
+            let mut writer = ledger.writer().unwrap();
+            for (i, entry) in entries.iter().enumerate() {
+                EntryWriter::write_entries(&mut writer, vec![entry.clone()]).unwrap();
+                let len: u64 = ledger.segments().unwrap().iter().map(|s| s.len).sum();
+                if i >= 4 {
+                    assert!(len <= max_bytes);
+                }
+            }
+        }
+
+        // the last snapshot replays to the same bank as the whole ledger
+        let snapshot = ledger.load_snapshot().unwrap().unwrap();
+        assert_eq!(snapshot.entry_height, 12);
+        let bank = Bank::from_snapshot(snapshot);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 90);
+        let starts: Vec<_> = ledger.segments().unwrap().iter().map(|s| s.start).collect();
+        assert_eq!(starts, vec![10, 12]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
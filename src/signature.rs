@@ -2,6 +2,7 @@
 
 use generic_array::typenum::{U32, U64};
 use generic_array::GenericArray;
+use keystore::{read_passphrase, KeypairFile};
 use rand::{ChaChaRng, Rng, SeedableRng};
 use rayon::prelude::*;
 use ring::error::Unspecified;
@@ -92,10 +93,17 @@ impl SecureRandom for GenKeys {
     }
 }
 
+/// Read the PKCS#8 bytes of a keypair file, asking for the passphrase with
+/// `keystore::read_passphrase` if it's encrypted.
 pub fn read_pkcs8(path: &str) -> Result<Vec<u8>, Box<error::Error>> {
     let file = File::open(path.to_string())?;
-    let pkcs8: Vec<u8> = serde_json::from_reader(file)?;
-    Ok(pkcs8)
+    match serde_json::from_reader(file)? {
+        KeypairFile::Plain(pkcs8) => Ok(pkcs8),
+        KeypairFile::Encrypted(keystore) => {
+            let passphrase = read_passphrase(&format!("Passphrase for {}: ", path))?;
+            Ok(keystore.decrypt(&passphrase)?)
+        }
+    }
 }
 
 pub fn read_keypair(path: &str) -> Result<KeyPair, Box<error::Error>> {
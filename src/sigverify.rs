@@ -1,17 +1,111 @@
 //! The `sigverify` module provides digital signature verification functions.
 //! By default, signatures are verified in parallel using all available CPU
 //! cores.  When `--features=cuda` is enabled, signature verification is
-//! offloaded to the GPU.
+//! offloaded to the GPU. A `SigDedup` filter in front of verification drops
+//! packets repeating a signed transaction seen recently, so retried and
+//! duplicated transactions are only verified once.
 //!
 
+use bloom::Bloom;
 use counter::Counter;
-use packet::{Packet, SharedPackets};
-use std::mem::size_of;
+use hash::hash;
+use packet::{Packet, SharedBlobs, SharedPackets};
+use signature::Signature;
+use std::mem::{self, size_of};
 use std::sync::atomic::AtomicUsize;
 use transaction::{PUB_KEY_OFFSET, SIGNED_DATA_OFFSET, SIG_OFFSET};
 
 pub const TX_OFFSET: usize = 0;
 
+/// Transactions a `SigDedup` filter remembers before it starts forgetting the oldest
+pub const DEDUP_CAPACITY: usize = 256 * 1024;
+
+/// Chance that a `SigDedup` filter drops a transaction it hasn't seen
+const DEDUP_FALSE_RATE: f64 = 0.0001;
+
+/// The most bits in each of a `SigDedup` filter's blooms
+const DEDUP_MAX_BITS: usize = 8 * 1024 * 1024;
+
+/// A rotating bloom filter of the transactions seen recently. It fills one
+/// filter while checking both, and forgets the older when the newer is full.
+/// Transactions are keyed by a hash of the signature and all the bytes after
+/// it rather than by signature alone. Remembering a signature before it's
+/// verified would let anyone who saw it send it first with a forged body, and
+/// have the real transaction dropped as a duplicate.
+pub struct SigDedup {
+    current: Bloom,
+    previous: Bloom,
+    num_current: usize,
+    capacity: usize,
+}
+
+impl SigDedup {
+    pub fn new(capacity: usize) -> Self {
+        let bloom = Bloom::random(capacity, DEDUP_FALSE_RATE, DEDUP_MAX_BITS);
+        SigDedup {
+            current: bloom.clone(),
+            previous: bloom,
+            num_current: 0,
+            capacity,
+        }
+    }
+
+    /// Remember the signed transaction `data`, returning whether it was seen before.
+    pub fn check_and_add(&mut self, data: &[u8]) -> bool {
+        let key = hash(data);
+        let key = key.as_ref();
+        if self.current.contains(key) || self.previous.contains(key) {
+            return true;
+        }
+        if self.num_current >= self.capacity {
+            self.previous.clear();
+            mem::swap(&mut self.current, &mut self.previous);
+            self.num_current = 0;
+        }
+        self.current.add(key);
+        self.num_current += 1;
+        false
+    }
+
+    /// Remove the packets holding a transaction seen before from `batches`,
+    /// returning how many were removed.
+    pub fn dedup_packets(&mut self, batches: &[SharedPackets]) -> usize {
+        let sig_start = TX_OFFSET + SIG_OFFSET;
+        let sig_end = sig_start + size_of::<Signature>();
+        let mut num_dups = 0;
+        for batch in batches {
+            let mut batch = batch.write().expect("'batch' write lock in dedup_packets");
+            let len = batch.packets.len();
+            // packets too short to hold a signature fail verification anyway
+            batch.packets.retain(|packet| {
+                packet.meta.size < sig_end
+                    || !self.check_and_add(&packet.data[sig_start..packet.meta.size])
+            });
+            num_dups += len - batch.packets.len();
+        }
+        if num_dups > 0 {
+            inc_new_counter!("sigverify-dedup_dropped", num_dups);
+        }
+        num_dups
+    }
+
+    /// Remove the blobs holding a transaction seen before from `blobs`,
+    /// returning them.
+    pub fn dedup_blobs(&mut self, blobs: &mut SharedBlobs) -> SharedBlobs {
+        let sig_start = TX_OFFSET + SIG_OFFSET;
+        let sig_end = sig_start + size_of::<Signature>();
+        let (dups, fresh): (SharedBlobs, SharedBlobs) = blobs.drain(..).partition(|blob| {
+            let blob = blob.read().expect("'blob' read lock in dedup_blobs");
+            blob.meta.size >= sig_end && self.check_and_add(&blob.data[sig_start..blob.meta.size])
+        });
+        *blobs = fresh;
+        if !dups.is_empty() {
+            inc_new_counter!("sigverify-dedup_dropped", dups.len());
+        }
+        dups
+    }
+}
+
 #[cfg(feature = "cuda")]
 #[repr(C)]
 struct Elems {
@@ -41,8 +135,8 @@ pub fn init() {
     // stub
 }
 
-#[cfg(not(feature = "cuda"))]
-fn verify_packet(packet: &Packet) -> u8 {
+/// Verify the signature of the serialized transaction held in `data`.
+pub fn verify_data(data: &[u8]) -> u8 {
     use ring::signature;
     use signature::{PublicKey, Signature};
     use untrusted;
@@ -53,19 +147,38 @@ fn verify_packet(packet: &Packet) -> u8 {
     let pub_key_start = TX_OFFSET + PUB_KEY_OFFSET;
     let pub_key_end = pub_key_start + size_of::<PublicKey>();
 
-    if packet.meta.size <= msg_start {
+    if data.len() <= msg_start {
         return 0;
     }
 
-    let msg_end = packet.meta.size;
     signature::verify(
         &signature::ED25519,
-        untrusted::Input::from(&packet.data[pub_key_start..pub_key_end]),
-        untrusted::Input::from(&packet.data[msg_start..msg_end]),
-        untrusted::Input::from(&packet.data[sig_start..sig_end]),
+        untrusted::Input::from(&data[pub_key_start..pub_key_end]),
+        untrusted::Input::from(&data[msg_start..]),
+        untrusted::Input::from(&data[sig_start..sig_end]),
     ).is_ok() as u8
 }
 
+#[cfg(not(feature = "cuda"))]
+fn verify_packet(packet: &Packet) -> u8 {
+    verify_data(&packet.data[..packet.meta.size])
+}
+
+/// Verify transactions that arrived over a stream, one transaction per blob.
+/// These are always verified on the CPU.
+pub fn ed25519_verify_blobs(blobs: &SharedBlobs) -> Vec<u8> {
+    use rayon::prelude::*;
+    let rv = blobs
+        .par_iter()
+        .map(|b| {
+            let blob = b.read().expect("'b' read lock in ed25519_verify_blobs");
+            verify_data(&blob.data[..blob.meta.size])
+        })
+        .collect();
+    inc_new_counter!("ed25519_verify_blobs", blobs.len());
+    rv
+}
+
 fn batch_size(batches: &[SharedPackets]) -> usize {
     batches
         .iter()
@@ -174,8 +287,9 @@ pub fn ed25519_verify(batches: &Vec<SharedPackets>) -> Vec<Vec<u8>> {
 #[cfg(test)]
 mod tests {
     use bincode::serialize;
-    use packet::{Packet, Packets, SharedPackets};
-    use sigverify;
+    use packet::{BlobRecycler, Packet, Packets, SharedPackets};
+    use sigverify::{self, SigDedup};
+    use std::collections::VecDeque;
     use std::sync::RwLock;
     use transaction::Transaction;
     use transaction::{memfind, test_tx};
@@ -242,4 +356,108 @@ mod tests {
     fn test_verify_fail() {
         test_verify_n(5, true);
     }
+
+    #[test]
+    fn test_verify_blobs() {
+        let tx = test_tx();
+        let tx_bytes = serialize(&tx).unwrap();
+        let recycler = BlobRecycler::default();
+        let mut blobs = VecDeque::new();
+        for i in 0..3 {
+            let b = recycler.allocate();
+            {
+                let mut w = b.write().unwrap();
+                w.data[..tx_bytes.len()].copy_from_slice(&tx_bytes);
+                w.meta.size = tx_bytes.len();
+                if i == 1 {
+                    w.data[20] = w.data[20].wrapping_add(10);
+                }
+            }
+            blobs.push_back(b);
+        }
+        assert_eq!(sigverify::ed25519_verify_blobs(&blobs), vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn test_dedup_packets() {
+        let packet = make_packet_from_transaction(test_tx());
+        let other = make_packet_from_transaction(test_tx());
+        let mut packets = Packets::default();
+        packets.packets = vec![
+            packet.clone(),
+            other.clone(),
+            packet.clone(),
+            Packet::default(),
+        ];
+        let mut repeats = Packets::default();
+        repeats.packets = vec![other, packet];
+        let batches = vec![
+            SharedPackets::new(RwLock::new(packets)),
+            SharedPackets::new(RwLock::new(repeats)),
+        ];
+
+        let mut dedup = SigDedup::new(10);
+        assert_eq!(dedup.dedup_packets(&batches), 3);
+        assert_eq!(batches[0].read().unwrap().packets.len(), 3);
+        assert!(batches[1].read().unwrap().packets.is_empty());
+
+        // the empty packet is kept for verification to fail
+        let ans = sigverify::ed25519_verify(&batches);
+        assert_eq!(ans, vec![vec![1, 1, 0], vec![]]);
+    }
+
+    #[test]
+    fn test_dedup_forged_body() {
+        // a copy of the signature over another body doesn't shadow the transaction
+        let packet = make_packet_from_transaction(test_tx());
+        let mut forged = packet.clone();
+        forged.data[forged.meta.size - 1] ^= 1;
+        let mut packets = Packets::default();
+        packets.packets = vec![forged, packet];
+        let batches = vec![SharedPackets::new(RwLock::new(packets))];
+
+        let mut dedup = SigDedup::new(10);
+        assert_eq!(dedup.dedup_packets(&batches), 0);
+        let ans = sigverify::ed25519_verify(&batches);
+        assert_eq!(ans, vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn test_dedup_blobs() {
+        let tx_bytes = serialize(&test_tx()).unwrap();
+        let recycler = BlobRecycler::default();
+        let mut blobs = VecDeque::new();
+        for _ in 0..2 {
+            let b = recycler.allocate();
+            {
+                let mut w = b.write().unwrap();
+                w.data[..tx_bytes.len()].copy_from_slice(&tx_bytes);
+                w.meta.size = tx_bytes.len();
+            }
+            blobs.push_back(b);
+        }
+
+        let mut dedup = SigDedup::new(10);
+        assert_eq!(dedup.dedup_blobs(&mut blobs).len(), 1);
+        assert_eq!(blobs.len(), 1);
+    }
+
+    #[test]
+    fn test_dedup_rotate() {
+        let sigs: Vec<_> = (0..5u8).map(|i| [i; 64]).collect();
+        let mut dedup = SigDedup::new(2);
+        assert!(!dedup.check_and_add(&sigs[0]));
+        assert!(!dedup.check_and_add(&sigs[1]));
+        assert!(dedup.check_and_add(&sigs[0]));
+
+        // the first two are kept while the next two fill the other filter
+        assert!(!dedup.check_and_add(&sigs[2]));
+        assert!(!dedup.check_and_add(&sigs[3]));
+        assert!(dedup.check_and_add(&sigs[1]));
+
+        // and forgotten when the one after that rotates them out
+        assert!(!dedup.check_and_add(&sigs[4]));
+        assert!(!dedup.check_and_add(&sigs[0]));
+        assert!(dedup.check_and_add(&sigs[3]));
+    }
 }
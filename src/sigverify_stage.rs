@@ -3,45 +3,180 @@
 //! top-level list with a list of booleans, telling the next stage whether the
 //! signature in that packet is valid. It assumes each packet contains one
 //! transaction. All processing is done on the CPU by default and on a GPU
-//! if the `cuda` feature is enabled with `--features=cuda`.
+//! if the `cuda` feature is enabled with `--features=cuda`. Transactions that
+//! arrive over stream connections are verified on the CPU by a separate thread.
+//! Packets and stream blobs repeating a transaction seen recently are dropped
+//! before verification.
+//! The verified transactions wait for the banking stage in a channel of
+//! `VERIFIED_QUEUE_CAPACITY`, which sheds the batches paying the lowest fees.
 
-use packet::SharedPackets;
+use bincode::deserialize;
+use bounded_queue::{bounded_channel, BoundedReceiver, BoundedSender, QueueItem, ShedPolicy};
+use counter::Counter;
+use packet::{BlobRecycler, PacketRecycler, SharedBlobs, SharedPackets};
 use rand::{thread_rng, Rng};
 use result::{Error, Result};
 use service::Service;
-use sigverify;
-use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use sigverify::{self, SigDedup, DEDUP_CAPACITY};
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, spawn, JoinHandle};
-use std::time::Instant;
-use streamer::{self, PacketReceiver};
+use std::time::{Duration, Instant};
+use streamer::BlobReceiver;
 use timing;
+use transaction::Transaction;
 
-pub type VerifiedPackets = Vec<(SharedPackets, Vec<u8>)>;
+/// Transactions the channel to the banking stage holds
+pub const VERIFIED_QUEUE_CAPACITY: usize = 64 * 1024;
+
+/// The most packets the stage verifies at once
+const MAX_BATCH_PACKETS: usize = 100_000;
+
+/// Transactions tagged with the result of verifying each one's signature
+pub enum Verified {
+    /// A batch of transactions received as UDP packets
+    Packets(SharedPackets, Vec<u8>),
+    /// Transactions read from stream connections, one per blob
+    Blobs(SharedBlobs, Vec<u8>),
+}
+
+pub type VerifiedPackets = Vec<Verified>;
+
+fn transaction_fee(data: &[u8]) -> i64 {
+    deserialize::<Transaction>(data)
+        .map(|tx| tx.fee)
+        .unwrap_or(0)
+}
+
+impl QueueItem for VerifiedPackets {
+    fn num_transactions(&self) -> usize {
+        self.iter()
+            .map(|x| match x {
+                Verified::Packets(_, vers) | Verified::Blobs(_, vers) => vers.len(),
+            })
+            .sum()
+    }
+
+    /// The mean fee of the transactions with valid signatures
+    fn fee(&self) -> i64 {
+        let mut fees = vec![];
+        for verified in self {
+            match verified {
+                Verified::Packets(msgs, vers) => {
+                    let msgs = msgs.read().unwrap();
+                    for (packet, _) in msgs.packets.iter().zip(vers).filter(|(_, v)| **v != 0) {
+                        fees.push(transaction_fee(&packet.data[..packet.meta.size]));
+                    }
+                }
+                Verified::Blobs(blobs, vers) => {
+                    for (blob, _) in blobs.iter().zip(vers).filter(|(_, v)| **v != 0) {
+                        let blob = blob.read().unwrap();
+                        fees.push(transaction_fee(&blob.data[..blob.meta.size]));
+                    }
+                }
+            }
+        }
+        if fees.is_empty() {
+            return 0;
+        }
+        fees.iter().sum::<i64>() / fees.len() as i64
+    }
+}
 
 pub struct SigVerifyStage {
     thread_hdls: Vec<JoinHandle<()>>,
 }
 
 impl SigVerifyStage {
-    pub fn new(packet_receiver: Receiver<SharedPackets>) -> (Self, Receiver<VerifiedPackets>) {
+    pub fn new(
+        packet_receiver: BoundedReceiver<SharedPackets>,
+        stream_receiver: BlobReceiver,
+        packet_recycler: PacketRecycler,
+        blob_recycler: BlobRecycler,
+    ) -> (Self, BoundedReceiver<VerifiedPackets>) {
         sigverify::init();
-        let (verified_sender, verified_receiver) = channel();
-        let thread_hdls = Self::verifier_services(packet_receiver, verified_sender);
+        let (verified_sender, verified_receiver) =
+            bounded_channel(VERIFIED_QUEUE_CAPACITY, ShedPolicy::DropLowestFee);
+        let thread_hdls = Self::verifier_services(
+            packet_receiver,
+            stream_receiver,
+            verified_sender,
+            packet_recycler,
+            blob_recycler,
+        );
         (SigVerifyStage { thread_hdls }, verified_receiver)
     }
 
     fn verify_batch(batch: Vec<SharedPackets>) -> VerifiedPackets {
         let r = sigverify::ed25519_verify(&batch);
-        batch.into_iter().zip(r).collect()
+        batch
+            .into_iter()
+            .zip(r)
+            .map(|(packets, v)| Verified::Packets(packets, v))
+            .collect()
+    }
+
+    fn recv_batch(recvr: &BoundedReceiver<SharedPackets>) -> Result<(Vec<SharedPackets>, usize)> {
+        let timer = Duration::new(1, 0);
+        let msgs = recvr.recv_timeout(timer)?;
+        let mut len = msgs.read().unwrap().packets.len();
+        let mut batch = vec![msgs];
+        while len <= MAX_BATCH_PACKETS {
+            match recvr.try_recv() {
+                Ok(more) => {
+                    len += more.read().unwrap().packets.len();
+                    batch.push(more);
+                }
+                Err(_) => break,
+            }
+        }
+        Ok((batch, len))
+    }
+
+    /// Queue `verified` for the banking stage, counting the transactions shed
+    /// to make room for it and recycling their buffers
+    fn send_verified(
+        sendr: &Arc<Mutex<BoundedSender<VerifiedPackets>>>,
+        verified: VerifiedPackets,
+        packet_recycler: &PacketRecycler,
+        blob_recycler: &BlobRecycler,
+    ) -> Result<()> {
+        let (shed, queue_depth) = {
+            let sendr = sendr.lock().expect("'sendr' lock in fn send_verified");
+            (sendr.send(verified)?, sendr.len())
+        };
+        let num_shed: usize = shed.iter().map(|v| v.num_transactions()).sum();
+        if num_shed > 0 {
+            inc_new_counter!("sigverify_stage-shed", num_shed);
+        }
+        for batch in shed {
+            for verified in batch {
+                match verified {
+                    Verified::Packets(msgs, _) => packet_recycler.recycle(msgs),
+                    Verified::Blobs(blobs, _) => for blob in blobs {
+                        blob_recycler.recycle(blob);
+                    },
+                }
+            }
+        }
+        inc_new_counter!("sigverify_stage-queue_depth", queue_depth);
+        Ok(())
     }
 
     fn verifier(
-        recvr: &Arc<Mutex<PacketReceiver>>,
-        sendr: &Arc<Mutex<Sender<VerifiedPackets>>>,
+        recvr: &Arc<Mutex<BoundedReceiver<SharedPackets>>>,
+        sendr: &Arc<Mutex<BoundedSender<VerifiedPackets>>>,
+        dedup: &Arc<Mutex<SigDedup>>,
+        packet_recycler: &PacketRecycler,
+        blob_recycler: &BlobRecycler,
     ) -> Result<()> {
-        let (batch, len) =
-            streamer::recv_batch(&recvr.lock().expect("'recvr' lock in fn verifier"))?;
+        let (batch, len) = Self::recv_batch(&recvr.lock().expect("'recvr' lock in fn verifier"))?;
+        let num_dups = dedup
+            .lock()
+            .expect("'dedup' lock in fn verifier")
+            .dedup_packets(&batch);
+        let len = len - num_dups;
 
         let now = Instant::now();
         let batch_len = batch.len();
@@ -54,10 +189,7 @@ impl SigVerifyStage {
         );
 
         let verified_batch = Self::verify_batch(batch);
-        sendr
-            .lock()
-            .expect("lock in fn verify_batch in tpu")
-            .send(verified_batch)?;
+        Self::send_verified(sendr, verified_batch, packet_recycler, blob_recycler)?;
 
         let total_time_ms = timing::duration_as_ms(&now.elapsed());
         let total_time_s = timing::duration_as_s(&now.elapsed());
@@ -74,11 +206,78 @@ impl SigVerifyStage {
     }
 
     fn verifier_service(
-        packet_receiver: Arc<Mutex<PacketReceiver>>,
-        verified_sender: Arc<Mutex<Sender<VerifiedPackets>>>,
+        packet_receiver: Arc<Mutex<BoundedReceiver<SharedPackets>>>,
+        verified_sender: Arc<Mutex<BoundedSender<VerifiedPackets>>>,
+        dedup: Arc<Mutex<SigDedup>>,
+        packet_recycler: PacketRecycler,
+        blob_recycler: BlobRecycler,
+    ) -> JoinHandle<()> {
+        spawn(move || loop {
+            if let Err(e) = Self::verifier(
+                &packet_receiver,
+                &verified_sender,
+                &dedup,
+                &packet_recycler,
+                &blob_recycler,
+            ) {
+                match e {
+                    Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                    Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                    _ => error!("{:?}", e),
+                }
+            }
+        })
+    }
+
+    fn stream_verifier(
+        recvr: &BlobReceiver,
+        sendr: &Arc<Mutex<BoundedSender<VerifiedPackets>>>,
+        dedup: &Arc<Mutex<SigDedup>>,
+        packet_recycler: &PacketRecycler,
+        blob_recycler: &BlobRecycler,
+    ) -> Result<()> {
+        let timer = Duration::new(1, 0);
+        let mut blobs = recvr.recv_timeout(timer)?;
+        while let Ok(mut more) = recvr.try_recv() {
+            blobs.append(&mut more);
+        }
+        let dups = dedup
+            .lock()
+            .expect("'dedup' lock in fn stream_verifier")
+            .dedup_blobs(&mut blobs);
+        for blob in dups {
+            blob_recycler.recycle(blob);
+        }
+        if blobs.is_empty() {
+            return Ok(());
+        }
+        let len = blobs.len();
+        let r = sigverify::ed25519_verify_blobs(&blobs);
+        Self::send_verified(
+            sendr,
+            vec![Verified::Blobs(blobs, r)],
+            packet_recycler,
+            blob_recycler,
+        )?;
+        inc_new_counter!("sigverify_stage-stream_verified", len);
+        Ok(())
+    }
+
+    fn stream_verifier_service(
+        stream_receiver: BlobReceiver,
+        verified_sender: Arc<Mutex<BoundedSender<VerifiedPackets>>>,
+        dedup: Arc<Mutex<SigDedup>>,
+        packet_recycler: PacketRecycler,
+        blob_recycler: BlobRecycler,
     ) -> JoinHandle<()> {
         spawn(move || loop {
-            if let Err(e) = Self::verifier(&packet_receiver, &verified_sender) {
+            if let Err(e) = Self::stream_verifier(
+                &stream_receiver,
+                &verified_sender,
+                &dedup,
+                &packet_recycler,
+                &blob_recycler,
+            ) {
                 match e {
                     Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
                     Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
@@ -89,14 +288,34 @@ impl SigVerifyStage {
     }
 
     fn verifier_services(
-        packet_receiver: PacketReceiver,
-        verified_sender: Sender<VerifiedPackets>,
+        packet_receiver: BoundedReceiver<SharedPackets>,
+        stream_receiver: BlobReceiver,
+        verified_sender: BoundedSender<VerifiedPackets>,
+        packet_recycler: PacketRecycler,
+        blob_recycler: BlobRecycler,
     ) -> Vec<JoinHandle<()>> {
         let sender = Arc::new(Mutex::new(verified_sender));
         let receiver = Arc::new(Mutex::new(packet_receiver));
-        (0..4)
-            .map(|_| Self::verifier_service(receiver.clone(), sender.clone()))
-            .collect()
+        let dedup = Arc::new(Mutex::new(SigDedup::new(DEDUP_CAPACITY)));
+        let mut thread_hdls: Vec<_> = (0..4)
+            .map(|_| {
+                Self::verifier_service(
+                    receiver.clone(),
+                    sender.clone(),
+                    dedup.clone(),
+                    packet_recycler.clone(),
+                    blob_recycler.clone(),
+                )
+            })
+            .collect();
+        thread_hdls.push(Self::stream_verifier_service(
+            stream_receiver,
+            sender,
+            dedup,
+            packet_recycler,
+            blob_recycler,
+        ));
+        thread_hdls
     }
 }
 
@@ -0,0 +1,270 @@
+//! The `stream_fetch_stage` accepts TCP connections on the TPU address and reads
+//! length-prefixed transaction frames from them. It carries transactions that are
+//! too large to fit in a single UDP packet. Each frame is copied into its own blob
+//! and sent to a channel for signature verification.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use counter::Counter;
+use packet::{Blob, BlobRecycler, BLOB_SIZE};
+use service::Service;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::Duration;
+use streamer::{BlobReceiver, BlobSender};
+
+/// Largest transaction accepted over a stream connection
+pub const MAX_STREAM_FRAME_SIZE: usize = 16 * 1024;
+
+/// Connections beyond this count are closed as soon as they are accepted
+pub const MAX_STREAM_CONNECTIONS: usize = 64;
+
+/// Seconds a connection may go without sending anything before it's closed, so
+/// that idle or slow clients can't hold on to the connections
+pub const STREAM_IDLE_TIMEOUT_SECS: u64 = 10;
+
+/// milliseconds the accept loop sleeps when there are no pending connections
+const ACCEPT_SLEEP_MILLIS: u64 = 100;
+
+/// Write `data` as a single frame: a little-endian `u32` length followed by the bytes.
+pub fn write_frame<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    if data.is_empty() || data.len() > MAX_STREAM_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("frame size {} out of range", data.len()),
+        ));
+    }
+    writer.write_u32::<LittleEndian>(data.len() as u32)?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// Read one frame into the front of `blob.data` and set `blob.meta.size`.
+pub fn read_frame<R: Read>(reader: &mut R, blob: &mut Blob) -> io::Result<()> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    if len == 0 || len > MAX_STREAM_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame size {} out of range", len),
+        ));
+    }
+    assert!(len <= BLOB_SIZE);
+    reader.read_exact(&mut blob.data[..len])?;
+    blob.meta.size = len;
+    Ok(())
+}
+
+pub struct StreamFetchStage {
+    exit: Arc<AtomicBool>,
+    thread_hdl: JoinHandle<()>,
+}
+
+impl StreamFetchStage {
+    pub fn new(
+        listener: TcpListener,
+        exit: Arc<AtomicBool>,
+        blob_recycler: &BlobRecycler,
+    ) -> (Self, BlobReceiver) {
+        let (blob_sender, blob_receiver) = channel();
+        let thread_exit = exit.clone();
+        let recycler = blob_recycler.clone();
+        let thread_hdl = Builder::new()
+            .name("solana-stream-fetch-stage".to_string())
+            .spawn(move || {
+                Self::accept_loop(&listener, &thread_exit, &recycler, &blob_sender);
+            })
+            .unwrap();
+        (StreamFetchStage { exit, thread_hdl }, blob_receiver)
+    }
+
+    pub fn close(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+    }
+
+    fn accept_loop(
+        listener: &TcpListener,
+        exit: &Arc<AtomicBool>,
+        recycler: &BlobRecycler,
+        blob_sender: &BlobSender,
+    ) {
+        listener
+            .set_nonblocking(true)
+            .expect("set_nonblocking on stream listener");
+        let (done_sender, done_receiver) = channel();
+        let mut connections: HashMap<u64, (TcpStream, JoinHandle<()>)> = HashMap::new();
+        let mut next_id = 0;
+        let idle_timeout = Duration::new(STREAM_IDLE_TIMEOUT_SECS, 0);
+        while !exit.load(Ordering::Relaxed) {
+            Self::reap_connections(&mut connections, &done_receiver);
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    if connections.len() >= MAX_STREAM_CONNECTIONS {
+                        inc_new_counter!("stream_fetch_stage-refused", 1);
+                        warn!("refusing stream connection from {}, too many open", addr);
+                        let _ = stream.shutdown(Shutdown::Both);
+                        continue;
+                    }
+                    let conn = stream
+                        .set_nonblocking(false)
+                        .and_then(|_| stream.set_read_timeout(Some(idle_timeout)))
+                        .and_then(|_| stream.try_clone());
+                    match conn {
+                        Ok(conn) => {
+                            debug!("stream connection from {}", addr);
+                            let hdl = Self::connection(
+                                next_id,
+                                conn,
+                                recycler.clone(),
+                                blob_sender.clone(),
+                                done_sender.clone(),
+                            );
+                            connections.insert(next_id, (stream, hdl));
+                            next_id += 1;
+                        }
+                        Err(e) => warn!("stream connection from {} failed: {:?}", addr, e),
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    sleep(Duration::from_millis(ACCEPT_SLEEP_MILLIS));
+                }
+                Err(e) => {
+                    warn!("stream accept error: {:?}", e);
+                    sleep(Duration::from_millis(ACCEPT_SLEEP_MILLIS));
+                }
+            }
+        }
+        // unblock the readers and wait for them
+        for (_, (stream, hdl)) in connections {
+            let _ = stream.shutdown(Shutdown::Both);
+            let _ = hdl.join();
+        }
+    }
+
+    fn reap_connections(
+        connections: &mut HashMap<u64, (TcpStream, JoinHandle<()>)>,
+        done_receiver: &Receiver<u64>,
+    ) {
+        while let Ok(id) = done_receiver.try_recv() {
+            if let Some((_, hdl)) = connections.remove(&id) {
+                let _ = hdl.join();
+            }
+        }
+    }
+
+    fn connection(
+        id: u64,
+        stream: TcpStream,
+        recycler: BlobRecycler,
+        blob_sender: BlobSender,
+        done_sender: Sender<u64>,
+    ) -> JoinHandle<()> {
+        Builder::new()
+            .name("solana-stream-connection".to_string())
+            .spawn(move || {
+                let addr = stream.peer_addr();
+                let mut reader = BufReader::new(stream);
+                loop {
+                    let blob = recycler.allocate();
+                    let result = {
+                        let mut b = blob.write().unwrap();
+                        let r = read_frame(&mut reader, &mut b);
+                        if let Ok(ref addr) = addr {
+                            b.meta.set_addr(addr);
+                        }
+                        r
+                    };
+                    if let Err(e) = result {
+                        trace!("stream connection {:?} closed: {:?}", addr, e);
+                        recycler.recycle(blob);
+                        break;
+                    }
+                    inc_new_counter!("stream_fetch_stage-frames", 1);
+                    let mut q = VecDeque::new();
+                    q.push_back(blob);
+                    if blob_sender.send(q).is_err() {
+                        break;
+                    }
+                }
+                let _ = done_sender.send(id);
+            })
+            .unwrap()
+    }
+}
+
+impl Service for StreamFetchStage {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packet::{Blob, BlobRecycler};
+    use service::Service;
+    use std::io::Cursor;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use stream_fetch_stage::{read_frame, write_frame, StreamFetchStage, MAX_STREAM_FRAME_SIZE};
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut buf = vec![];
+        write_frame(&mut buf, &[1, 2, 3]).unwrap();
+        write_frame(&mut buf, &vec![9; MAX_STREAM_FRAME_SIZE]).unwrap();
+        let mut cursor = Cursor::new(buf);
+        let mut blob = Blob::default();
+        read_frame(&mut cursor, &mut blob).unwrap();
+        assert_eq!(blob.meta.size, 3);
+        assert_eq!(&blob.data[..3], &[1, 2, 3]);
+        read_frame(&mut cursor, &mut blob).unwrap();
+        assert_eq!(blob.meta.size, MAX_STREAM_FRAME_SIZE);
+        assert!(read_frame(&mut cursor, &mut blob).is_err());
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        let mut buf = vec![];
+        assert!(write_frame(&mut buf, &vec![0; MAX_STREAM_FRAME_SIZE + 1]).is_err());
+        assert!(write_frame(&mut buf, &[]).is_err());
+        assert!(buf.is_empty());
+
+        let mut cursor = Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0]);
+        assert!(read_frame(&mut cursor, &mut Blob::default()).is_err());
+    }
+
+    #[test]
+    fn test_stream_fetch_stage() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let exit = Arc::new(AtomicBool::new(false));
+        let recycler = BlobRecycler::default();
+        let (stage, receiver) = StreamFetchStage::new(listener, exit.clone(), &recycler);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let data = vec![7u8; 4096];
+        write_frame(&mut stream, &data).unwrap();
+        write_frame(&mut stream, &data[..10]).unwrap();
+
+        let timer = Duration::new(5, 0);
+        let first = receiver.recv_timeout(timer).unwrap();
+        let b = first[0].read().unwrap();
+        assert_eq!(b.meta.size, 4096);
+        assert_eq!(&b.data[..4096], &data[..]);
+        assert_eq!(b.meta.addr(), stream.local_addr().unwrap());
+        let second = receiver.recv_timeout(timer).unwrap();
+        assert_eq!(second[0].read().unwrap().meta.size, 10);
+
+        stage.close();
+        stage.join().unwrap();
+    }
+}
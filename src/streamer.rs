@@ -1,9 +1,12 @@
 //! The `streamer` module defines a set of services for efficiently pulling data from UDP sockets.
 //!
+use bounded_queue::{BoundedSender, QueueItem};
 use counter::Counter;
 use crdt::{Crdt, CrdtError, NodeInfo};
 #[cfg(feature = "erasure")]
 use erasure;
+use erasure::CodingRate;
+use net_utils::{is_ipv6, send_addr, udp_bind};
 use packet::{
     Blob, BlobRecycler, PacketRecycler, SharedBlob, SharedBlobs, SharedPackets, BLOB_SIZE,
 };
@@ -19,6 +22,8 @@ use std::thread::{Builder, JoinHandle};
 use std::time::Duration;
 
 pub const WINDOW_SIZE: u64 = 2 * 1024;
+/// Blobs the window counts between reports of its blob loss
+const BLOB_LOSS_SAMPLE: u64 = 1024;
 pub type PacketReceiver = Receiver<SharedPackets>;
 pub type PacketSender = Sender<SharedPackets>;
 pub type BlobSender = Sender<SharedBlobs>;
@@ -30,12 +35,15 @@ pub enum WindowError {
     GenericError,
 }
 
-fn recv_loop(
+fn recv_loop<F>(
     sock: &UdpSocket,
     exit: &Arc<AtomicBool>,
     re: &PacketRecycler,
-    channel: &PacketSender,
-) -> Result<()> {
+    send: F,
+) -> Result<()>
+where
+    F: Fn(SharedPackets) -> Result<()>,
+{
     loop {
         let msgs = re.allocate();
         loop {
@@ -44,7 +52,7 @@ fn recv_loop(
                 .recv_from(sock);
             match result {
                 Ok(()) => {
-                    channel.send(msgs)?;
+                    send(msgs)?;
                     break;
                 }
                 Err(_) => {
@@ -71,7 +79,37 @@ pub fn receiver(
     Builder::new()
         .name("solana-receiver".to_string())
         .spawn(move || {
-            let _ = recv_loop(&sock, &exit, &recycler, &packet_sender);
+            let _ = recv_loop(&sock, &exit, &recycler, |msgs| {
+                packet_sender.send(msgs)?;
+                Ok(())
+            });
+            ()
+        })
+        .unwrap()
+}
+
+/// A `receiver` into a bounded channel, which sheds the oldest packets when
+/// the next stage falls behind
+pub fn bounded_receiver(
+    sock: UdpSocket,
+    exit: Arc<AtomicBool>,
+    recycler: PacketRecycler,
+    packet_sender: BoundedSender<SharedPackets>,
+) -> JoinHandle<()> {
+    let res = sock.set_read_timeout(Some(Duration::new(1, 0)));
+    if res.is_err() {
+        panic!("streamer::bounded_receiver set_read_timeout error");
+    }
+    Builder::new()
+        .name("solana-receiver".to_string())
+        .spawn(move || {
+            let _ = recv_loop(&sock, &exit, &recycler, |msgs| {
+                for shed in packet_sender.send(msgs)? {
+                    inc_new_counter!("streamer-receiver-shed", shed.num_transactions());
+                    recycler.recycle(shed);
+                }
+                Ok(())
+            });
             ()
         })
         .unwrap()
@@ -181,6 +219,57 @@ fn find_next_missing(
     Ok(reqs)
 }
 
+/// Measures how many of the leader's blobs never reach this node, so the leader
+/// can size its erasure sets to match
+struct BlobLoss {
+    /// blobs below this index have been counted
+    counted: u64,
+    missing: u64,
+    total: u64,
+}
+
+impl BlobLoss {
+    fn new(entry_height: u64) -> Self {
+        BlobLoss {
+            counted: entry_height,
+            missing: 0,
+            total: 0,
+        }
+    }
+
+    /// Count the blobs up to `received`, taking those still missing from the
+    /// window as lost. Returns the loss in parts per million of the blobs sent
+    /// each time a sample is complete.
+    fn update(
+        &mut self,
+        window: &[Option<SharedBlob>],
+        consumed: u64,
+        received: u64,
+    ) -> Option<u32> {
+        if received <= self.counted {
+            return None;
+        }
+        // everything below consumed arrived, or wasn't needed
+        for ix in cmp::max(self.counted, consumed)..received {
+            let arrived = window[(ix % WINDOW_SIZE) as usize]
+                .as_ref()
+                .map_or(false, |b| b.read().unwrap().get_index().ok() == Some(ix));
+            if !arrived {
+                self.missing += 1;
+            }
+        }
+        self.total += received - self.counted;
+        self.counted = received;
+        if self.total < BLOB_LOSS_SAMPLE {
+            return None;
+        }
+        let loss_ppm = self.missing * 1_000_000 / self.total;
+        self.missing = 0;
+        self.total = 0;
+        Some(loss_ppm as u32)
+    }
+}
+
 fn repair_window(
     debug_id: u64,
     locked_window: &Window,
@@ -190,7 +279,13 @@ fn repair_window(
     times: &mut usize,
     consumed: &mut u64,
     received: &mut u64,
+    loss: &mut BlobLoss,
 ) -> Result<()> {
+    let loss_ppm = loss.update(&locked_window.read().unwrap(), *consumed, *received);
+    if let Some(loss_ppm) = loss_ppm {
+        debug!("{:x}: blob loss {} ppm", debug_id, loss_ppm);
+        crdt.write().unwrap().set_blob_loss(loss_ppm);
+    }
     #[cfg(feature = "erasure")]
     {
         if erasure::recover(
@@ -233,7 +328,8 @@ fn repair_window(
             reqs.len()
         );
     }
-    let sock = UdpSocket::bind("0.0.0.0:0")?;
+    let sock = udp_bind(0)?;
+    let ipv6 = is_ipv6(&sock);
     for (to, req) in reqs {
         //todo cache socket
         debug!(
@@ -241,7 +337,7 @@ fn repair_window(
             debug_id, *consumed, *received, to
         );
         assert!(req.len() < BLOB_SIZE);
-        sock.send_to(&req, to)?;
+        sock.send_to(&req, send_addr(ipv6, &to))?;
     }
     Ok(())
 }
@@ -269,7 +365,11 @@ fn retransmit_all_leader_blocks(
                 p.meta.addr(),
                 leader_id
             );
-            if p.get_id().expect("get_id in fn recv_window") == leader_id {
+            // blobs keep the leader's id all the way down the broadcast tree,
+            // the ones this node already has were passed on when they arrived
+            if p.get_id().expect("get_id in fn recv_window") == leader_id
+                && p.get_index().expect("get_index in fn recv_window") >= *consumed
+            {
                 //TODO
                 //need to copy the retransmitted blob
                 //otherwise we get into races with which thread
@@ -378,7 +478,7 @@ fn process_blob(
                     window[(j % WINDOW_SIZE) as usize] = None;
                 }
 
-                *consumed += erasure::MAX_MISSING as u64;
+                *consumed = coding_end;
                 debug!(
                     "skipping processing coding blob k: {} consumed: {}",
                     k, *consumed
@@ -566,6 +666,7 @@ pub fn window(
             let mut received = entry_height;
             let mut last = entry_height;
             let mut times = 0;
+            let mut loss = BlobLoss::new(entry_height);
             let debug_id = crdt.read().unwrap().debug_id();
             trace!("{:x}: RECV_WINDOW started", debug_id);
             loop {
@@ -598,6 +699,7 @@ pub fn window(
                     &mut times,
                     &mut consumed,
                     &mut received,
+                    &mut loss,
                 );
                 assert!(consumed <= (received + 1));
             }
@@ -607,13 +709,14 @@ pub fn window(
 
 fn broadcast(
     me: &NodeInfo,
-    broadcast_table: &[NodeInfo],
+    broadcast_table: &[(NodeInfo, u64)],
     window: &Window,
     recycler: &BlobRecycler,
     r: &BlobReceiver,
     sock: &UdpSocket,
     transmit_index: &mut u64,
     receive_index: &mut u64,
+    _coding_rate: &mut CodingRate,
 ) -> Result<()> {
     let debug_id = me.debug_id();
     let timer = Duration::new(1, 0);
@@ -634,7 +737,7 @@ fn broadcast(
     for mut blobs in blobs_chunked {
         // Insert the coding blobs into the blob stream
         #[cfg(feature = "erasure")]
-        erasure::add_coding_blobs(recycler, &mut blobs, *receive_index);
+        erasure::add_coding_blobs(recycler, &mut blobs, *receive_index, _coding_rate);
 
         let blobs_len = blobs.len();
         debug!("{:x} broadcast blobs.len: {}", debug_id, blobs_len);
@@ -716,8 +819,15 @@ pub fn broadcaster(
             let mut transmit_index = entry_height;
             let mut receive_index = entry_height;
             let me = crdt.read().unwrap().my_data().clone();
+            let mut coding_rate = CodingRate::default();
             loop {
-                let broadcast_table = crdt.read().unwrap().compute_broadcast_table();
+                let (broadcast_table, blob_loss) = {
+                    let crdt = crdt.read().unwrap();
+                    (crdt.compute_broadcast_table(), crdt.blob_loss())
+                };
+                if let Some(loss_ppm) = blob_loss {
+                    coding_rate.set_loss(loss_ppm);
+                }
                 if let Err(e) = broadcast(
                     &me,
                     &broadcast_table,
@@ -727,6 +837,7 @@ pub fn broadcaster(
                     &sock,
                     &mut transmit_index,
                     &mut receive_index,
+                    &mut coding_rate,
                 ) {
                     match e {
                         Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
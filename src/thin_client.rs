@@ -5,14 +5,17 @@
 
 use bincode::{deserialize, serialize};
 use hash::Hash;
+use net_utils::{canonical_addr, is_ipv6, send_addr};
+use packet::BLOB_DATA_SIZE;
 use request::{Request, Response};
 use signature::{KeyPair, PublicKey, Signature};
 use std::collections::HashMap;
 use std::io;
-use std::net::{SocketAddr, UdpSocket};
-use std::time::Instant;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use stream_fetch_stage::{write_frame, STREAM_IDLE_TIMEOUT_SECS};
 use timing;
-use transaction::Transaction;
+use transaction::{Plan, Transaction};
 
 use influx_db_client as influxdb;
 use metrics;
@@ -23,9 +26,12 @@ pub struct ThinClient {
     requests_socket: UdpSocket,
     transactions_addr: SocketAddr,
     transactions_socket: UdpSocket,
+    /// the stream connection and when it was last written to
+    transactions_stream: Option<(TcpStream, Instant)>,
     last_id: Option<Hash>,
     transaction_count: u64,
     balances: HashMap<PublicKey, i64>,
+    pending_contracts: HashMap<(PublicKey, Option<Signature>), Vec<(Signature, Plan)>>,
     signature_status: bool,
 }
 
@@ -39,20 +45,26 @@ impl ThinClient {
         transactions_addr: SocketAddr,
         transactions_socket: UdpSocket,
     ) -> Self {
+        // sockets bound to IPv6 reach IPv4 servers at IPv4-mapped addresses
+        let requests_addr = send_addr(is_ipv6(&requests_socket), &requests_addr);
+        let transactions_addr = send_addr(is_ipv6(&transactions_socket), &transactions_addr);
         ThinClient {
             requests_addr,
             requests_socket,
             transactions_addr,
             transactions_socket,
+            transactions_stream: None,
             last_id: None,
             transaction_count: 0,
             balances: HashMap::new(),
+            pending_contracts: HashMap::new(),
             signature_status: false,
         }
     }
 
     pub fn recv_response(&self) -> io::Result<Response> {
-        let mut buf = vec![0u8; 1024];
+        // a list of pending contracts outgrows a small buffer
+        let mut buf = vec![0u8; BLOB_DATA_SIZE];
         trace!("start recv_from");
         self.requests_socket.recv_from(&mut buf)?;
         trace!("end recv_from");
@@ -81,6 +93,15 @@ impl ThinClient {
                     trace!("Response signature not found");
                 }
             }
+            Response::PendingContracts {
+                key,
+                after,
+                ref contracts,
+            } => {
+                trace!("Response pending contracts {:?} {:?}", key, contracts);
+                self.pending_contracts
+                    .insert((key, after), contracts.clone());
+            }
         }
     }
 
@@ -92,6 +113,34 @@ impl ThinClient {
             .send_to(&data, &self.transactions_addr)
     }
 
+    /// Send a signed Transaction to the server as a frame over a TCP connection to
+    /// its transactions address. Use this for transactions too large to fit in a
+    /// UDP packet. The connection is kept open for later calls, unless it idles
+    /// long enough that the server may have closed it. This method does not wait
+    /// for a response.
+    pub fn transfer_signed_stream(&mut self, tx: &Transaction) -> io::Result<usize> {
+        let data = serialize(&tx).expect("serialize Transaction in pub fn transfer_signed_stream");
+        let max_idle = Duration::new(STREAM_IDLE_TIMEOUT_SECS / 2, 0);
+        let idle = match self.transactions_stream {
+            Some((_, last_write)) => last_write.elapsed() >= max_idle,
+            None => true,
+        };
+        if idle {
+            let addr = canonical_addr(&self.transactions_addr);
+            self.transactions_stream = Some((TcpStream::connect(&addr)?, Instant::now()));
+        }
+        let result = {
+            let (stream, last_write) = self.transactions_stream.as_mut().unwrap();
+            *last_write = Instant::now();
+            write_frame(stream, &data)
+        };
+        if result.is_err() {
+            // reconnect on the next call
+            self.transactions_stream = None;
+        }
+        result.map(|_| data.len())
+    }
+
     /// Creates, signs, and processes a Transaction. Useful for writing unit-tests.
     pub fn transfer(
         &self,
@@ -141,6 +190,44 @@ impl ThinClient {
             .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "nokey"))
     }
 
+    /// Request the contracts awaiting a witness that pay `pubkey` or that it may
+    /// witness, at most `MAX_PENDING_CONTRACTS` of them, starting past the
+    /// signature `after` if given. This method blocks until the server sends
+    /// a response.
+    pub fn get_pending_contracts(
+        &mut self,
+        pubkey: &PublicKey,
+        after: Option<Signature>,
+    ) -> io::Result<Vec<(Signature, Plan)>> {
+        trace!("get_pending_contracts");
+        let req = Request::GetPendingContracts {
+            key: *pubkey,
+            after,
+        };
+        let data =
+            serialize(&req).expect("serialize GetPendingContracts in pub fn get_pending_contracts");
+        self.requests_socket
+            .send_to(&data, &self.requests_addr)
+            .expect("buffer error in pub fn get_pending_contracts");
+        let mut done = false;
+        while !done {
+            let resp = self.recv_response()?;
+            trace!("recv_response {:?}", resp);
+            // a late answer to a request for another page doesn't count
+            done = match resp {
+                Response::PendingContracts {
+                    key, after: page, ..
+                } => key == *pubkey && page == after,
+                _ => false,
+            };
+            self.process_response(&resp);
+        }
+        Ok(self
+            .pending_contracts
+            .remove(&(*pubkey, after))
+            .unwrap_or_default())
+    }
+
     /// Request the transaction count.  If the response packet is dropped by the network,
     /// this method will hang.
     pub fn transaction_count(&mut self) -> u64 {
@@ -214,6 +301,22 @@ impl ThinClient {
         balance
     }
 
+    /// Check a signature in the bank once. Unlike `check_signature`, this gives
+    /// up when no answer comes within the read timeout of the requests socket.
+    pub fn try_check_signature(&mut self, sig: &Signature) -> io::Result<bool> {
+        trace!("try_check_signature");
+        let req = Request::GetSignature { signature: *sig };
+        let data = serialize(&req).expect("serialize GetSignature in pub fn try_check_signature");
+        self.requests_socket.send_to(&data, &self.requests_addr)?;
+        loop {
+            let resp = self.recv_response()?;
+            self.process_response(&resp);
+            if let Response::SignatureStatus { signature_status } = resp {
+                return Ok(signature_status);
+            }
+        }
+    }
+
     /// Check a signature in the bank. This method blocks
     /// until the server sends a response.
     pub fn check_signature(&mut self, sig: &Signature) -> bool {
@@ -257,11 +360,14 @@ impl Drop for ThinClient {
 mod tests {
     use super::*;
     use bank::Bank;
-    use budget::Budget;
+    use budget::{Budget, Condition};
+    use choose_gossip_peer_strategy::GossipStrategy;
+    use chrono::prelude::*;
     use crdt::TestNode;
     use fullnode::FullNode;
     use logger;
     use mint::Mint;
+    use packet::PACKET_DATA_SIZE;
     use service::Service;
     use signature::{KeyPair, KeyPairUtil};
     use std::io::sink;
@@ -290,6 +396,8 @@ mod tests {
             leader,
             exit.clone(),
             sink(),
+            None,
+            GossipStrategy::default(),
         );
         sleep(Duration::from_millis(900));
 
@@ -312,6 +420,127 @@ mod tests {
         server.join().unwrap();
     }
 
+    #[cfg(feature = "ipv6")]
+    #[test]
+    fn test_thin_client_ipv6() {
+        logger::setup();
+        let leader = TestNode::new_localhost_ipv6();
+        let leader_data = leader.data.clone();
+
+        let alice = Mint::new(10_000);
+        let bank = Bank::new(&alice);
+        let bob_pubkey = KeyPair::new().pubkey();
+        let exit = Arc::new(AtomicBool::new(false));
+
+        let server = FullNode::new_leader(
+            bank,
+            0,
+            None,
+            Some(Duration::from_millis(30)),
+            leader,
+            exit.clone(),
+            sink(),
+            None,
+            GossipStrategy::default(),
+        );
+        sleep(Duration::from_millis(900));
+
+        let requests_socket = UdpSocket::bind("[::]:0").unwrap();
+        let transactions_socket = UdpSocket::bind("[::]:0").unwrap();
+
+        let mut client = ThinClient::new(
+            leader_data.contact_info.rpu,
+            requests_socket,
+            leader_data.contact_info.tpu,
+            transactions_socket,
+        );
+        let last_id = client.get_last_id();
+        let _sig = client
+            .transfer(500, &alice.keypair(), bob_pubkey, &last_id)
+            .unwrap();
+        let balance = client.poll_get_balance(&bob_pubkey);
+        assert_eq!(balance.unwrap(), 500);
+        exit.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_thin_client_stream() {
+        logger::setup();
+        let leader = TestNode::new_localhost();
+        let leader_data = leader.data.clone();
+
+        let alice = Mint::new(10_000);
+        let bank = Bank::new(&alice);
+        let bob_pubkey = KeyPair::new().pubkey();
+        let exit = Arc::new(AtomicBool::new(false));
+
+        let server = FullNode::new_leader(
+            bank,
+            0,
+            None,
+            Some(Duration::from_millis(30)),
+            leader,
+            exit.clone(),
+            sink(),
+            None,
+            GossipStrategy::default(),
+        );
+        sleep(Duration::from_millis(900));
+
+        let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let transactions_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+
+        let mut client = ThinClient::new(
+            leader_data.contact_info.rpu,
+            requests_socket,
+            leader_data.contact_info.tpu,
+            transactions_socket,
+        );
+        let last_id = client.get_last_id();
+        let tx = Transaction::new(&alice.keypair(), bob_pubkey, 500, last_id);
+        client.transfer_signed_stream(&tx).unwrap();
+        let balance = client.poll_get_balance(&bob_pubkey);
+        assert_eq!(balance.unwrap(), 500);
+
+        let tx = Transaction::new(&alice.keypair(), bob_pubkey, 300, last_id);
+        client.transfer_signed_stream(&tx).unwrap();
+        let mut balance = 0;
+        for _ in 0..10 {
+            balance = client.poll_get_balance(&bob_pubkey).unwrap();
+            if balance == 800 {
+                break;
+            }
+        }
+        assert_eq!(balance, 800);
+
+        // a cancelable contract outgrows a packet, and only fits in a stream frame
+        let dt = Utc::now();
+        let budget = Budget::new_cancelable_payment(
+            Condition::Timestamp(dt, alice.pubkey()),
+            alice.pubkey(),
+            100,
+            bob_pubkey,
+        );
+        let tx = Transaction::new_budget(&alice.keypair(), budget.clone(), 100, last_id);
+        assert!(serialize(&tx).unwrap().len() > PACKET_DATA_SIZE);
+        client.transfer_signed_stream(&tx).unwrap();
+        let mut confirmed = false;
+        for _ in 0..10 {
+            confirmed = client.check_signature(&tx.sig);
+            if confirmed {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        assert!(confirmed);
+        let contracts = client.get_pending_contracts(&bob_pubkey, None).unwrap();
+        assert_eq!(contracts, vec![(tx.sig, Plan::Budget(budget))]);
+
+        exit.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
+
     // sleep(Duration::from_millis(300)); is unstable
     #[test]
     #[ignore]
@@ -332,6 +561,8 @@ mod tests {
             leader,
             exit.clone(),
             sink(),
+            None,
+            GossipStrategy::default(),
         );
         //TODO: remove this sleep, or add a retry so CI is stable
         sleep(Duration::from_millis(300));
@@ -385,6 +616,8 @@ mod tests {
             leader,
             exit.clone(),
             sink(),
+            None,
+            GossipStrategy::default(),
         );
         sleep(Duration::from_millis(300));
 
@@ -410,4 +643,69 @@ mod tests {
         exit.store(true, Ordering::Relaxed);
         server.join().unwrap();
     }
+
+    #[test]
+    fn test_client_pending_contracts() {
+        logger::setup();
+        let leader = TestNode::new_localhost();
+        let alice = Mint::new(10_000);
+        let bank = Bank::new(&alice);
+        let bob_pubkey = KeyPair::new().pubkey();
+        let exit = Arc::new(AtomicBool::new(false));
+        let leader_data = leader.data.clone();
+        let server = FullNode::new_leader(
+            bank,
+            0,
+            None,
+            Some(Duration::from_millis(30)),
+            leader,
+            exit.clone(),
+            sink(),
+            None,
+            GossipStrategy::default(),
+        );
+        sleep(Duration::from_millis(300));
+
+        let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        requests_socket
+            .set_read_timeout(Some(Duration::new(5, 0)))
+            .unwrap();
+        let transactions_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let mut client = ThinClient::new(
+            leader_data.contact_info.rpu,
+            requests_socket,
+            leader_data.contact_info.tpu,
+            transactions_socket,
+        );
+        let last_id = client.get_last_id();
+        let budget = Budget::new_authorized_payment(alice.pubkey(), 500, bob_pubkey);
+        let tx = Transaction::new_budget(&alice.keypair(), budget.clone(), 500, last_id);
+        client.transfer_signed(&tx).unwrap();
+        let mut confirmed = false;
+        for _ in 0..10 {
+            confirmed = client.check_signature(&tx.sig);
+            if confirmed {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        assert!(confirmed);
+
+        let contracts = client.get_pending_contracts(&bob_pubkey, None).unwrap();
+        assert_eq!(contracts, vec![(tx.sig, Plan::Budget(budget))]);
+        let contracts = client
+            .get_pending_contracts(&bob_pubkey, Some(tx.sig))
+            .unwrap();
+        assert!(contracts.is_empty());
+
+        let witness = Transaction::new_signature(&alice.keypair(), tx.sig, last_id);
+        client.transfer_signed(&witness).unwrap();
+        let balance = client.poll_get_balance(&bob_pubkey);
+        assert_eq!(balance.unwrap(), 500);
+        let contracts = client.get_pending_contracts(&bob_pubkey, None).unwrap();
+        assert!(contracts.is_empty());
+
+        exit.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
 }
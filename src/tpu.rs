@@ -1,5 +1,7 @@
 //! The `tpu` module implements the Transaction Processing Unit, a
-//! 5-stage transaction processing pipeline in software.
+//! 5-stage transaction processing pipeline in software. Transactions that don't
+//! fit in a UDP packet are accepted as length-prefixed frames over TCP on the
+//! same port by the `StreamFetchStage` and join the pipeline at the `SigVerifyStage`.
 //!
 //! ```text
 //!             .---------------------------------------------------------------.
@@ -30,24 +32,28 @@ use banking_stage::BankingStage;
 use crdt::Crdt;
 use fetch_stage::FetchStage;
 use packet::{BlobRecycler, PacketRecycler};
-use record_stage::RecordStage;
+use record_stage::{MixinSender, RecordStage};
 use service::Service;
 use sigverify_stage::SigVerifyStage;
 use std::io::Write;
-use std::net::UdpSocket;
+use std::net::{TcpListener, UdpSocket};
 use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::channel;
 use std::sync::{Arc, RwLock};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
+use stream_fetch_stage::StreamFetchStage;
 use streamer::BlobReceiver;
 use write_stage::WriteStage;
 
 pub struct Tpu {
     fetch_stage: FetchStage,
+    stream_fetch_stage: StreamFetchStage,
     sigverify_stage: SigVerifyStage,
     banking_stage: BankingStage,
     record_stage: RecordStage,
     write_stage: WriteStage,
+    mixin_sender: MixinSender,
 }
 
 impl Tpu {
@@ -56,6 +62,7 @@ impl Tpu {
         crdt: &Arc<RwLock<Crdt>>,
         tick_duration: Option<Duration>,
         transactions_socket: UdpSocket,
+        transactions_stream: TcpListener,
         blob_recycler: &BlobRecycler,
         exit: Arc<AtomicBool>,
         writer: W,
@@ -63,18 +70,39 @@ impl Tpu {
         let packet_recycler = PacketRecycler::default();
 
         let (fetch_stage, packet_receiver) =
-            FetchStage::new(transactions_socket, exit, &packet_recycler);
+            FetchStage::new(transactions_socket, exit.clone(), &packet_recycler);
 
-        let (sigverify_stage, verified_receiver) = SigVerifyStage::new(packet_receiver);
+        let (stream_fetch_stage, stream_receiver) =
+            StreamFetchStage::new(transactions_stream, exit, blob_recycler);
 
-        let (banking_stage, signal_receiver) =
-            BankingStage::new(bank.clone(), verified_receiver, packet_recycler.clone());
+        let (sigverify_stage, verified_receiver) = SigVerifyStage::new(
+            packet_receiver,
+            stream_receiver,
+            packet_recycler.clone(),
+            blob_recycler.clone(),
+        );
+
+        let (signal_sender, signal_receiver) = channel();
+        let mixin_sender = MixinSender::new(signal_sender.clone());
+        let banking_stage = BankingStage::new(
+            bank.clone(),
+            verified_receiver,
+            signal_sender,
+            packet_recycler.clone(),
+            blob_recycler.clone(),
+        );
 
-        let (record_stage, entry_receiver) = match tick_duration {
-            Some(tick_duration) => {
+        let (record_stage, entry_receiver) = match (bank.hashes_per_tick(), tick_duration) {
+            (Some(hashes_per_tick), _) => RecordStage::new_with_hashes_per_tick(
+                signal_receiver,
+                &bank.last_id(),
+                hashes_per_tick,
+                bank.hashes_since_tick(),
+            ),
+            (None, Some(tick_duration)) => {
                 RecordStage::new_with_clock(signal_receiver, &bank.last_id(), tick_duration)
             }
-            None => RecordStage::new(signal_receiver, &bank.last_id()),
+            (None, None) => RecordStage::new(signal_receiver, &bank.last_id()),
         };
 
         let (write_stage, blob_receiver) = WriteStage::new(
@@ -87,16 +115,24 @@ impl Tpu {
 
         let tpu = Tpu {
             fetch_stage,
+            stream_fetch_stage,
             sigverify_stage,
             banking_stage,
             record_stage,
             write_stage,
+            mixin_sender,
         };
         (tpu, blob_receiver)
     }
 
+    /// A handle for mixing external values into the PoH stream.
+    pub fn mixin_sender(&self) -> MixinSender {
+        self.mixin_sender.clone()
+    }
+
     pub fn close(self) -> thread::Result<()> {
         self.fetch_stage.close();
+        self.stream_fetch_stage.close();
         self.join()
     }
 }
@@ -105,6 +141,7 @@ impl Service for Tpu {
     fn thread_hdls(self) -> Vec<JoinHandle<()>> {
         let mut thread_hdls = vec![];
         thread_hdls.extend(self.fetch_stage.thread_hdls().into_iter());
+        thread_hdls.extend(self.stream_fetch_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.sigverify_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.banking_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.record_stage.thread_hdls().into_iter());
@@ -1,7 +1,7 @@
 //! The `transaction` module provides functionality for creating log transactions.
 
 use bincode::serialize;
-use budget::{Budget, Condition};
+use budget::Budget;
 use chrono::prelude::*;
 use hash::Hash;
 use payment_plan::{Payment, PaymentPlan, Witness};
@@ -37,6 +37,12 @@ impl PaymentPlan for Plan {
             Plan::Budget(budget) => budget.apply_witness(witness, from),
         }
     }
+
+    fn is_party(&self, pubkey: &PublicKey) -> bool {
+        match self {
+            Plan::Budget(budget) => budget.is_party(pubkey),
+        }
+    }
 }
 
 /// A smart contract.
@@ -58,6 +64,19 @@ pub struct Vote {
     // TODO: add signature of the state here as well
 }
 
+/// Proof that a replicator stores a ledger segment encrypted with its identity, see RFC-003.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct StorageProof {
+    /// The height of the first entry of the segment.
+    pub entry_height: u64,
+    /// The replicator's signature of the id of that entry, the key the segment is encrypted with.
+    pub identity: Signature,
+    /// The replicator's signature of the transaction's `last_id`, which picks the samples.
+    pub sample_seed: Signature,
+    /// The hash of the sampled blocks of the encrypted segment.
+    pub hash: Hash,
+}
+
 /// An instruction to progress the smart contract.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum Instruction {
@@ -73,6 +92,17 @@ pub enum Instruction {
 
     /// Vote for a PoH that is equal to the lastid of this transaction
     NewVote(Vote),
+
+    /// Prove that the containing transaction's `PublicKey` stores a ledger segment.
+    NewStorageProof(StorageProof),
+
+    /// Approve the `NewStorageProof` transactions with the given `Signature`s, which pays
+    /// their replicators a reward from the mint.
+    ApproveStorageProofs(Vec<Signature>),
+
+    /// Fix the number of hashes in every tick of the ledger. Only honored in
+    /// the genesis entries, signed by the mint.
+    SetHashesPerTick(u64),
 }
 
 /// An instruction signed by a client with `PublicKey`.
@@ -132,18 +162,41 @@ impl Transaction {
         Self::new_from_instruction(from_keypair, instruction, last_id, fee)
     }
 
+    /// Create an unsigned payment of `tokens` from `from` to `to`, for a keypair
+    /// kept offline to `sign` later.
+    pub fn new_unsigned(from: PublicKey, to: PublicKey, tokens: i64, last_id: Hash) -> Self {
+        let budget = Budget::Pay(Payment { tokens, to });
+        let plan = Plan::Budget(budget);
+        let instruction = Instruction::NewContract(Contract { plan, tokens });
+        Transaction {
+            sig: Signature::default(),
+            from,
+            instruction,
+            last_id,
+            fee: 0,
+        }
+    }
+
+    /// Create and sign a Transaction that locks `tokens` in `budget` until
+    /// its witnesses release them.
+    pub fn new_budget(from_keypair: &KeyPair, budget: Budget, tokens: i64, last_id: Hash) -> Self {
+        let plan = Plan::Budget(budget);
+        let instruction = Instruction::NewContract(Contract { plan, tokens });
+        Self::new_from_instruction(from_keypair, instruction, last_id, 0)
+    }
+
     /// Create and sign a new Transaction. Used for unit-testing.
     pub fn new(from_keypair: &KeyPair, to: PublicKey, tokens: i64, last_id: Hash) -> Self {
         Self::new_taxed(from_keypair, to, tokens, 0, last_id)
     }
 
-    /// Create and sign a new Witness Timestamp. Used for unit-testing.
+    /// Create and sign a new Witness Timestamp.
     pub fn new_timestamp(from_keypair: &KeyPair, dt: DateTime<Utc>, last_id: Hash) -> Self {
         let instruction = Instruction::ApplyTimestamp(dt);
         Self::new_from_instruction(from_keypair, instruction, last_id, 0)
     }
 
-    /// Create and sign a new Witness Signature. Used for unit-testing.
+    /// Create and sign a new Witness Signature.
     pub fn new_signature(from_keypair: &KeyPair, tx_sig: Signature, last_id: Hash) -> Self {
         let instruction = Instruction::ApplySignature(tx_sig);
         Self::new_from_instruction(from_keypair, instruction, last_id, 0)
@@ -153,6 +206,35 @@ impl Transaction {
         Transaction::new_from_instruction(&from_keypair, Instruction::NewVote(vote), last_id, fee)
     }
 
+    pub fn new_storage_proof(
+        from_keypair: &KeyPair,
+        proof: StorageProof,
+        last_id: Hash,
+        fee: i64,
+    ) -> Self {
+        let instruction = Instruction::NewStorageProof(proof);
+        Transaction::new_from_instruction(&from_keypair, instruction, last_id, fee)
+    }
+
+    pub fn new_storage_approval(
+        from_keypair: &KeyPair,
+        proof_sigs: Vec<Signature>,
+        last_id: Hash,
+        fee: i64,
+    ) -> Self {
+        let instruction = Instruction::ApproveStorageProofs(proof_sigs);
+        Transaction::new_from_instruction(&from_keypair, instruction, last_id, fee)
+    }
+
+    pub fn new_hashes_per_tick(
+        from_keypair: &KeyPair,
+        hashes_per_tick: u64,
+        last_id: Hash,
+    ) -> Self {
+        let instruction = Instruction::SetHashesPerTick(hashes_per_tick);
+        Transaction::new_from_instruction(&from_keypair, instruction, last_id, 0)
+    }
+
     /// Create and sign a postdated Transaction. Used for unit-testing.
     pub fn new_on_date(
         from_keypair: &KeyPair,
@@ -162,13 +244,8 @@ impl Transaction {
         last_id: Hash,
     ) -> Self {
         let from = from_keypair.pubkey();
-        let budget = Budget::Or(
-            (Condition::Timestamp(dt, from), Payment { tokens, to }),
-            (Condition::Signature(from), Payment { tokens, to: from }),
-        );
-        let plan = Plan::Budget(budget);
-        let instruction = Instruction::NewContract(Contract { plan, tokens });
-        Self::new_from_instruction(from_keypair, instruction, last_id, 0)
+        let budget = Budget::new_cancelable_future_payment(dt, from, tokens, to);
+        Self::new_budget(from_keypair, budget, tokens, last_id)
     }
 
     /// Get the transaction data to sign.
@@ -260,6 +337,28 @@ mod tests {
         assert!(!Transaction::new_taxed(&keypair0, pubkey1, 1, -1, zero).verify_plan());
     }
 
+    #[test]
+    fn test_sign_unsigned() {
+        let zero = Hash::default();
+        let keypair = KeyPair::new();
+        let pubkey1 = KeyPair::new().pubkey();
+        let mut tx = Transaction::new_unsigned(keypair.pubkey(), pubkey1, 42, zero);
+        assert!(!tx.verify_sig());
+        tx.sign(&keypair);
+        assert!(tx.verify_sig());
+        assert_eq!(tx, Transaction::new(&keypair, pubkey1, 42, zero));
+    }
+
+    #[test]
+    fn test_new_budget() {
+        let zero = Hash::default();
+        let keypair = KeyPair::new();
+        let pubkey1 = KeyPair::new().pubkey();
+        let budget = Budget::new_authorized_payment(keypair.pubkey(), 42, pubkey1);
+        assert!(Transaction::new_budget(&keypair, budget.clone(), 42, zero).verify_plan());
+        assert!(!Transaction::new_budget(&keypair, budget, 43, zero).verify_plan());
+    }
+
     #[test]
     fn test_serialize_claim() {
         let budget = Budget::Pay(Payment {
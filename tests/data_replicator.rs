@@ -16,7 +16,10 @@ use std::thread::sleep;
 use std::time::Duration;
 
 fn test_node(exit: Arc<AtomicBool>) -> (Arc<RwLock<Crdt>>, Ncp, UdpSocket) {
-    let tn = TestNode::new_localhost();
+    ncp_node(TestNode::new_localhost(), exit)
+}
+
+fn ncp_node(tn: TestNode, exit: Arc<AtomicBool>) -> (Arc<RwLock<Crdt>>, Ncp, UdpSocket) {
     let crdt = Crdt::new(tn.data.clone()).expect("Crdt::new");
     let c = Arc::new(RwLock::new(crdt));
     let w = Arc::new(RwLock::new(vec![]));
@@ -35,12 +38,21 @@ fn test_node(exit: Arc<AtomicBool>) -> (Arc<RwLock<Crdt>>, Ncp, UdpSocket) {
 /// Check that nodes stop sending updates after all the NodeInfo has been shared.
 /// tests that actually use this function are below
 fn run_gossip_topo<F>(topo: F)
+where
+    F: Fn(&Vec<(Arc<RwLock<Crdt>>, Ncp, UdpSocket)>) -> (),
+{
+    run_gossip_topo_with(TestNode::new_localhost, topo)
+}
+
+fn run_gossip_topo_with<F>(new_node: fn() -> TestNode, topo: F)
 where
     F: Fn(&Vec<(Arc<RwLock<Crdt>>, Ncp, UdpSocket)>) -> (),
 {
     let num: usize = 5;
     let exit = Arc::new(AtomicBool::new(false));
-    let listen: Vec<_> = (0..num).map(|_| test_node(exit.clone())).collect();
+    let listen: Vec<_> = (0..num)
+        .map(|_| ncp_node(new_node(), exit.clone()))
+        .collect();
     topo(&listen);
     let mut done = true;
     for i in 0..(num * 32) {
@@ -70,21 +82,30 @@ where
     assert!(done);
 }
 /// ring a -> b -> c -> d -> e -> a
+fn ring(listen: &[(Arc<RwLock<Crdt>>, Ncp, UdpSocket)]) {
+    let num = listen.len();
+    for n in 0..num {
+        let y = n % listen.len();
+        let x = (n + 1) % listen.len();
+        let mut xv = listen[x].0.write().unwrap();
+        let yv = listen[y].0.read().unwrap();
+        let mut d = yv.table[&yv.me].clone();
+        d.version = 0;
+        xv.insert(&d);
+    }
+}
+
 #[test]
 fn gossip_ring() {
     logger::setup();
-    run_gossip_topo(|listen| {
-        let num = listen.len();
-        for n in 0..num {
-            let y = n % listen.len();
-            let x = (n + 1) % listen.len();
-            let mut xv = listen[x].0.write().unwrap();
-            let yv = listen[y].0.read().unwrap();
-            let mut d = yv.table[&yv.me].clone();
-            d.version = 0;
-            xv.insert(&d);
-        }
-    });
+    run_gossip_topo(|listen| ring(listen));
+}
+
+#[cfg(feature = "ipv6")]
+#[test]
+fn gossip_ring_ipv6() {
+    logger::setup();
+    run_gossip_topo_with(TestNode::new_localhost_ipv6, |listen| ring(listen));
 }
 
 /// star a -> (b,c,d,e)
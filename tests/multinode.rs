@@ -4,6 +4,7 @@ extern crate bincode;
 extern crate serde_json;
 extern crate solana;
 
+use solana::choose_gossip_peer_strategy::GossipStrategy;
 use solana::crdt::TestNode;
 use solana::crdt::{Crdt, NodeInfo};
 use solana::entry_writer::EntryWriter;
@@ -98,6 +99,7 @@ fn test_multi_node_validator_catchup_from_zero() {
         LedgerFile::Path(ledger_path.clone()),
         None,
         None,
+        GossipStrategy::default(),
     );
     let mut nodes = vec![server];
     for _ in 0..N {
@@ -109,6 +111,7 @@ fn test_multi_node_validator_catchup_from_zero() {
             LedgerFile::Path(ledger_path.clone()),
             Some(keypair),
             Some(leader_data.contact_info.ncp),
+            GossipStrategy::default(),
         );
         nodes.push(val);
     }
@@ -143,6 +146,7 @@ fn test_multi_node_validator_catchup_from_zero() {
         LedgerFile::Path(ledger_path.clone()),
         Some(keypair),
         Some(leader_data.contact_info.ncp),
+        GossipStrategy::default(),
     );
     nodes.push(val);
     //contains the leader and new node
@@ -197,6 +201,7 @@ fn test_multi_node_basic() {
         LedgerFile::Path(ledger_path.clone()),
         None,
         None,
+        GossipStrategy::default(),
     );
     let mut nodes = vec![server];
     for _ in 0..N {
@@ -208,6 +213,7 @@ fn test_multi_node_basic() {
             LedgerFile::Path(ledger_path.clone()),
             Some(keypair),
             Some(leader_data.contact_info.ncp),
+            GossipStrategy::default(),
         );
         nodes.push(val);
     }
@@ -250,6 +256,7 @@ fn test_boot_validator_from_file() {
         LedgerFile::Path(ledger_path.clone()),
         None,
         None,
+        GossipStrategy::default(),
     );
     let leader_balance =
         send_tx_and_retry_get_balance(&leader_data, &alice, &bob_pubkey, Some(500)).unwrap();
@@ -267,6 +274,7 @@ fn test_boot_validator_from_file() {
         LedgerFile::Path(ledger_path.clone()),
         Some(keypair),
         Some(leader_data.contact_info.ncp),
+        GossipStrategy::default(),
     );
     let mut client = mk_client(&validator_data);
     let getbal = retry_get_balance(&mut client, &bob_pubkey, Some(leader_balance));
@@ -286,6 +294,7 @@ fn create_leader(ledger_path: &str) -> (NodeInfo, FullNode) {
         LedgerFile::Path(ledger_path.to_string()),
         None,
         None,
+        GossipStrategy::default(),
     );
     (leader_data, leader_fullnode)
 }
@@ -337,6 +346,7 @@ fn test_leader_restart_validator_start_from_old_ledger() {
         LedgerFile::Path(stale_ledger_path.clone()),
         Some(keypair),
         Some(leader_data.contact_info.ncp),
+        GossipStrategy::default(),
     );
 
     // trigger broadcast, validator should catch up from leader, whose window contains
@@ -380,6 +390,7 @@ fn test_multi_node_dynamic_network() {
         LedgerFile::Path(ledger_path.clone()),
         None,
         None,
+        GossipStrategy::default(),
     );
     info!("{:x} LEADER", leader_data.debug_id());
     let leader_balance =
@@ -405,6 +416,7 @@ fn test_multi_node_dynamic_network() {
                 LedgerFile::Path(ledger_path.clone()),
                 Some(keypair),
                 Some(leader_data.contact_info.ncp),
+                GossipStrategy::default(),
             );
             info!("started[{}/{}] {:x}", n, N, rd.debug_id());
             (rd, val)